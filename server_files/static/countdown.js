@@ -0,0 +1,13 @@
+// keeps every element with a data-ends-at unix timestamp counting down to it
+function tick() {
+    const now = Date.now() / 1000;
+    for (const el of document.querySelectorAll("[data-ends-at]")) {
+        const left = Math.max(0, Math.floor(Number(el.dataset.endsAt) - now));
+        const days = Math.floor(left / 86400);
+        const hours = Math.floor((left % 86400) / 3600);
+        const minutes = Math.floor((left % 3600) / 60);
+        const seconds = left % 60;
+        el.textContent = (days > 0 ? `${days}d ` : "") + `${hours}h ${minutes}m ${seconds}s`;
+    }
+}
+setInterval(tick, 1000);
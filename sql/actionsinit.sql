@@ -2,7 +2,9 @@ CREATE TABLE users (
   id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
   email VARCHAR(255) NOT NULL,
   password VARCHAR(255) NOT NULL,
-  is_admin BOOLEAN NOT NULL DEFAULT FALSE
+  is_admin BOOLEAN NOT NULL DEFAULT FALSE,
+  --ISO 4217 code prices are shown in, null until the customer picks one
+  currency VARCHAR(3)
 );
 
 CREATE TABLE sessions (
@@ -16,9 +18,54 @@ CREATE TABLE products (
     id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
     title VARCHAR(255) NOT NULL,
     description VARCHAR(255) NOT NULL,
+    --long form Markdown shown on the product page, description is the summary on cards
+    details TEXT NOT NULL DEFAULT '',
     imgname VARCHAR(255) NOT NULL,
-    cost DECIMAL(4, 2) NOT NULL,
-    listed BOOLEAN NOT NULL DEFAULT TRUE
+    listed BOOLEAN NOT NULL DEFAULT TRUE,
+    slug VARCHAR(255) NOT NULL UNIQUE,
+    --archived products are hidden from customers but kept for order history
+    archived_at TIMESTAMPTZ,
+    --seasonal products are listed at publish_at and unlisted at unpublish_at
+    publish_at TIMESTAMPTZ,
+    unpublish_at TIMESTAMPTZ,
+    CHECK (archived_at IS NULL OR NOT listed),
+    CHECK (unpublish_at > publish_at),
+    CHECK (archived_at IS NULL OR (publish_at IS NULL AND unpublish_at IS NULL))
+);
+
+--old slugs are kept after a product is renamed so existing links can be redirected
+CREATE TABLE productredirects (
+    slug VARCHAR(255) PRIMARY KEY,
+    product_id INTEGER NOT NULL,
+    FOREIGN KEY (product_id) REFERENCES products(id)
+);
+
+CREATE TABLE productvariants (
+    id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    product_id INTEGER NOT NULL,
+    sku VARCHAR(64) NOT NULL UNIQUE,
+    size VARCHAR(64) NOT NULL,
+    wicks INTEGER NOT NULL DEFAULT 1,
+    cost DECIMAL(10, 2) NOT NULL,
+    listed BOOLEAN NOT NULL DEFAULT TRUE,
+    stock INTEGER NOT NULL DEFAULT 0 CHECK (stock >= 0),
+    --roughly how long the candle burns for, NULL when it hasn't been measured
+    burn_hours INTEGER CHECK (burn_hours > 0),
+    FOREIGN KEY (product_id) REFERENCES products(id)
+);
+
+CREATE TABLE imageblobs (
+    key VARCHAR(255) PRIMARY KEY,
+    refcount INTEGER NOT NULL CHECK (refcount >= 0)
+);
+
+CREATE TABLE productimages (
+    id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    product_id INTEGER NOT NULL,
+    imgname VARCHAR(255) NOT NULL,
+    alt_text VARCHAR(255) NOT NULL,
+    position INTEGER NOT NULL,
+    FOREIGN KEY (product_id) REFERENCES products(id)
 );
 
 CREATE TABLE addresses (
@@ -37,52 +84,446 @@ CREATE TABLE orders (
     id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
     user_id INTEGER NOT NULL,
     address_id INTEGER NOT NULL,
+    --the currency prices were shown in and the rate used, the order is always charged in pounds
+    currency VARCHAR(3) NOT NULL DEFAULT 'GBP',
+    exchange_rate DECIMAL(12, 6) NOT NULL DEFAULT 1,
+    settlement_total DECIMAL(10, 2) NOT NULL,
+    --the sum of the lines as they were shown, in currency
+    shown_total DECIMAL(18, 2) NOT NULL,
     FOREIGN KEY (user_id) REFERENCES users(id),
     FOREIGN KEY (address_id) REFERENCES addresses(id)
 );
 
 CREATE TABLE productorders (
-    product_id INTEGER NOT NULL,
+    variant_id INTEGER NOT NULL,
     order_id INTEGER NOT NULL,
     quantity INTEGER NOT NULL,
-    FOREIGN KEY (product_id) REFERENCES products(id),
+    --the price paid including any surcharge, so later price changes do not rewrite past orders
+    unit_cost DECIMAL(10, 2) NOT NULL,
+    --what the customer filled in, one "name: value" line per personalisation field
+    personalisation VARCHAR(1024) NOT NULL DEFAULT '',
+    --how much of unit_cost the personalisation added
+    surcharge DECIMAL(10, 2) NOT NULL DEFAULT 0,
+    FOREIGN KEY (variant_id) REFERENCES productvariants(id),
+    FOREIGN KEY (order_id) REFERENCES orders(id),
+    PRIMARY KEY (variant_id, order_id, personalisation)
+);
+
+CREATE TABLE stockadjustments (
+    id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    --cleared when a product that was never ordered is purged, the SKU is kept for the history
+    variant_id INTEGER,
+    sku VARCHAR(64) NOT NULL,
+    delta INTEGER NOT NULL,
+    reason VARCHAR(255) NOT NULL,
+    order_id INTEGER,
+    user_id INTEGER NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    FOREIGN KEY (variant_id) REFERENCES productvariants(id),
     FOREIGN KEY (order_id) REFERENCES orders(id),
-    PRIMARY KEY (product_id, order_id)
+    FOREIGN KEY (user_id) REFERENCES users(id)
 );
 
 CREATE TABLE cartproducts (
+    --identifies the line when it is removed
+    id INTEGER GENERATED ALWAYS AS IDENTITY UNIQUE,
     user_id INTEGER NOT NULL,
-    product_id INTEGER NOT NULL,
+    variant_id INTEGER NOT NULL,
     quantity INTEGER NOT NULL,
+    --the same variant can be in the cart more than once when it is personalised differently
+    personalisation VARCHAR(1024) NOT NULL DEFAULT '',
+    --added to each unit's price, as it was when the item was added
+    surcharge DECIMAL(10, 2) NOT NULL DEFAULT 0,
+    FOREIGN KEY (user_id) REFERENCES users(id),
+    FOREIGN KEY (variant_id) REFERENCES productvariants(id),
+    PRIMARY KEY (variant_id, user_id, personalisation)
+);
+
+CREATE TABLE likedproducts (
+    user_id INTEGER NOT NULL,
+    product_id INTEGER NOT NULL,
     FOREIGN KEY (user_id) REFERENCES users(id),
     FOREIGN KEY (product_id) REFERENCES products(id),
     PRIMARY KEY (product_id, user_id)
 );
 
-CREATE TABLE likedproducts (
+--customers who asked to hear when a liked product is relisted, back in stock or cheaper, along with
+--what they were last told about it so each change is only announced once
+CREATE TABLE productalerts (
     user_id INTEGER NOT NULL,
     product_id INTEGER NOT NULL,
+    --lets the alert be turned off from an email without signing in
+    token VARCHAR(64) NOT NULL UNIQUE,
+    was_listed BOOLEAN NOT NULL,
+    was_in_stock BOOLEAN NOT NULL,
+    last_price DECIMAL(10, 2),
     FOREIGN KEY (user_id) REFERENCES users(id),
     FOREIGN KEY (product_id) REFERENCES products(id),
     PRIMARY KEY (product_id, user_id)
 );
 
+CREATE TABLE notifications (
+    id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    user_id INTEGER NOT NULL,
+    product_id INTEGER NOT NULL,
+    message TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    read_at TIMESTAMPTZ,
+    FOREIGN KEY (user_id) REFERENCES users(id),
+    FOREIGN KEY (product_id) REFERENCES products(id)
+);
+
+--named lists customers keep alongside their favourites, anyone with the share token can see a list
+CREATE TABLE wishlists (
+    id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    user_id INTEGER NOT NULL,
+    name VARCHAR(64) NOT NULL,
+    share_token VARCHAR(64) UNIQUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    FOREIGN KEY (user_id) REFERENCES users(id),
+    UNIQUE (user_id, name)
+);
+
+CREATE TABLE wishlistproducts (
+    wishlist_id INTEGER NOT NULL,
+    product_id INTEGER NOT NULL,
+    added_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    FOREIGN KEY (wishlist_id) REFERENCES wishlists(id),
+    FOREIGN KEY (product_id) REFERENCES products(id),
+    PRIMARY KEY (wishlist_id, product_id)
+);
+
+--products a signed in customer has picked to compare, kept for as long as they stay signed in
+CREATE TABLE comparisons (
+    session_id VARCHAR(255) NOT NULL,
+    product_id INTEGER NOT NULL,
+    added_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    FOREIGN KEY (session_id) REFERENCES sessions(id),
+    FOREIGN KEY (product_id) REFERENCES products(id),
+    PRIMARY KEY (session_id, product_id)
+);
+
+--sections of the homepage, each shows its pinned products in order and then, unless its rule is
+--'manual', fills the rest of its places with the newest or best selling products
+CREATE TABLE collections (
+    id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    name VARCHAR(64) NOT NULL,
+    rule VARCHAR(16) NOT NULL DEFAULT 'manual' CHECK (rule IN ('manual', 'newest', 'bestsellers')),
+    max_products INTEGER NOT NULL DEFAULT 8 CHECK (max_products BETWEEN 1 AND 24),
+    --sections are shown in position order, hidden collections are kept but not shown
+    position INTEGER NOT NULL,
+    shown BOOLEAN NOT NULL DEFAULT TRUE
+);
+
+CREATE TABLE collectionproducts (
+    collection_id INTEGER NOT NULL,
+    product_id INTEGER NOT NULL,
+    position INTEGER NOT NULL,
+    FOREIGN KEY (collection_id) REFERENCES collections(id),
+    FOREIGN KEY (product_id) REFERENCES products(id),
+    PRIMARY KEY (collection_id, product_id)
+);
+
+--attributes admins can give products, such as wax type or vessel, customers filter /browse by them
+CREATE TABLE attributes (
+    id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    name VARCHAR(64) NOT NULL UNIQUE,
+    --the attribute's name in /browse links
+    slug VARCHAR(64) NOT NULL UNIQUE,
+    kind VARCHAR(16) NOT NULL CHECK (kind IN ('text', 'number', 'flag')),
+    --shown after number values, such as 'cm'
+    unit VARCHAR(16) NOT NULL DEFAULT '',
+    position INTEGER NOT NULL
+);
+
+--a product's value for an attribute, only the column for the attribute's kind is set
+CREATE TABLE productattributes (
+    product_id INTEGER NOT NULL,
+    attribute_id INTEGER NOT NULL,
+    text_value VARCHAR(64),
+    number_value INTEGER,
+    flag_value BOOLEAN,
+    FOREIGN KEY (product_id) REFERENCES products(id),
+    FOREIGN KEY (attribute_id) REFERENCES attributes(id),
+    PRIMARY KEY (product_id, attribute_id),
+    CHECK (num_nonnulls(text_value, number_value, flag_value) = 1)
+);
+
+--the notes candles can smell of, each belongs to one scent family
+CREATE TABLE scentnotes (
+    id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    name VARCHAR(64) NOT NULL UNIQUE,
+    family VARCHAR(16) NOT NULL CHECK (family IN ('fruity', 'floral', 'gourmand', 'fresh'))
+);
+
+--a product's scent profile, each note is smelt first (top), once the candle warms (middle) or
+--lingers longest (base)
+CREATE TABLE productscentnotes (
+    product_id INTEGER NOT NULL,
+    note_id INTEGER NOT NULL,
+    tier VARCHAR(8) NOT NULL CHECK (tier IN ('top', 'middle', 'base')),
+    FOREIGN KEY (product_id) REFERENCES products(id),
+    FOREIGN KEY (note_id) REFERENCES scentnotes(id),
+    PRIMARY KEY (product_id, note_id)
+);
+
+--boxes a customer fills in when buying a product, such as the text on a custom label
+CREATE TABLE personalisationfields (
+    id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    product_id INTEGER NOT NULL,
+    name VARCHAR(64) NOT NULL,
+    --text is typed in up to max_length characters, choice is one of the comma separated choices
+    kind VARCHAR(8) NOT NULL CHECK (kind IN ('text', 'choice')),
+    max_length INTEGER NOT NULL DEFAULT 64 CHECK (max_length BETWEEN 1 AND 128),
+    choices VARCHAR(255) NOT NULL DEFAULT '',
+    --added to the price of each unit when the field is filled in
+    surcharge DECIMAL(10, 2) NOT NULL DEFAULT 0 CHECK (surcharge >= 0),
+    required BOOLEAN NOT NULL DEFAULT FALSE,
+    position INTEGER NOT NULL,
+    FOREIGN KEY (product_id) REFERENCES products(id),
+    UNIQUE (product_id, name)
+);
+
+--reviews wait in the moderation queue until an admin approves or rejects them
+CREATE TABLE reviews (
+    id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    product_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    rating INTEGER NOT NULL CHECK (rating BETWEEN 1 AND 5),
+    body VARCHAR(2000) NOT NULL,
+    status VARCHAR(16) NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'approved', 'rejected')),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    FOREIGN KEY (product_id) REFERENCES products(id),
+    FOREIGN KEY (user_id) REFERENCES users(id),
+    UNIQUE (product_id, user_id)
+);
+
+--products most often bought or liked together, rebuilt periodically from orders and likes
+CREATE TABLE productrecommendations (
+    product_id INTEGER NOT NULL,
+    recommended_id INTEGER NOT NULL,
+    score INTEGER NOT NULL,
+    FOREIGN KEY (product_id) REFERENCES products(id),
+    FOREIGN KEY (recommended_id) REFERENCES products(id),
+    PRIMARY KEY (product_id, recommended_id)
+);
+
+--the last few products each signed in customer looked at, guests keep theirs in a signed cookie
+CREATE TABLE recentlyviewed (
+    user_id INTEGER NOT NULL,
+    product_id INTEGER NOT NULL,
+    viewed_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    FOREIGN KEY (user_id) REFERENCES users(id),
+    FOREIGN KEY (product_id) REFERENCES products(id),
+    PRIMARY KEY (user_id, product_id)
+);
+
+--a sale takes percent_off the price of every variant of a product, or of every product when product_id
+--is null, from starts_at until ends_at
+CREATE TABLE sales (
+    id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    name VARCHAR(255) NOT NULL,
+    product_id INTEGER,
+    --a sale on a collection covers the products pinned to it, sales without a product or a
+    --collection are on everything
+    collection_id INTEGER,
+    percent_off INTEGER NOT NULL CHECK (percent_off BETWEEN 1 AND 90),
+    starts_at TIMESTAMPTZ NOT NULL,
+    ends_at TIMESTAMPTZ NOT NULL,
+    FOREIGN KEY (product_id) REFERENCES products(id),
+    FOREIGN KEY (collection_id) REFERENCES collections(id),
+    CHECK (ends_at > starts_at),
+    CHECK (product_id IS NULL OR collection_id IS NULL)
+);
+
+--how many units of currency one pound buys, prices can only be shown in currencies listed here
+CREATE TABLE exchangerates (
+    currency VARCHAR(3) PRIMARY KEY,
+    rate DECIMAL(12, 6) NOT NULL CHECK (rate > 0),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+--answers to the gift finder quiz for merchandising, nothing links them to who gave them and only the
+--day they were given is kept
+CREATE TABLE giftquizanswers (
+    id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    occasion VARCHAR(16) NOT NULL,
+    season VARCHAR(16) NOT NULL,
+    --comma separated scent families, empty when any scent will do
+    families VARCHAR(64) NOT NULL,
+    --a price range in the base currency such as 10-20, or 30- for no upper limit
+    budget VARCHAR(16) NOT NULL,
+    answered_on DATE NOT NULL DEFAULT CURRENT_DATE
+);
+
 --user for unit and integration testing
 INSERT INTO users VALUES(DEFAULT, 'testemail@securecart.com', '$argon2id$v=19$m=19456,t=2,p=1$xuZYri28ZUljWt1CvMXuwA$/j2hNwrsniZslvru/Te4CgOQb80/D9qwg28ZG64CLRM', FALSE);
 --default admin user, pass=@8*aUxB2#fEnT]E
 INSERT INTO users VALUES(DEFAULT, 'admin@securecart.com', '$argon2id$v=19$m=19456,t=2,p=1$Rh8lGJODGahQiqlyvR48/Q$gNzg7gIWtjEI6pFnrgh1ZWxMxuS/xfGmvlEI/sSPRns', TRUE);
 
-INSERT INTO products VALUES 
-(DEFAULT,'Cinnamon Scented Candle','A candle that gives that warm smell to all those around it, a perfect candle for the autumn season','cinnamon.jpg', 12.50, DEFAULT),
-(DEFAULT,'Cherry Scented Candle','A candle that gives a distinct cherry scent, like it was straight from the tree','cherry.jpg', 14.50, DEFAULT),
-(DEFAULT,'Blackberry Scented Candle','Blackberry is a popular scent, giving a strong berry smell to fill the room','blackberry.jpg', 12, DEFAULT),
-(DEFAULT,'Citrus Scented Candle','A strong orange and lemon smell, this candle can freshen up any room it is lit in','citrus.jpg', 10.50, DEFAULT),
-(DEFAULT,'Coffee Scented Candle','Straight from coffee beans, lighting this in the morning is just like drinking a fresh cup of coffee!','coffee.jpg', 15, DEFAULT),
-(DEFAULT,'Dahlia Scented Candle','A scented candle filled with the smell of dahlias, just like it came straight from the garden centre','dahlia.jpg', 14.50, DEFAULT),
-(DEFAULT,'Floral Scented Candle','Bring the outside inside with this floral candle, which freshens any room that it is used in','floral.jpg', 10, DEFAULT),
-(DEFAULT,'Lavender Scented Candle','A smell from the forest that is sure to bring a nice strong countryside smell to those near it','lavender.jpg', 14, DEFAULT),
-(DEFAULT,'Ocean Scented Candle','Bring the seaside to your home with this ocean candle, with salty shores and bright sunets it is sure not to dissapoint','ocean.jpg', 15, DEFAULT),
-(DEFAULT,'Peach Scented Candle','A fruity smell, it is sure to bring the tropical envrionment to yourdoorstep with this relaxing candle','peach.jpg', 11.50, DEFAULT),
-(DEFAULT,'Pineapple Scented Candle','A tangy and sharp smell, the pineapple candle is sure to make a point in any area that its used in','pineapple.jpg', 16, DEFAULT),
-(DEFAULT,'Pumpkin Scented Candle','A popular scent from the autumn season, the pumpkin candle is a definite pick for the spooky season bringing a warm atmosphere with it','pumpkin.jpg', 12.50, DEFAULT),
-(DEFAULT,'Raspberry Scented Candle','A summer smell sure to brighten the day, anyone using this candle is sure to feel happier near it!','raspberry.jpg', 13.50, DEFAULT);
+INSERT INTO products (title, description, imgname, slug) VALUES 
+('Cinnamon Scented Candle','A candle that gives that warm smell to all those around it, a perfect candle for the autumn season','cinnamon.jpg','cinnamon'),
+('Cherry Scented Candle','A candle that gives a distinct cherry scent, like it was straight from the tree','cherry.jpg','cherry'),
+('Blackberry Scented Candle','Blackberry is a popular scent, giving a strong berry smell to fill the room','blackberry.jpg','blackberry'),
+('Citrus Scented Candle','A strong orange and lemon smell, this candle can freshen up any room it is lit in','citrus.jpg','citrus'),
+('Coffee Scented Candle','Straight from coffee beans, lighting this in the morning is just like drinking a fresh cup of coffee!','coffee.jpg','coffee'),
+('Dahlia Scented Candle','A scented candle filled with the smell of dahlias, just like it came straight from the garden centre','dahlia.jpg','dahlia'),
+('Floral Scented Candle','Bring the outside inside with this floral candle, which freshens any room that it is used in','floral.jpg','floral'),
+('Lavender Scented Candle','A smell from the forest that is sure to bring a nice strong countryside smell to those near it','lavender.jpg','lavender'),
+('Ocean Scented Candle','Bring the seaside to your home with this ocean candle, with salty shores and bright sunets it is sure not to dissapoint','ocean.jpg','ocean'),
+('Peach Scented Candle','A fruity smell, it is sure to bring the tropical envrionment to yourdoorstep with this relaxing candle','peach.jpg','peach'),
+('Pineapple Scented Candle','A tangy and sharp smell, the pineapple candle is sure to make a point in any area that its used in','pineapple.jpg','pineapple'),
+('Pumpkin Scented Candle','A popular scent from the autumn season, the pumpkin candle is a definite pick for the spooky season bringing a warm atmosphere with it','pumpkin.jpg','pumpkin'),
+('Raspberry Scented Candle','A summer smell sure to brighten the day, anyone using this candle is sure to feel happier near it!','raspberry.jpg','raspberry');
+
+--every product starts with a standard single wick variant, sku is derived from the product id
+INSERT INTO productvariants (product_id, sku, size, wicks, cost, stock)
+SELECT products.id, 'SC' || LPAD(products.id::TEXT, 4, '0') || '-STD', 'Standard', 1, prices.cost, 25
+FROM products INNER JOIN (VALUES 
+('cinnamon.jpg', 12.50),
+('cherry.jpg', 14.50),
+('blackberry.jpg', 12.00),
+('citrus.jpg', 10.50),
+('coffee.jpg', 15.00),
+('dahlia.jpg', 14.50),
+('floral.jpg', 10.00),
+('lavender.jpg', 14.00),
+('ocean.jpg', 15.00),
+('peach.jpg', 11.50),
+('pineapple.jpg', 16.00),
+('pumpkin.jpg', 12.50),
+('raspberry.jpg', 13.50)
+) AS prices(imgname, cost) ON prices.imgname = products.imgname;
+
+INSERT INTO productvariants (product_id, sku, size, wicks, cost, stock)
+SELECT products.id, 'SC' || LPAD(products.id::TEXT, 4, '0') || '-LG3', 'Large', 3, prices.cost, 5
+FROM products INNER JOIN (VALUES 
+('cinnamon.jpg', 24.50),
+('pumpkin.jpg', 24.50),
+('lavender.jpg', 27.00)
+) AS prices(imgname, cost) ON prices.imgname = products.imgname;
+
+--the image each product was created with becomes the first image in its gallery
+INSERT INTO productimages (product_id, imgname, alt_text, position)
+SELECT id, imgname, title, 0 FROM products;
+
+--every product and gallery row holds a reference to its stored image
+INSERT INTO imageblobs (key, refcount)
+SELECT imgname, COUNT(*) FROM (
+    SELECT imgname FROM products UNION ALL SELECT imgname FROM productimages
+) AS refs GROUP BY imgname;
+
+INSERT INTO collections (name, rule, max_products, position) VALUES
+    ('Featured', 'manual', 7, 1),
+    ('New arrivals', 'newest', 7, 2),
+    ('Bestsellers', 'bestsellers', 7, 3);
+
+INSERT INTO attributes (name, slug, kind, position) VALUES
+    ('Wax type', 'wax-type', 'text', 1),
+    ('Vessel', 'vessel', 'text', 2);
+
+INSERT INTO productattributes (product_id, attribute_id, text_value)
+SELECT products.id, attributes.id, values.value
+FROM products INNER JOIN (VALUES
+('cinnamon', 'wax-type', 'Soy'),
+('cherry', 'wax-type', 'Soy'),
+('blackberry', 'wax-type', 'Soy'),
+('citrus', 'wax-type', 'Soy'),
+('coffee', 'wax-type', 'Soy'),
+('dahlia', 'wax-type', 'Soy'),
+('floral', 'wax-type', 'Soy'),
+('lavender', 'wax-type', 'Soy'),
+('ocean', 'wax-type', 'Coconut'),
+('peach', 'wax-type', 'Coconut'),
+('pineapple', 'wax-type', 'Coconut'),
+('pumpkin', 'wax-type', 'Soy'),
+('raspberry', 'wax-type', 'Soy'),
+('cinnamon', 'vessel', 'Glass'),
+('cherry', 'vessel', 'Glass'),
+('blackberry', 'vessel', 'Glass'),
+('citrus', 'vessel', 'Tin'),
+('coffee', 'vessel', 'Glass'),
+('dahlia', 'vessel', 'Glass'),
+('floral', 'vessel', 'Tin'),
+('lavender', 'vessel', 'Glass'),
+('ocean', 'vessel', 'Glass'),
+('peach', 'vessel', 'Glass'),
+('pineapple', 'vessel', 'Glass'),
+('pumpkin', 'vessel', 'Glass'),
+('raspberry', 'vessel', 'Glass')
+) AS values(slug, attribute, value) ON values.slug = products.slug
+INNER JOIN attributes ON attributes.slug = values.attribute;
+
+INSERT INTO scentnotes (name, family) VALUES
+    ('Cherry', 'fruity'),
+    ('Blackberry', 'fruity'),
+    ('Raspberry', 'fruity'),
+    ('Peach', 'fruity'),
+    ('Pineapple', 'fruity'),
+    ('Dahlia', 'floral'),
+    ('Rose', 'floral'),
+    ('Jasmine', 'floral'),
+    ('Lavender', 'floral'),
+    ('Lily', 'floral'),
+    ('Cinnamon', 'gourmand'),
+    ('Clove', 'gourmand'),
+    ('Coffee', 'gourmand'),
+    ('Caramel', 'gourmand'),
+    ('Vanilla', 'gourmand'),
+    ('Almond', 'gourmand'),
+    ('Pumpkin', 'gourmand'),
+    ('Lemon', 'fresh'),
+    ('Orange', 'fresh'),
+    ('Bergamot', 'fresh'),
+    ('Mint', 'fresh'),
+    ('Sea salt', 'fresh'),
+    ('Ozone', 'fresh');
+
+INSERT INTO productscentnotes (product_id, note_id, tier)
+SELECT products.id, scentnotes.id, notes.tier
+FROM products INNER JOIN (VALUES
+('cinnamon', 'Orange', 'top'),
+('cinnamon', 'Cinnamon', 'middle'),
+('cinnamon', 'Clove', 'middle'),
+('cinnamon', 'Vanilla', 'base'),
+('cherry', 'Cherry', 'top'),
+('cherry', 'Almond', 'middle'),
+('cherry', 'Vanilla', 'base'),
+('blackberry', 'Blackberry', 'top'),
+('blackberry', 'Raspberry', 'middle'),
+('blackberry', 'Vanilla', 'base'),
+('citrus', 'Lemon', 'top'),
+('citrus', 'Orange', 'top'),
+('citrus', 'Bergamot', 'middle'),
+('citrus', 'Mint', 'base'),
+('coffee', 'Coffee', 'top'),
+('coffee', 'Caramel', 'middle'),
+('coffee', 'Vanilla', 'base'),
+('dahlia', 'Dahlia', 'top'),
+('dahlia', 'Rose', 'middle'),
+('dahlia', 'Jasmine', 'base'),
+('floral', 'Lily', 'top'),
+('floral', 'Rose', 'middle'),
+('floral', 'Jasmine', 'middle'),
+('floral', 'Lavender', 'base'),
+('lavender', 'Lavender', 'top'),
+('lavender', 'Mint', 'middle'),
+('lavender', 'Vanilla', 'base'),
+('ocean', 'Sea salt', 'top'),
+('ocean', 'Ozone', 'middle'),
+('ocean', 'Bergamot', 'base'),
+('peach', 'Peach', 'top'),
+('peach', 'Jasmine', 'middle'),
+('peach', 'Vanilla', 'base'),
+('pineapple', 'Pineapple', 'top'),
+('pineapple', 'Orange', 'middle'),
+('pineapple', 'Caramel', 'base'),
+('pumpkin', 'Pumpkin', 'top'),
+('pumpkin', 'Cinnamon', 'middle'),
+('pumpkin', 'Clove', 'middle'),
+('pumpkin', 'Caramel', 'base'),
+('raspberry', 'Raspberry', 'top'),
+('raspberry', 'Rose', 'middle'),
+('raspberry', 'Vanilla', 'base')
+) AS notes(slug, note, tier) ON notes.slug = products.slug
+INNER JOIN scentnotes ON scentnotes.name = notes.note;
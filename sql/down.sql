@@ -1,6 +1,29 @@
 USE ecom_db;
+DROP TABLE personalisationfields;
+DROP TABLE giftquizanswers;
+DROP TABLE productscentnotes;
+DROP TABLE scentnotes;
+DROP TABLE productattributes;
+DROP TABLE attributes;
+DROP TABLE sales;
+DROP TABLE collectionproducts;
+DROP TABLE collections;
+DROP TABLE comparisons;
+DROP TABLE wishlistproducts;
+DROP TABLE wishlists;
+DROP TABLE notifications;
+DROP TABLE productalerts;
+DROP TABLE reviews;
+DROP TABLE productrecommendations;
+DROP TABLE recentlyviewed;
+DROP TABLE exchangerates;
 DROP TABLE users;
 DROP TABLE sessions;
+DROP TABLE stockadjustments;
+DROP TABLE productvariants;
+DROP TABLE productimages;
+DROP TABLE imageblobs;
+DROP TABLE productredirects;
 DROP TABLE products;
 DROP TABLE addresses;
 DROP TABLE productorders;
@@ -0,0 +1,33 @@
+--moves pricing from products onto variants, every existing product gets a single default variant
+--and any carts or past orders are repointed at that variant
+CREATE TABLE productvariants (
+    id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    product_id INTEGER NOT NULL,
+    sku VARCHAR(64) NOT NULL UNIQUE,
+    size VARCHAR(64) NOT NULL,
+    wicks INTEGER NOT NULL DEFAULT 1,
+    cost DECIMAL(4, 2) NOT NULL,
+    listed BOOLEAN NOT NULL DEFAULT TRUE,
+    FOREIGN KEY (product_id) REFERENCES products(id)
+);
+
+INSERT INTO productvariants (product_id, sku, size, wicks, cost, listed)
+SELECT id, 'SC' || LPAD(id::TEXT, 4, '0') || '-STD', 'Standard', 1, cost, listed FROM products;
+
+ALTER TABLE cartproducts ADD COLUMN variant_id INTEGER REFERENCES productvariants(id);
+UPDATE cartproducts SET variant_id = productvariants.id
+FROM productvariants WHERE productvariants.product_id = cartproducts.product_id;
+ALTER TABLE cartproducts DROP CONSTRAINT cartproducts_pkey;
+ALTER TABLE cartproducts DROP COLUMN product_id;
+ALTER TABLE cartproducts ALTER COLUMN variant_id SET NOT NULL;
+ALTER TABLE cartproducts ADD PRIMARY KEY (variant_id, user_id);
+
+ALTER TABLE productorders ADD COLUMN variant_id INTEGER REFERENCES productvariants(id);
+UPDATE productorders SET variant_id = productvariants.id
+FROM productvariants WHERE productvariants.product_id = productorders.product_id;
+ALTER TABLE productorders DROP CONSTRAINT productorders_pkey;
+ALTER TABLE productorders DROP COLUMN product_id;
+ALTER TABLE productorders ALTER COLUMN variant_id SET NOT NULL;
+ALTER TABLE productorders ADD PRIMARY KEY (variant_id, order_id);
+
+ALTER TABLE products DROP COLUMN cost;
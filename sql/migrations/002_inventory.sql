@@ -0,0 +1,15 @@
+--stock is tracked per variant, existing variants start out of stock until counted in the admin panel
+ALTER TABLE productvariants ADD COLUMN stock INTEGER NOT NULL DEFAULT 0 CHECK (stock >= 0);
+
+CREATE TABLE stockadjustments (
+    id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    variant_id INTEGER NOT NULL,
+    delta INTEGER NOT NULL,
+    reason VARCHAR(255) NOT NULL,
+    order_id INTEGER,
+    user_id INTEGER NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    FOREIGN KEY (variant_id) REFERENCES productvariants(id),
+    FOREIGN KEY (order_id) REFERENCES orders(id),
+    FOREIGN KEY (user_id) REFERENCES users(id)
+);
@@ -0,0 +1,12 @@
+CREATE TABLE productimages (
+    id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    product_id INTEGER NOT NULL,
+    imgname VARCHAR(255) NOT NULL,
+    alt_text VARCHAR(255) NOT NULL,
+    position INTEGER NOT NULL,
+    FOREIGN KEY (product_id) REFERENCES products(id)
+);
+
+--the image each product was created with becomes the first image in its gallery
+INSERT INTO productimages (product_id, imgname, alt_text, position)
+SELECT id, imgname, title, 0 FROM products;
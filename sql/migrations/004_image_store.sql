@@ -0,0 +1,10 @@
+--stored images are reference counted so shared blobs are only removed once nothing uses them
+CREATE TABLE imageblobs (
+    key VARCHAR(255) PRIMARY KEY,
+    refcount INTEGER NOT NULL CHECK (refcount >= 0)
+);
+--every product and gallery row holds a reference to its stored image
+INSERT INTO imageblobs (key, refcount)
+SELECT imgname, COUNT(*) FROM (
+    SELECT imgname FROM products UNION ALL SELECT imgname FROM productimages
+) AS refs GROUP BY imgname;
@@ -0,0 +1,5 @@
+--record the price paid for each order line, existing orders take the current price
+ALTER TABLE productorders ADD COLUMN unit_cost DECIMAL(4, 2);
+UPDATE productorders SET unit_cost = productvariants.cost
+FROM productvariants WHERE productvariants.id = productorders.variant_id;
+ALTER TABLE productorders ALTER COLUMN unit_cost SET NOT NULL;
@@ -0,0 +1,16 @@
+--products were previously addressed by their image name, keep those urls working as slugs
+ALTER TABLE products ADD COLUMN slug VARCHAR(255);
+UPDATE products SET slug = CASE
+    WHEN (SELECT COUNT(*) FROM products p WHERE split_part(p.imgname, '.', 1) = split_part(products.imgname, '.', 1)) > 1
+    THEN split_part(imgname, '.', 1) || '-' || id
+    ELSE split_part(imgname, '.', 1)
+END;
+ALTER TABLE products ALTER COLUMN slug SET NOT NULL;
+ALTER TABLE products ADD CONSTRAINT products_slug_key UNIQUE (slug);
+
+--old slugs are kept after a product is renamed so existing links can be redirected
+CREATE TABLE productredirects (
+    slug VARCHAR(255) PRIMARY KEY,
+    product_id INTEGER NOT NULL,
+    FOREIGN KEY (product_id) REFERENCES products(id)
+);
@@ -0,0 +1,9 @@
+--products most often bought or liked together, rebuilt periodically from orders and likes
+CREATE TABLE productrecommendations (
+    product_id INTEGER NOT NULL,
+    recommended_id INTEGER NOT NULL,
+    score INTEGER NOT NULL,
+    FOREIGN KEY (product_id) REFERENCES products(id),
+    FOREIGN KEY (recommended_id) REFERENCES products(id),
+    PRIMARY KEY (product_id, recommended_id)
+);
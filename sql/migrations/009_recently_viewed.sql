@@ -0,0 +1,9 @@
+--the last few products each signed in customer looked at, guests keep theirs in a signed cookie
+CREATE TABLE recentlyviewed (
+    user_id INTEGER NOT NULL,
+    product_id INTEGER NOT NULL,
+    viewed_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    FOREIGN KEY (user_id) REFERENCES users(id),
+    FOREIGN KEY (product_id) REFERENCES products(id),
+    PRIMARY KEY (user_id, product_id)
+);
@@ -0,0 +1,12 @@
+--a sale takes percent_off the price of every variant of a product, or of every product when product_id
+--is null, from starts_at until ends_at
+CREATE TABLE sales (
+    id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    name VARCHAR(255) NOT NULL,
+    product_id INTEGER,
+    percent_off INTEGER NOT NULL CHECK (percent_off BETWEEN 1 AND 90),
+    starts_at TIMESTAMPTZ NOT NULL,
+    ends_at TIMESTAMPTZ NOT NULL,
+    FOREIGN KEY (product_id) REFERENCES products(id),
+    CHECK (ends_at > starts_at)
+);
@@ -0,0 +1,3 @@
+--archived products are hidden from customers but kept for order history
+ALTER TABLE products ADD COLUMN archived_at TIMESTAMPTZ;
+ALTER TABLE products ADD CONSTRAINT products_archived_unlisted CHECK (archived_at IS NULL OR NOT listed);
@@ -0,0 +1,2 @@
+--long form Markdown shown on the product page, description is the summary on cards
+ALTER TABLE products ADD COLUMN details TEXT NOT NULL DEFAULT '';
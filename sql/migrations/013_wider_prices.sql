@@ -0,0 +1,3 @@
+--prices up to £99,999,999.99
+ALTER TABLE productvariants ALTER COLUMN cost TYPE DECIMAL(10, 2);
+ALTER TABLE productorders ALTER COLUMN unit_cost TYPE DECIMAL(10, 2);
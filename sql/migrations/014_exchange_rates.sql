@@ -0,0 +1,18 @@
+--how many units of currency one pound buys, prices can only be shown in currencies listed here
+CREATE TABLE exchangerates (
+    currency VARCHAR(3) PRIMARY KEY,
+    rate DECIMAL(12, 6) NOT NULL CHECK (rate > 0),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+--ISO 4217 code prices are shown in, null until the customer picks one
+ALTER TABLE users ADD COLUMN currency VARCHAR(3);
+
+--the currency prices were shown in and the rate used, the order is always charged in pounds
+ALTER TABLE orders ADD COLUMN currency VARCHAR(3) NOT NULL DEFAULT 'GBP';
+ALTER TABLE orders ADD COLUMN exchange_rate DECIMAL(12, 6) NOT NULL DEFAULT 1;
+ALTER TABLE orders ADD COLUMN settlement_total DECIMAL(10, 2);
+UPDATE orders SET settlement_total = (
+    SELECT COALESCE(SUM(quantity * unit_cost), 0) FROM productorders WHERE order_id = orders.id
+);
+ALTER TABLE orders ALTER COLUMN settlement_total SET NOT NULL;
@@ -0,0 +1,6 @@
+--seasonal products are listed at publish_at and unlisted at unpublish_at
+ALTER TABLE products ADD COLUMN publish_at TIMESTAMPTZ;
+ALTER TABLE products ADD COLUMN unpublish_at TIMESTAMPTZ;
+ALTER TABLE products ADD CONSTRAINT products_publishing_window CHECK (unpublish_at > publish_at);
+ALTER TABLE products ADD CONSTRAINT products_archived_unscheduled
+    CHECK (archived_at IS NULL OR (publish_at IS NULL AND unpublish_at IS NULL));
@@ -0,0 +1,25 @@
+--customers who asked to hear when a liked product is relisted, back in stock or cheaper, along with
+--what they were last told about it so each change is only announced once
+CREATE TABLE productalerts (
+    user_id INTEGER NOT NULL,
+    product_id INTEGER NOT NULL,
+    --lets the alert be turned off from an email without signing in
+    token VARCHAR(64) NOT NULL UNIQUE,
+    was_listed BOOLEAN NOT NULL,
+    was_in_stock BOOLEAN NOT NULL,
+    last_price DECIMAL(10, 2),
+    FOREIGN KEY (user_id) REFERENCES users(id),
+    FOREIGN KEY (product_id) REFERENCES products(id),
+    PRIMARY KEY (product_id, user_id)
+);
+
+CREATE TABLE notifications (
+    id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    user_id INTEGER NOT NULL,
+    product_id INTEGER NOT NULL,
+    message VARCHAR(255) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    read_at TIMESTAMPTZ,
+    FOREIGN KEY (user_id) REFERENCES users(id),
+    FOREIGN KEY (product_id) REFERENCES products(id)
+);
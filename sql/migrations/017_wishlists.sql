@@ -0,0 +1,19 @@
+--named lists customers keep alongside their favourites, anyone with the share token can see a list
+CREATE TABLE wishlists (
+    id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    user_id INTEGER NOT NULL,
+    name VARCHAR(64) NOT NULL,
+    share_token VARCHAR(64) UNIQUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    FOREIGN KEY (user_id) REFERENCES users(id),
+    UNIQUE (user_id, name)
+);
+
+CREATE TABLE wishlistproducts (
+    wishlist_id INTEGER NOT NULL,
+    product_id INTEGER NOT NULL,
+    added_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    FOREIGN KEY (wishlist_id) REFERENCES wishlists(id),
+    FOREIGN KEY (product_id) REFERENCES products(id),
+    PRIMARY KEY (wishlist_id, product_id)
+);
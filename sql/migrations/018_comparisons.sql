@@ -0,0 +1,15 @@
+--free text such as "cherry, almond, vanilla", shown when comparing products
+ALTER TABLE products ADD COLUMN scent_notes VARCHAR(255) NOT NULL DEFAULT '';
+
+--roughly how long the candle burns for, NULL when it hasn't been measured
+ALTER TABLE productvariants ADD COLUMN burn_hours INTEGER CHECK (burn_hours > 0);
+
+--products a signed in customer has picked to compare, kept for as long as they stay signed in
+CREATE TABLE comparisons (
+    session_id VARCHAR(255) NOT NULL,
+    product_id INTEGER NOT NULL,
+    added_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    FOREIGN KEY (session_id) REFERENCES sessions(id),
+    FOREIGN KEY (product_id) REFERENCES products(id),
+    PRIMARY KEY (session_id, product_id)
+);
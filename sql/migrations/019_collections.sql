@@ -0,0 +1,25 @@
+--sections of the homepage, each shows its pinned products in order and then, unless its rule is
+--'manual', fills the rest of its places with the newest or best selling products
+CREATE TABLE collections (
+    id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    name VARCHAR(64) NOT NULL,
+    rule VARCHAR(16) NOT NULL DEFAULT 'manual' CHECK (rule IN ('manual', 'newest', 'bestsellers')),
+    max_products INTEGER NOT NULL DEFAULT 8 CHECK (max_products BETWEEN 1 AND 24),
+    --sections are shown in position order, hidden collections are kept but not shown
+    position INTEGER NOT NULL,
+    shown BOOLEAN NOT NULL DEFAULT TRUE
+);
+
+CREATE TABLE collectionproducts (
+    collection_id INTEGER NOT NULL,
+    product_id INTEGER NOT NULL,
+    position INTEGER NOT NULL,
+    FOREIGN KEY (collection_id) REFERENCES collections(id),
+    FOREIGN KEY (product_id) REFERENCES products(id),
+    PRIMARY KEY (collection_id, product_id)
+);
+
+INSERT INTO collections (name, rule, max_products, position) VALUES
+    ('Featured', 'manual', 7, 1),
+    ('New arrivals', 'newest', 7, 2),
+    ('Bestsellers', 'bestsellers', 7, 3);
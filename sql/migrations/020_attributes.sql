@@ -0,0 +1,28 @@
+--attributes admins can give products, such as wax type or vessel, customers filter /browse by them
+CREATE TABLE attributes (
+    id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    name VARCHAR(64) NOT NULL UNIQUE,
+    --the attribute's name in /browse links
+    slug VARCHAR(64) NOT NULL UNIQUE,
+    kind VARCHAR(16) NOT NULL CHECK (kind IN ('text', 'number', 'flag')),
+    --shown after number values, such as 'cm'
+    unit VARCHAR(16) NOT NULL DEFAULT '',
+    position INTEGER NOT NULL
+);
+
+--a product's value for an attribute, only the column for the attribute's kind is set
+CREATE TABLE productattributes (
+    product_id INTEGER NOT NULL,
+    attribute_id INTEGER NOT NULL,
+    text_value VARCHAR(64),
+    number_value INTEGER,
+    flag_value BOOLEAN,
+    FOREIGN KEY (product_id) REFERENCES products(id),
+    FOREIGN KEY (attribute_id) REFERENCES attributes(id),
+    PRIMARY KEY (product_id, attribute_id),
+    CHECK (num_nonnulls(text_value, number_value, flag_value) = 1)
+);
+
+INSERT INTO attributes (name, slug, kind, position) VALUES
+    ('Wax type', 'wax-type', 'text', 1),
+    ('Vessel', 'vessel', 'text', 2);
@@ -0,0 +1,42 @@
+--the notes candles can smell of, each belongs to one scent family
+CREATE TABLE scentnotes (
+    id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    name VARCHAR(64) NOT NULL UNIQUE,
+    family VARCHAR(16) NOT NULL CHECK (family IN ('fruity', 'floral', 'gourmand', 'fresh'))
+);
+
+--a product's scent profile, each note is smelt first (top), once the candle warms (middle) or
+--lingers longest (base)
+CREATE TABLE productscentnotes (
+    product_id INTEGER NOT NULL,
+    note_id INTEGER NOT NULL,
+    tier VARCHAR(8) NOT NULL CHECK (tier IN ('top', 'middle', 'base')),
+    FOREIGN KEY (product_id) REFERENCES products(id),
+    FOREIGN KEY (note_id) REFERENCES scentnotes(id),
+    PRIMARY KEY (product_id, note_id)
+);
+
+INSERT INTO scentnotes (name, family) VALUES
+    ('Cherry', 'fruity'),
+    ('Blackberry', 'fruity'),
+    ('Raspberry', 'fruity'),
+    ('Peach', 'fruity'),
+    ('Pineapple', 'fruity'),
+    ('Dahlia', 'floral'),
+    ('Rose', 'floral'),
+    ('Jasmine', 'floral'),
+    ('Lavender', 'floral'),
+    ('Lily', 'floral'),
+    ('Cinnamon', 'gourmand'),
+    ('Clove', 'gourmand'),
+    ('Coffee', 'gourmand'),
+    ('Caramel', 'gourmand'),
+    ('Vanilla', 'gourmand'),
+    ('Almond', 'gourmand'),
+    ('Pumpkin', 'gourmand'),
+    ('Lemon', 'fresh'),
+    ('Orange', 'fresh'),
+    ('Bergamot', 'fresh'),
+    ('Mint', 'fresh'),
+    ('Sea salt', 'fresh'),
+    ('Ozone', 'fresh');
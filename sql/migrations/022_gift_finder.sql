@@ -0,0 +1,12 @@
+--answers to the gift finder quiz for merchandising, nothing links them to who gave them and only the
+--day they were given is kept
+CREATE TABLE giftquizanswers (
+    id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    occasion VARCHAR(16) NOT NULL,
+    season VARCHAR(16) NOT NULL,
+    --comma separated scent families, empty when any scent will do
+    families VARCHAR(64) NOT NULL,
+    --a price range in the base currency such as 10-20, or 30- for no upper limit
+    budget VARCHAR(16) NOT NULL,
+    answered_on DATE NOT NULL DEFAULT CURRENT_DATE
+);
@@ -0,0 +1,29 @@
+--boxes a customer fills in when buying a product, such as the text on a custom label
+CREATE TABLE personalisationfields (
+    id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    product_id INTEGER NOT NULL,
+    name VARCHAR(64) NOT NULL,
+    --text is typed in up to max_length characters, choice is one of the comma separated choices
+    kind VARCHAR(8) NOT NULL CHECK (kind IN ('text', 'choice')),
+    max_length INTEGER NOT NULL DEFAULT 64 CHECK (max_length BETWEEN 1 AND 128),
+    choices VARCHAR(255) NOT NULL DEFAULT '',
+    --added to the price of each unit when the field is filled in
+    surcharge DECIMAL(10, 2) NOT NULL DEFAULT 0 CHECK (surcharge >= 0),
+    required BOOLEAN NOT NULL DEFAULT FALSE,
+    position INTEGER NOT NULL,
+    FOREIGN KEY (product_id) REFERENCES products(id),
+    UNIQUE (product_id, name)
+);
+
+--what the customer filled in, one "name: value" line per field, and what it adds to each unit. The
+--same variant can be in a cart or order more than once when it is personalised differently
+ALTER TABLE cartproducts ADD COLUMN personalisation VARCHAR(1024) NOT NULL DEFAULT '';
+ALTER TABLE cartproducts ADD COLUMN surcharge DECIMAL(10, 2) NOT NULL DEFAULT 0;
+ALTER TABLE cartproducts DROP CONSTRAINT cartproducts_pkey;
+ALTER TABLE cartproducts ADD PRIMARY KEY (variant_id, user_id, personalisation);
+
+--unit_cost includes the surcharge
+ALTER TABLE productorders ADD COLUMN personalisation VARCHAR(1024) NOT NULL DEFAULT '';
+ALTER TABLE productorders ADD COLUMN surcharge DECIMAL(10, 2) NOT NULL DEFAULT 0;
+ALTER TABLE productorders DROP CONSTRAINT productorders_pkey;
+ALTER TABLE productorders ADD PRIMARY KEY (variant_id, order_id, personalisation);
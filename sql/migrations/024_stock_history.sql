@@ -0,0 +1,7 @@
+--adjustments keep the SKU they were made against, so the history stays readable once a purged
+--product's variants are gone and variant_id is cleared
+ALTER TABLE stockadjustments ADD COLUMN sku VARCHAR(64) NOT NULL DEFAULT '';
+UPDATE stockadjustments SET sku = productvariants.sku FROM productvariants
+    WHERE productvariants.id = stockadjustments.variant_id;
+ALTER TABLE stockadjustments ALTER COLUMN sku DROP DEFAULT;
+ALTER TABLE stockadjustments ALTER COLUMN variant_id DROP NOT NULL;
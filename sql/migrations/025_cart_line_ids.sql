@@ -0,0 +1,2 @@
+--cart lines are removed by id, so the stored personalisation doesn't have to be sent back exactly
+ALTER TABLE cartproducts ADD COLUMN id INTEGER GENERATED ALWAYS AS IDENTITY UNIQUE;
@@ -0,0 +1,2 @@
+--scent is described by the notes in productscentnotes, which the compare page now shows too
+ALTER TABLE products DROP COLUMN scent_notes;
@@ -0,0 +1,8 @@
+--the sum of the lines as they were shown, in currency. Each unit price is converted and rounded
+--before it is multiplied, the same as the order page does
+ALTER TABLE orders ADD COLUMN shown_total DECIMAL(18, 2);
+UPDATE orders SET shown_total = (
+    SELECT COALESCE(SUM(quantity * ROUND(unit_cost * orders.exchange_rate, 2)), 0)
+    FROM productorders WHERE order_id = orders.id
+);
+ALTER TABLE orders ALTER COLUMN shown_total SET NOT NULL;
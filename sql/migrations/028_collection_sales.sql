@@ -0,0 +1,4 @@
+--a sale on a collection covers the products pinned to it, sales without a product or a
+--collection are on everything
+ALTER TABLE sales ADD COLUMN collection_id INTEGER REFERENCES collections(id);
+ALTER TABLE sales ADD CHECK (product_id IS NULL OR collection_id IS NULL);
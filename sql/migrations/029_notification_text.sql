@@ -0,0 +1,2 @@
+--messages include the product title, which can already be 255 characters on its own
+ALTER TABLE notifications ALTER COLUMN message TYPE TEXT;
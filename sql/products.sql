@@ -1,14 +1,51 @@
-INSERT INTO products VALUES 
-(DEFAULT,'Cinnamon Scented Candle','A candle that gives that warm smell to all those around it, a perfect candle for the autumn season','cinnamon.jpg', 12.50, DEFAULT),
-(DEFAULT,'Cherry Scented Candle','A candle that gives a distinct cherry scent, like it was straight from the tree','cherry.jpg', 14.50, DEFAULT),
-(DEFAULT,'Blackberry Scented Candle','Blackberry is a popular scent, giving a strong berry smell to fill the room','blackberry.jpg', 12, DEFAULT),
-(DEFAULT,'Citrus Scented Candle','A strong orange and lemon smell, this candle can freshen up any room it is lit in','citrus.jpg', 10.50, DEFAULT),
-(DEFAULT,'Coffee Scented Candle','Straight from coffee beans, lighting this in the morning is just like drinking a fresh cup of coffee!','coffee.jpg', 15, DEFAULT),
-(DEFAULT,'Dahlia Scented Candle','A scented candle filled with the smell of dahlias, just like it came straight from the garden centre','dahlia.jpg', 14.50, DEFAULT),
-(DEFAULT,'Floral Scented Candle','Bring the outside inside with this floral candle, which freshens any room that it is used in','floral.jpg', 10, DEFAULT),
-(DEFAULT,'Lavender Scented Candle','A smell from the forest that is sure to bring a nice strong countryside smell to those near it','lavender.jpg', 14, DEFAULT),
-(DEFAULT,'Ocean Scented Candle','Bring the seaside to your home with this ocean candle, with salty shores and bright sunets it is sure not to dissapoint','ocean.jpg', 15, DEFAULT),
-(DEFAULT,'Peach Scented Candle','A fruity smell, it is sure to bring the tropical envrionment to yourdoorstep with this relaxing candle','peach.jpg', 11.50, DEFAULT),
-(DEFAULT,'Pineapple Scented Candle','A tangy and sharp smell, the pineapple candle is sure to make a point in any area that its used in','pineapple.jpg', 16, DEFAULT),
-(DEFAULT,'Pumpkin Scented Candle','A popular scent from the autumn season, the pumpkin candle is a definite pick for the spooky season bringing a warm atmosphere with it','pumpkin.jpg', 12.50, DEFAULT),
-(DEFAULT,'Raspberry Scented Candle','A summer smell sure to brighten the day, anyone using this candle is sure to feel happier near it!','raspberry.jpg', 13.50, DEFAULT);
+INSERT INTO products (title, description, imgname, slug) VALUES 
+('Cinnamon Scented Candle','A candle that gives that warm smell to all those around it, a perfect candle for the autumn season','cinnamon.jpg','cinnamon'),
+('Cherry Scented Candle','A candle that gives a distinct cherry scent, like it was straight from the tree','cherry.jpg','cherry'),
+('Blackberry Scented Candle','Blackberry is a popular scent, giving a strong berry smell to fill the room','blackberry.jpg','blackberry'),
+('Citrus Scented Candle','A strong orange and lemon smell, this candle can freshen up any room it is lit in','citrus.jpg','citrus'),
+('Coffee Scented Candle','Straight from coffee beans, lighting this in the morning is just like drinking a fresh cup of coffee!','coffee.jpg','coffee'),
+('Dahlia Scented Candle','A scented candle filled with the smell of dahlias, just like it came straight from the garden centre','dahlia.jpg','dahlia'),
+('Floral Scented Candle','Bring the outside inside with this floral candle, which freshens any room that it is used in','floral.jpg','floral'),
+('Lavender Scented Candle','A smell from the forest that is sure to bring a nice strong countryside smell to those near it','lavender.jpg','lavender'),
+('Ocean Scented Candle','Bring the seaside to your home with this ocean candle, with salty shores and bright sunets it is sure not to dissapoint','ocean.jpg','ocean'),
+('Peach Scented Candle','A fruity smell, it is sure to bring the tropical envrionment to yourdoorstep with this relaxing candle','peach.jpg','peach'),
+('Pineapple Scented Candle','A tangy and sharp smell, the pineapple candle is sure to make a point in any area that its used in','pineapple.jpg','pineapple'),
+('Pumpkin Scented Candle','A popular scent from the autumn season, the pumpkin candle is a definite pick for the spooky season bringing a warm atmosphere with it','pumpkin.jpg','pumpkin'),
+('Raspberry Scented Candle','A summer smell sure to brighten the day, anyone using this candle is sure to feel happier near it!','raspberry.jpg','raspberry');
+
+--every product starts with a standard single wick variant, sku is derived from the product id
+INSERT INTO productvariants (product_id, sku, size, wicks, cost, stock)
+SELECT products.id, 'SC' || LPAD(products.id::TEXT, 4, '0') || '-STD', 'Standard', 1, prices.cost, 25
+FROM products INNER JOIN (VALUES 
+('cinnamon.jpg', 12.50),
+('cherry.jpg', 14.50),
+('blackberry.jpg', 12.00),
+('citrus.jpg', 10.50),
+('coffee.jpg', 15.00),
+('dahlia.jpg', 14.50),
+('floral.jpg', 10.00),
+('lavender.jpg', 14.00),
+('ocean.jpg', 15.00),
+('peach.jpg', 11.50),
+('pineapple.jpg', 16.00),
+('pumpkin.jpg', 12.50),
+('raspberry.jpg', 13.50)
+) AS prices(imgname, cost) ON prices.imgname = products.imgname;
+
+INSERT INTO productvariants (product_id, sku, size, wicks, cost, stock)
+SELECT products.id, 'SC' || LPAD(products.id::TEXT, 4, '0') || '-LG3', 'Large', 3, prices.cost, 5
+FROM products INNER JOIN (VALUES 
+('cinnamon.jpg', 24.50),
+('pumpkin.jpg', 24.50),
+('lavender.jpg', 27.00)
+) AS prices(imgname, cost) ON prices.imgname = products.imgname;
+
+--the image each product was created with becomes the first image in its gallery
+INSERT INTO productimages (product_id, imgname, alt_text, position)
+SELECT id, imgname, title, 0 FROM products;
+
+--every product and gallery row holds a reference to its stored image
+INSERT INTO imageblobs (key, refcount)
+SELECT imgname, COUNT(*) FROM (
+    SELECT imgname FROM products UNION ALL SELECT imgname FROM productimages
+) AS refs GROUP BY imgname;
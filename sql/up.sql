@@ -2,7 +2,9 @@ CREATE TABLE users (
   id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
   email VARCHAR(255) NOT NULL,
   password VARCHAR(255) NOT NULL,
-  is_admin BOOLEAN NOT NULL DEFAULT FALSE
+  is_admin BOOLEAN NOT NULL DEFAULT FALSE,
+  --ISO 4217 code prices are shown in, null until the customer picks one
+  currency VARCHAR(3)
 );
 
 CREATE TABLE sessions (
@@ -16,9 +18,54 @@ CREATE TABLE products (
     id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
     title VARCHAR(255) NOT NULL,
     description VARCHAR(255) NOT NULL,
+    --long form Markdown shown on the product page, description is the summary on cards
+    details TEXT NOT NULL DEFAULT '',
     imgname VARCHAR(255) NOT NULL,
-    cost DECIMAL(4, 2) NOT NULL,
-    listed BOOLEAN NOT NULL DEFAULT TRUE
+    listed BOOLEAN NOT NULL DEFAULT TRUE,
+    slug VARCHAR(255) NOT NULL UNIQUE,
+    --archived products are hidden from customers but kept for order history
+    archived_at TIMESTAMPTZ,
+    --seasonal products are listed at publish_at and unlisted at unpublish_at
+    publish_at TIMESTAMPTZ,
+    unpublish_at TIMESTAMPTZ,
+    CHECK (archived_at IS NULL OR NOT listed),
+    CHECK (unpublish_at > publish_at),
+    CHECK (archived_at IS NULL OR (publish_at IS NULL AND unpublish_at IS NULL))
+);
+
+--old slugs are kept after a product is renamed so existing links can be redirected
+CREATE TABLE productredirects (
+    slug VARCHAR(255) PRIMARY KEY,
+    product_id INTEGER NOT NULL,
+    FOREIGN KEY (product_id) REFERENCES products(id)
+);
+
+CREATE TABLE productvariants (
+    id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    product_id INTEGER NOT NULL,
+    sku VARCHAR(64) NOT NULL UNIQUE,
+    size VARCHAR(64) NOT NULL,
+    wicks INTEGER NOT NULL DEFAULT 1,
+    cost DECIMAL(10, 2) NOT NULL,
+    listed BOOLEAN NOT NULL DEFAULT TRUE,
+    stock INTEGER NOT NULL DEFAULT 0 CHECK (stock >= 0),
+    --roughly how long the candle burns for, NULL when it hasn't been measured
+    burn_hours INTEGER CHECK (burn_hours > 0),
+    FOREIGN KEY (product_id) REFERENCES products(id)
+);
+
+CREATE TABLE imageblobs (
+    key VARCHAR(255) PRIMARY KEY,
+    refcount INTEGER NOT NULL CHECK (refcount >= 0)
+);
+
+CREATE TABLE productimages (
+    id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    product_id INTEGER NOT NULL,
+    imgname VARCHAR(255) NOT NULL,
+    alt_text VARCHAR(255) NOT NULL,
+    position INTEGER NOT NULL,
+    FOREIGN KEY (product_id) REFERENCES products(id)
 );
 
 CREATE TABLE addresses (
@@ -37,37 +84,313 @@ CREATE TABLE orders (
     id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
     user_id INTEGER NOT NULL,
     address_id INTEGER NOT NULL,
+    --the currency prices were shown in and the rate used, the order is always charged in pounds
+    currency VARCHAR(3) NOT NULL DEFAULT 'GBP',
+    exchange_rate DECIMAL(12, 6) NOT NULL DEFAULT 1,
+    settlement_total DECIMAL(10, 2) NOT NULL,
+    --the sum of the lines as they were shown, in currency
+    shown_total DECIMAL(18, 2) NOT NULL,
     FOREIGN KEY (user_id) REFERENCES users(id),
     FOREIGN KEY (address_id) REFERENCES addresses(id)
 );
 
 CREATE TABLE productorders (
-    product_id INTEGER NOT NULL,
+    variant_id INTEGER NOT NULL,
     order_id INTEGER NOT NULL,
     quantity INTEGER NOT NULL,
-    FOREIGN KEY (product_id) REFERENCES products(id),
+    --the price paid including any surcharge, so later price changes do not rewrite past orders
+    unit_cost DECIMAL(10, 2) NOT NULL,
+    --what the customer filled in, one "name: value" line per personalisation field
+    personalisation VARCHAR(1024) NOT NULL DEFAULT '',
+    --how much of unit_cost the personalisation added
+    surcharge DECIMAL(10, 2) NOT NULL DEFAULT 0,
+    FOREIGN KEY (variant_id) REFERENCES productvariants(id),
     FOREIGN KEY (order_id) REFERENCES orders(id),
-    PRIMARY KEY (product_id, order_id)
+    PRIMARY KEY (variant_id, order_id, personalisation)
+);
+
+CREATE TABLE stockadjustments (
+    id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    --cleared when a product that was never ordered is purged, the SKU is kept for the history
+    variant_id INTEGER,
+    sku VARCHAR(64) NOT NULL,
+    delta INTEGER NOT NULL,
+    reason VARCHAR(255) NOT NULL,
+    order_id INTEGER,
+    user_id INTEGER NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    FOREIGN KEY (variant_id) REFERENCES productvariants(id),
+    FOREIGN KEY (order_id) REFERENCES orders(id),
+    FOREIGN KEY (user_id) REFERENCES users(id)
 );
 
 CREATE TABLE cartproducts (
+    --identifies the line when it is removed
+    id INTEGER GENERATED ALWAYS AS IDENTITY UNIQUE,
     user_id INTEGER NOT NULL,
-    product_id INTEGER NOT NULL,
+    variant_id INTEGER NOT NULL,
     quantity INTEGER NOT NULL,
+    --the same variant can be in the cart more than once when it is personalised differently
+    personalisation VARCHAR(1024) NOT NULL DEFAULT '',
+    --added to each unit's price, as it was when the item was added
+    surcharge DECIMAL(10, 2) NOT NULL DEFAULT 0,
+    FOREIGN KEY (user_id) REFERENCES users(id),
+    FOREIGN KEY (variant_id) REFERENCES productvariants(id),
+    PRIMARY KEY (variant_id, user_id, personalisation)
+);
+
+CREATE TABLE likedproducts (
+    user_id INTEGER NOT NULL,
+    product_id INTEGER NOT NULL,
     FOREIGN KEY (user_id) REFERENCES users(id),
     FOREIGN KEY (product_id) REFERENCES products(id),
     PRIMARY KEY (product_id, user_id)
 );
 
-CREATE TABLE likedproducts (
+--customers who asked to hear when a liked product is relisted, back in stock or cheaper, along with
+--what they were last told about it so each change is only announced once
+CREATE TABLE productalerts (
     user_id INTEGER NOT NULL,
     product_id INTEGER NOT NULL,
+    --lets the alert be turned off from an email without signing in
+    token VARCHAR(64) NOT NULL UNIQUE,
+    was_listed BOOLEAN NOT NULL,
+    was_in_stock BOOLEAN NOT NULL,
+    last_price DECIMAL(10, 2),
     FOREIGN KEY (user_id) REFERENCES users(id),
     FOREIGN KEY (product_id) REFERENCES products(id),
     PRIMARY KEY (product_id, user_id)
 );
 
+CREATE TABLE notifications (
+    id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    user_id INTEGER NOT NULL,
+    product_id INTEGER NOT NULL,
+    message TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    read_at TIMESTAMPTZ,
+    FOREIGN KEY (user_id) REFERENCES users(id),
+    FOREIGN KEY (product_id) REFERENCES products(id)
+);
+
+--named lists customers keep alongside their favourites, anyone with the share token can see a list
+CREATE TABLE wishlists (
+    id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    user_id INTEGER NOT NULL,
+    name VARCHAR(64) NOT NULL,
+    share_token VARCHAR(64) UNIQUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    FOREIGN KEY (user_id) REFERENCES users(id),
+    UNIQUE (user_id, name)
+);
+
+CREATE TABLE wishlistproducts (
+    wishlist_id INTEGER NOT NULL,
+    product_id INTEGER NOT NULL,
+    added_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    FOREIGN KEY (wishlist_id) REFERENCES wishlists(id),
+    FOREIGN KEY (product_id) REFERENCES products(id),
+    PRIMARY KEY (wishlist_id, product_id)
+);
+
+--products a signed in customer has picked to compare, kept for as long as they stay signed in
+CREATE TABLE comparisons (
+    session_id VARCHAR(255) NOT NULL,
+    product_id INTEGER NOT NULL,
+    added_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    FOREIGN KEY (session_id) REFERENCES sessions(id),
+    FOREIGN KEY (product_id) REFERENCES products(id),
+    PRIMARY KEY (session_id, product_id)
+);
+
+--sections of the homepage, each shows its pinned products in order and then, unless its rule is
+--'manual', fills the rest of its places with the newest or best selling products
+CREATE TABLE collections (
+    id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    name VARCHAR(64) NOT NULL,
+    rule VARCHAR(16) NOT NULL DEFAULT 'manual' CHECK (rule IN ('manual', 'newest', 'bestsellers')),
+    max_products INTEGER NOT NULL DEFAULT 8 CHECK (max_products BETWEEN 1 AND 24),
+    --sections are shown in position order, hidden collections are kept but not shown
+    position INTEGER NOT NULL,
+    shown BOOLEAN NOT NULL DEFAULT TRUE
+);
+
+CREATE TABLE collectionproducts (
+    collection_id INTEGER NOT NULL,
+    product_id INTEGER NOT NULL,
+    position INTEGER NOT NULL,
+    FOREIGN KEY (collection_id) REFERENCES collections(id),
+    FOREIGN KEY (product_id) REFERENCES products(id),
+    PRIMARY KEY (collection_id, product_id)
+);
+
+--attributes admins can give products, such as wax type or vessel, customers filter /browse by them
+CREATE TABLE attributes (
+    id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    name VARCHAR(64) NOT NULL UNIQUE,
+    --the attribute's name in /browse links
+    slug VARCHAR(64) NOT NULL UNIQUE,
+    kind VARCHAR(16) NOT NULL CHECK (kind IN ('text', 'number', 'flag')),
+    --shown after number values, such as 'cm'
+    unit VARCHAR(16) NOT NULL DEFAULT '',
+    position INTEGER NOT NULL
+);
+
+--a product's value for an attribute, only the column for the attribute's kind is set
+CREATE TABLE productattributes (
+    product_id INTEGER NOT NULL,
+    attribute_id INTEGER NOT NULL,
+    text_value VARCHAR(64),
+    number_value INTEGER,
+    flag_value BOOLEAN,
+    FOREIGN KEY (product_id) REFERENCES products(id),
+    FOREIGN KEY (attribute_id) REFERENCES attributes(id),
+    PRIMARY KEY (product_id, attribute_id),
+    CHECK (num_nonnulls(text_value, number_value, flag_value) = 1)
+);
+
+--the notes candles can smell of, each belongs to one scent family
+CREATE TABLE scentnotes (
+    id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    name VARCHAR(64) NOT NULL UNIQUE,
+    family VARCHAR(16) NOT NULL CHECK (family IN ('fruity', 'floral', 'gourmand', 'fresh'))
+);
+
+--a product's scent profile, each note is smelt first (top), once the candle warms (middle) or
+--lingers longest (base)
+CREATE TABLE productscentnotes (
+    product_id INTEGER NOT NULL,
+    note_id INTEGER NOT NULL,
+    tier VARCHAR(8) NOT NULL CHECK (tier IN ('top', 'middle', 'base')),
+    FOREIGN KEY (product_id) REFERENCES products(id),
+    FOREIGN KEY (note_id) REFERENCES scentnotes(id),
+    PRIMARY KEY (product_id, note_id)
+);
+
+--boxes a customer fills in when buying a product, such as the text on a custom label
+CREATE TABLE personalisationfields (
+    id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    product_id INTEGER NOT NULL,
+    name VARCHAR(64) NOT NULL,
+    --text is typed in up to max_length characters, choice is one of the comma separated choices
+    kind VARCHAR(8) NOT NULL CHECK (kind IN ('text', 'choice')),
+    max_length INTEGER NOT NULL DEFAULT 64 CHECK (max_length BETWEEN 1 AND 128),
+    choices VARCHAR(255) NOT NULL DEFAULT '',
+    --added to the price of each unit when the field is filled in
+    surcharge DECIMAL(10, 2) NOT NULL DEFAULT 0 CHECK (surcharge >= 0),
+    required BOOLEAN NOT NULL DEFAULT FALSE,
+    position INTEGER NOT NULL,
+    FOREIGN KEY (product_id) REFERENCES products(id),
+    UNIQUE (product_id, name)
+);
+
+--reviews wait in the moderation queue until an admin approves or rejects them
+CREATE TABLE reviews (
+    id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    product_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    rating INTEGER NOT NULL CHECK (rating BETWEEN 1 AND 5),
+    body VARCHAR(2000) NOT NULL,
+    status VARCHAR(16) NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'approved', 'rejected')),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    FOREIGN KEY (product_id) REFERENCES products(id),
+    FOREIGN KEY (user_id) REFERENCES users(id),
+    UNIQUE (product_id, user_id)
+);
+
+--products most often bought or liked together, rebuilt periodically from orders and likes
+CREATE TABLE productrecommendations (
+    product_id INTEGER NOT NULL,
+    recommended_id INTEGER NOT NULL,
+    score INTEGER NOT NULL,
+    FOREIGN KEY (product_id) REFERENCES products(id),
+    FOREIGN KEY (recommended_id) REFERENCES products(id),
+    PRIMARY KEY (product_id, recommended_id)
+);
+
+--the last few products each signed in customer looked at, guests keep theirs in a signed cookie
+CREATE TABLE recentlyviewed (
+    user_id INTEGER NOT NULL,
+    product_id INTEGER NOT NULL,
+    viewed_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    FOREIGN KEY (user_id) REFERENCES users(id),
+    FOREIGN KEY (product_id) REFERENCES products(id),
+    PRIMARY KEY (user_id, product_id)
+);
+
+--a sale takes percent_off the price of every variant of a product, or of every product when product_id
+--is null, from starts_at until ends_at
+CREATE TABLE sales (
+    id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    name VARCHAR(255) NOT NULL,
+    product_id INTEGER,
+    --a sale on a collection covers the products pinned to it, sales without a product or a
+    --collection are on everything
+    collection_id INTEGER,
+    percent_off INTEGER NOT NULL CHECK (percent_off BETWEEN 1 AND 90),
+    starts_at TIMESTAMPTZ NOT NULL,
+    ends_at TIMESTAMPTZ NOT NULL,
+    FOREIGN KEY (product_id) REFERENCES products(id),
+    FOREIGN KEY (collection_id) REFERENCES collections(id),
+    CHECK (ends_at > starts_at),
+    CHECK (product_id IS NULL OR collection_id IS NULL)
+);
+
+--how many units of currency one pound buys, prices can only be shown in currencies listed here
+CREATE TABLE exchangerates (
+    currency VARCHAR(3) PRIMARY KEY,
+    rate DECIMAL(12, 6) NOT NULL CHECK (rate > 0),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+--answers to the gift finder quiz for merchandising, nothing links them to who gave them and only the
+--day they were given is kept
+CREATE TABLE giftquizanswers (
+    id INTEGER PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    occasion VARCHAR(16) NOT NULL,
+    season VARCHAR(16) NOT NULL,
+    --comma separated scent families, empty when any scent will do
+    families VARCHAR(64) NOT NULL,
+    --a price range in the base currency such as 10-20, or 30- for no upper limit
+    budget VARCHAR(16) NOT NULL,
+    answered_on DATE NOT NULL DEFAULT CURRENT_DATE
+);
+
 --user for unit and integration testing
 INSERT INTO users VALUES(DEFAULT, 'testemail@securecart.com', '$argon2id$v=19$m=19456,t=2,p=1$xuZYri28ZUljWt1CvMXuwA$/j2hNwrsniZslvru/Te4CgOQb80/D9qwg28ZG64CLRM', FALSE);
 --default admin user, pass=@8*aUxB2#fEnT]E
 INSERT INTO users VALUES(DEFAULT, 'admin@securecart.com', '$argon2id$v=19$m=19456,t=2,p=1$Rh8lGJODGahQiqlyvR48/Q$gNzg7gIWtjEI6pFnrgh1ZWxMxuS/xfGmvlEI/sSPRns', TRUE);
+
+INSERT INTO collections (name, rule, max_products, position) VALUES
+    ('Featured', 'manual', 7, 1),
+    ('New arrivals', 'newest', 7, 2),
+    ('Bestsellers', 'bestsellers', 7, 3);
+
+INSERT INTO attributes (name, slug, kind, position) VALUES
+    ('Wax type', 'wax-type', 'text', 1),
+    ('Vessel', 'vessel', 'text', 2);
+
+INSERT INTO scentnotes (name, family) VALUES
+    ('Cherry', 'fruity'),
+    ('Blackberry', 'fruity'),
+    ('Raspberry', 'fruity'),
+    ('Peach', 'fruity'),
+    ('Pineapple', 'fruity'),
+    ('Dahlia', 'floral'),
+    ('Rose', 'floral'),
+    ('Jasmine', 'floral'),
+    ('Lavender', 'floral'),
+    ('Lily', 'floral'),
+    ('Cinnamon', 'gourmand'),
+    ('Clove', 'gourmand'),
+    ('Coffee', 'gourmand'),
+    ('Caramel', 'gourmand'),
+    ('Vanilla', 'gourmand'),
+    ('Almond', 'gourmand'),
+    ('Pumpkin', 'gourmand'),
+    ('Lemon', 'fresh'),
+    ('Orange', 'fresh'),
+    ('Bergamot', 'fresh'),
+    ('Mint', 'fresh'),
+    ('Sea salt', 'fresh'),
+    ('Ozone', 'fresh');
@@ -186,17 +186,15 @@ pub mod signup {
 
 pub mod signout {
 
-    use crate::{db::schema::sessions, internal_error, AppState, SESSION_COOKIE_NAME};
+    use crate::{internal_error, AppState, SESSION_COOKIE_NAME};
     use axum::{
         extract::State,
         http::StatusCode,
         response::{AppendHeaders, IntoResponse},
     };
     use axum_extra::extract::CookieJar;
-    use diesel::{delete, ExpressionMethods};
-    use diesel_async::RunQueryDsl;
 
-    use super::session::validate_session;
+    use super::session::{end_session, validate_session};
 
     pub async fn sign_out(
         jar: CookieJar,
@@ -207,9 +205,7 @@ pub mod signout {
             .get(SESSION_COOKIE_NAME)
             .ok_or((StatusCode::UNAUTHORIZED, String::from("Invalid Session")))?;
         let session = validate_session(session_cookie.value().to_owned(), &state.pool).await?;
-        delete(sessions::table)
-            .filter(sessions::id.eq(session.id))
-            .execute(&mut conn)
+        end_session(&mut conn, &session.id)
             .await
             .map_err(internal_error)?;
         let jar = jar.remove(SESSION_COOKIE_NAME);
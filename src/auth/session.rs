@@ -1,13 +1,17 @@
 use argon2::password_hash::rand_core::{OsRng, RngCore};
 use axum::http::StatusCode;
-use diesel::{delete, insert_into, ExpressionMethods, QueryDsl};
+use axum_extra::extract::CookieJar;
+use diesel::{delete, insert_into, ExpressionMethods, QueryDsl, QueryResult};
 use diesel_async::{pooled_connection::deadpool::Pool, AsyncPgConnection, RunQueryDsl};
 use sha2::Digest;
 use time::Duration;
 
 use crate::{
-    db::{models::Session, schema::sessions},
-    internal_error,
+    db::{
+        models::Session,
+        schema::{comparisons, sessions},
+    },
+    internal_error, SESSION_COOKIE_NAME,
 };
 
 pub async fn create_session(
@@ -61,15 +65,42 @@ pub async fn validate_session(
         .await
         .map_err(|_| (StatusCode::UNAUTHORIZED, String::from("401 Unauthorized")))?;
     if time::OffsetDateTime::now_utc() > session.expires_at {
-        delete(sessions::table)
-            .filter(sessions::id.eq(session_id))
-            .execute(&mut conn)
+        end_session(&mut conn, &session_id)
             .await
             .map_err(internal_error)?;
     }
     Ok(session)
 }
 
+/// The session of the signed in customer making a request, 401 when they aren't signed in
+pub async fn require_session(
+    jar: &CookieJar,
+    pool: &Pool<AsyncPgConnection>,
+) -> Result<Session, (StatusCode, String)> {
+    let session_cookie = jar
+        .get(SESSION_COOKIE_NAME)
+        .ok_or((StatusCode::UNAUTHORIZED, String::from("401 unauthorized")))?;
+    validate_session(session_cookie.value().to_owned(), pool).await
+}
+
+/// The session for pages guests can see too, `None` when the customer isn't signed in
+pub async fn optional_session(jar: &CookieJar, pool: &Pool<AsyncPgConnection>) -> Option<Session> {
+    require_session(jar, pool).await.ok()
+}
+
+/// Deletes a session along with everything kept for it, such as the products being compared
+pub async fn end_session(conn: &mut AsyncPgConnection, session_id: &str) -> QueryResult<()> {
+    delete(comparisons::table)
+        .filter(comparisons::session_id.eq(session_id))
+        .execute(conn)
+        .await?;
+    delete(sessions::table)
+        .filter(sessions::id.eq(session_id))
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
 fn generate_session_token() -> String {
     let mut bytes = [0; 20];
     OsRng.fill_bytes(&mut bytes);
@@ -1,9 +1,13 @@
 use crate::db::schema::{
-    addresses, cartproducts, orders, productorders, products, sessions, users,
+    addresses, attributes, cartproducts, collections, exchangerates, notifications, orders,
+    personalisationfields, productalerts, productattributes, productimages, productorders, productredirects, products,
+    productvariants, reviews, sales, scentnotes, sessions, stockadjustments, users, wishlists,
 };
 use bigdecimal::BigDecimal;
 use diesel::prelude::*;
 
+use crate::money::Money;
+
 #[derive(Queryable, Selectable)]
 #[diesel(table_name = users)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
@@ -12,6 +16,8 @@ pub struct User {
     pub email: String,
     pub password: String,
     pub is_admin: bool,
+    /// ISO 4217 code of the currency the customer chose to see prices in
+    pub currency: Option<String>,
 }
 
 #[derive(Insertable)]
@@ -31,16 +37,24 @@ pub struct Session {
     pub expires_at: time::OffsetDateTime,
 }
 
-#[derive(Queryable, Selectable, Insertable, Clone, Debug)]
+#[derive(Queryable, Selectable, Insertable, Identifiable, Clone, Debug)]
 #[diesel(table_name = products)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
 pub struct Product {
     pub id: i32,
     pub title: String,
     pub description: String,
+    /// Markdown, render it with `ecom::markdown::render` before showing it
+    pub details: String,
     pub imgname: String,
-    pub cost: BigDecimal,
     pub listed: bool,
+    pub slug: String,
+    /// Archived products are unlisted and hidden from customers but kept for past orders
+    pub archived_at: Option<time::OffsetDateTime>,
+    /// When the product will be listed, see `ecom::schedule`
+    pub publish_at: Option<time::OffsetDateTime>,
+    /// When the product will be unlisted
+    pub unpublish_at: Option<time::OffsetDateTime>,
 }
 
 #[derive(Insertable)]
@@ -50,8 +64,67 @@ pub struct NewProduct {
     pub id: Option<i32>,
     pub title: String,
     pub description: String,
+    pub details: String,
+    pub imgname: String,
+    pub slug: String,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = productredirects)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct ProductRedirect {
+    pub slug: String,
+    pub product_id: i32,
+}
+
+#[derive(Queryable, Selectable, Identifiable, Associations, Clone, Debug)]
+#[diesel(belongs_to(Product))]
+#[diesel(table_name = productimages)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct ProductImage {
+    pub id: i32,
+    pub product_id: i32,
+    pub imgname: String,
+    pub alt_text: String,
+    pub position: i32,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = productimages)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct NewProductImage {
+    pub product_id: i32,
     pub imgname: String,
-    pub cost: BigDecimal,
+    pub alt_text: String,
+    pub position: i32,
+}
+
+#[derive(Queryable, Selectable, Identifiable, Associations, Clone, Debug)]
+#[diesel(belongs_to(Product))]
+#[diesel(table_name = productvariants)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct ProductVariant {
+    pub id: i32,
+    pub product_id: i32,
+    pub sku: String,
+    pub size: String,
+    pub wicks: i32,
+    pub cost: Money,
+    pub listed: bool,
+    pub stock: i32,
+    pub burn_hours: Option<i32>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = productvariants)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct NewProductVariant {
+    pub product_id: i32,
+    pub sku: String,
+    pub size: String,
+    pub wicks: i32,
+    pub cost: Money,
+    pub burn_hours: Option<i32>,
 }
 
 #[derive(Queryable, Selectable, Insertable)]
@@ -68,14 +141,18 @@ pub struct Address {
 
 #[derive(Identifiable, Selectable, Queryable, Associations, Insertable)]
 #[diesel(belongs_to(User))]
-#[diesel(belongs_to(Product))]
+#[diesel(belongs_to(ProductVariant, foreign_key = variant_id))]
 #[diesel(table_name = cartproducts)]
-#[diesel(primary_key(user_id, product_id))]
+#[diesel(primary_key(user_id, variant_id, personalisation))]
 #[diesel(check_for_backend(diesel::pg::Pg))]
 pub struct CartProduct {
     pub user_id: i32,
-    pub product_id: i32,
+    pub variant_id: i32,
     pub quantity: i32,
+    /// One "name: value" line per personalisation field that was filled in
+    pub personalisation: String,
+    /// Added to each unit's price for the personalisation
+    pub surcharge: Money,
 }
 
 #[derive(Insertable)]
@@ -84,6 +161,10 @@ pub struct CartProduct {
 pub struct Order {
     pub user_id: i32,
     pub address_id: i32,
+    pub currency: String,
+    pub exchange_rate: BigDecimal,
+    pub settlement_total: Money,
+    pub shown_total: BigDecimal,
 }
 
 #[derive(Queryable, Default)]
@@ -93,13 +174,225 @@ pub struct OrderWithId {
     pub id: i32,
     pub user_id: i32,
     pub address_id: i32,
+    /// The currency prices were shown in when the order was placed
+    pub currency: String,
+    /// Units of `currency` to the pound when the order was placed
+    pub exchange_rate: BigDecimal,
+    /// What was charged, always in the base currency
+    pub settlement_total: Money,
+    /// The total the customer was shown, in `currency`
+    pub shown_total: BigDecimal,
 }
 
 #[derive(Insertable, Selectable, Queryable)]
 #[diesel(table_name = productorders)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
 pub struct ProductInOrder {
-    pub product_id: i32,
+    pub variant_id: i32,
     pub order_id: i32,
     pub quantity: i32,
+    /// What each unit cost including the surcharge
+    pub unit_cost: Money,
+    pub personalisation: String,
+    pub surcharge: Money,
+}
+
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = stockadjustments)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct StockAdjustment {
+    pub id: i32,
+    /// `None` once the variant's product has been purged
+    pub variant_id: Option<i32>,
+    pub sku: String,
+    pub delta: i32,
+    pub reason: String,
+    pub order_id: Option<i32>,
+    pub user_id: i32,
+    pub created_at: time::OffsetDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = stockadjustments)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct NewStockAdjustment {
+    pub variant_id: i32,
+    pub sku: String,
+    pub delta: i32,
+    pub reason: String,
+    pub order_id: Option<i32>,
+    pub user_id: i32,
+}
+
+#[derive(Queryable, Selectable, Identifiable, Associations, Clone, Debug)]
+#[diesel(belongs_to(Product))]
+#[diesel(table_name = reviews)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Review {
+    pub id: i32,
+    pub product_id: i32,
+    pub user_id: i32,
+    pub rating: i32,
+    pub body: String,
+    pub status: String,
+    pub created_at: time::OffsetDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = reviews)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct NewReview {
+    pub product_id: i32,
+    pub user_id: i32,
+    pub rating: i32,
+    pub body: String,
+}
+
+#[derive(Queryable, Selectable, Clone, Debug)]
+#[diesel(table_name = sales)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Sale {
+    pub id: i32,
+    pub name: String,
+    pub product_id: Option<i32>,
+    /// Sales on a collection cover the products pinned to it
+    pub collection_id: Option<i32>,
+    pub percent_off: i32,
+    pub starts_at: time::OffsetDateTime,
+    pub ends_at: time::OffsetDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = sales)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct NewSale {
+    pub name: String,
+    pub product_id: Option<i32>,
+    pub collection_id: Option<i32>,
+    pub percent_off: i32,
+    pub starts_at: time::OffsetDateTime,
+    pub ends_at: time::OffsetDateTime,
+}
+
+#[derive(Queryable, Selectable, Insertable, Clone, Debug)]
+#[diesel(table_name = exchangerates)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct ExchangeRate {
+    pub currency: String,
+    /// Units of `currency` one pound buys
+    pub rate: BigDecimal,
+    pub updated_at: time::OffsetDateTime,
+}
+
+/// What the customer was last told about a product they asked to be alerted about
+#[derive(Queryable, Selectable, Insertable, Clone, Debug)]
+#[diesel(table_name = productalerts)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct ProductAlert {
+    pub user_id: i32,
+    pub product_id: i32,
+    pub token: String,
+    pub was_listed: bool,
+    pub was_in_stock: bool,
+    /// The cheapest listed variant including any sale, `None` when nothing was for sale
+    pub last_price: Option<Money>,
+}
+
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = notifications)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Notification {
+    pub id: i32,
+    pub user_id: i32,
+    pub product_id: i32,
+    pub message: String,
+    pub created_at: time::OffsetDateTime,
+    pub read_at: Option<time::OffsetDateTime>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = notifications)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct NewNotification {
+    pub user_id: i32,
+    pub product_id: i32,
+    pub message: String,
+}
+
+#[derive(Queryable, Selectable, Clone, Debug)]
+#[diesel(table_name = wishlists)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Wishlist {
+    pub id: i32,
+    pub user_id: i32,
+    pub name: String,
+    /// Set while the list is shared, anyone with the token can see it
+    pub share_token: Option<String>,
+    pub created_at: time::OffsetDateTime,
+}
+
+#[derive(Queryable, Selectable, Clone, Debug)]
+#[diesel(table_name = collections)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Collection {
+    pub id: i32,
+    pub name: String,
+    /// How places left after the pinned products are filled, one of `collections::RULES`
+    pub rule: String,
+    pub max_products: i32,
+    pub position: i32,
+    pub shown: bool,
+}
+
+#[derive(Queryable, Selectable, Clone, Debug)]
+#[diesel(table_name = attributes)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Attribute {
+    pub id: i32,
+    pub name: String,
+    pub slug: String,
+    /// Which of a product's value columns is used, one of `facets::KINDS`
+    pub kind: String,
+    pub unit: String,
+    pub position: i32,
+}
+
+#[derive(Queryable, Selectable, Insertable, Clone, Debug)]
+#[diesel(table_name = productattributes)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct ProductAttribute {
+    pub product_id: i32,
+    pub attribute_id: i32,
+    pub text_value: Option<String>,
+    pub number_value: Option<i32>,
+    pub flag_value: Option<bool>,
+}
+
+#[derive(Queryable, Selectable, Clone, Debug, PartialEq)]
+#[diesel(table_name = scentnotes)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct ScentNote {
+    pub id: i32,
+    pub name: String,
+    /// One of `scents::FAMILIES`
+    pub family: String,
+}
+
+#[derive(Queryable, Selectable, Identifiable, Associations, Clone, Debug)]
+#[diesel(belongs_to(Product))]
+#[diesel(table_name = personalisationfields)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct PersonalisationField {
+    pub id: i32,
+    pub product_id: i32,
+    pub name: String,
+    /// One of `personalisation::KINDS`
+    pub kind: String,
+    /// The most characters a text field takes
+    pub max_length: i32,
+    /// Comma separated, the options of a choice field
+    pub choices: String,
+    pub surcharge: Money,
+    pub required: bool,
+    pub position: i32,
 }
@@ -18,10 +18,91 @@ diesel::table! {
 }
 
 diesel::table! {
-    cartproducts (product_id, user_id) {
+    attributes (id) {
+        id -> Integer,
+        #[max_length = 64]
+        name -> Varchar,
+        #[max_length = 64]
+        slug -> Varchar,
+        #[max_length = 16]
+        kind -> Varchar,
+        #[max_length = 16]
+        unit -> Varchar,
+        position -> Integer,
+    }
+}
+
+diesel::table! {
+    cartproducts (variant_id, user_id, personalisation) {
+        id -> Integer,
         user_id -> Integer,
-        product_id -> Integer,
+        variant_id -> Integer,
         quantity -> Integer,
+        #[max_length = 1024]
+        personalisation -> Varchar,
+        surcharge -> Decimal,
+    }
+}
+
+diesel::table! {
+    collectionproducts (collection_id, product_id) {
+        collection_id -> Integer,
+        product_id -> Integer,
+        position -> Integer,
+    }
+}
+
+diesel::table! {
+    collections (id) {
+        id -> Integer,
+        #[max_length = 64]
+        name -> Varchar,
+        #[max_length = 16]
+        rule -> Varchar,
+        max_products -> Integer,
+        position -> Integer,
+        shown -> Bool,
+    }
+}
+
+diesel::table! {
+    comparisons (session_id, product_id) {
+        #[max_length = 255]
+        session_id -> Varchar,
+        product_id -> Integer,
+        added_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    exchangerates (currency) {
+        #[max_length = 3]
+        currency -> Varchar,
+        rate -> Decimal,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    giftquizanswers (id) {
+        id -> Integer,
+        #[max_length = 16]
+        occasion -> Varchar,
+        #[max_length = 16]
+        season -> Varchar,
+        #[max_length = 64]
+        families -> Varchar,
+        #[max_length = 16]
+        budget -> Varchar,
+        answered_on -> Date,
+    }
+}
+
+diesel::table! {
+    imageblobs (key) {
+        #[max_length = 255]
+        key -> Varchar,
+        refcount -> Integer,
     }
 }
 
@@ -32,19 +113,91 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    notifications (id) {
+        id -> Integer,
+        user_id -> Integer,
+        product_id -> Integer,
+        message -> Text,
+        created_at -> Timestamptz,
+        read_at -> Nullable<Timestamptz>,
+    }
+}
+
 diesel::table! {
     orders (id) {
         id -> Integer,
         user_id -> Integer,
         address_id -> Integer,
+        #[max_length = 3]
+        currency -> Varchar,
+        exchange_rate -> Decimal,
+        settlement_total -> Decimal,
+        shown_total -> Decimal,
     }
 }
 
 diesel::table! {
-    productorders (product_id, order_id) {
-        product_id -> Integer,
+    productorders (variant_id, order_id, personalisation) {
+        variant_id -> Integer,
         order_id -> Integer,
         quantity -> Integer,
+        unit_cost -> Decimal,
+        #[max_length = 1024]
+        personalisation -> Varchar,
+        surcharge -> Decimal,
+    }
+}
+
+diesel::table! {
+    personalisationfields (id) {
+        id -> Integer,
+        product_id -> Integer,
+        #[max_length = 64]
+        name -> Varchar,
+        #[max_length = 8]
+        kind -> Varchar,
+        max_length -> Integer,
+        #[max_length = 255]
+        choices -> Varchar,
+        surcharge -> Decimal,
+        required -> Bool,
+        position -> Integer,
+    }
+}
+
+diesel::table! {
+    productalerts (product_id, user_id) {
+        user_id -> Integer,
+        product_id -> Integer,
+        #[max_length = 64]
+        token -> Varchar,
+        was_listed -> Bool,
+        was_in_stock -> Bool,
+        last_price -> Nullable<Decimal>,
+    }
+}
+
+diesel::table! {
+    productattributes (product_id, attribute_id) {
+        product_id -> Integer,
+        attribute_id -> Integer,
+        #[max_length = 64]
+        text_value -> Nullable<Varchar>,
+        number_value -> Nullable<Integer>,
+        flag_value -> Nullable<Bool>,
+    }
+}
+
+diesel::table! {
+    productimages (id) {
+        id -> Integer,
+        product_id -> Integer,
+        #[max_length = 255]
+        imgname -> Varchar,
+        #[max_length = 255]
+        alt_text -> Varchar,
+        position -> Integer,
     }
 }
 
@@ -55,10 +208,101 @@ diesel::table! {
         title -> Varchar,
         #[max_length = 255]
         description -> Varchar,
+        details -> Text,
         #[max_length = 255]
         imgname -> Varchar,
+        listed -> Bool,
+        #[max_length = 255]
+        slug -> Varchar,
+        archived_at -> Nullable<Timestamptz>,
+        publish_at -> Nullable<Timestamptz>,
+        unpublish_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    productrecommendations (product_id, recommended_id) {
+        product_id -> Integer,
+        recommended_id -> Integer,
+        score -> Integer,
+    }
+}
+
+diesel::table! {
+    productredirects (slug) {
+        #[max_length = 255]
+        slug -> Varchar,
+        product_id -> Integer,
+    }
+}
+
+diesel::table! {
+    productscentnotes (product_id, note_id) {
+        product_id -> Integer,
+        note_id -> Integer,
+        #[max_length = 8]
+        tier -> Varchar,
+    }
+}
+
+diesel::table! {
+    productvariants (id) {
+        id -> Integer,
+        product_id -> Integer,
+        #[max_length = 64]
+        sku -> Varchar,
+        #[max_length = 64]
+        size -> Varchar,
+        wicks -> Integer,
         cost -> Decimal,
         listed -> Bool,
+        stock -> Integer,
+        burn_hours -> Nullable<Integer>,
+    }
+}
+
+diesel::table! {
+    recentlyviewed (user_id, product_id) {
+        user_id -> Integer,
+        product_id -> Integer,
+        viewed_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    reviews (id) {
+        id -> Integer,
+        product_id -> Integer,
+        user_id -> Integer,
+        rating -> Integer,
+        #[max_length = 2000]
+        body -> Varchar,
+        #[max_length = 16]
+        status -> Varchar,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    sales (id) {
+        id -> Integer,
+        #[max_length = 255]
+        name -> Varchar,
+        product_id -> Nullable<Integer>,
+        collection_id -> Nullable<Integer>,
+        percent_off -> Integer,
+        starts_at -> Timestamptz,
+        ends_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    scentnotes (id) {
+        id -> Integer,
+        #[max_length = 64]
+        name -> Varchar,
+        #[max_length = 16]
+        family -> Varchar,
     }
 }
 
@@ -71,6 +315,21 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    stockadjustments (id) {
+        id -> Integer,
+        variant_id -> Nullable<Integer>,
+        #[max_length = 64]
+        sku -> Varchar,
+        delta -> Integer,
+        #[max_length = 255]
+        reason -> Varchar,
+        order_id -> Nullable<Integer>,
+        user_id -> Integer,
+        created_at -> Timestamptz,
+    }
+}
+
 diesel::table! {
     users (id) {
         id -> Integer,
@@ -79,27 +338,102 @@ diesel::table! {
         #[max_length = 255]
         password -> Varchar,
         is_admin -> Bool,
+        #[max_length = 3]
+        currency -> Nullable<Varchar>,
+    }
+}
+
+diesel::table! {
+    wishlistproducts (wishlist_id, product_id) {
+        wishlist_id -> Integer,
+        product_id -> Integer,
+        added_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    wishlists (id) {
+        id -> Integer,
+        user_id -> Integer,
+        #[max_length = 64]
+        name -> Varchar,
+        #[max_length = 64]
+        share_token -> Nullable<Varchar>,
+        created_at -> Timestamptz,
     }
 }
 
 diesel::joinable!(addresses -> users (user_id));
-diesel::joinable!(cartproducts -> products (product_id));
+diesel::joinable!(cartproducts -> productvariants (variant_id));
 diesel::joinable!(cartproducts -> users (user_id));
+diesel::joinable!(collectionproducts -> collections (collection_id));
+diesel::joinable!(collectionproducts -> products (product_id));
+diesel::joinable!(comparisons -> products (product_id));
+diesel::joinable!(comparisons -> sessions (session_id));
 diesel::joinable!(likedproducts -> products (product_id));
 diesel::joinable!(likedproducts -> users (user_id));
+diesel::joinable!(notifications -> products (product_id));
+diesel::joinable!(notifications -> users (user_id));
 diesel::joinable!(orders -> addresses (address_id));
 diesel::joinable!(orders -> users (user_id));
 diesel::joinable!(productorders -> orders (order_id));
-diesel::joinable!(productorders -> products (product_id));
+diesel::joinable!(personalisationfields -> products (product_id));
+diesel::joinable!(productalerts -> products (product_id));
+diesel::joinable!(productalerts -> users (user_id));
+diesel::joinable!(productattributes -> attributes (attribute_id));
+diesel::joinable!(productattributes -> products (product_id));
+diesel::joinable!(productimages -> products (product_id));
+diesel::joinable!(productorders -> productvariants (variant_id));
+diesel::joinable!(productrecommendations -> products (recommended_id));
+diesel::joinable!(productredirects -> products (product_id));
+diesel::joinable!(productscentnotes -> products (product_id));
+diesel::joinable!(productscentnotes -> scentnotes (note_id));
+diesel::joinable!(productvariants -> products (product_id));
+diesel::joinable!(recentlyviewed -> products (product_id));
+diesel::joinable!(recentlyviewed -> users (user_id));
+diesel::joinable!(reviews -> products (product_id));
+diesel::joinable!(reviews -> users (user_id));
+diesel::joinable!(sales -> collections (collection_id));
+diesel::joinable!(sales -> products (product_id));
 diesel::joinable!(sessions -> users (user_id));
+diesel::joinable!(stockadjustments -> orders (order_id));
+diesel::joinable!(stockadjustments -> productvariants (variant_id));
+diesel::joinable!(stockadjustments -> users (user_id));
+
+diesel::joinable!(wishlistproducts -> products (product_id));
+diesel::joinable!(wishlistproducts -> wishlists (wishlist_id));
+diesel::joinable!(wishlists -> users (user_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
     addresses,
+    attributes,
     cartproducts,
+    collectionproducts,
+    collections,
+    comparisons,
+    exchangerates,
+    giftquizanswers,
+    imageblobs,
     likedproducts,
+    notifications,
     orders,
+    personalisationfields,
+    productalerts,
+    productattributes,
+    productimages,
     productorders,
+    productrecommendations,
+    productredirects,
     products,
+    productscentnotes,
+    productvariants,
+    recentlyviewed,
+    reviews,
+    sales,
+    scentnotes,
     sessions,
+    stockadjustments,
     users,
+    wishlistproducts,
+    wishlists,
 );
@@ -1,34 +1,109 @@
-use std::str::FromStr;
-
 use askama::Template;
 use axum::{
     body::Bytes,
-    extract::{Multipart, State},
+    extract::{DefaultBodyLimit, Multipart, State},
     http::StatusCode,
     response::{AppendHeaders, Html, IntoResponse, Response},
     routing::{get, post},
     Router,
 };
 use axum_extra::extract::{CookieJar, Form};
-use bigdecimal::BigDecimal;
-use diesel::{delete, insert_into, update, ExpressionMethods, QueryDsl};
-use diesel_async::{pooled_connection::deadpool::Pool, AsyncPgConnection, RunQueryDsl};
+use diesel::{
+    delete, dsl::exists, insert_into, select, update, BelongingToDsl, BoolExpressionMethods,
+    ExpressionMethods, GroupedBy, NullableExpressionMethods, OptionalExtension, QueryDsl,
+    SelectableHelper,
+};
+use diesel_async::{
+    pooled_connection::deadpool::Pool, scoped_futures::ScopedFutureExt, AsyncConnection,
+    AsyncPgConnection, RunQueryDsl,
+};
 use serde::Deserialize;
 use sha2::Digest;
-use tokio::{fs, io::AsyncWriteExt};
+use time::{macros::format_description, OffsetDateTime, PrimitiveDateTime};
 
 use crate::{
+    auth::session::end_session,
     db::{
-        models::{NewProduct, Product, Session},
-        schema::{products, sessions, users},
+        models::{
+            Address, Attribute, Collection, ExchangeRate, ScentNote, NewProduct, NewProductImage, NewProductVariant, NewSale,
+            NewStockAdjustment, OrderWithId, PersonalisationField, Product, ProductImage, ProductRedirect, ProductVariant, Review,
+            Sale, Session, StockAdjustment,
+        },
+        schema::{
+            addresses, cartproducts, collectionproducts, collections as collections_table, productattributes, productscentnotes, comparisons, exchangerates, likedproducts, notifications, orders, personalisationfields, productalerts,
+            productimages, productorders, productrecommendations, productredirects, products,
+            productvariants, recentlyviewed, reviews, sales, sessions, stockadjustments, users,
+            wishlistproducts,
+        },
     },
-    internal_error, AppState, SESSION_COOKIE_NAME,
+    images::{self, filters},
+    internal_error,
+    money::{Currency, Money},
+    AppState, SESSION_COOKIE_NAME,
+};
+
+use super::{
+    currency,
+    gifts::AnswerCounts,
+    is_valid_slug,
+    markdown::{self, MAX_DETAILS_CHARS},
+    reviews::{APPROVED, PENDING, REJECTED},
+    scents::ScentProfile,
+    slugify,
 };
 
+mod attributes;
+mod catalog;
+mod collections;
+mod personalisation;
+mod scents;
+
+/// The longest burn time a variant can be given, in hours
+const MAX_BURN_HOURS: i32 = 1000;
+
+/// How many of the latest orders the dashboard shows for packing
+const RECENT_ORDERS: i64 = 20;
+
 #[derive(Template)]
 #[template(path = "admin.html")]
 struct AdminDashboardPage {
-    products: Vec<Product>,
+    products: Vec<AdminProduct>,
+    archived: Vec<Product>,
+    adjustments: Vec<StockAdjustment>,
+    /// Reviews waiting for moderation with the product title and reviewer's email
+    reviews: Vec<(Review, String, String)>,
+    /// Sales that have not ended yet with the title of their product or the name of their
+    /// collection, both `None` for sales on everything
+    sales: Vec<(Sale, Option<String>, Option<String>)>,
+    rates: Vec<ExchangeRate>,
+    /// Products with a publish or unpublish time, soonest first
+    upcoming: Vec<Product>,
+    /// Every homepage collection in order with its pinned products
+    collections: Vec<(Collection, Vec<Product>)>,
+    attributes: Vec<Attribute>,
+    /// Every scent note, grouped by family
+    notes: Vec<ScentNote>,
+    gift_answers: AnswerCounts,
+    /// The latest orders with what to send, newest first
+    orders: Vec<FulfilmentOrder>,
+}
+
+struct AdminProduct {
+    product: Product,
+    variants: Vec<ProductVariant>,
+    images: Vec<ProductImage>,
+    /// Every attribute with the product's value for it, empty when it has none
+    attributes: Vec<(Attribute, String)>,
+    scent: ScentProfile,
+    personalisation: Vec<PersonalisationField>,
+}
+
+/// An order as it needs packing, each line is the title, variant SKU and size, quantity and
+/// personalisation lines
+struct FulfilmentOrder {
+    order: OrderWithId,
+    address: Address,
+    lines: Vec<(String, String, String, i32, String)>,
 }
 
 #[derive(Default)]
@@ -37,7 +112,8 @@ struct AddProductForm {
     image_title: String,
     title: String,
     description: String,
-    cost: BigDecimal,
+    details: String,
+    cost: Money,
 }
 
 #[derive(Deserialize)]
@@ -45,55 +121,421 @@ struct ProductForm {
     id: i32,
 }
 
-impl AddProductForm {
+#[derive(Deserialize)]
+struct VariantForm {
+    id: i32,
+}
+
+#[derive(Deserialize)]
+struct ReviewForm {
+    id: i32,
+}
+
+#[derive(Deserialize)]
+struct DetailsForm {
+    details: String,
+}
+
+#[derive(Deserialize)]
+struct SaleForm {
+    id: i32,
+}
+
+#[derive(Deserialize)]
+struct RateForm {
+    currency: String,
+    rate: String,
+}
+
+#[derive(Deserialize)]
+struct RateCurrencyForm {
+    currency: String,
+}
+
+/// Times come from `datetime-local` inputs, so have no offset and are taken as UTC
+#[derive(Deserialize)]
+struct AddSaleForm {
+    name: String,
+    /// At most one of `product_id` and `collection_id`, the sale is on everything without either
+    product_id: Option<i32>,
+    collection_id: Option<i32>,
+    percent_off: i32,
+    starts_at: String,
+    ends_at: String,
+}
+
+/// Either time can be left empty, times come from `datetime-local` inputs like sales
+#[derive(Deserialize)]
+struct ScheduleForm {
+    id: i32,
+    publish_at: String,
+    unpublish_at: String,
+}
+
+#[derive(Deserialize)]
+struct ImageForm {
+    id: i32,
+}
+
+#[derive(Deserialize)]
+enum Direction {
+    Up,
+    Down,
+}
+
+#[derive(Deserialize)]
+struct MoveImageForm {
+    id: i32,
+    direction: Direction,
+}
+
+#[derive(Default)]
+struct AddImageForm {
+    product_id: i32,
+    image: Bytes,
+    image_title: String,
+    alt_text: String,
+}
+
+impl AddImageForm {
     async fn parse_from_multipart(mut form: Multipart) -> Result<Self, Response> {
         let mut ret = Self::default();
-        while let Some(field) = form.next_field().await.unwrap() {
+        while let Some(field) = form.next_field().await.map_err(|e| e.into_response())? {
             match field.name() {
-                Some(name) => match name {
-                    "image" => {
-                        ret.image_title = field
-                            .file_name()
-                            .ok_or(
-                                (StatusCode::BAD_REQUEST, String::from("Incorrect Fields"))
-                                    .into_response(),
-                            )?
-                            .to_owned();
-                        ret.image = field.bytes().await.map_err(|e| e.into_response())?;
-                    }
-                    "title" => ret.title = field.text().await.map_err(|e| e.into_response())?,
-                    "description" => {
-                        ret.description = field.text().await.map_err(|e| e.into_response())?
-                    }
-                    "cost" => {
-                        ret.cost = BigDecimal::from_str(
-                            &field.text().await.map_err(|e| e.into_response())?,
-                        )
+                Some("image") => {
+                    ret.image_title = field
+                        .file_name()
+                        .ok_or(
+                            (StatusCode::BAD_REQUEST, String::from("Incorrect Fields"))
+                                .into_response(),
+                        )?
+                        .to_owned();
+                    ret.image = field.bytes().await.map_err(|e| e.into_response())?;
+                }
+                Some("product_id") => {
+                    ret.product_id = field
+                        .text()
+                        .await
+                        .map_err(|e| e.into_response())?
+                        .parse()
                         .map_err(|_| {
                             (StatusCode::BAD_REQUEST, String::from("Incorrect Fields"))
                                 .into_response()
                         })?;
-                    }
-                    _ => (),
-                },
-                None => (),
+                }
+                Some("alt_text") => {
+                    ret.alt_text = field.text().await.map_err(|e| e.into_response())?
+                }
+                _ => (),
             }
         }
-
-        if ret.image.len() == 0 || ret.title.len() == 0 || ret.description.len() == 0 {
+        if ret.image.is_empty() || ret.alt_text.is_empty() || ret.alt_text.len() > 255 {
             return Err((StatusCode::BAD_REQUEST, String::from("Incorrect Fields")).into_response());
         }
+        Ok(ret)
+    }
+}
+
+#[derive(Default)]
+struct EditProductForm {
+    id: i32,
+    slug: String,
+    title: String,
+    description: String,
+    /// `None` when the form leaves the details alone
+    details: Option<String>,
+    /// Empty when the image is being kept
+    image: Bytes,
+}
+
+impl EditProductForm {
+    async fn parse_from_multipart(mut form: Multipart) -> Result<Self, (StatusCode, String)> {
+        let incorrect = || (StatusCode::BAD_REQUEST, String::from("Incorrect Fields"));
+        let mut ret = Self::default();
+        while let Some(field) = form.next_field().await.map_err(|_| incorrect())? {
+            match field.name() {
+                Some("id") => {
+                    ret.id = field
+                        .text()
+                        .await
+                        .map_err(|_| incorrect())?
+                        .parse()
+                        .map_err(|_| incorrect())?
+                }
+                Some("slug") => ret.slug = field.text().await.map_err(|_| incorrect())?,
+                Some("title") => ret.title = field.text().await.map_err(|_| incorrect())?,
+                Some("description") => {
+                    ret.description = field.text().await.map_err(|_| incorrect())?
+                }
+                Some("details") => ret.details = Some(field.text().await.map_err(|_| incorrect())?),
+                Some("image") => ret.image = field.bytes().await.map_err(|_| incorrect())?,
+                _ => (),
+            }
+        }
+        ret.slug = ret.slug.trim().to_owned();
+        if !is_valid_slug(&ret.slug) {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                String::from("URLs may only contain lowercase letters, numbers and dashes"),
+            ));
+        }
+        check_title_and_description(&mut ret.title, &mut ret.description)?;
+        if let Some(details) = &ret.details {
+            check_details(details)?;
+        }
+        Ok(ret)
+    }
+}
+
+/// Trims a product's title and description, both are required and are stored as VARCHAR(255)
+fn check_title_and_description(
+    title: &mut String,
+    description: &mut String,
+) -> Result<(), (StatusCode, String)> {
+    *title = title.trim().to_owned();
+    *description = description.trim().to_owned();
+    if title.is_empty()
+        || description.is_empty()
+        || title.chars().count() > 255
+        || description.chars().count() > 255
+    {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            String::from("Please enter a title and description of at most 255 characters"),
+        ));
+    }
+    Ok(())
+}
+
+fn check_details(details: &str) -> Result<(), (StatusCode, String)> {
+    if details.chars().count() > MAX_DETAILS_CHARS {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("Details can be at most {} characters", MAX_DETAILS_CHARS),
+        ));
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct EditVariantForm {
+    id: i32,
+    cost: String,
+    /// `None` when the form leaves the burn time alone, empty when it isn't known
+    burn_hours: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct StockForm {
+    id: i32,
+    delta: i32,
+    reason: String,
+}
+
+#[derive(Deserialize)]
+struct AddVariantForm {
+    product_id: i32,
+    sku: String,
+    size: String,
+    wicks: i32,
+    cost: String,
+    /// Empty when the burn time isn't known
+    #[serde(default)]
+    burn_hours: String,
+}
+
+/// Burn times are whole hours, an empty field means the burn time isn't known
+fn parse_burn_hours(hours: &str) -> Result<Option<i32>, (StatusCode, String)> {
+    let hours = hours.trim();
+    if hours.is_empty() {
+        return Ok(None);
+    }
+    match hours.parse() {
+        Ok(hours) if (1..=MAX_BURN_HOURS).contains(&hours) => Ok(Some(hours)),
+        _ => Err((
+            StatusCode::BAD_REQUEST,
+            format!("Burn time must be between 1 and {} hours", MAX_BURN_HOURS),
+        )),
+    }
+}
+
+impl AddVariantForm {
+    fn to_sql_insert(&self) -> Result<NewProductVariant, (StatusCode, String)> {
+        let sku = self.sku.trim().to_uppercase();
+        if sku.is_empty()
+            || sku.len() > 64
+            || !sku.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                String::from("SKUs may only contain letters, numbers and dashes"),
+            ));
+        }
+        let size = self.size.trim().to_owned();
+        if size.is_empty() || size.len() > 64 {
+            return Err((StatusCode::BAD_REQUEST, String::from("Please enter a size")));
+        }
+        if !(1..=6).contains(&self.wicks) {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                String::from("A candle can have 1 to 6 wicks"),
+            ));
+        }
+        let cost = parse_cost(&self.cost)?;
+        Ok(NewProductVariant {
+            product_id: self.product_id,
+            sku,
+            size,
+            wicks: self.wicks,
+            cost,
+            burn_hours: parse_burn_hours(&self.burn_hours)?,
+        })
+    }
+}
+
+/// Costs are entered in the base currency and must fit in the database's DECIMAL(10, 2)
+fn parse_cost(cost: &str) -> Result<Money, (StatusCode, String)> {
+    let cost = Money::parse(cost, Currency::BASE).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    if !cost.is_valid_price() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            String::from("Cost must be between £0.01 and £99,999,999.99"),
+        ));
+    }
+    Ok(cost)
+}
+
+impl AddSaleForm {
+    fn to_sql_insert(&self) -> Result<NewSale, (StatusCode, String)> {
+        let name = self.name.trim().to_owned();
+        if name.is_empty() || name.len() > 255 {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                String::from("Sale names must be between 1 and 255 characters"),
+            ));
+        }
+        if !(1..=90).contains(&self.percent_off) {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                String::from("Sales must take 1% to 90% off"),
+            ));
+        }
+        if self.product_id.is_some() && self.collection_id.is_some() {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                String::from("Sales can be on a product or a collection, not both"),
+            ));
+        }
+        let starts_at = parse_datetime(&self.starts_at)?;
+        let ends_at = parse_datetime(&self.ends_at)?;
+        if ends_at <= starts_at || ends_at <= OffsetDateTime::now_utc() {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                String::from("Sales must end after they start and after now"),
+            ));
+        }
+        Ok(NewSale {
+            name,
+            product_id: self.product_id,
+            collection_id: self.collection_id,
+            percent_off: self.percent_off,
+            starts_at,
+            ends_at,
+        })
+    }
+}
 
-        return Ok(ret);
+impl ScheduleForm {
+    fn times(&self) -> Result<(Option<OffsetDateTime>, Option<OffsetDateTime>), (StatusCode, String)> {
+        let optional = |datetime: &str| match datetime.trim() {
+            "" => Ok(None),
+            datetime => parse_datetime(datetime).map(Some),
+        };
+        let publish_at = optional(&self.publish_at)?;
+        let unpublish_at = optional(&self.unpublish_at)?;
+        if let (Some(publish_at), Some(unpublish_at)) = (publish_at, unpublish_at) {
+            if unpublish_at <= publish_at {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    String::from("Products must be unpublished after they are published"),
+                ));
+            }
+        }
+        Ok((publish_at, unpublish_at))
+    }
+}
+
+fn parse_datetime(datetime: &str) -> Result<OffsetDateTime, (StatusCode, String)> {
+    let datetime = datetime.trim();
+    // browsers leave the seconds off unless they are set
+    PrimitiveDateTime::parse(
+        datetime,
+        format_description!("[year]-[month]-[day]T[hour]:[minute]"),
+    )
+    .or_else(|_| {
+        PrimitiveDateTime::parse(
+            datetime,
+            format_description!("[year]-[month]-[day]T[hour]:[minute]:[second]"),
+        )
+    })
+    .map(|datetime| datetime.assume_utc())
+    .map_err(|_| {
+        (
+            StatusCode::BAD_REQUEST,
+            String::from("Please enter a valid date and time"),
+        )
+    })
+}
+
+impl AddProductForm {
+    async fn parse_from_multipart(mut form: Multipart) -> Result<Self, (StatusCode, String)> {
+        let incorrect = || (StatusCode::BAD_REQUEST, String::from("Incorrect Fields"));
+        let mut ret = Self::default();
+        while let Some(field) = form.next_field().await.map_err(|_| incorrect())? {
+            match field.name() {
+                Some("image") => {
+                    ret.image_title = field.file_name().ok_or_else(incorrect)?.to_owned();
+                    ret.image = field.bytes().await.map_err(|_| incorrect())?;
+                }
+                Some("title") => ret.title = field.text().await.map_err(|_| incorrect())?,
+                Some("description") => {
+                    ret.description = field.text().await.map_err(|_| incorrect())?
+                }
+                Some("details") => ret.details = field.text().await.map_err(|_| incorrect())?,
+                Some("cost") => {
+                    ret.cost = parse_cost(&field.text().await.map_err(|_| incorrect())?)?
+                }
+                _ => (),
+            }
+        }
+        if ret.image.is_empty() {
+            return Err(incorrect());
+        }
+        check_title_and_description(&mut ret.title, &mut ret.description)?;
+        check_details(&ret.details)?;
+        Ok(ret)
     }
 
-    fn to_sql_insert(self) -> NewProduct {
+    fn to_sql_insert(&self, imgname: String, slug: String) -> NewProduct {
         NewProduct {
             id: None,
-            title: self.title,
-            description: self.description,
-            imgname: self.image_title,
-            cost: self.cost,
+            title: self.title.clone(),
+            description: self.description.clone(),
+            details: self.details.clone(),
+            imgname,
+            slug,
+        }
+    }
+
+    /// Every new product is created with a single standard variant carrying its price
+    fn default_variant(&self, product_id: i32) -> NewProductVariant {
+        NewProductVariant {
+            product_id,
+            sku: format!("SC{:04}-STD", product_id),
+            size: String::from("Standard"),
+            wicks: 1,
+            cost: self.cost.clone(),
+            burn_hours: None,
         }
     }
 }
@@ -102,9 +544,130 @@ pub fn admin_routes() -> Router<AppState> {
     Router::new()
         .route("/", get(admin_dashboard))
         .route("/addproduct", post(handle_add_product))
-        .route("/removeproduct", post(handle_remove_product))
+        .route("/editproduct", post(handle_edit_product))
+        .route("/previewdetails", post(handle_preview_details))
+        .route("/archiveproduct", post(handle_archive_product))
+        .route("/restoreproduct", post(handle_restore_product))
+        .route("/purgeproduct", post(handle_purge_product))
         .route("/unlist", post(handle_unlist_product))
         .route("/relist", post(handle_relist_product))
+        .route("/scheduleproduct", post(handle_schedule_product))
+        .route("/addvariant", post(handle_add_variant))
+        .route("/unlistvariant", post(handle_unlist_variant))
+        .route("/relistvariant", post(handle_relist_variant))
+        .route("/editvariant", post(handle_edit_variant))
+        .route("/adjuststock", post(handle_adjust_stock))
+        .route("/addimage", post(handle_add_image))
+        .route("/moveimage", post(handle_move_image))
+        .route("/removeimage", post(handle_remove_image))
+        .route("/approvereview", post(handle_approve_review))
+        .route("/rejectreview", post(handle_reject_review))
+        .route("/addsale", post(handle_add_sale))
+        .route("/removesale", post(handle_remove_sale))
+        .route("/setrate", post(handle_set_rate))
+        .route("/removerate", post(handle_remove_rate))
+        .route("/importrates", post(handle_import_rates))
+        .route("/addattribute", post(attributes::handle_add_attribute))
+        .route("/removeattribute", post(attributes::handle_remove_attribute))
+        .route("/setattributes", post(attributes::handle_set_attributes))
+        .route("/addnote", post(scents::handle_add_note))
+        .route("/removenote", post(scents::handle_remove_note))
+        .route("/setnotes", post(scents::handle_set_notes))
+        .route("/addfield", post(personalisation::handle_add_field))
+        .route("/removefield", post(personalisation::handle_remove_field))
+        .route("/addcollection", post(collections::handle_add_collection))
+        .route("/editcollection", post(collections::handle_edit_collection))
+        .route("/removecollection", post(collections::handle_remove_collection))
+        .route("/pinproduct", post(collections::handle_pin_product))
+        .route("/unpinproduct", post(collections::handle_unpin_product))
+        .route("/movepinned", post(collections::handle_move_pinned))
+        .route("/export", get(catalog::handle_export))
+        // leave room for the other multipart fields, the image itself is checked against
+        // MAX_UPLOAD_BYTES once it has been read
+        .layer(DefaultBodyLimit::max(images::MAX_UPLOAD_BYTES + 64 * 1024))
+        // imports carry a whole catalogue and an archive of images, so are allowed to be larger
+        .route(
+            "/import",
+            post(catalog::handle_import)
+                .layer(DefaultBodyLimit::max(2 * catalog::MAX_IMPORT_BYTES)),
+        )
+}
+
+/// Finds a slug for a new product based on its title, adding a number if the slug is in use.
+/// Old slugs that are only redirects are also avoided so existing links keep working
+async fn unused_slug(
+    conn: &mut AsyncPgConnection,
+    title: &str,
+) -> Result<String, diesel::result::Error> {
+    let base = slugify(title);
+    let mut slug = base.clone();
+    for n in 2.. {
+        let taken: bool = select(
+            exists(products::table.filter(products::slug.eq(&slug)))
+                .or(exists(productredirects::table.filter(productredirects::slug.eq(&slug)))),
+        )
+        .get_result(conn)
+        .await?;
+        if !taken {
+            break;
+        }
+        slug = format!("{}-{}", base, n);
+    }
+    Ok(slug)
+}
+
+/// Points a product and the first image of its gallery at a stored image, returning the keys
+/// that are no longer referenced so their renditions can be removed once the transaction commits
+async fn replace_primary_image(
+    conn: &mut AsyncPgConnection,
+    product: &Product,
+    imgname: &str,
+) -> Result<Vec<String>, diesel::result::Error> {
+    let mut replaced = vec![product.imgname.clone()];
+    update(products::table)
+        .set(products::imgname.eq(imgname))
+        .filter(products::id.eq(product.id))
+        .execute(conn)
+        .await?;
+    images::acquire(conn, imgname, 1).await?;
+    let primary: Option<ProductImage> = productimages::table
+        .select(ProductImage::as_select())
+        .filter(productimages::product_id.eq(product.id))
+        .order(productimages::position.asc())
+        .first(conn)
+        .await
+        .optional()?;
+    if let Some(primary) = primary {
+        update(productimages::table)
+            .set(productimages::imgname.eq(imgname))
+            .filter(productimages::id.eq(primary.id))
+            .execute(conn)
+            .await?;
+        images::acquire(conn, imgname, 1).await?;
+        replaced.push(primary.imgname);
+    }
+    let mut unreferenced = vec![];
+    for img in replaced {
+        if images::release(conn, &img).await? {
+            unreferenced.push(img);
+        }
+    }
+    Ok(unreferenced)
+}
+
+/// Runs an upload through the image pipeline and stores the renditions, returning the key to use
+/// as an `imgname`. The caller must take a reference to the key and then pass it to
+/// `images::finish_upload` whether or not that worked
+async fn save_image(
+    state: &AppState,
+    conn: &mut AsyncPgConnection,
+    bytes: Bytes,
+) -> Result<String, (StatusCode, String)> {
+    let webp = state.webp_images;
+    let processed = tokio::task::spawn_blocking(move || images::process_upload(&bytes, webp))
+        .await
+        .map_err(internal_error)??;
+    images::store_upload(conn, state.images.as_ref(), processed).await
 }
 
 async fn validate_admin(
@@ -126,9 +689,7 @@ async fn validate_admin(
             .map_err(|_| (StatusCode::UNAUTHORIZED, String::from("401 Unauthorized")))?;
 
         if time::OffsetDateTime::now_utc() > session.0.expires_at {
-            delete(sessions::table)
-                .filter(sessions::id.eq(session_id))
-                .execute(&mut conn)
+            end_session(&mut conn, &session_id)
                 .await
                 .map_err(internal_error)?;
         }
@@ -145,13 +706,179 @@ async fn admin_dashboard(
 ) -> Result<(StatusCode, Html<String>), (StatusCode, String)> {
     let _session = validate_admin(jar, &state.pool).await?;
     let mut conn = state.pool.get().await.map_err(internal_error)?;
-    let products = products::table
+    let products: Vec<Product> = products::table
         .select(products::all_columns)
+        .filter(products::archived_at.is_null())
         .order(products::id.asc())
         .load(&mut conn)
         .await
         .map_err(internal_error)?;
-    let template = AdminDashboardPage { products };
+    let variants = ProductVariant::belonging_to(&products)
+        .select(ProductVariant::as_select())
+        .order(productvariants::id.asc())
+        .load(&mut conn)
+        .await
+        .map_err(internal_error)?;
+    let images = ProductImage::belonging_to(&products)
+        .select(ProductImage::as_select())
+        .order(productimages::position.asc())
+        .load(&mut conn)
+        .await
+        .map_err(internal_error)?;
+    let attributes = super::facets::all_attributes(&mut conn)
+        .await
+        .map_err(internal_error)?;
+    let mut admin_products = vec![];
+    for ((variants, images), product) in variants
+        .grouped_by(&products)
+        .into_iter()
+        .zip(images.grouped_by(&products))
+        .zip(products)
+    {
+        admin_products.push(AdminProduct {
+            attributes: attributes::product_values(&mut conn, &attributes, product.id)
+                .await
+                .map_err(internal_error)?,
+            scent: super::scents::profile(&mut conn, product.id)
+                .await
+                .map_err(internal_error)?,
+            personalisation: super::personalisation::fields(&mut conn, product.id)
+                .await
+                .map_err(internal_error)?,
+            product,
+            variants,
+            images,
+        });
+    }
+    let adjustments = stockadjustments::table
+        .select(StockAdjustment::as_select())
+        .order(stockadjustments::id.desc())
+        .limit(25)
+        .load(&mut conn)
+        .await
+        .map_err(internal_error)?;
+    let reviews = reviews::table
+        .inner_join(products::table)
+        .inner_join(users::table)
+        .select((Review::as_select(), products::title, users::email))
+        .filter(reviews::status.eq(PENDING))
+        .order(reviews::created_at.asc())
+        .load(&mut conn)
+        .await
+        .map_err(internal_error)?;
+    let sales = sales::table
+        .left_join(products::table)
+        .left_join(collections_table::table)
+        .select((
+            Sale::as_select(),
+            products::title.nullable(),
+            collections_table::name.nullable(),
+        ))
+        .filter(sales::ends_at.gt(OffsetDateTime::now_utc()))
+        .order(sales::starts_at.asc())
+        .load(&mut conn)
+        .await
+        .map_err(internal_error)?;
+    let archived = products::table
+        .select(products::all_columns)
+        .filter(products::archived_at.is_not_null())
+        .order(products::archived_at.desc())
+        .load(&mut conn)
+        .await
+        .map_err(internal_error)?;
+    let rates = exchangerates::table
+        .select(ExchangeRate::as_select())
+        .order(exchangerates::currency.asc())
+        .load(&mut conn)
+        .await
+        .map_err(internal_error)?;
+    let mut upcoming: Vec<Product> = products::table
+        .select(products::all_columns)
+        .filter(
+            products::publish_at
+                .is_not_null()
+                .or(products::unpublish_at.is_not_null()),
+        )
+        .load(&mut conn)
+        .await
+        .map_err(internal_error)?;
+    upcoming.sort_by_key(|p| p.publish_at.or(p.unpublish_at));
+    let mut collections = vec![];
+    for collection in super::collections::all(&mut conn)
+        .await
+        .map_err(internal_error)?
+    {
+        let pinned = super::collections::pinned(&mut conn, collection.id)
+            .await
+            .map_err(internal_error)?;
+        collections.push((collection, pinned));
+    }
+    let recent_orders: Vec<(OrderWithId, Address)> = orders::table
+        .inner_join(addresses::table)
+        .select((
+            orders::all_columns,
+            (
+                addresses::user_id,
+                addresses::recipient_name,
+                addresses::line_1,
+                addresses::line_2,
+                addresses::postcode,
+                addresses::county,
+            ),
+        ))
+        .order(orders::id.desc())
+        .limit(RECENT_ORDERS)
+        .load(&mut conn)
+        .await
+        .map_err(internal_error)?;
+    let order_ids: Vec<i32> = recent_orders.iter().map(|(order, _)| order.id).collect();
+    let lines: Vec<(i32, String, String, String, i32, String)> = productorders::table
+        .inner_join(productvariants::table.inner_join(products::table))
+        .select((
+            productorders::order_id,
+            products::title,
+            productvariants::sku,
+            productvariants::size,
+            productorders::quantity,
+            productorders::personalisation,
+        ))
+        .filter(productorders::order_id.eq_any(&order_ids))
+        .order((productvariants::sku.asc(), productorders::personalisation.asc()))
+        .load(&mut conn)
+        .await
+        .map_err(internal_error)?;
+    let orders = recent_orders
+        .into_iter()
+        .map(|(order, address)| FulfilmentOrder {
+            lines: lines
+                .iter()
+                .filter(|line| line.0 == order.id)
+                .map(|(_, title, sku, size, quantity, personalisation)| {
+                    (title.clone(), sku.clone(), size.clone(), *quantity, personalisation.clone())
+                })
+                .collect(),
+            order,
+            address,
+        })
+        .collect();
+    let template = AdminDashboardPage {
+        orders,
+        upcoming,
+        collections,
+        attributes,
+        notes: super::scents::all_notes(&mut conn)
+            .await
+            .map_err(internal_error)?,
+        gift_answers: super::gifts::answer_counts(&mut conn)
+            .await
+            .map_err(internal_error)?,
+        products: admin_products,
+        archived,
+        adjustments,
+        reviews,
+        sales,
+        rates,
+    };
     let html = template.render().unwrap();
     Ok((StatusCode::OK, Html(html)))
 }
@@ -163,52 +890,333 @@ async fn handle_add_product(
 ) -> Result<Response, (StatusCode, String)> {
     let _session = validate_admin(jar, &state.pool).await?;
     let mut conn = state.pool.get().await.map_err(internal_error)?;
-    let form = AddProductForm::parse_from_multipart(form)
+    let form = AddProductForm::parse_from_multipart(form).await?;
+    let imgname = save_image(&state, &mut conn, form.image.clone()).await?;
+    let upload = imgname.clone();
+    let result = conn
+        .transaction::<_, diesel::result::Error, _>(|conn| {
+            async move {
+                let slug = unused_slug(conn, &form.title).await?;
+                let product_id: i32 = insert_into(products::table)
+                    .values(form.to_sql_insert(imgname.clone(), slug))
+                    .returning(products::id)
+                    .get_result(conn)
+                    .await?;
+                insert_into(productvariants::table)
+                    .values(form.default_variant(product_id))
+                    .execute(conn)
+                    .await?;
+                insert_into(productimages::table)
+                    .values(NewProductImage {
+                        product_id,
+                        imgname: imgname.clone(),
+                        alt_text: form.title.clone(),
+                        position: 0,
+                    })
+                    .execute(conn)
+                    .await?;
+                // one reference for the product and one for its gallery
+                images::acquire(conn, &imgname, 2).await?;
+                Ok(())
+            }
+            .scope_boxed()
+        })
+        .await;
+    images::finish_upload(&mut conn, state.images.as_ref(), &upload).await;
+    result.map_err(internal_error)?;
+    Ok(AppendHeaders([("HX-Refresh", "true")]).into_response())
+}
+
+/// Replacing the image swaps out the product's primary image, the rest of its gallery is kept
+async fn handle_edit_product(
+    jar: CookieJar,
+    State(state): State<AppState>,
+    form: Multipart,
+) -> Result<Response, (StatusCode, String)> {
+    let _session = validate_admin(jar, &state.pool).await?;
+    let form = EditProductForm::parse_from_multipart(form).await?;
+    let mut conn = state.pool.get().await.map_err(internal_error)?;
+    let product: Product = products::table
+        .select(products::all_columns)
+        .filter(products::id.eq(form.id))
+        .first(&mut conn)
         .await
-        .map_err(|_| {
-            (
-                StatusCode::BAD_REQUEST,
-                String::from("Incorrect form fields"),
-            )
-        })?;
-    let mut file = fs::File::options()
-        .write(true)
-        .create_new(true)
-        .open(["server_files\\images\\", &form.image_title].concat())
+        .map_err(|_| (StatusCode::NOT_FOUND, String::from("404 Not Found")))?;
+    let slug_taken: bool = select(exists(
+        products::table
+            .filter(products::slug.eq(&form.slug))
+            .filter(products::id.ne(form.id)),
+    ))
+    .get_result(&mut conn)
+    .await
+    .map_err(internal_error)?;
+    if slug_taken {
+        return Err((
+            StatusCode::CONFLICT,
+            String::from("That URL is already used by another product"),
+        ));
+    }
+    let imgname = if form.image.is_empty() {
+        None
+    } else {
+        Some(save_image(&state, &mut conn, form.image.clone()).await?)
+    };
+    let upload = imgname.clone();
+    let result = conn
+        .transaction::<_, diesel::result::Error, _>(|conn| {
+            async move {
+                if form.slug != product.slug {
+                    // the new slug may have belonged to a product before, it now resolves here
+                    delete(productredirects::table)
+                        .filter(productredirects::slug.eq(&form.slug))
+                        .execute(conn)
+                        .await?;
+                    insert_into(productredirects::table)
+                        .values(ProductRedirect {
+                            slug: product.slug.clone(),
+                            product_id: form.id,
+                        })
+                        .execute(conn)
+                        .await?;
+                }
+                update(products::table)
+                    .set((
+                        products::slug.eq(&form.slug),
+                        products::title.eq(&form.title),
+                        products::description.eq(&form.description),
+                        products::details.eq(form.details.as_ref().unwrap_or(&product.details)),
+                    ))
+                    .filter(products::id.eq(form.id))
+                    .execute(conn)
+                    .await?;
+                match imgname {
+                    Some(imgname) => replace_primary_image(conn, &product, &imgname).await,
+                    None => Ok(vec![]),
+                }
+            }
+            .scope_boxed()
+        })
+        .await;
+    if let Some(upload) = upload {
+        images::finish_upload(&mut conn, state.images.as_ref(), &upload).await;
+    }
+    for img in result.map_err(internal_error)? {
+        images::remove_unreferenced(&mut conn, state.images.as_ref(), &img).await;
+    }
+    Ok(AppendHeaders([("HX-Refresh", "true")]).into_response())
+}
+
+/// Hides a product from customers and takes it out of carts, it stays in past orders and can be
+/// restored
+async fn handle_archive_product(
+    jar: CookieJar,
+    State(state): State<AppState>,
+    Form(form): Form<ProductForm>,
+) -> Result<Response, (StatusCode, String)> {
+    let _session = validate_admin(jar, &state.pool).await?;
+    let mut conn = state.pool.get().await.map_err(internal_error)?;
+    let n = conn
+        .transaction::<_, diesel::result::Error, _>(|conn| {
+            async move {
+                let n = update(products::table)
+                    .set((
+                        products::listed.eq(false),
+                        products::archived_at.eq(Some(OffsetDateTime::now_utc())),
+                        products::publish_at.eq(None::<OffsetDateTime>),
+                        products::unpublish_at.eq(None::<OffsetDateTime>),
+                    ))
+                    .filter(products::id.eq(form.id))
+                    .filter(products::archived_at.is_null())
+                    .execute(conn)
+                    .await?;
+                let variant_ids = productvariants::table
+                    .select(productvariants::id)
+                    .filter(productvariants::product_id.eq(form.id));
+                delete(cartproducts::table)
+                    .filter(cartproducts::variant_id.eq_any(variant_ids))
+                    .execute(conn)
+                    .await?;
+                Ok(n)
+            }
+            .scope_boxed()
+        })
         .await
         .map_err(internal_error)?;
-    file.write_all(&form.image).await.map_err(internal_error)?;
-    insert_into(products::table)
-        .values(form.to_sql_insert())
+    if n == 0 {
+        return Err((StatusCode::NOT_FOUND, String::from("404 Not Found")));
+    }
+    Ok(AppendHeaders([("HX-Refresh", "true")]).into_response())
+}
+
+/// Brings an archived product back unlisted, so it can be checked over before being relisted
+async fn handle_restore_product(
+    jar: CookieJar,
+    State(state): State<AppState>,
+    Form(form): Form<ProductForm>,
+) -> Result<Response, (StatusCode, String)> {
+    let _session = validate_admin(jar, &state.pool).await?;
+    let mut conn = state.pool.get().await.map_err(internal_error)?;
+    let n = update(products::table)
+        .set(products::archived_at.eq(None::<OffsetDateTime>))
+        .filter(products::id.eq(form.id))
+        .filter(products::archived_at.is_not_null())
         .execute(&mut conn)
         .await
         .map_err(internal_error)?;
+    if n == 0 {
+        return Err((StatusCode::NOT_FOUND, String::from("404 Not Found")));
+    }
     Ok(AppendHeaders([("HX-Refresh", "true")]).into_response())
 }
 
-async fn handle_remove_product(
+/// Deletes an archived product for good, products that have been ordered are kept for the order
+/// history and can only stay archived
+async fn handle_purge_product(
     jar: CookieJar,
     State(state): State<AppState>,
     Form(form): Form<ProductForm>,
 ) -> Result<Response, (StatusCode, String)> {
     let _session = validate_admin(jar, &state.pool).await?;
     let mut conn = state.pool.get().await.map_err(internal_error)?;
-    let img: String = delete(products::table)
+    let archived: Option<bool> = products::table
+        .select(products::archived_at.is_not_null())
         .filter(products::id.eq(form.id))
-        .returning(products::imgname)
-        .get_result(&mut conn)
+        .first(&mut conn)
         .await
-        .map_err(|_| {
-            (
+        .optional()
+        .map_err(internal_error)?;
+    match archived {
+        None => return Err((StatusCode::NOT_FOUND, String::from("404 Not Found"))),
+        Some(false) => {
+            return Err((
                 StatusCode::CONFLICT,
-                String::from(
-                    "Unable to remove product from database due to foreign key constraints",
-                ),
-            )
-        })?;
-    fs::remove_file(["server_files\\images\\", &img].concat())
+                String::from("Archive the product before purging it"),
+            ))
+        }
+        Some(true) => {}
+    }
+    let ordered: bool = select(exists(
+        productorders::table
+            .inner_join(productvariants::table)
+            .filter(productvariants::product_id.eq(form.id)),
+    ))
+    .get_result(&mut conn)
+    .await
+    .map_err(internal_error)?;
+    if ordered {
+        return Err((
+            StatusCode::CONFLICT,
+            String::from("This product has been ordered, so it can only be archived"),
+        ));
+    }
+    let unreferenced: Vec<String> = conn
+        .transaction::<_, diesel::result::Error, _>(|conn| {
+            async move {
+                let variant_ids = productvariants::table
+                    .select(productvariants::id)
+                    .filter(productvariants::product_id.eq(form.id));
+                delete(cartproducts::table)
+                    .filter(cartproducts::variant_id.eq_any(variant_ids))
+                    .execute(conn)
+                    .await?;
+                // the stock history is kept, it still names the SKU
+                update(stockadjustments::table)
+                    .set(stockadjustments::variant_id.eq(None::<i32>))
+                    .filter(stockadjustments::variant_id.eq_any(variant_ids.nullable()))
+                    .execute(conn)
+                    .await?;
+                delete(productvariants::table)
+                    .filter(productvariants::product_id.eq(form.id))
+                    .execute(conn)
+                    .await?;
+                delete(productredirects::table)
+                    .filter(productredirects::product_id.eq(form.id))
+                    .execute(conn)
+                    .await?;
+                delete(reviews::table)
+                    .filter(reviews::product_id.eq(form.id))
+                    .execute(conn)
+                    .await?;
+                delete(productrecommendations::table)
+                    .filter(
+                        productrecommendations::product_id
+                            .eq(form.id)
+                            .or(productrecommendations::recommended_id.eq(form.id)),
+                    )
+                    .execute(conn)
+                    .await?;
+                delete(recentlyviewed::table)
+                    .filter(recentlyviewed::product_id.eq(form.id))
+                    .execute(conn)
+                    .await?;
+                delete(sales::table)
+                    .filter(sales::product_id.eq(form.id))
+                    .execute(conn)
+                    .await?;
+                delete(notifications::table)
+                    .filter(notifications::product_id.eq(form.id))
+                    .execute(conn)
+                    .await?;
+                delete(productalerts::table)
+                    .filter(productalerts::product_id.eq(form.id))
+                    .execute(conn)
+                    .await?;
+                delete(comparisons::table)
+                    .filter(comparisons::product_id.eq(form.id))
+                    .execute(conn)
+                    .await?;
+                delete(wishlistproducts::table)
+                    .filter(wishlistproducts::product_id.eq(form.id))
+                    .execute(conn)
+                    .await?;
+                delete(likedproducts::table)
+                    .filter(likedproducts::product_id.eq(form.id))
+                    .execute(conn)
+                    .await?;
+                delete(collectionproducts::table)
+                    .filter(collectionproducts::product_id.eq(form.id))
+                    .execute(conn)
+                    .await?;
+                delete(productattributes::table)
+                    .filter(productattributes::product_id.eq(form.id))
+                    .execute(conn)
+                    .await?;
+                delete(productscentnotes::table)
+                    .filter(productscentnotes::product_id.eq(form.id))
+                    .execute(conn)
+                    .await?;
+                delete(personalisationfields::table)
+                    .filter(personalisationfields::product_id.eq(form.id))
+                    .execute(conn)
+                    .await?;
+                let mut imgs: Vec<String> = delete(productimages::table)
+                    .filter(productimages::product_id.eq(form.id))
+                    .returning(productimages::imgname)
+                    .get_results(conn)
+                    .await?;
+                imgs.push(
+                    delete(products::table)
+                        .filter(products::id.eq(form.id))
+                        .returning(products::imgname)
+                        .get_result(conn)
+                        .await?,
+                );
+                let mut unreferenced = vec![];
+                for img in imgs {
+                    if images::release(conn, &img).await? {
+                        unreferenced.push(img);
+                    }
+                }
+                Ok(unreferenced)
+            }
+            .scope_boxed()
+        })
         .await
         .map_err(internal_error)?;
+    for img in unreferenced {
+        images::remove_unreferenced(&mut conn, state.images.as_ref(), &img).await;
+    }
+    state.homepage.invalidate();
     Ok(AppendHeaders([("HX-Refresh", "true")]).into_response())
 }
 
@@ -219,8 +1227,13 @@ async fn handle_unlist_product(
 ) -> Result<Response, (StatusCode, String)> {
     let _session = validate_admin(jar, &state.pool).await?;
     let mut conn = state.pool.get().await.map_err(internal_error)?;
+    // unlisting by hand cancels anything scheduled
     update(products::table)
-        .set(products::listed.eq(false))
+        .set((
+            products::listed.eq(false),
+            products::publish_at.eq(None::<OffsetDateTime>),
+            products::unpublish_at.eq(None::<OffsetDateTime>),
+        ))
         .filter(products::id.eq(form.id))
         .execute(&mut conn)
         .await
@@ -235,12 +1248,518 @@ async fn handle_relist_product(
 ) -> Result<Response, (StatusCode, String)> {
     let _session = validate_admin(jar, &state.pool).await?;
     let mut conn = state.pool.get().await.map_err(internal_error)?;
+    // archived products have to be restored first, a scheduled unpublish still happens
     update(products::table)
-        .set(products::listed.eq(true))
+        .set((
+            products::listed.eq(true),
+            products::publish_at.eq(None::<OffsetDateTime>),
+        ))
         .filter(products::id.eq(form.id))
+        .filter(products::archived_at.is_null())
+        .execute(&mut conn)
+        .await
+        .map_err(internal_error)?;
+
+    Ok(AppendHeaders([("HX-Refresh", "true")]).into_response())
+}
+
+/// Sets when a product is listed and unlisted. A product given a publish time is unlisted until
+/// then, leaving both times empty clears the schedule
+async fn handle_schedule_product(
+    jar: CookieJar,
+    State(state): State<AppState>,
+    Form(form): Form<ScheduleForm>,
+) -> Result<Response, (StatusCode, String)> {
+    let _session = validate_admin(jar, &state.pool).await?;
+    let (publish_at, unpublish_at) = form.times()?;
+    let mut conn = state.pool.get().await.map_err(internal_error)?;
+    let product: Product = products::table
+        .select(products::all_columns)
+        .filter(products::id.eq(form.id))
+        .filter(products::archived_at.is_null())
+        .first(&mut conn)
+        .await
+        .map_err(|_| (StatusCode::NOT_FOUND, String::from("404 Not Found")))?;
+    update(products::table)
+        .set((
+            products::listed.eq(product.listed && publish_at.is_none()),
+            products::publish_at.eq(publish_at),
+            products::unpublish_at.eq(unpublish_at),
+        ))
+        .filter(products::id.eq(product.id))
+        .execute(&mut conn)
+        .await
+        .map_err(internal_error)?;
+    Ok(AppendHeaders([("HX-Refresh", "true")]).into_response())
+}
+
+async fn handle_add_variant(
+    jar: CookieJar,
+    State(state): State<AppState>,
+    Form(form): Form<AddVariantForm>,
+) -> Result<Response, (StatusCode, String)> {
+    let _session = validate_admin(jar, &state.pool).await?;
+    let mut conn = state.pool.get().await.map_err(internal_error)?;
+    insert_into(productvariants::table)
+        .values(form.to_sql_insert()?)
+        .execute(&mut conn)
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::CONFLICT,
+                String::from("Unable to add variant, the SKU may already be in use"),
+            )
+        })?;
+    Ok(AppendHeaders([("HX-Refresh", "true")]).into_response())
+}
+
+async fn handle_unlist_variant(
+    jar: CookieJar,
+    State(state): State<AppState>,
+    Form(form): Form<VariantForm>,
+) -> Result<Response, (StatusCode, String)> {
+    let _session = validate_admin(jar, &state.pool).await?;
+    let mut conn = state.pool.get().await.map_err(internal_error)?;
+    update(productvariants::table)
+        .set(productvariants::listed.eq(false))
+        .filter(productvariants::id.eq(form.id))
+        .execute(&mut conn)
+        .await
+        .map_err(internal_error)?;
+    Ok(AppendHeaders([("HX-Refresh", "true")]).into_response())
+}
+
+async fn handle_relist_variant(
+    jar: CookieJar,
+    State(state): State<AppState>,
+    Form(form): Form<VariantForm>,
+) -> Result<Response, (StatusCode, String)> {
+    let _session = validate_admin(jar, &state.pool).await?;
+    let mut conn = state.pool.get().await.map_err(internal_error)?;
+    update(productvariants::table)
+        .set(productvariants::listed.eq(true))
+        .filter(productvariants::id.eq(form.id))
+        .execute(&mut conn)
+        .await
+        .map_err(internal_error)?;
+    Ok(AppendHeaders([("HX-Refresh", "true")]).into_response())
+}
+
+/// Orders record the price paid, so changing a cost only affects future orders
+async fn handle_edit_variant(
+    jar: CookieJar,
+    State(state): State<AppState>,
+    Form(form): Form<EditVariantForm>,
+) -> Result<Response, (StatusCode, String)> {
+    let _session = validate_admin(jar, &state.pool).await?;
+    let cost = parse_cost(&form.cost)?;
+    let mut conn = state.pool.get().await.map_err(internal_error)?;
+    let n = match &form.burn_hours {
+        Some(hours) => {
+            update(productvariants::table)
+                .set((
+                    productvariants::cost.eq(cost),
+                    productvariants::burn_hours.eq(parse_burn_hours(hours)?),
+                ))
+                .filter(productvariants::id.eq(form.id))
+                .execute(&mut conn)
+                .await
+        }
+        None => {
+            update(productvariants::table)
+                .set(productvariants::cost.eq(cost))
+                .filter(productvariants::id.eq(form.id))
+                .execute(&mut conn)
+                .await
+        }
+    }
+    .map_err(internal_error)?;
+    if n == 0 {
+        return Err((StatusCode::NOT_FOUND, String::from("404 Not Found")));
+    }
+    Ok(AppendHeaders([("HX-Refresh", "true")]).into_response())
+}
+
+async fn handle_approve_review(
+    jar: CookieJar,
+    State(state): State<AppState>,
+    Form(form): Form<ReviewForm>,
+) -> Result<Response, (StatusCode, String)> {
+    let _session = validate_admin(jar, &state.pool).await?;
+    let mut conn = state.pool.get().await.map_err(internal_error)?;
+    update(reviews::table)
+        .set(reviews::status.eq(APPROVED))
+        .filter(reviews::id.eq(form.id))
+        .execute(&mut conn)
+        .await
+        .map_err(internal_error)?;
+    Ok(AppendHeaders([("HX-Refresh", "true")]).into_response())
+}
+
+async fn handle_reject_review(
+    jar: CookieJar,
+    State(state): State<AppState>,
+    Form(form): Form<ReviewForm>,
+) -> Result<Response, (StatusCode, String)> {
+    let _session = validate_admin(jar, &state.pool).await?;
+    let mut conn = state.pool.get().await.map_err(internal_error)?;
+    update(reviews::table)
+        .set(reviews::status.eq(REJECTED))
+        .filter(reviews::id.eq(form.id))
+        .execute(&mut conn)
+        .await
+        .map_err(internal_error)?;
+    Ok(AppendHeaders([("HX-Refresh", "true")]).into_response())
+}
+
+async fn handle_adjust_stock(
+    jar: CookieJar,
+    State(state): State<AppState>,
+    Form(form): Form<StockForm>,
+) -> Result<Response, (StatusCode, String)> {
+    let session = validate_admin(jar, &state.pool).await?;
+    let reason = form.reason.trim().to_owned();
+    if form.delta == 0 || reason.is_empty() || reason.len() > 255 {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            String::from("Please enter a non zero adjustment and a reason"),
+        ));
+    }
+    let mut conn = state.pool.get().await.map_err(internal_error)?;
+    let adjusted = conn
+        .transaction::<_, diesel::result::Error, _>(|conn| {
+            async move {
+                let sku: Option<String> = update(productvariants::table)
+                    .filter(productvariants::id.eq(form.id))
+                    .filter((productvariants::stock + form.delta).ge(0))
+                    .set(productvariants::stock.eq(productvariants::stock + form.delta))
+                    .returning(productvariants::sku)
+                    .get_result(conn)
+                    .await
+                    .optional()?;
+                let adjusted = sku.is_some();
+                if let Some(sku) = sku {
+                    insert_into(stockadjustments::table)
+                        .values(NewStockAdjustment {
+                            variant_id: form.id,
+                            sku,
+                            delta: form.delta,
+                            reason,
+                            order_id: None,
+                            user_id: session.user_id,
+                        })
+                        .execute(conn)
+                        .await?;
+                }
+                Ok(adjusted)
+            }
+            .scope_boxed()
+        })
+        .await
+        .map_err(internal_error)?;
+    if !adjusted {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            String::from("Stock levels cannot go below zero"),
+        ));
+    }
+    Ok(AppendHeaders([("HX-Refresh", "true")]).into_response())
+}
+
+async fn handle_add_image(
+    jar: CookieJar,
+    State(state): State<AppState>,
+    form: Multipart,
+) -> Result<Response, (StatusCode, String)> {
+    let _session = validate_admin(jar, &state.pool).await?;
+    let mut conn = state.pool.get().await.map_err(internal_error)?;
+    let form = AddImageForm::parse_from_multipart(form)
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::BAD_REQUEST,
+                String::from("Incorrect form fields"),
+            )
+        })?;
+    let found: bool = select(exists(products::table.filter(products::id.eq(form.product_id))))
+        .get_result(&mut conn)
+        .await
+        .map_err(internal_error)?;
+    if !found {
+        return Err((StatusCode::NOT_FOUND, String::from("404 Not Found")));
+    }
+    let position: Option<i32> = productimages::table
+        .select(productimages::position)
+        .filter(productimages::product_id.eq(form.product_id))
+        .order(productimages::position.desc())
+        .first(&mut conn)
+        .await
+        .optional()
+        .map_err(internal_error)?;
+    let imgname = save_image(&state, &mut conn, form.image).await?;
+    let upload = imgname.clone();
+    let result = conn
+        .transaction::<_, diesel::result::Error, _>(|conn| {
+            async move {
+                insert_into(productimages::table)
+                    .values(NewProductImage {
+                        product_id: form.product_id,
+                        imgname: imgname.clone(),
+                        alt_text: form.alt_text,
+                        position: position.map_or(0, |p| p + 1),
+                    })
+                    .execute(conn)
+                    .await?;
+                images::acquire(conn, &imgname, 1).await
+            }
+            .scope_boxed()
+        })
+        .await;
+    images::finish_upload(&mut conn, state.images.as_ref(), &upload).await;
+    result.map_err(internal_error)?;
+    Ok(AppendHeaders([("HX-Refresh", "true")]).into_response())
+}
+
+async fn handle_move_image(
+    jar: CookieJar,
+    State(state): State<AppState>,
+    Form(form): Form<MoveImageForm>,
+) -> Result<Response, (StatusCode, String)> {
+    let _session = validate_admin(jar, &state.pool).await?;
+    let mut conn = state.pool.get().await.map_err(internal_error)?;
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        async move {
+            let image: ProductImage = productimages::table
+                .select(ProductImage::as_select())
+                .filter(productimages::id.eq(form.id))
+                .first(conn)
+                .await?;
+            let neighbour = productimages::table
+                .select(ProductImage::as_select())
+                .filter(productimages::product_id.eq(image.product_id))
+                .into_boxed();
+            let neighbour = match form.direction {
+                Direction::Up => neighbour
+                    .filter(productimages::position.lt(image.position))
+                    .order(productimages::position.desc()),
+                Direction::Down => neighbour
+                    .filter(productimages::position.gt(image.position))
+                    .order(productimages::position.asc()),
+            };
+            // already at the start or end of the gallery
+            let Some(neighbour) = neighbour.first(conn).await.optional()? else {
+                return Ok(());
+            };
+            update(productimages::table)
+                .set(productimages::position.eq(neighbour.position))
+                .filter(productimages::id.eq(image.id))
+                .execute(conn)
+                .await?;
+            update(productimages::table)
+                .set(productimages::position.eq(image.position))
+                .filter(productimages::id.eq(neighbour.id))
+                .execute(conn)
+                .await?;
+            Ok(())
+        }
+        .scope_boxed()
+    })
+    .await
+    .map_err(internal_error)?;
+    Ok(AppendHeaders([("HX-Refresh", "true")]).into_response())
+}
+
+async fn handle_remove_image(
+    jar: CookieJar,
+    State(state): State<AppState>,
+    Form(form): Form<ImageForm>,
+) -> Result<Response, (StatusCode, String)> {
+    let _session = validate_admin(jar, &state.pool).await?;
+    let mut conn = state.pool.get().await.map_err(internal_error)?;
+    let image: ProductImage = productimages::table
+        .select(ProductImage::as_select())
+        .filter(productimages::id.eq(form.id))
+        .first(&mut conn)
+        .await
+        .map_err(|_| (StatusCode::NOT_FOUND, String::from("404 Not Found")))?;
+    // the product's row is locked while counting, so removals made at the same time can't both
+    // pass the check and take its last image
+    let unreferenced = conn
+        .transaction::<_, diesel::result::Error, _>(|conn| {
+            async move {
+                let primary: String = products::table
+                    .select(products::imgname)
+                    .filter(products::id.eq(image.product_id))
+                    .for_update()
+                    .first(conn)
+                    .await?;
+                let remaining: i64 = productimages::table
+                    .filter(productimages::product_id.eq(image.product_id))
+                    .count()
+                    .get_result(conn)
+                    .await?;
+                if remaining <= 1 {
+                    return Ok(None);
+                }
+                let removed = delete(productimages::table)
+                    .filter(productimages::id.eq(image.id))
+                    .execute(conn)
+                    .await?;
+                if removed == 0 {
+                    return Ok(Some(vec![]));
+                }
+                let mut released = vec![image.imgname];
+                // the product's own image follows the first image left in its gallery
+                let first: String = productimages::table
+                    .select(productimages::imgname)
+                    .filter(productimages::product_id.eq(image.product_id))
+                    .order(productimages::position.asc())
+                    .first(conn)
+                    .await?;
+                if first != primary {
+                    update(products::table)
+                        .set(products::imgname.eq(&first))
+                        .filter(products::id.eq(image.product_id))
+                        .execute(conn)
+                        .await?;
+                    images::acquire(conn, &first, 1).await?;
+                    released.push(primary);
+                }
+                let mut unreferenced = vec![];
+                for img in released {
+                    if images::release(conn, &img).await? {
+                        unreferenced.push(img);
+                    }
+                }
+                Ok(Some(unreferenced))
+            }
+            .scope_boxed()
+        })
+        .await
+        .map_err(internal_error)?
+        .ok_or((
+            StatusCode::BAD_REQUEST,
+            String::from("A product must keep at least one image"),
+        ))?;
+    for img in unreferenced {
+        images::remove_unreferenced(&mut conn, state.images.as_ref(), &img).await;
+    }
+    Ok(AppendHeaders([("HX-Refresh", "true")]).into_response())
+}
+
+async fn handle_add_sale(
+    jar: CookieJar,
+    State(state): State<AppState>,
+    Form(form): Form<AddSaleForm>,
+) -> Result<Response, (StatusCode, String)> {
+    let _session = validate_admin(jar, &state.pool).await?;
+    let sale = form.to_sql_insert()?;
+    let mut conn = state.pool.get().await.map_err(internal_error)?;
+    if let Some(product_id) = sale.product_id {
+        let found: bool = select(exists(products::table.filter(products::id.eq(product_id))))
+            .get_result(&mut conn)
+            .await
+            .map_err(internal_error)?;
+        if !found {
+            return Err((StatusCode::NOT_FOUND, String::from("404 Not Found")));
+        }
+    }
+    if let Some(collection_id) = sale.collection_id {
+        let collection = collections_table::table.filter(collections_table::id.eq(collection_id));
+        let found: bool = select(exists(collection))
+            .get_result(&mut conn)
+            .await
+            .map_err(internal_error)?;
+        if !found {
+            return Err((StatusCode::NOT_FOUND, String::from("404 Not Found")));
+        }
+    }
+    insert_into(sales::table)
+        .values(sale)
+        .execute(&mut conn)
+        .await
+        .map_err(internal_error)?;
+    Ok(AppendHeaders([("HX-Refresh", "true")]).into_response())
+}
+
+/// Ending a sale early, orders already placed keep the price they were charged
+async fn handle_remove_sale(
+    jar: CookieJar,
+    State(state): State<AppState>,
+    Form(form): Form<SaleForm>,
+) -> Result<Response, (StatusCode, String)> {
+    let _session = validate_admin(jar, &state.pool).await?;
+    let mut conn = state.pool.get().await.map_err(internal_error)?;
+    delete(sales::table)
+        .filter(sales::id.eq(form.id))
+        .execute(&mut conn)
+        .await
+        .map_err(internal_error)?;
+    Ok(AppendHeaders([("HX-Refresh", "true")]).into_response())
+}
+
+async fn handle_set_rate(
+    jar: CookieJar,
+    State(state): State<AppState>,
+    Form(form): Form<RateForm>,
+) -> Result<Response, (StatusCode, String)> {
+    let _session = validate_admin(jar, &state.pool).await?;
+    let bad_request = |e| (StatusCode::BAD_REQUEST, e);
+    let currency = currency::parse_rate_currency(&form.currency).map_err(bad_request)?;
+    let rate = currency::parse_rate(&form.rate).map_err(bad_request)?;
+    let mut conn = state.pool.get().await.map_err(internal_error)?;
+    currency::save_rates(&mut conn, &[(currency, rate)])
+        .await
+        .map_err(internal_error)?;
+    Ok(AppendHeaders([("HX-Refresh", "true")]).into_response())
+}
+
+/// Customers who chose the currency see prices in pounds until it has a rate again
+async fn handle_remove_rate(
+    jar: CookieJar,
+    State(state): State<AppState>,
+    Form(form): Form<RateCurrencyForm>,
+) -> Result<Response, (StatusCode, String)> {
+    let _session = validate_admin(jar, &state.pool).await?;
+    let mut conn = state.pool.get().await.map_err(internal_error)?;
+    delete(exchangerates::table)
+        .filter(exchangerates::currency.eq(&form.currency))
         .execute(&mut conn)
         .await
         .map_err(internal_error)?;
+    Ok(AppendHeaders([("HX-Refresh", "true")]).into_response())
+}
 
+/// Sets every rate in an uploaded rates file, nothing changes if any line of it is wrong
+async fn handle_import_rates(
+    jar: CookieJar,
+    State(state): State<AppState>,
+    mut form: Multipart,
+) -> Result<Response, (StatusCode, String)> {
+    let _session = validate_admin(jar, &state.pool).await?;
+    let incorrect = || (StatusCode::BAD_REQUEST, String::from("Incorrect Fields"));
+    let mut file = Bytes::new();
+    while let Some(field) = form.next_field().await.map_err(|_| incorrect())? {
+        if field.name() == Some("rates") {
+            file = field.bytes().await.map_err(|_| incorrect())?;
+        }
+    }
+    let rates =
+        currency::parse_rates(&file).map_err(|errors| (StatusCode::BAD_REQUEST, errors.join(", ")))?;
+    let mut conn = state.pool.get().await.map_err(internal_error)?;
+    currency::save_rates(&mut conn, &rates)
+        .await
+        .map_err(internal_error)?;
     Ok(AppendHeaders([("HX-Refresh", "true")]).into_response())
 }
+
+/// Shows product details as they will appear on the product page while they are being written
+async fn handle_preview_details(
+    jar: CookieJar,
+    State(state): State<AppState>,
+    Form(form): Form<DetailsForm>,
+) -> Result<Html<String>, (StatusCode, String)> {
+    let _session = validate_admin(jar, &state.pool).await?;
+    check_details(&form.details)?;
+    Ok(Html(markdown::render(&form.details)))
+}
@@ -0,0 +1,256 @@
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{AppendHeaders, IntoResponse, Response},
+};
+use axum_extra::extract::{CookieJar, Form};
+use diesel::{
+    delete, dsl, insert_into, BoolExpressionMethods, ExpressionMethods, QueryDsl, QueryResult,
+    SelectableHelper,
+};
+use diesel_async::{
+    scoped_futures::ScopedFutureExt, AsyncConnection, AsyncPgConnection, RunQueryDsl,
+};
+use serde::Deserialize;
+
+use super::validate_admin;
+use crate::{
+    db::{
+        models::{Attribute, ProductAttribute},
+        schema::{attributes, productattributes, products},
+    },
+    ecom::{
+        facets::{self, FLAG, KINDS, NUMBER, RESERVED},
+        slugify,
+    },
+    internal_error, AppState,
+};
+
+const MAX_NAME_LENGTH: usize = 64;
+const MAX_UNIT_LENGTH: usize = 16;
+const MAX_VALUE_LENGTH: usize = 64;
+
+#[derive(Deserialize)]
+pub struct AddAttributeForm {
+    name: String,
+    kind: String,
+    #[serde(default)]
+    unit: String,
+}
+
+#[derive(Deserialize)]
+pub struct AttributeForm {
+    id: i32,
+}
+
+/// A product's value for an attribute from what was typed into the dashboard, `None` when it was
+/// left empty
+pub fn parse_value(
+    attribute: &Attribute,
+    product_id: i32,
+    input: &str,
+) -> Result<Option<ProductAttribute>, String> {
+    let input = input.trim();
+    if input.is_empty() {
+        return Ok(None);
+    }
+    let mut value = ProductAttribute {
+        product_id,
+        attribute_id: attribute.id,
+        text_value: None,
+        number_value: None,
+        flag_value: None,
+    };
+    match attribute.kind.as_str() {
+        NUMBER => {
+            value.number_value = Some(
+                input
+                    .parse()
+                    .map_err(|_| format!("{} must be a whole number", attribute.name))?,
+            )
+        }
+        FLAG => {
+            value.flag_value = Some(match input {
+                "yes" => true,
+                "no" => false,
+                _ => return Err(format!("{} must be yes or no", attribute.name)),
+            })
+        }
+        _ => {
+            if input.chars().count() > MAX_VALUE_LENGTH {
+                return Err(format!(
+                    "{} must be at most {} characters",
+                    attribute.name, MAX_VALUE_LENGTH
+                ));
+            }
+            value.text_value = Some(input.to_owned())
+        }
+    }
+    Ok(Some(value))
+}
+
+/// Each attribute with a product's value for it as typed into the dashboard, empty when unset
+pub async fn product_values(
+    conn: &mut AsyncPgConnection,
+    attributes: &[Attribute],
+    product_id: i32,
+) -> QueryResult<Vec<(Attribute, String)>> {
+    let values: Vec<ProductAttribute> = productattributes::table
+        .select(ProductAttribute::as_select())
+        .filter(productattributes::product_id.eq(product_id))
+        .load(conn)
+        .await?;
+    Ok(attributes
+        .iter()
+        .map(|attribute| {
+            let value = values
+                .iter()
+                .find(|v| v.attribute_id == attribute.id)
+                .map(|v| facets::display_value(attribute, v).1)
+                .unwrap_or_default();
+            (attribute.clone(), value)
+        })
+        .collect())
+}
+
+/// New attributes go after the others, their slug is used for them in browse links
+pub async fn handle_add_attribute(
+    jar: CookieJar,
+    State(state): State<AppState>,
+    Form(form): Form<AddAttributeForm>,
+) -> Result<Response, (StatusCode, String)> {
+    let _session = validate_admin(jar, &state.pool).await?;
+    let bad_request = |e: &str| (StatusCode::BAD_REQUEST, String::from(e));
+    let name = form.name.trim();
+    let slug = slugify(name);
+    if slug.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+        return Err(bad_request(
+            "Attribute names must be between 1 and 64 characters",
+        ));
+    }
+    if RESERVED.contains(&slug.as_str()) {
+        return Err(bad_request(
+            "Size, burn time, price and scent family are already filters",
+        ));
+    }
+    if !KINDS.contains(&form.kind.as_str()) {
+        return Err(bad_request("Unknown kind of attribute"));
+    }
+    if form.unit.trim().chars().count() > MAX_UNIT_LENGTH {
+        return Err(bad_request("Units must be at most 16 characters"));
+    }
+    let mut conn = state.pool.get().await.map_err(internal_error)?;
+    let taken: bool = diesel::select(dsl::exists(
+        attributes::table.filter(attributes::name.eq(name).or(attributes::slug.eq(&slug))),
+    ))
+    .get_result(&mut conn)
+    .await
+    .map_err(internal_error)?;
+    if taken {
+        return Err(bad_request("An attribute with that name already exists"));
+    }
+    let last: Option<i32> = attributes::table
+        .select(dsl::max(attributes::position))
+        .first(&mut conn)
+        .await
+        .map_err(internal_error)?;
+    insert_into(attributes::table)
+        .values((
+            attributes::name.eq(name),
+            attributes::slug.eq(&slug),
+            attributes::kind.eq(&form.kind),
+            attributes::unit.eq(form.unit.trim()),
+            attributes::position.eq(last.unwrap_or(0) + 1),
+        ))
+        .execute(&mut conn)
+        .await
+        .map_err(internal_error)?;
+    Ok(AppendHeaders([("HX-Refresh", "true")]).into_response())
+}
+
+/// Removes the attribute from every product, links filtering by it show everything instead
+pub async fn handle_remove_attribute(
+    jar: CookieJar,
+    State(state): State<AppState>,
+    Form(form): Form<AttributeForm>,
+) -> Result<Response, (StatusCode, String)> {
+    let _session = validate_admin(jar, &state.pool).await?;
+    let mut conn = state.pool.get().await.map_err(internal_error)?;
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        async move {
+            delete(productattributes::table)
+                .filter(productattributes::attribute_id.eq(form.id))
+                .execute(conn)
+                .await?;
+            delete(attributes::table)
+                .filter(attributes::id.eq(form.id))
+                .execute(conn)
+                .await?;
+            Ok(())
+        }
+        .scope_boxed()
+    })
+    .await
+    .map_err(internal_error)?;
+    Ok(AppendHeaders([("HX-Refresh", "true")]).into_response())
+}
+
+/// Sets all of a product's attributes at once from fields named `attribute-{id}`, empty fields
+/// clear the value. Nothing changes if any value is wrong
+pub async fn handle_set_attributes(
+    jar: CookieJar,
+    State(state): State<AppState>,
+    Form(fields): Form<Vec<(String, String)>>,
+) -> Result<Response, (StatusCode, String)> {
+    let _session = validate_admin(jar, &state.pool).await?;
+    let product_id: i32 = fields
+        .iter()
+        .find(|(name, _)| name == "product_id")
+        .and_then(|(_, id)| id.parse().ok())
+        .ok_or((StatusCode::BAD_REQUEST, String::from("Incorrect Fields")))?;
+    let mut conn = state.pool.get().await.map_err(internal_error)?;
+    let found: bool = diesel::select(dsl::exists(
+        products::table.filter(products::id.eq(product_id)),
+    ))
+    .get_result(&mut conn)
+    .await
+    .map_err(internal_error)?;
+    if !found {
+        return Err((StatusCode::NOT_FOUND, String::from("404 Not Found")));
+    }
+    let attributes = facets::all_attributes(&mut conn)
+        .await
+        .map_err(internal_error)?;
+    let mut changes = vec![];
+    for attribute in &attributes {
+        let field = format!("attribute-{}", attribute.id);
+        let Some((_, input)) = fields.iter().find(|(name, _)| *name == field) else {
+            continue;
+        };
+        let value =
+            parse_value(attribute, product_id, input).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+        changes.push((attribute.id, value));
+    }
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        async move {
+            for (attribute_id, value) in changes {
+                delete(productattributes::table)
+                    .filter(productattributes::product_id.eq(product_id))
+                    .filter(productattributes::attribute_id.eq(attribute_id))
+                    .execute(conn)
+                    .await?;
+                if let Some(value) = value {
+                    insert_into(productattributes::table)
+                        .values(value)
+                        .execute(conn)
+                        .await?;
+                }
+            }
+            Ok(())
+        }
+        .scope_boxed()
+    })
+    .await
+    .map_err(internal_error)?;
+    Ok(AppendHeaders([("HX-Refresh", "true")]).into_response())
+}
@@ -0,0 +1,617 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::{Cursor, Read},
+};
+
+use askama::Template;
+use axum::{
+    body::Bytes,
+    extract::{Multipart, Query, State},
+    http::{header, StatusCode},
+    response::{Html, IntoResponse, Response},
+};
+use axum_extra::extract::CookieJar;
+use diesel::{
+    delete, insert_into,
+    result::{DatabaseErrorKind, Error},
+    update, ExpressionMethods, QueryDsl, SelectableHelper,
+};
+use diesel_async::{scoped_futures::ScopedFutureExt, AsyncConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+
+use super::{replace_primary_image, validate_admin, AddVariantForm};
+use crate::{
+    db::{
+        models::{NewProduct, NewProductImage, NewProductVariant, Product, ProductVariant},
+        schema::{imageblobs, productimages, productredirects, products, productvariants},
+    },
+    ecom::{is_valid_slug, markdown::MAX_DETAILS_CHARS},
+    images::{self, ProcessedImage},
+    internal_error, AppState,
+};
+
+/// The largest catalogue file or image archive accepted by an import
+pub const MAX_IMPORT_BYTES: usize = 64 * 1024 * 1024;
+
+/// One variant of a product, the product fields are repeated on every row for that product.
+/// `image` is either a path inside the uploaded archive or the key of an already stored image
+#[derive(Serialize, Deserialize)]
+pub struct CatalogRow {
+    pub slug: String,
+    pub title: String,
+    pub description: String,
+    pub image: String,
+    pub sku: String,
+    pub size: String,
+    pub wicks: i32,
+    pub cost: String,
+    /// Markdown, products keep their current details when this is left out or empty
+    #[serde(default)]
+    pub details: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CatalogFormat {
+    Csv,
+    Json,
+}
+
+impl CatalogFormat {
+    fn from_file_name(name: &str) -> Option<Self> {
+        let name = name.to_lowercase();
+        if name.ends_with(".csv") {
+            Some(Self::Csv)
+        } else if name.ends_with(".json") {
+            Some(Self::Json)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ExportQuery {
+    format: CatalogFormat,
+}
+
+#[derive(Template)]
+#[template(path = "catalog_import.html")]
+struct ImportReport {
+    applied: bool,
+    errors: Vec<String>,
+    changes: Vec<String>,
+}
+
+#[derive(Default)]
+struct ImportForm {
+    catalog: Bytes,
+    format: Option<CatalogFormat>,
+    archive: Bytes,
+    apply: bool,
+}
+
+impl ImportForm {
+    async fn parse_from_multipart(mut form: Multipart) -> Result<Self, (StatusCode, String)> {
+        let incorrect = || (StatusCode::BAD_REQUEST, String::from("Incorrect Fields"));
+        let mut ret = Self::default();
+        while let Some(field) = form.next_field().await.map_err(|_| incorrect())? {
+            match field.name() {
+                Some("catalog") => {
+                    ret.format = field.file_name().and_then(CatalogFormat::from_file_name);
+                    ret.catalog = field.bytes().await.map_err(|_| incorrect())?;
+                }
+                Some("archive") => ret.archive = field.bytes().await.map_err(|_| incorrect())?,
+                Some("apply") => ret.apply = field.text().await.map_err(|_| incorrect())? == "true",
+                _ => (),
+            }
+        }
+        if ret.catalog.is_empty() || ret.format.is_none() {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                String::from("Please upload a .csv or .json catalogue"),
+            ));
+        }
+        Ok(ret)
+    }
+}
+
+/// Reads every row of a catalogue, along with where it came from so problems can be reported
+/// against the line or item that caused them
+fn parse_catalog(bytes: &[u8], format: CatalogFormat) -> (Vec<(String, CatalogRow)>, Vec<String>) {
+    let mut rows = vec![];
+    let mut errors = vec![];
+    match format {
+        CatalogFormat::Csv => {
+            let mut reader = csv::Reader::from_reader(bytes);
+            let headers = match reader.headers() {
+                Ok(headers) => headers.clone(),
+                Err(e) => return (rows, vec![format!("Unable to read the header row: {}", e)]),
+            };
+            for record in reader.records() {
+                let (line, row) = match record {
+                    Ok(record) => {
+                        let line = record.position().map_or(0, |p| p.line());
+                        (line, record.deserialize::<CatalogRow>(Some(&headers)))
+                    }
+                    Err(e) => (e.position().map_or(0, |p| p.line()), Err(e)),
+                };
+                match row {
+                    Ok(row) => rows.push((format!("line {}", line), row)),
+                    Err(e) => errors.push(format!("line {}: {}", line, e)),
+                }
+            }
+        }
+        CatalogFormat::Json => {
+            let items: Vec<serde_json::Value> = match serde_json::from_slice(bytes) {
+                Ok(items) => items,
+                Err(e) => return (rows, vec![format!("Expected a JSON array of rows: {}", e)]),
+            };
+            for (i, item) in items.into_iter().enumerate() {
+                match serde_json::from_value::<CatalogRow>(item) {
+                    Ok(row) => rows.push((format!("item {}", i + 1), row)),
+                    Err(e) => errors.push(format!("item {}: {}", i + 1, e)),
+                }
+            }
+        }
+    }
+    (rows, errors)
+}
+
+/// A product from the catalogue along with all of its variants
+struct ProductPlan {
+    location: String,
+    slug: String,
+    title: String,
+    description: String,
+    details: Option<String>,
+    image: String,
+    variants: Vec<NewProductVariant>,
+}
+
+/// Checks each row on its own, then that rows for the same product agree with each other
+fn plan_products(rows: Vec<(String, CatalogRow)>, errors: &mut Vec<String>) -> Vec<ProductPlan> {
+    let mut plans: Vec<ProductPlan> = vec![];
+    let mut skus = HashSet::new();
+    for (location, row) in rows {
+        let slug = row.slug.trim().to_owned();
+        let title = row.title.trim().to_owned();
+        let description = row.description.trim().to_owned();
+        let image = row.image.trim().to_owned();
+        let details = row.details.filter(|d| !d.trim().is_empty());
+        if !is_valid_slug(&slug) {
+            errors.push(format!(
+                "{}: slugs may only contain lowercase letters, numbers and dashes",
+                location
+            ));
+            continue;
+        }
+        if title.is_empty()
+            || description.is_empty()
+            || title.len() > 255
+            || description.len() > 255
+        {
+            errors.push(format!(
+                "{}: please enter a title and description of at most 255 characters",
+                location
+            ));
+            continue;
+        }
+        if details
+            .as_ref()
+            .is_some_and(|d| d.chars().count() > MAX_DETAILS_CHARS)
+        {
+            errors.push(format!(
+                "{}: details can be at most {} characters",
+                location, MAX_DETAILS_CHARS
+            ));
+            continue;
+        }
+        let variant = AddVariantForm {
+            product_id: 0,
+            sku: row.sku,
+            size: row.size,
+            wicks: row.wicks,
+            cost: row.cost,
+            burn_hours: String::new(),
+        }
+        .to_sql_insert();
+        let variant = match variant {
+            Ok(variant) => variant,
+            Err((_, e)) => {
+                errors.push(format!("{}: {}", location, e));
+                continue;
+            }
+        };
+        if !skus.insert(variant.sku.clone()) {
+            errors.push(format!(
+                "{}: SKU {} appears more than once",
+                location, variant.sku
+            ));
+            continue;
+        }
+        match plans.iter_mut().find(|p| p.slug == slug) {
+            Some(plan) => {
+                if plan.title != title
+                    || plan.description != description
+                    || plan.details != details
+                    || plan.image != image
+                {
+                    errors.push(format!(
+                        "{}: the title, description, details and image of {} differ from {}",
+                        location, slug, plan.location
+                    ));
+                    continue;
+                }
+                plan.variants.push(variant);
+            }
+            None => plans.push(ProductPlan {
+                location,
+                slug,
+                title,
+                description,
+                details,
+                image,
+                variants: vec![variant],
+            }),
+        }
+    }
+    plans
+}
+
+/// Reads a single image out of the uploaded archive, refusing anything over the upload limit
+fn read_from_archive(archive: &[u8], name: &str) -> Result<Vec<u8>, String> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(archive))
+        .map_err(|e| format!("the image archive could not be read: {}", e))?;
+    let file = archive
+        .by_name(name)
+        .map_err(|_| format!("{} is not in the image archive", name))?;
+    if file.size() > images::MAX_UPLOAD_BYTES as u64 {
+        return Err(format!("{} is larger than 4MB", name));
+    }
+    let mut bytes = vec![];
+    // the declared size could be wrong, so never read past the limit
+    file.take(images::MAX_UPLOAD_BYTES as u64 + 1)
+        .read_to_end(&mut bytes)
+        .map_err(|e| format!("{} could not be read: {}", name, e))?;
+    if bytes.len() > images::MAX_UPLOAD_BYTES {
+        return Err(format!("{} is larger than 4MB", name));
+    }
+    Ok(bytes)
+}
+
+/// Validates a catalogue against the current products and shows what would change, the changes
+/// are only made when `apply` is set and there are no errors, all in one transaction
+pub async fn handle_import(
+    jar: CookieJar,
+    State(state): State<AppState>,
+    form: Multipart,
+) -> Result<Response, (StatusCode, String)> {
+    let _session = validate_admin(jar, &state.pool).await?;
+    let form = ImportForm::parse_from_multipart(form).await?;
+    let format = form.format.unwrap_or(CatalogFormat::Csv);
+    let (rows, mut errors) = parse_catalog(&form.catalog, format);
+    let plans = plan_products(rows, &mut errors);
+
+    let mut conn = state.pool.get().await.map_err(internal_error)?;
+    let slugs: Vec<&String> = plans.iter().map(|p| &p.slug).collect();
+    let existing: HashMap<String, Product> = products::table
+        .select(products::all_columns)
+        .filter(products::slug.eq_any(slugs))
+        .load::<Product>(&mut conn)
+        .await
+        .map_err(internal_error)?
+        .into_iter()
+        .map(|p| (p.slug.clone(), p))
+        .collect();
+    let skus: Vec<&String> = plans
+        .iter()
+        .flat_map(|p| p.variants.iter().map(|v| &v.sku))
+        .collect();
+    let existing_variants: HashMap<String, (ProductVariant, String)> = productvariants::table
+        .inner_join(products::table)
+        .select((ProductVariant::as_select(), products::slug))
+        .filter(productvariants::sku.eq_any(skus))
+        .load::<(ProductVariant, String)>(&mut conn)
+        .await
+        .map_err(internal_error)?
+        .into_iter()
+        .map(|v| (v.0.sku.clone(), v))
+        .collect();
+    let images_in_catalog: Vec<&String> = plans.iter().map(|p| &p.image).collect();
+    let stored: HashSet<String> = imageblobs::table
+        .select(imageblobs::key)
+        .filter(imageblobs::key.eq_any(images_in_catalog))
+        .load(&mut conn)
+        .await
+        .map_err(internal_error)?
+        .into_iter()
+        .collect();
+
+    let mut changes = vec![];
+    // archive images are decoded up front so a bad image is reported before anything is applied
+    let mut uploads: HashMap<String, ProcessedImage> = HashMap::new();
+    for plan in &plans {
+        for variant in &plan.variants {
+            if let Some((_, owner)) = existing_variants.get(&variant.sku) {
+                if *owner != plan.slug {
+                    errors.push(format!(
+                        "{}: SKU {} belongs to {}",
+                        plan.location, variant.sku, owner
+                    ));
+                }
+            }
+        }
+        let current = existing.get(&plan.slug);
+        let replaces_image = match current {
+            Some(product) => !plan.image.is_empty() && plan.image != product.imgname,
+            None => true,
+        };
+        if replaces_image && !stored.contains(&plan.image) && !uploads.contains_key(&plan.image) {
+            if plan.image.is_empty() {
+                errors.push(format!("{}: new products need an image", plan.location));
+                continue;
+            }
+            let processed = match read_from_archive(&form.archive, &plan.image) {
+                Ok(bytes) => {
+                    let webp = state.webp_images;
+                    tokio::task::spawn_blocking(move || images::process_upload(&bytes, webp))
+                        .await
+                        .map_err(internal_error)?
+                        .map_err(|(_, e)| format!("{}: {}", plan.image, e))
+                }
+                Err(e) => Err(e),
+            };
+            match processed {
+                Ok(processed) => {
+                    uploads.insert(plan.image.clone(), processed);
+                }
+                Err(e) => {
+                    errors.push(format!("{}: {}", plan.location, e));
+                    continue;
+                }
+            }
+        }
+        match current {
+            None => {
+                changes.push(format!("Add product {}: {}", plan.slug, plan.title));
+            }
+            Some(product) => {
+                if product.title != plan.title {
+                    changes.push(format!(
+                        "Change the title of {} from \"{}\" to \"{}\"",
+                        plan.slug, product.title, plan.title
+                    ));
+                }
+                if product.description != plan.description {
+                    changes.push(format!("Change the description of {}", plan.slug));
+                }
+                if plan.details.as_ref().is_some_and(|d| *d != product.details) {
+                    changes.push(format!("Change the details of {}", plan.slug));
+                }
+                if replaces_image {
+                    changes.push(format!(
+                        "Replace the image of {} with {}",
+                        plan.slug, plan.image
+                    ));
+                }
+            }
+        }
+        for variant in &plan.variants {
+            match existing_variants.get(&variant.sku) {
+                None => changes.push(format!(
+                    "Add variant {} to {}: {}, {} wick, {}",
+                    variant.sku, plan.slug, variant.size, variant.wicks, variant.cost
+                )),
+                Some((current, _)) => {
+                    let mut fields = vec![];
+                    if current.size != variant.size {
+                        fields.push(format!("size {} to {}", current.size, variant.size));
+                    }
+                    if current.wicks != variant.wicks {
+                        fields.push(format!("wicks {} to {}", current.wicks, variant.wicks));
+                    }
+                    if current.cost != variant.cost {
+                        fields.push(format!("cost {} to {}", current.cost, variant.cost));
+                    }
+                    if !fields.is_empty() {
+                        changes.push(format!("Change {}: {}", variant.sku, fields.join(", ")));
+                    }
+                }
+            }
+        }
+    }
+
+    if !errors.is_empty() || !form.apply || changes.is_empty() {
+        let html = ImportReport {
+            applied: false,
+            errors,
+            changes,
+        }
+        .render()
+        .unwrap();
+        return Ok(Html(html).into_response());
+    }
+
+    let mut keys: HashMap<String, String> = HashMap::new();
+    for (name, processed) in uploads {
+        match images::store_upload(&mut conn, state.images.as_ref(), processed).await {
+            Ok(key) => {
+                keys.insert(name, key);
+            }
+            Err(e) => {
+                for key in keys.values() {
+                    images::finish_upload(&mut conn, state.images.as_ref(), key).await;
+                }
+                return Err(e);
+            }
+        }
+    }
+    let written: Vec<String> = keys.values().cloned().collect();
+    let result = conn
+        .transaction::<_, Error, _>(|conn| {
+            async move {
+                let mut unreferenced = vec![];
+                for plan in plans {
+                    let imgname = keys.get(&plan.image).unwrap_or(&plan.image);
+                    let product_id = match existing.get(&plan.slug) {
+                        Some(product) => {
+                            update(products::table)
+                                .set((
+                                    products::title.eq(&plan.title),
+                                    products::description.eq(&plan.description),
+                                    products::details
+                                        .eq(plan.details.as_ref().unwrap_or(&product.details)),
+                                ))
+                                .filter(products::id.eq(product.id))
+                                .execute(conn)
+                                .await?;
+                            if !plan.image.is_empty() && plan.image != product.imgname {
+                                unreferenced
+                                    .extend(replace_primary_image(conn, product, imgname).await?);
+                            }
+                            product.id
+                        }
+                        None => {
+                            // a new product takes over the slug if it used to redirect elsewhere
+                            delete(productredirects::table)
+                                .filter(productredirects::slug.eq(&plan.slug))
+                                .execute(conn)
+                                .await?;
+                            let product_id: i32 = insert_into(products::table)
+                                .values(NewProduct {
+                                    id: None,
+                                    title: plan.title.clone(),
+                                    description: plan.description.clone(),
+                                    details: plan.details.clone().unwrap_or_default(),
+                                    imgname: imgname.clone(),
+                                    slug: plan.slug.clone(),
+                                })
+                                .returning(products::id)
+                                .get_result(conn)
+                                .await?;
+                            insert_into(productimages::table)
+                                .values(NewProductImage {
+                                    product_id,
+                                    imgname: imgname.clone(),
+                                    alt_text: plan.title.clone(),
+                                    position: 0,
+                                })
+                                .execute(conn)
+                                .await?;
+                            images::acquire(conn, imgname, 2).await?;
+                            product_id
+                        }
+                    };
+                    for variant in plan.variants {
+                        if existing_variants.contains_key(&variant.sku) {
+                            update(productvariants::table)
+                                .set((
+                                    productvariants::size.eq(variant.size),
+                                    productvariants::wicks.eq(variant.wicks),
+                                    productvariants::cost.eq(variant.cost),
+                                ))
+                                .filter(productvariants::sku.eq(variant.sku))
+                                .execute(conn)
+                                .await?;
+                        } else {
+                            insert_into(productvariants::table)
+                                .values(NewProductVariant {
+                                    product_id,
+                                    ..variant
+                                })
+                                .execute(conn)
+                                .await?;
+                        }
+                    }
+                }
+                Ok(unreferenced)
+            }
+            .scope_boxed()
+        })
+        .await;
+    for key in &written {
+        images::finish_upload(&mut conn, state.images.as_ref(), key).await;
+    }
+    let unreferenced = match result {
+        Ok(unreferenced) => unreferenced,
+        Err(e) => {
+            // a slug or SKU taken since the preview was made
+            return Err(match e {
+                Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => (
+                    StatusCode::CONFLICT,
+                    String::from("The catalogue changed while importing, please preview it again"),
+                ),
+                e => internal_error(e),
+            });
+        }
+    };
+    for img in unreferenced {
+        images::remove_unreferenced(&mut conn, state.images.as_ref(), &img).await;
+    }
+    let html = ImportReport {
+        applied: true,
+        errors,
+        changes,
+    }
+    .render()
+    .unwrap();
+    Ok(Html(html).into_response())
+}
+
+/// Exports every variant in the format accepted by [`handle_import`]
+pub async fn handle_export(
+    jar: CookieJar,
+    State(state): State<AppState>,
+    Query(query): Query<ExportQuery>,
+) -> Result<Response, (StatusCode, String)> {
+    let _session = validate_admin(jar, &state.pool).await?;
+    let mut conn = state.pool.get().await.map_err(internal_error)?;
+    let rows: Vec<CatalogRow> = productvariants::table
+        .inner_join(products::table)
+        .select((products::all_columns, ProductVariant::as_select()))
+        .filter(products::archived_at.is_null())
+        .order((products::id.asc(), productvariants::id.asc()))
+        .load::<(Product, ProductVariant)>(&mut conn)
+        .await
+        .map_err(internal_error)?
+        .into_iter()
+        .map(|(product, variant)| CatalogRow {
+            slug: product.slug,
+            title: product.title,
+            description: product.description,
+            image: product.imgname,
+            sku: variant.sku,
+            size: variant.size,
+            wicks: variant.wicks,
+            cost: variant.cost.amount().to_string(),
+            details: Some(product.details),
+        })
+        .collect();
+    let (body, content_type, file_name) = match query.format {
+        CatalogFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(vec![]);
+            for row in rows {
+                writer.serialize(row).map_err(internal_error)?;
+            }
+            let body = writer.into_inner().map_err(internal_error)?;
+            (body, "text/csv; charset=utf-8", "catalog.csv")
+        }
+        CatalogFormat::Json => (
+            serde_json::to_vec_pretty(&rows).map_err(internal_error)?,
+            "application/json",
+            "catalog.json",
+        ),
+    };
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type.to_owned()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", file_name),
+            ),
+        ],
+        body,
+    )
+        .into_response())
+}
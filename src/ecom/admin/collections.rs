@@ -0,0 +1,300 @@
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{AppendHeaders, IntoResponse, Response},
+};
+use axum_extra::extract::{CookieJar, Form};
+use diesel::{delete, dsl, insert_into, update, ExpressionMethods, OptionalExtension, QueryDsl};
+use diesel_async::{scoped_futures::ScopedFutureExt, AsyncConnection, RunQueryDsl};
+use serde::Deserialize;
+
+use super::{validate_admin, Direction};
+use crate::{
+    db::schema::{collectionproducts, collections, products, sales},
+    ecom::collections::RULES,
+    internal_error, AppState,
+};
+
+const MAX_NAME_LENGTH: usize = 64;
+
+/// The most products one homepage section can show
+const MAX_PRODUCTS: i32 = 24;
+
+#[derive(Deserialize)]
+pub struct AddCollectionForm {
+    name: String,
+    rule: String,
+    max_products: i32,
+}
+
+/// `shown` is a checkbox, so is only sent when ticked
+#[derive(Deserialize)]
+pub struct EditCollectionForm {
+    id: i32,
+    name: String,
+    rule: String,
+    max_products: i32,
+    position: i32,
+    shown: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct CollectionForm {
+    id: i32,
+}
+
+#[derive(Deserialize)]
+pub struct PinForm {
+    collection_id: i32,
+    slug: String,
+}
+
+#[derive(Deserialize)]
+pub struct PinnedForm {
+    collection_id: i32,
+    product_id: i32,
+}
+
+#[derive(Deserialize)]
+pub struct MovePinnedForm {
+    collection_id: i32,
+    product_id: i32,
+    direction: Direction,
+}
+
+fn check_fields(name: &str, rule: &str, max_products: i32) -> Result<String, (StatusCode, String)> {
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!(
+                "Collection names must be between 1 and {} characters",
+                MAX_NAME_LENGTH
+            ),
+        ));
+    }
+    if !RULES.contains(&rule) {
+        return Err((StatusCode::BAD_REQUEST, String::from("Unknown rule")));
+    }
+    if !(1..=MAX_PRODUCTS).contains(&max_products) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!(
+                "A collection can show between 1 and {} products",
+                MAX_PRODUCTS
+            ),
+        ));
+    }
+    Ok(name.to_owned())
+}
+
+/// New collections go to the bottom of the homepage
+pub async fn handle_add_collection(
+    jar: CookieJar,
+    State(state): State<AppState>,
+    Form(form): Form<AddCollectionForm>,
+) -> Result<Response, (StatusCode, String)> {
+    let _session = validate_admin(jar, &state.pool).await?;
+    let name = check_fields(&form.name, &form.rule, form.max_products)?;
+    let mut conn = state.pool.get().await.map_err(internal_error)?;
+    let last: Option<i32> = collections::table
+        .select(dsl::max(collections::position))
+        .first(&mut conn)
+        .await
+        .map_err(internal_error)?;
+    insert_into(collections::table)
+        .values((
+            collections::name.eq(name),
+            collections::rule.eq(&form.rule),
+            collections::max_products.eq(form.max_products),
+            collections::position.eq(last.unwrap_or(0) + 1),
+        ))
+        .execute(&mut conn)
+        .await
+        .map_err(internal_error)?;
+    state.homepage.invalidate();
+    Ok(AppendHeaders([("HX-Refresh", "true")]).into_response())
+}
+
+pub async fn handle_edit_collection(
+    jar: CookieJar,
+    State(state): State<AppState>,
+    Form(form): Form<EditCollectionForm>,
+) -> Result<Response, (StatusCode, String)> {
+    let _session = validate_admin(jar, &state.pool).await?;
+    let name = check_fields(&form.name, &form.rule, form.max_products)?;
+    let mut conn = state.pool.get().await.map_err(internal_error)?;
+    let updated = update(collections::table)
+        .filter(collections::id.eq(form.id))
+        .set((
+            collections::name.eq(name),
+            collections::rule.eq(&form.rule),
+            collections::max_products.eq(form.max_products),
+            collections::position.eq(form.position),
+            collections::shown.eq(form.shown.is_some()),
+        ))
+        .execute(&mut conn)
+        .await
+        .map_err(internal_error)?;
+    if updated == 0 {
+        return Err((StatusCode::NOT_FOUND, String::from("404 Not Found")));
+    }
+    state.homepage.invalidate();
+    Ok(AppendHeaders([("HX-Refresh", "true")]).into_response())
+}
+
+pub async fn handle_remove_collection(
+    jar: CookieJar,
+    State(state): State<AppState>,
+    Form(form): Form<CollectionForm>,
+) -> Result<Response, (StatusCode, String)> {
+    let _session = validate_admin(jar, &state.pool).await?;
+    let mut conn = state.pool.get().await.map_err(internal_error)?;
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        async move {
+            // its sales end with it
+            delete(sales::table)
+                .filter(sales::collection_id.eq(form.id))
+                .execute(conn)
+                .await?;
+            delete(collectionproducts::table)
+                .filter(collectionproducts::collection_id.eq(form.id))
+                .execute(conn)
+                .await?;
+            delete(collections::table)
+                .filter(collections::id.eq(form.id))
+                .execute(conn)
+                .await?;
+            Ok(())
+        }
+        .scope_boxed()
+    })
+    .await
+    .map_err(internal_error)?;
+    state.homepage.invalidate();
+    Ok(AppendHeaders([("HX-Refresh", "true")]).into_response())
+}
+
+/// Pins a product to the end of a collection. Unlisted products can be pinned ahead of time, they
+/// are only shown once they are for sale
+pub async fn handle_pin_product(
+    jar: CookieJar,
+    State(state): State<AppState>,
+    Form(form): Form<PinForm>,
+) -> Result<Response, (StatusCode, String)> {
+    let _session = validate_admin(jar, &state.pool).await?;
+    let mut conn = state.pool.get().await.map_err(internal_error)?;
+    let product_id: i32 = products::table
+        .select(products::id)
+        .filter(products::slug.eq(form.slug.trim()))
+        .filter(products::archived_at.is_null())
+        .first(&mut conn)
+        .await
+        .optional()
+        .map_err(internal_error)?
+        .ok_or((
+            StatusCode::NOT_FOUND,
+            String::from("No product has that slug"),
+        ))?;
+    let found: Option<i32> = collections::table
+        .select(collections::id)
+        .filter(collections::id.eq(form.collection_id))
+        .first(&mut conn)
+        .await
+        .optional()
+        .map_err(internal_error)?;
+    if found.is_none() {
+        return Err((StatusCode::NOT_FOUND, String::from("404 Not Found")));
+    }
+    let last: Option<i32> = collectionproducts::table
+        .select(dsl::max(collectionproducts::position))
+        .filter(collectionproducts::collection_id.eq(form.collection_id))
+        .first(&mut conn)
+        .await
+        .map_err(internal_error)?;
+    insert_into(collectionproducts::table)
+        .values((
+            collectionproducts::collection_id.eq(form.collection_id),
+            collectionproducts::product_id.eq(product_id),
+            collectionproducts::position.eq(last.unwrap_or(0) + 1),
+        ))
+        .on_conflict_do_nothing()
+        .execute(&mut conn)
+        .await
+        .map_err(internal_error)?;
+    state.homepage.invalidate();
+    Ok(AppendHeaders([("HX-Refresh", "true")]).into_response())
+}
+
+pub async fn handle_unpin_product(
+    jar: CookieJar,
+    State(state): State<AppState>,
+    Form(form): Form<PinnedForm>,
+) -> Result<Response, (StatusCode, String)> {
+    let _session = validate_admin(jar, &state.pool).await?;
+    let mut conn = state.pool.get().await.map_err(internal_error)?;
+    delete(collectionproducts::table)
+        .filter(collectionproducts::collection_id.eq(form.collection_id))
+        .filter(collectionproducts::product_id.eq(form.product_id))
+        .execute(&mut conn)
+        .await
+        .map_err(internal_error)?;
+    state.homepage.invalidate();
+    Ok(AppendHeaders([("HX-Refresh", "true")]).into_response())
+}
+
+/// Swaps a pinned product with the one before or after it, like images in a gallery
+pub async fn handle_move_pinned(
+    jar: CookieJar,
+    State(state): State<AppState>,
+    Form(form): Form<MovePinnedForm>,
+) -> Result<Response, (StatusCode, String)> {
+    let _session = validate_admin(jar, &state.pool).await?;
+    let mut conn = state.pool.get().await.map_err(internal_error)?;
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        async move {
+            let position: i32 = collectionproducts::table
+                .select(collectionproducts::position)
+                .filter(collectionproducts::collection_id.eq(form.collection_id))
+                .filter(collectionproducts::product_id.eq(form.product_id))
+                .first(conn)
+                .await?;
+            let neighbour = collectionproducts::table
+                .select((collectionproducts::product_id, collectionproducts::position))
+                .filter(collectionproducts::collection_id.eq(form.collection_id))
+                .into_boxed();
+            let neighbour = match form.direction {
+                Direction::Up => neighbour
+                    .filter(collectionproducts::position.lt(position))
+                    .order(collectionproducts::position.desc()),
+                Direction::Down => neighbour
+                    .filter(collectionproducts::position.gt(position))
+                    .order(collectionproducts::position.asc()),
+            };
+            // already at the start or end of the collection
+            let Some((neighbour_id, neighbour_position)) =
+                neighbour.first::<(i32, i32)>(conn).await.optional()?
+            else {
+                return Ok(());
+            };
+            update(collectionproducts::table)
+                .set(collectionproducts::position.eq(neighbour_position))
+                .filter(collectionproducts::collection_id.eq(form.collection_id))
+                .filter(collectionproducts::product_id.eq(form.product_id))
+                .execute(conn)
+                .await?;
+            update(collectionproducts::table)
+                .set(collectionproducts::position.eq(position))
+                .filter(collectionproducts::collection_id.eq(form.collection_id))
+                .filter(collectionproducts::product_id.eq(neighbour_id))
+                .execute(conn)
+                .await?;
+            Ok(())
+        }
+        .scope_boxed()
+    })
+    .await
+    .map_err(internal_error)?;
+    state.homepage.invalidate();
+    Ok(AppendHeaders([("HX-Refresh", "true")]).into_response())
+}
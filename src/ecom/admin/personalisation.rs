@@ -0,0 +1,153 @@
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{AppendHeaders, IntoResponse, Response},
+};
+use axum_extra::extract::{CookieJar, Form};
+use diesel::{delete, dsl, insert_into, ExpressionMethods, QueryDsl};
+use diesel_async::RunQueryDsl;
+use serde::Deserialize;
+
+use super::validate_admin;
+use crate::{
+    db::schema::{personalisationfields, products},
+    ecom::personalisation::{CHOICE, KINDS, MAX_LENGTH},
+    internal_error,
+    money::{Currency, Money},
+    AppState,
+};
+
+const MAX_NAME_LENGTH: usize = 64;
+const MAX_CHOICES_LENGTH: usize = 255;
+
+/// How long typed in text can be when no limit is given
+const DEFAULT_MAX_LENGTH: i32 = 64;
+
+/// `required` is a checkbox, so is only sent when ticked. `max_length` is only used by text
+/// fields and `choices` only by choice fields
+#[derive(Deserialize)]
+pub struct AddFieldForm {
+    product_id: i32,
+    name: String,
+    kind: String,
+    max_length: Option<i32>,
+    #[serde(default)]
+    choices: String,
+    #[serde(default)]
+    surcharge: String,
+    required: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct FieldForm {
+    id: i32,
+}
+
+/// New fields go after the product's others, an empty surcharge is free
+pub async fn handle_add_field(
+    jar: CookieJar,
+    State(state): State<AppState>,
+    Form(form): Form<AddFieldForm>,
+) -> Result<Response, (StatusCode, String)> {
+    let _session = validate_admin(jar, &state.pool).await?;
+    let bad_request = |e: String| (StatusCode::BAD_REQUEST, e);
+    let name = form.name.trim();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+        return Err(bad_request(format!(
+            "Field names must be between 1 and {} characters",
+            MAX_NAME_LENGTH
+        )));
+    }
+    if !KINDS.contains(&form.kind.as_str()) {
+        return Err(bad_request(String::from("Unknown kind of field")));
+    }
+    let max_length = form.max_length.unwrap_or(DEFAULT_MAX_LENGTH);
+    if !(1..=MAX_LENGTH).contains(&max_length) {
+        return Err(bad_request(format!(
+            "Text can be limited to between 1 and {} characters",
+            MAX_LENGTH
+        )));
+    }
+    let choices: Vec<&str> = form
+        .choices
+        .split(',')
+        .map(str::trim)
+        .filter(|c| !c.is_empty())
+        .collect();
+    let choices = choices.join(", ");
+    if form.kind == CHOICE && (choices.is_empty() || choices.chars().count() > MAX_CHOICES_LENGTH) {
+        return Err(bad_request(format!(
+            "Give between 1 and {} characters of comma separated choices",
+            MAX_CHOICES_LENGTH
+        )));
+    }
+    let surcharge = match form.surcharge.trim() {
+        "" => Money::zero(Currency::BASE),
+        surcharge => Money::parse(surcharge, Currency::BASE).map_err(bad_request)?,
+    };
+    if surcharge != Money::zero(Currency::BASE) && !surcharge.is_valid_price() {
+        return Err(bad_request(String::from(
+            "Surcharges must be between £0.00 and £99,999,999.99",
+        )));
+    }
+    let mut conn = state.pool.get().await.map_err(internal_error)?;
+    let found: bool = diesel::select(dsl::exists(
+        products::table.filter(products::id.eq(form.product_id)),
+    ))
+    .get_result(&mut conn)
+    .await
+    .map_err(internal_error)?;
+    if !found {
+        return Err((StatusCode::NOT_FOUND, String::from("404 Not Found")));
+    }
+    let taken: bool = diesel::select(dsl::exists(
+        personalisationfields::table
+            .filter(personalisationfields::product_id.eq(form.product_id))
+            .filter(personalisationfields::name.eq(name)),
+    ))
+    .get_result(&mut conn)
+    .await
+    .map_err(internal_error)?;
+    if taken {
+        return Err(bad_request(String::from(
+            "This product already has a field with that name",
+        )));
+    }
+    let last: Option<i32> = personalisationfields::table
+        .select(dsl::max(personalisationfields::position))
+        .filter(personalisationfields::product_id.eq(form.product_id))
+        .first(&mut conn)
+        .await
+        .map_err(internal_error)?;
+    insert_into(personalisationfields::table)
+        .values((
+            personalisationfields::product_id.eq(form.product_id),
+            personalisationfields::name.eq(name),
+            personalisationfields::kind.eq(&form.kind),
+            personalisationfields::max_length.eq(max_length),
+            personalisationfields::choices.eq(choices),
+            personalisationfields::surcharge.eq(surcharge),
+            personalisationfields::required.eq(form.required.is_some()),
+            personalisationfields::position.eq(last.unwrap_or(0) + 1),
+        ))
+        .execute(&mut conn)
+        .await
+        .map_err(internal_error)?;
+    Ok(AppendHeaders([("HX-Refresh", "true")]).into_response())
+}
+
+/// Carts and orders keep what was already filled in for the field
+pub async fn handle_remove_field(
+    jar: CookieJar,
+    State(state): State<AppState>,
+    Form(form): Form<FieldForm>,
+) -> Result<Response, (StatusCode, String)> {
+    let _session = validate_admin(jar, &state.pool).await?;
+    let mut conn = state.pool.get().await.map_err(internal_error)?;
+    delete(personalisationfields::table)
+        .filter(personalisationfields::id.eq(form.id))
+        .execute(&mut conn)
+        .await
+        .map_err(internal_error)?;
+    Ok(AppendHeaders([("HX-Refresh", "true")]).into_response())
+}
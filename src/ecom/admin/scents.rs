@@ -0,0 +1,183 @@
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{AppendHeaders, IntoResponse, Response},
+};
+use axum_extra::extract::{CookieJar, Form};
+use diesel::{delete, dsl, insert_into, ExpressionMethods, QueryDsl};
+use diesel_async::{scoped_futures::ScopedFutureExt, AsyncConnection, RunQueryDsl};
+use serde::Deserialize;
+
+use super::validate_admin;
+use crate::{
+    db::{
+        models::ScentNote,
+        schema::{products, productscentnotes, scentnotes},
+    },
+    ecom::scents::{self, BASE, FAMILIES, MIDDLE, TOP},
+    internal_error, AppState,
+};
+
+const MAX_NAME_LENGTH: usize = 64;
+
+#[derive(Deserialize)]
+pub struct AddNoteForm {
+    name: String,
+    family: String,
+}
+
+#[derive(Deserialize)]
+pub struct NoteForm {
+    id: i32,
+}
+
+/// Each tier is a comma separated list of note names
+#[derive(Deserialize)]
+pub struct ScentNotesForm {
+    product_id: i32,
+    top: String,
+    middle: String,
+    base: String,
+}
+
+/// Matches the names in a tier's list to notes, ignoring case. Each note can only be in one tier
+/// of a product
+pub fn parse_notes(
+    notes: &[ScentNote],
+    tiers: &[(&'static str, &str)],
+) -> Result<Vec<(i32, &'static str)>, String> {
+    let mut parsed: Vec<(i32, &'static str)> = vec![];
+    for (tier, list) in tiers {
+        for name in list.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+            let note = notes
+                .iter()
+                .find(|n| n.name.eq_ignore_ascii_case(name))
+                .ok_or(format!("Unknown scent note: {}", name))?;
+            if parsed.iter().any(|(id, _)| *id == note.id) {
+                return Err(format!("{} can only be in one tier", note.name));
+            }
+            parsed.push((note.id, *tier));
+        }
+    }
+    Ok(parsed)
+}
+
+pub async fn handle_add_note(
+    jar: CookieJar,
+    State(state): State<AppState>,
+    Form(form): Form<AddNoteForm>,
+) -> Result<Response, (StatusCode, String)> {
+    let _session = validate_admin(jar, &state.pool).await?;
+    let name = form.name.trim();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH || name.contains(',') {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!(
+                "Note names must be between 1 and {} characters without commas",
+                MAX_NAME_LENGTH
+            ),
+        ));
+    }
+    if !FAMILIES.contains(&form.family.as_str()) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            String::from("Unknown scent family"),
+        ));
+    }
+    let mut conn = state.pool.get().await.map_err(internal_error)?;
+    let notes = scents::all_notes(&mut conn).await.map_err(internal_error)?;
+    if notes.iter().any(|n| n.name.eq_ignore_ascii_case(name)) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            String::from("That scent note already exists"),
+        ));
+    }
+    insert_into(scentnotes::table)
+        .values((
+            scentnotes::name.eq(name),
+            scentnotes::family.eq(&form.family),
+        ))
+        .execute(&mut conn)
+        .await
+        .map_err(internal_error)?;
+    Ok(AppendHeaders([("HX-Refresh", "true")]).into_response())
+}
+
+/// Takes the note out of every product's scent profile
+pub async fn handle_remove_note(
+    jar: CookieJar,
+    State(state): State<AppState>,
+    Form(form): Form<NoteForm>,
+) -> Result<Response, (StatusCode, String)> {
+    let _session = validate_admin(jar, &state.pool).await?;
+    let mut conn = state.pool.get().await.map_err(internal_error)?;
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        async move {
+            delete(productscentnotes::table)
+                .filter(productscentnotes::note_id.eq(form.id))
+                .execute(conn)
+                .await?;
+            delete(scentnotes::table)
+                .filter(scentnotes::id.eq(form.id))
+                .execute(conn)
+                .await?;
+            Ok(())
+        }
+        .scope_boxed()
+    })
+    .await
+    .map_err(internal_error)?;
+    Ok(AppendHeaders([("HX-Refresh", "true")]).into_response())
+}
+
+/// Replaces a product's whole scent profile
+pub async fn handle_set_notes(
+    jar: CookieJar,
+    State(state): State<AppState>,
+    Form(form): Form<ScentNotesForm>,
+) -> Result<Response, (StatusCode, String)> {
+    let _session = validate_admin(jar, &state.pool).await?;
+    let mut conn = state.pool.get().await.map_err(internal_error)?;
+    let found: bool = diesel::select(dsl::exists(
+        products::table.filter(products::id.eq(form.product_id)),
+    ))
+    .get_result(&mut conn)
+    .await
+    .map_err(internal_error)?;
+    if !found {
+        return Err((StatusCode::NOT_FOUND, String::from("404 Not Found")));
+    }
+    let notes = scents::all_notes(&mut conn).await.map_err(internal_error)?;
+    let parsed = parse_notes(
+        &notes,
+        &[(TOP, &form.top), (MIDDLE, &form.middle), (BASE, &form.base)],
+    )
+    .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        async move {
+            delete(productscentnotes::table)
+                .filter(productscentnotes::product_id.eq(form.product_id))
+                .execute(conn)
+                .await?;
+            let rows: Vec<_> = parsed
+                .into_iter()
+                .map(|(note_id, tier)| {
+                    (
+                        productscentnotes::product_id.eq(form.product_id),
+                        productscentnotes::note_id.eq(note_id),
+                        productscentnotes::tier.eq(tier),
+                    )
+                })
+                .collect();
+            insert_into(productscentnotes::table)
+                .values(rows)
+                .execute(conn)
+                .await?;
+            Ok(())
+        }
+        .scope_boxed()
+    })
+    .await
+    .map_err(internal_error)?;
+    Ok(AppendHeaders([("HX-Refresh", "true")]).into_response())
+}
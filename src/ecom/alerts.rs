@@ -0,0 +1,454 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use askama::Template;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{Html, IntoResponse},
+};
+use axum_extra::extract::{CookieJar, Form};
+use diesel::{
+    delete, dsl::exists, insert_into, update, BoolExpressionMethods, ExpressionMethods, JoinOnDsl,
+    NullableExpressionMethods, OptionalExtension, QueryDsl, QueryResult, SelectableHelper,
+};
+use diesel_async::{
+    pooled_connection::deadpool::Pool, scoped_futures::ScopedFutureExt, AsyncConnection,
+    AsyncPgConnection, RunQueryDsl,
+};
+use time::OffsetDateTime;
+
+use crate::{
+    auth::session::validate_session,
+    db::{
+        models::{NewNotification, Notification, ProductAlert},
+        schema::{likedproducts, notifications, productalerts, products, productvariants, users},
+    },
+    internal_error,
+    mailer::{Mail, Mailer},
+    money::Money,
+    AppState, SESSION_COOKIE_NAME,
+};
+
+use super::{generate_token, sales, schedule, site_url, Action, LikeAction};
+
+/// How often liked products are checked for changes worth telling customers about
+const CHECK_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Notifications shown on the notifications page
+const MAX_NOTIFICATIONS: i64 = 50;
+
+/// What a customer could see of a product
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Snapshot {
+    pub listed: bool,
+    pub in_stock: bool,
+    /// The cheapest listed variant including any sale
+    pub price: Option<Money>,
+}
+
+impl From<&ProductAlert> for Snapshot {
+    fn from(alert: &ProductAlert) -> Self {
+        Snapshot {
+            listed: alert.was_listed,
+            in_stock: alert.was_in_stock,
+            price: alert.last_price.clone(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Event {
+    Relisted,
+    BackInStock,
+    PriceDrop { was: Money, now: Money },
+}
+
+impl Event {
+    fn message(&self, title: &str) -> String {
+        match self {
+            Event::Relisted => format!("{} is back on sale", title),
+            Event::BackInStock => format!("{} is back in stock", title),
+            Event::PriceDrop { was, now } => {
+                format!("{} has dropped in price from {} to {}", title, was, now)
+            }
+        }
+    }
+}
+
+/// The changes worth telling a customer about between what they were last told and what the
+/// product looks like now. Coming back on sale covers coming back in stock, and nothing is
+/// announced while the product can't be bought
+pub fn events(before: &Snapshot, after: &Snapshot) -> Vec<Event> {
+    let mut events = vec![];
+    if !after.listed {
+        return events;
+    }
+    if !before.listed {
+        events.push(Event::Relisted);
+    } else if after.in_stock && !before.in_stock {
+        events.push(Event::BackInStock);
+    }
+    if let (Some(was), Some(now)) = (&before.price, &after.price) {
+        if now.amount() < was.amount() {
+            events.push(Event::PriceDrop {
+                was: was.clone(),
+                now: now.clone(),
+            });
+        }
+    }
+    events
+}
+
+/// What to remember of a product after checking it. While it can't be bought only that is
+/// remembered, so changes to its stock and price are announced once it is back on sale
+fn seen(before: &Snapshot, after: &Snapshot) -> Snapshot {
+    if after.listed {
+        after.clone()
+    } else {
+        Snapshot {
+            listed: false,
+            ..before.clone()
+        }
+    }
+}
+
+/// How each of `ids` looks to customers right now
+pub async fn snapshots(
+    conn: &mut AsyncPgConnection,
+    ids: &[i32],
+) -> QueryResult<HashMap<i32, Snapshot>> {
+    let live: Vec<i32> = products::table
+        .select(products::id)
+        .filter(products::id.eq_any(ids))
+        .filter(schedule::is_live())
+        .load(conn)
+        .await?;
+    let variants: Vec<(i32, Money, i32)> = productvariants::table
+        .select((
+            productvariants::product_id,
+            productvariants::cost,
+            productvariants::stock,
+        ))
+        .filter(productvariants::product_id.eq_any(ids))
+        .filter(productvariants::listed.eq(true))
+        .load(conn)
+        .await?;
+    let sales = sales::current_sales(conn, ids).await?;
+    let mut snapshots = HashMap::new();
+    for id in ids {
+        let variants = variants.iter().filter(|v| v.0 == *id);
+        let price = variants
+            .clone()
+            .map(|v| sales::current_price(&sales, *id, &v.1))
+            .min_by(|a, b| a.amount().cmp(b.amount()));
+        snapshots.insert(
+            *id,
+            Snapshot {
+                listed: live.contains(id),
+                in_stock: variants.clone().any(|v| v.2 > 0),
+                price,
+            },
+        );
+    }
+    Ok(snapshots)
+}
+
+/// Starts alerting a customer about a product, from how it looks now
+pub async fn subscribe(
+    conn: &mut AsyncPgConnection,
+    user_id: i32,
+    product_id: i32,
+) -> QueryResult<()> {
+    let snapshot = snapshots(conn, &[product_id])
+        .await?
+        .remove(&product_id)
+        .unwrap_or(Snapshot {
+            listed: false,
+            in_stock: false,
+            price: None,
+        });
+    insert_into(productalerts::table)
+        .values(ProductAlert {
+            user_id,
+            product_id,
+            token: generate_token(),
+            was_listed: snapshot.listed,
+            was_in_stock: snapshot.in_stock,
+            last_price: snapshot.price,
+        })
+        .on_conflict_do_nothing()
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// Compares every alert with its product, records a notification for each change and emails it.
+/// The new state is saved along with the notification so each change is only announced once,
+/// an email that fails to send is not retried as the notification is still in the app.
+/// An alert that can't be saved is skipped so it doesn't hold up the others, returns how many
+/// emails were sent
+pub async fn check(conn: &mut AsyncPgConnection, mailer: &dyn Mailer) -> QueryResult<usize> {
+    let alerts: Vec<(ProductAlert, String, String, String)> = productalerts::table
+        .inner_join(users::table)
+        .inner_join(products::table)
+        .select((
+            ProductAlert::as_select(),
+            users::email,
+            products::title,
+            products::slug,
+        ))
+        .load(conn)
+        .await?;
+    let mut ids: Vec<i32> = alerts.iter().map(|a| a.0.product_id).collect();
+    ids.sort();
+    ids.dedup();
+    let snapshots = snapshots(conn, &ids).await?;
+    let site_url = site_url();
+    let mut sent = 0;
+    for (alert, email, title, slug) in alerts {
+        let Some(now) = snapshots.get(&alert.product_id) else {
+            continue;
+        };
+        let before = Snapshot::from(&alert);
+        let now = seen(&before, now);
+        if before == now {
+            continue;
+        }
+        let events = events(&before, &now);
+        let saved = conn.transaction::<_, diesel::result::Error, _>(|conn| {
+            let alert = alert.clone();
+            let messages: Vec<NewNotification> = events
+                .iter()
+                .map(|e| NewNotification {
+                    user_id: alert.user_id,
+                    product_id: alert.product_id,
+                    message: e.message(&title),
+                })
+                .collect();
+            async move {
+                update(productalerts::table)
+                    .filter(productalerts::user_id.eq(alert.user_id))
+                    .filter(productalerts::product_id.eq(alert.product_id))
+                    .set((
+                        productalerts::was_listed.eq(now.listed),
+                        productalerts::was_in_stock.eq(now.in_stock),
+                        productalerts::last_price.eq(now.price),
+                    ))
+                    .execute(conn)
+                    .await?;
+                if !messages.is_empty() {
+                    insert_into(notifications::table)
+                        .values(messages)
+                        .execute(conn)
+                        .await?;
+                }
+                Ok(())
+            }
+            .scope_boxed()
+        })
+        .await;
+        if let Err(e) = saved {
+            tracing::warn!("unable to save an alert for {}: {}", email, e);
+            continue;
+        }
+        for event in events {
+            let mail = Mail {
+                to: email.clone(),
+                subject: event.message(&title),
+                body: format!(
+                    "{}.\n\nTake a look: {}/browse/{}\n\nTo stop hearing about {}: {}/alerts/unsubscribe/{}",
+                    event.message(&title),
+                    site_url,
+                    slug,
+                    title,
+                    site_url,
+                    alert.token
+                ),
+            };
+            match mailer.send(&mail).await {
+                Ok(()) => sent += 1,
+                Err(e) => tracing::warn!("unable to email an alert to {}: {}", mail.to, e),
+            }
+        }
+    }
+    Ok(sent)
+}
+
+/// Checks alerts for as long as the server runs
+pub async fn check_periodically(pool: Pool<AsyncPgConnection>, mailer: Arc<dyn Mailer>) {
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let result = match pool.get().await {
+            Ok(mut conn) => check(&mut conn, mailer.as_ref())
+                .await
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+        if let Err(e) = result {
+            tracing::warn!("unable to check product alerts: {}", e);
+        }
+    }
+}
+
+pub async fn alert_post_handler(
+    jar: CookieJar,
+    State(state): State<AppState>,
+    Form(payload): Form<LikeAction>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let session_cookie = jar
+        .get(SESSION_COOKIE_NAME)
+        .ok_or((StatusCode::UNAUTHORIZED, String::from("401 unauthorized")))?;
+    let session = validate_session(session_cookie.value().to_owned(), &state.pool).await?;
+    let mut conn = state.pool.get().await.map_err(internal_error)?;
+    match payload.action {
+        Action::Add => {
+            let liked: bool = diesel::select(exists(
+                likedproducts::table
+                    .filter(likedproducts::user_id.eq(session.user_id))
+                    .filter(likedproducts::product_id.eq(payload.product_id)),
+            ))
+            .get_result(&mut conn)
+            .await
+            .map_err(internal_error)?;
+            if !liked {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    String::from("Add this product to your favourites to get alerts"),
+                ));
+            }
+            subscribe(&mut conn, session.user_id, payload.product_id)
+                .await
+                .map_err(internal_error)?;
+            Ok("Alerts on ✔")
+        }
+        Action::Remove => {
+            delete(productalerts::table)
+                .filter(productalerts::user_id.eq(session.user_id))
+                .filter(productalerts::product_id.eq(payload.product_id))
+                .execute(&mut conn)
+                .await
+                .map_err(internal_error)?;
+            Ok("Alerts off ✔")
+        }
+    }
+}
+
+#[derive(Template)]
+#[template(path = "unsubscribe.html")]
+struct UnsubscribeTemplate {
+    logged_in: bool,
+    token: String,
+    /// `None` once the alert has been turned off
+    title: Option<String>,
+}
+
+async fn alert_title(conn: &mut AsyncPgConnection, token: &str) -> QueryResult<Option<String>> {
+    productalerts::table
+        .inner_join(products::table)
+        .select(products::title)
+        .filter(productalerts::token.eq(token))
+        .first(conn)
+        .await
+        .optional()
+}
+
+/// Asks before turning an alert off, so link checkers that follow email links don't turn it off
+pub async fn unsubscribe_page(
+    jar: CookieJar,
+    Path(token): Path<String>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let mut conn = state.pool.get().await.map_err(internal_error)?;
+    let template = UnsubscribeTemplate {
+        logged_in: jar.get(SESSION_COOKIE_NAME).is_some(),
+        title: alert_title(&mut conn, &token)
+            .await
+            .map_err(internal_error)?,
+        token,
+    };
+    Ok(Html(template.render().unwrap()))
+}
+
+/// Turns an alert off from the link in its email, no sign in is needed as the token is secret
+pub async fn unsubscribe(
+    Path(token): Path<String>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let mut conn = state.pool.get().await.map_err(internal_error)?;
+    let title = alert_title(&mut conn, &token)
+        .await
+        .map_err(internal_error)?;
+    delete(productalerts::table)
+        .filter(productalerts::token.eq(&token))
+        .execute(&mut conn)
+        .await
+        .map_err(internal_error)?;
+    Ok(match title {
+        Some(title) => format!("You won't hear about {} any more", title),
+        None => String::from("This alert has already been turned off"),
+    })
+}
+
+pub struct NotificationEntry {
+    pub notification: Notification,
+    pub slug: String,
+    /// Present while the customer still gets alerts for the product
+    pub token: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "notifications.html")]
+struct NotificationsTemplate {
+    logged_in: bool,
+    notifications: Vec<NotificationEntry>,
+}
+
+/// The customer's most recent notifications, which are marked as read once shown
+pub async fn notifications_page(
+    jar: CookieJar,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let session_cookie = jar
+        .get(SESSION_COOKIE_NAME)
+        .ok_or((StatusCode::UNAUTHORIZED, String::from("401 unauthorized")))?;
+    let session = validate_session(session_cookie.value().to_owned(), &state.pool).await?;
+    let mut conn = state.pool.get().await.map_err(internal_error)?;
+    let rows: Vec<(Notification, String, Option<String>)> = notifications::table
+        .inner_join(products::table)
+        .left_join(
+            productalerts::table.on(productalerts::product_id
+                .eq(notifications::product_id)
+                .and(productalerts::user_id.eq(notifications::user_id))),
+        )
+        .select((
+            Notification::as_select(),
+            products::slug,
+            productalerts::token.nullable(),
+        ))
+        .filter(notifications::user_id.eq(session.user_id))
+        .order(notifications::created_at.desc())
+        .limit(MAX_NOTIFICATIONS)
+        .load(&mut conn)
+        .await
+        .map_err(internal_error)?;
+    update(notifications::table)
+        .filter(notifications::user_id.eq(session.user_id))
+        .filter(notifications::read_at.is_null())
+        .set(notifications::read_at.eq(OffsetDateTime::now_utc()))
+        .execute(&mut conn)
+        .await
+        .map_err(internal_error)?;
+    let template = NotificationsTemplate {
+        logged_in: true,
+        notifications: rows
+            .into_iter()
+            .map(|(notification, slug, token)| NotificationEntry {
+                notification,
+                slug,
+                token,
+            })
+            .collect(),
+    };
+    Ok(Html(template.render().unwrap()))
+}
@@ -0,0 +1,182 @@
+use std::{
+    sync::RwLock,
+    time::{Duration, Instant},
+};
+
+use axum::http::StatusCode;
+use diesel::{dsl, ExpressionMethods, QueryDsl, QueryResult, SelectableHelper};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+
+use crate::{
+    db::{
+        models::{Collection, Product},
+        schema::{collectionproducts, collections, productorders, products, productvariants},
+    },
+    internal_error,
+};
+
+use super::{product_cards, schedule, ProductCard};
+
+/// Only the pinned products are shown
+pub const MANUAL: &str = "manual";
+/// Places left after the pinned products go to the most recently added products
+pub const NEWEST: &str = "newest";
+/// Places left after the pinned products go to the products with the most units sold
+pub const BESTSELLERS: &str = "bestsellers";
+pub const RULES: &[&str] = &[MANUAL, NEWEST, BESTSELLERS];
+
+/// How long the homepage sections are kept before being worked out again. Each server keeps its
+/// own copy, admin changes clear the copy on the server that made them straight away and other
+/// servers show the old sections for up to this long
+const CACHE_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// A collection as it appears on the homepage
+#[derive(Clone, Debug)]
+pub struct Section {
+    pub name: String,
+    pub product_ids: Vec<i32>,
+}
+
+/// Every collection in homepage order, hidden ones included
+pub async fn all(conn: &mut AsyncPgConnection) -> QueryResult<Vec<Collection>> {
+    collections::table
+        .select(Collection::as_select())
+        .order((collections::position.asc(), collections::id.asc()))
+        .load(conn)
+        .await
+}
+
+/// Shown collections in homepage order
+pub async fn shown(conn: &mut AsyncPgConnection) -> QueryResult<Vec<Collection>> {
+    collections::table
+        .select(Collection::as_select())
+        .filter(collections::shown.eq(true))
+        .order((collections::position.asc(), collections::id.asc()))
+        .load(conn)
+        .await
+}
+
+/// Pinned products of a collection in the order the admin put them, including any that aren't for
+/// sale right now
+pub async fn pinned(conn: &mut AsyncPgConnection, collection_id: i32) -> QueryResult<Vec<Product>> {
+    collectionproducts::table
+        .inner_join(products::table)
+        .select(products::all_columns)
+        .filter(collectionproducts::collection_id.eq(collection_id))
+        .order(collectionproducts::position.asc())
+        .load(conn)
+        .await
+}
+
+/// The products a collection shows, its live pinned products and then, following its rule, as
+/// many others as fit
+pub async fn resolve(
+    conn: &mut AsyncPgConnection,
+    collection: &Collection,
+) -> QueryResult<Vec<i32>> {
+    let limit = collection.max_products as i64;
+    let mut ids: Vec<i32> = collectionproducts::table
+        .inner_join(products::table)
+        .select(products::id)
+        .filter(collectionproducts::collection_id.eq(collection.id))
+        .filter(schedule::is_live())
+        .order(collectionproducts::position.asc())
+        .limit(limit)
+        .load(conn)
+        .await?;
+    let remaining = limit - ids.len() as i64;
+    if remaining <= 0 {
+        return Ok(ids);
+    }
+    let filled: Vec<i32> = match collection.rule.as_str() {
+        NEWEST => {
+            products::table
+                .select(products::id)
+                .filter(schedule::is_live())
+                .filter(products::id.ne_all(&ids))
+                .order(products::id.desc())
+                .limit(remaining)
+                .load(conn)
+                .await?
+        }
+        BESTSELLERS => {
+            productorders::table
+                .inner_join(productvariants::table.inner_join(products::table))
+                .group_by(products::id)
+                .select(products::id)
+                .filter(schedule::is_live())
+                .filter(products::id.ne_all(&ids))
+                .order((dsl::sum(productorders::quantity).desc(), products::id.asc()))
+                .limit(remaining)
+                .load(conn)
+                .await?
+        }
+        _ => vec![],
+    };
+    ids.extend(filled);
+    Ok(ids)
+}
+
+/// Works out every shown collection, leaving out those with nothing to show
+pub async fn sections(conn: &mut AsyncPgConnection) -> QueryResult<Vec<Section>> {
+    let mut sections = vec![];
+    for collection in shown(conn).await? {
+        let product_ids = resolve(conn, &collection).await?;
+        if !product_ids.is_empty() {
+            sections.push(Section {
+                name: collection.name,
+                product_ids,
+            });
+        }
+    }
+    Ok(sections)
+}
+
+/// Keeps the homepage sections between requests so the bestseller totals aren't added up for
+/// every visitor
+#[derive(Default)]
+pub struct HomepageCache {
+    sections: RwLock<Option<(Instant, Vec<Section>)>>,
+}
+
+impl HomepageCache {
+    pub async fn sections(&self, conn: &mut AsyncPgConnection) -> QueryResult<Vec<Section>> {
+        if let Some((at, sections)) = self.sections.read().unwrap().as_ref() {
+            if at.elapsed() < CACHE_TTL {
+                return Ok(sections.clone());
+            }
+        }
+        let fresh = sections(conn).await?;
+        *self.sections.write().unwrap() = Some((Instant::now(), fresh.clone()));
+        Ok(fresh)
+    }
+
+    /// Makes the next homepage visit work the sections out again
+    pub fn invalidate(&self) {
+        *self.sections.write().unwrap() = None;
+    }
+}
+
+/// The homepage sections with their product cards. Products that have stopped being for sale
+/// since the sections were cached are left out, as are sections left with nothing in them
+pub async fn homepage(
+    cache: &HomepageCache,
+    conn: &mut AsyncPgConnection,
+) -> Result<Vec<(String, Vec<ProductCard>)>, (StatusCode, String)> {
+    let mut cards = vec![];
+    for section in cache.sections(conn).await.map_err(internal_error)? {
+        let mut products: Vec<Product> = products::table
+            .select(products::all_columns)
+            .filter(products::id.eq_any(&section.product_ids))
+            .filter(schedule::is_live())
+            .load(conn)
+            .await
+            .map_err(internal_error)?;
+        if products.is_empty() {
+            continue;
+        }
+        products.sort_by_key(|p| section.product_ids.iter().position(|id| *id == p.id));
+        cards.push((section.name, product_cards(products, conn).await?));
+    }
+    Ok(cards)
+}
@@ -0,0 +1,204 @@
+use askama::Template;
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{AppendHeaders, Html, IntoResponse},
+};
+use axum_extra::extract::{CookieJar, Form};
+use diesel::{
+    delete, dsl::count_star, insert_into, ExpressionMethods, OptionalExtension, QueryDsl,
+    QueryResult, SelectableHelper,
+};
+use diesel_async::{
+    scoped_futures::ScopedFutureExt, AsyncConnection, AsyncPgConnection, RunQueryDsl,
+};
+
+use crate::{
+    auth::session::require_session,
+    db::{
+        models::{Product, ProductVariant, Session},
+        schema::{comparisons, products, productvariants, sessions},
+    },
+    images::filters,
+    internal_error, AppState,
+};
+
+use super::{
+    currency::{self, DisplayCurrency},
+    product_cards, schedule,
+    scents::{self, ScentProfile},
+    Action, LikeAction, ProductCard,
+};
+
+/// How many products can be compared at once
+pub const MAX_COMPARED: i64 = 4;
+
+/// The add or remove button shown under a product card
+#[derive(Template)]
+#[template(path = "compare_toggle.html")]
+struct CompareToggle {
+    product_id: i32,
+    is_compared: bool,
+}
+
+#[derive(Template)]
+#[template(path = "compare.html")]
+struct ComparePageTemplate {
+    logged_in: bool,
+    /// Each product with its listed variants, cheapest first, and its scent profile
+    products: Vec<(ProductCard, Vec<ProductVariant>, ScentProfile)>,
+    currency: DisplayCurrency,
+}
+
+/// Products the session is comparing, in the order they were picked
+pub async fn compared_ids(
+    conn: &mut AsyncPgConnection,
+    session: &Session,
+) -> QueryResult<Vec<i32>> {
+    comparisons::table
+        .select(comparisons::product_id)
+        .filter(comparisons::session_id.eq(&session.id))
+        .order(comparisons::added_at.asc())
+        .load(conn)
+        .await
+}
+
+/// Adds a product to or removes it from the comparison, answering with the new button. Pages
+/// showing the comparison listen for the `compare-changed` event to update themselves
+pub async fn compare_post_handler(
+    jar: CookieJar,
+    State(state): State<AppState>,
+    Form(payload): Form<LikeAction>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let session = require_session(&jar, &state.pool).await?;
+    let mut conn = state.pool.get().await.map_err(internal_error)?;
+    match payload.action {
+        Action::Add => {
+            let live: Option<bool> = products::table
+                .select(schedule::is_live())
+                .filter(products::id.eq(payload.product_id))
+                .first(&mut conn)
+                .await
+                .optional()
+                .map_err(internal_error)?;
+            if live != Some(true) {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    String::from("This product is no longer for sale"),
+                ));
+            }
+            // the session's row is locked while counting, so requests made at the same time can't
+            // both take the last place
+            let session_id = session.id.clone();
+            let added = conn
+                .transaction::<_, diesel::result::Error, _>(|conn| {
+                    async move {
+                        sessions::table
+                            .select(sessions::id)
+                            .filter(sessions::id.eq(&session_id))
+                            .for_update()
+                            .execute(conn)
+                            .await?;
+                        // products that have stopped being for sale aren't shown, so they don't
+                        // use up a place
+                        let compared: i64 = comparisons::table
+                            .inner_join(products::table)
+                            .select(count_star())
+                            .filter(comparisons::session_id.eq(&session_id))
+                            .filter(comparisons::product_id.ne(payload.product_id))
+                            .filter(schedule::is_live())
+                            .first(conn)
+                            .await?;
+                        if compared >= MAX_COMPARED {
+                            return Ok(false);
+                        }
+                        insert_into(comparisons::table)
+                            .values((
+                                comparisons::session_id.eq(&session_id),
+                                comparisons::product_id.eq(payload.product_id),
+                            ))
+                            .on_conflict_do_nothing()
+                            .execute(conn)
+                            .await?;
+                        Ok(true)
+                    }
+                    .scope_boxed()
+                })
+                .await
+                .map_err(internal_error)?;
+            if !added {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    format!(
+                        "You can compare up to {} products, remove one first",
+                        MAX_COMPARED
+                    ),
+                ));
+            }
+        }
+        Action::Remove => {
+            delete(comparisons::table)
+                .filter(comparisons::session_id.eq(&session.id))
+                .filter(comparisons::product_id.eq(payload.product_id))
+                .execute(&mut conn)
+                .await
+                .map_err(internal_error)?;
+        }
+    }
+    let toggle = CompareToggle {
+        product_id: payload.product_id,
+        is_compared: matches!(payload.action, Action::Add),
+    };
+    Ok((
+        AppendHeaders([("HX-Trigger", "compare-changed")]),
+        Html(toggle.render().unwrap()),
+    ))
+}
+
+/// The products being compared side by side, leaving out any that have stopped being for sale
+pub async fn compare(
+    jar: CookieJar,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let session = require_session(&jar, &state.pool).await?;
+    let mut conn = state.pool.get().await.map_err(internal_error)?;
+    let ids = compared_ids(&mut conn, &session)
+        .await
+        .map_err(internal_error)?;
+    let mut products: Vec<Product> = products::table
+        .select(products::all_columns)
+        .filter(products::id.eq_any(&ids))
+        .filter(schedule::is_live())
+        .load(&mut conn)
+        .await
+        .map_err(internal_error)?;
+    products.sort_by_key(|p| ids.iter().position(|id| *id == p.id));
+    let variants: Vec<ProductVariant> = productvariants::table
+        .select(ProductVariant::as_select())
+        .filter(productvariants::product_id.eq_any(&ids))
+        .filter(productvariants::listed.eq(true))
+        .order(productvariants::cost.asc())
+        .load(&mut conn)
+        .await
+        .map_err(internal_error)?;
+    let mut compared = vec![];
+    for card in product_cards(products, &mut conn).await? {
+        let variants = variants
+            .iter()
+            .filter(|v| v.product_id == card.product.id)
+            .cloned()
+            .collect();
+        let scent = scents::profile(&mut conn, card.product.id)
+            .await
+            .map_err(internal_error)?;
+        compared.push((card, variants, scent));
+    }
+    let template = ComparePageTemplate {
+        logged_in: true,
+        products: compared,
+        currency: currency::display_currency(&mut conn, Some(&session), &jar)
+            .await
+            .map_err(internal_error)?,
+    };
+    Ok(Html(template.render().unwrap()))
+}
@@ -0,0 +1,291 @@
+use std::str::FromStr;
+
+use askama::Template;
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{AppendHeaders, Html, IntoResponse},
+};
+use axum_extra::extract::{
+    cookie::{Cookie, SameSite},
+    CookieJar, Form,
+};
+use bigdecimal::{BigDecimal, Zero};
+use diesel::{
+    insert_into, update, upsert::excluded, ExpressionMethods, OptionalExtension, QueryDsl,
+    QueryResult,
+};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::Deserialize;
+use time::{Duration, OffsetDateTime};
+
+use crate::{
+    auth::session::optional_session,
+    db::{
+        models::{ExchangeRate, Session},
+        schema::{exchangerates, users},
+    },
+    ecom::CartItem,
+    internal_error,
+    money::{Currency, CurrencyMismatch, Money},
+    AppState,
+};
+
+/// Guests' choice of currency, customers' choice is saved on their account
+pub const CURRENCY_COOKIE_NAME: &str = "sc-currency";
+
+/// Rates are stored as DECIMAL(12, 6)
+const MAX_RATE: i64 = 1_000_000;
+const RATE_DECIMALS: i64 = 6;
+
+/// The currency prices are shown in and how many units of it one pound buys
+#[derive(Clone, Debug)]
+pub struct DisplayCurrency {
+    pub currency: Currency,
+    pub rate: BigDecimal,
+}
+
+impl DisplayCurrency {
+    pub fn base() -> Self {
+        DisplayCurrency {
+            currency: Currency::BASE,
+            rate: BigDecimal::from(1),
+        }
+    }
+
+    /// Whether prices are shown as they are charged
+    pub fn is_base(&self) -> bool {
+        self.currency == Currency::BASE
+    }
+
+    /// A price in the base currency as the customer sees it
+    pub fn convert(&self, amount: &Money) -> Money {
+        amount.convert(self.currency, &self.rate)
+    }
+
+    /// `quantity` of an item costing `unit_cost` as the customer sees it. The unit price is
+    /// converted before multiplying so the line always matches the price shown per item
+    pub fn line_total(&self, unit_cost: &Money, quantity: i32) -> Money {
+        self.convert(unit_cost).times(quantity)
+    }
+
+    /// What the customer sees as the total, the sum of the lines as they were shown rather than
+    /// the converted base total, which can be a minor unit out from them
+    pub fn total<'a>(
+        &self,
+        items: impl IntoIterator<Item = &'a CartItem>,
+    ) -> Result<Money, CurrencyMismatch> {
+        items.into_iter().try_fold(Money::zero(self.currency), |total, (_, variant, quantity, _)| {
+            total.checked_add(&self.line_total(&variant.cost, *quantity))
+        })
+    }
+}
+
+#[derive(Template)]
+#[template(path = "currency_select.html")]
+struct CurrencySelect {
+    selected: Currency,
+    choices: Vec<Currency>,
+}
+
+#[derive(Deserialize)]
+pub struct CurrencyForm {
+    currency: String,
+}
+
+#[derive(Deserialize)]
+struct RateRow {
+    currency: String,
+    rate: String,
+}
+
+/// The currency a customer sees prices in, their saved choice if they are signed in and have
+/// made one, otherwise the one in their cookie. Currencies without a rate fall back to pounds
+pub async fn display_currency(
+    conn: &mut AsyncPgConnection,
+    session: Option<&Session>,
+    jar: &CookieJar,
+) -> QueryResult<DisplayCurrency> {
+    let saved = match session {
+        Some(session) => {
+            users::table
+                .select(users::currency)
+                .filter(users::id.eq(session.user_id))
+                .first::<Option<String>>(conn)
+                .await?
+        }
+        None => None,
+    };
+    let chosen = saved.or_else(|| jar.get(CURRENCY_COOKIE_NAME).map(|c| c.value().to_owned()));
+    let Some(currency) = chosen.as_deref().and_then(Currency::from_code) else {
+        return Ok(DisplayCurrency::base());
+    };
+    if currency == Currency::BASE {
+        return Ok(DisplayCurrency::base());
+    }
+    let rate: Option<BigDecimal> = exchangerates::table
+        .select(exchangerates::rate)
+        .filter(exchangerates::currency.eq(currency.code()))
+        .first(conn)
+        .await
+        .optional()?;
+    Ok(match rate {
+        Some(rate) => DisplayCurrency { currency, rate },
+        None => DisplayCurrency::base(),
+    })
+}
+
+/// Every currency prices can be shown in, the base currency first
+async fn choices(conn: &mut AsyncPgConnection) -> QueryResult<Vec<Currency>> {
+    let codes: Vec<String> = exchangerates::table
+        .select(exchangerates::currency)
+        .load(conn)
+        .await?;
+    Ok(Currency::ALL
+        .into_iter()
+        .filter(|c| *c == Currency::BASE || codes.iter().any(|code| code == c.code()))
+        .collect())
+}
+
+/// A rate typed by an admin or read from a rates file, e.g. "1.17"
+pub fn parse_rate(rate: &str) -> Result<BigDecimal, String> {
+    let rate =
+        BigDecimal::from_str(rate.trim()).map_err(|_| String::from("Please enter a valid rate"))?;
+    if rate <= BigDecimal::zero()
+        || rate >= BigDecimal::from(MAX_RATE)
+        || rate.normalized().fractional_digit_count() > RATE_DECIMALS
+    {
+        return Err(String::from(
+            "Rates must be between 0.000001 and 999,999.999999",
+        ));
+    }
+    Ok(rate)
+}
+
+/// Checks that rates can be set for `code`, the base currency's rate is always 1
+pub fn parse_rate_currency(code: &str) -> Result<Currency, String> {
+    match Currency::from_code(code) {
+        Some(Currency::BASE) => Err(format!(
+            "{} is the currency prices are set in, its rate is always 1",
+            Currency::BASE.code()
+        )),
+        Some(currency) => Ok(currency),
+        None => Err(format!(
+            "{} is not a currency prices can be shown in",
+            code.trim()
+        )),
+    }
+}
+
+/// Reads a rates file, a CSV with a `currency,rate` header and then one currency per line, e.g.
+/// `EUR,1.17`. Every problem is returned along with the line it is on
+pub fn parse_rates(bytes: &[u8]) -> Result<Vec<(Currency, BigDecimal)>, Vec<String>> {
+    let mut rates: Vec<(Currency, BigDecimal)> = vec![];
+    let mut errors = vec![];
+    let mut reader = csv::Reader::from_reader(bytes);
+    for (i, record) in reader.deserialize::<RateRow>().enumerate() {
+        // the header is line 1
+        let line = i + 2;
+        let row = match record {
+            Ok(row) => row,
+            Err(e) => {
+                errors.push(format!("line {}: {}", line, e));
+                continue;
+            }
+        };
+        match (parse_rate_currency(&row.currency), parse_rate(&row.rate)) {
+            (Ok(currency), _) if rates.iter().any(|r| r.0 == currency) => errors.push(format!(
+                "line {}: {} is listed twice",
+                line,
+                currency.code()
+            )),
+            (Ok(currency), Ok(rate)) => rates.push((currency, rate)),
+            (Err(e), _) | (_, Err(e)) => errors.push(format!("line {}: {}", line, e)),
+        }
+    }
+    if rates.is_empty() && errors.is_empty() {
+        errors.push(String::from("The file has no rates in it"));
+    }
+    if errors.is_empty() {
+        Ok(rates)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Adds or replaces the rates for each currency
+pub async fn save_rates(
+    conn: &mut AsyncPgConnection,
+    rates: &[(Currency, BigDecimal)],
+) -> QueryResult<()> {
+    let now = OffsetDateTime::now_utc();
+    let rows: Vec<ExchangeRate> = rates
+        .iter()
+        .map(|(currency, rate)| ExchangeRate {
+            currency: currency.code().to_owned(),
+            rate: rate.clone(),
+            updated_at: now,
+        })
+        .collect();
+    insert_into(exchangerates::table)
+        .values(rows)
+        .on_conflict(exchangerates::currency)
+        .do_update()
+        .set((
+            exchangerates::rate.eq(excluded(exchangerates::rate)),
+            exchangerates::updated_at.eq(excluded(exchangerates::updated_at)),
+        ))
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// The picker in the header, it is loaded by every page rather than rendered with it
+pub async fn currency_select(
+    jar: CookieJar,
+    State(state): State<AppState>,
+) -> Result<Html<String>, (StatusCode, String)> {
+    let mut conn = state.pool.get().await.map_err(internal_error)?;
+    let session = optional_session(&jar, &state.pool).await;
+    let selected = display_currency(&mut conn, session.as_ref(), &jar)
+        .await
+        .map_err(internal_error)?
+        .currency;
+    let template = CurrencySelect {
+        selected,
+        choices: choices(&mut conn).await.map_err(internal_error)?,
+    };
+    Ok(Html(template.render().unwrap()))
+}
+
+pub async fn choose_currency(
+    jar: CookieJar,
+    State(state): State<AppState>,
+    Form(form): Form<CurrencyForm>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let mut conn = state.pool.get().await.map_err(internal_error)?;
+    let choices = choices(&mut conn).await.map_err(internal_error)?;
+    let currency = Currency::from_code(&form.currency).filter(|c| choices.contains(c));
+    let Some(currency) = currency else {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            String::from("Prices can't be shown in that currency"),
+        ));
+    };
+    if let Some(session) = optional_session(&jar, &state.pool).await {
+        update(users::table)
+            .filter(users::id.eq(session.user_id))
+            .set(users::currency.eq(currency.code()))
+            .execute(&mut conn)
+            .await
+            .map_err(internal_error)?;
+    }
+    let jar = jar.add(
+        Cookie::build((CURRENCY_COOKIE_NAME, currency.code()))
+            .http_only(true)
+            .same_site(SameSite::Lax)
+            .path("/")
+            .max_age(Duration::days(365)),
+    );
+    Ok((jar, AppendHeaders([("HX-Refresh", "true")])))
+}
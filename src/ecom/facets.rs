@@ -0,0 +1,365 @@
+use std::collections::{BTreeSet, HashMap};
+
+use bigdecimal::BigDecimal;
+use diesel::{ExpressionMethods, QueryDsl, QueryResult, SelectableHelper};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+
+use crate::{
+    db::{
+        models::{Attribute, ProductAttribute},
+        schema::{attributes, productattributes, productscentnotes, productvariants, scentnotes},
+    },
+    money::Currency,
+};
+
+use super::{
+    currency::DisplayCurrency,
+    scents::{self, FAMILIES},
+    ProductCard,
+};
+
+/// Any text up to 64 characters, each different value can be filtered on
+pub const TEXT: &str = "text";
+/// A whole number, shown with the attribute's unit
+pub const NUMBER: &str = "number";
+/// Yes or no
+pub const FLAG: &str = "flag";
+pub const KINDS: &[&str] = &[TEXT, NUMBER, FLAG];
+
+/// Filters every product has, attribute slugs can't take these names
+pub const SIZE: &str = "size";
+pub const BURN_TIME: &str = "burn";
+pub const PRICE: &str = "price";
+pub const FAMILY: &str = "family";
+pub const RESERVED: &[&str] = &[SIZE, BURN_TIME, PRICE, FAMILY];
+
+/// Price ranges in the currency prices are shown in, a product is in a range when its price after
+/// any sale is at least the lower bound and below the upper one
+pub const PRICE_RANGES: &[(i32, Option<i32>)] =
+    &[(0, Some(10)), (10, Some(20)), (20, Some(30)), (30, None)];
+
+/// Burn time ranges in hours, a product is in every range one of its variants is in
+const BURN_RANGES: &[(i32, Option<i32>)] =
+    &[(0, Some(20)), (20, Some(40)), (40, Some(60)), (60, None)];
+
+/// One value customers can filter by, `href` is the browse page with this value toggled
+pub struct FacetOption {
+    pub label: String,
+    pub count: usize,
+    pub selected: bool,
+    pub href: String,
+}
+
+pub struct Facet {
+    pub label: String,
+    pub options: Vec<FacetOption>,
+}
+
+/// The filters to show alongside the products they leave
+pub struct Filtered {
+    pub facets: Vec<Facet>,
+    pub cards: Vec<ProductCard>,
+    pub any_selected: bool,
+}
+
+/// A filter and the values products can have for it, in the order they are shown
+struct Definition {
+    key: String,
+    label: String,
+    values: Vec<(String, String)>,
+}
+
+pub fn range_value((lower, upper): (i32, Option<i32>)) -> String {
+    match upper {
+        Some(upper) => format!("{}-{}", lower, upper),
+        None => format!("{}-", lower),
+    }
+}
+
+pub fn in_range(value: &BigDecimal, (lower, upper): (i32, Option<i32>)) -> bool {
+    *value >= BigDecimal::from(lower) && upper.is_none_or(|upper| *value < BigDecimal::from(upper))
+}
+
+pub fn price_label((lower, upper): (i32, Option<i32>), currency: Currency) -> String {
+    let symbol = currency.symbol();
+    match upper {
+        Some(upper) if lower == 0 => format!("Under {}{}", symbol, upper),
+        Some(upper) => format!("{}{} to {}{}", symbol, lower, symbol, upper),
+        None => format!("{}{} and over", symbol, lower),
+    }
+}
+
+fn burn_label((lower, upper): (i32, Option<i32>)) -> String {
+    match upper {
+        Some(upper) if lower == 0 => format!("Under {} hours", upper),
+        Some(upper) => format!("{} to {} hours", lower, upper),
+        None => format!("{} hours and over", lower),
+    }
+}
+
+/// How a product's value for an attribute is shown and the value used for it in links
+pub fn display_value(attribute: &Attribute, value: &ProductAttribute) -> (String, String) {
+    if let Some(text) = &value.text_value {
+        (text.clone(), text.clone())
+    } else if let Some(number) = value.number_value {
+        let label = match attribute.unit.as_str() {
+            "" => number.to_string(),
+            unit => format!("{} {}", number, unit),
+        };
+        (label, number.to_string())
+    } else if value.flag_value == Some(true) {
+        (String::from("Yes"), String::from("yes"))
+    } else {
+        (String::from("No"), String::from("no"))
+    }
+}
+
+/// Every attribute in the order they are shown
+pub async fn all_attributes(conn: &mut AsyncPgConnection) -> QueryResult<Vec<Attribute>> {
+    attributes::table
+        .select(Attribute::as_select())
+        .order((attributes::position.asc(), attributes::id.asc()))
+        .load(conn)
+        .await
+}
+
+/// A product's attributes with their values as shown to customers
+pub async fn product_attributes(
+    conn: &mut AsyncPgConnection,
+    product_id: i32,
+) -> QueryResult<Vec<(String, String)>> {
+    let values: Vec<(Attribute, ProductAttribute)> = productattributes::table
+        .inner_join(attributes::table)
+        .select((Attribute::as_select(), ProductAttribute::as_select()))
+        .filter(productattributes::product_id.eq(product_id))
+        .order((attributes::position.asc(), attributes::id.asc()))
+        .load(conn)
+        .await?;
+    Ok(values
+        .iter()
+        .map(|(attribute, value)| (attribute.name.clone(), display_value(attribute, value).0))
+        .collect())
+}
+
+/// The browse page for a set of chosen values, in the order the filters are shown so the same
+/// filters always give the same link
+fn browse_href(definitions: &[Definition], selected: &HashMap<String, BTreeSet<String>>) -> String {
+    let mut query = form_urlencoded::Serializer::new(String::new());
+    let mut empty = true;
+    for definition in definitions {
+        for (value, _) in &definition.values {
+            if selected
+                .get(&definition.key)
+                .is_some_and(|values| values.contains(value))
+            {
+                query.append_pair(&definition.key, value);
+                empty = false;
+            }
+        }
+    }
+    if empty {
+        String::from("/browse")
+    } else {
+        format!("/browse?{}", query.finish())
+    }
+}
+
+/// Narrows the cards down to the chosen filters. A product needs one of the chosen values of every
+/// filter, so the count next to each value is how many products choosing it as well would show.
+/// Unknown filters and values are ignored
+pub async fn filter(
+    conn: &mut AsyncPgConnection,
+    cards: Vec<ProductCard>,
+    query: &[(String, String)],
+    currency: &DisplayCurrency,
+) -> QueryResult<Filtered> {
+    let ids: Vec<i32> = cards.iter().map(|c| c.product.id).collect();
+    let variants: Vec<(i32, String, Option<i32>)> = productvariants::table
+        .select((
+            productvariants::product_id,
+            productvariants::size,
+            productvariants::burn_hours,
+        ))
+        .filter(productvariants::product_id.eq_any(&ids))
+        .filter(productvariants::listed.eq(true))
+        .load(conn)
+        .await?;
+    let attributes = all_attributes(conn).await?;
+    let attribute_values: Vec<ProductAttribute> = productattributes::table
+        .select(ProductAttribute::as_select())
+        .filter(productattributes::product_id.eq_any(&ids))
+        .load(conn)
+        .await?;
+    let families: Vec<(i32, String)> = productscentnotes::table
+        .inner_join(scentnotes::table)
+        .select((productscentnotes::product_id, scentnotes::family))
+        .filter(productscentnotes::product_id.eq_any(&ids))
+        .load(conn)
+        .await?;
+
+    // the values each product has for each filter
+    let mut has: HashMap<(i32, String), BTreeSet<String>> = HashMap::new();
+    let mut add = |product_id: i32, key: &str, value: String| {
+        has.entry((product_id, key.to_owned()))
+            .or_default()
+            .insert(value);
+    };
+    let mut sizes = BTreeSet::new();
+    for (product_id, size, burn_hours) in &variants {
+        sizes.insert(size.clone());
+        add(*product_id, SIZE, size.clone());
+        if let Some(hours) = burn_hours {
+            for range in BURN_RANGES {
+                if in_range(&BigDecimal::from(*hours), *range) {
+                    add(*product_id, BURN_TIME, range_value(*range));
+                }
+            }
+        }
+    }
+    for card in &cards {
+        let Some(cost) = &card.from_cost else {
+            continue;
+        };
+        let price = match &card.sale {
+            Some(sale) => currency.convert(&sale.price(cost)),
+            None => currency.convert(cost),
+        };
+        for range in PRICE_RANGES {
+            if in_range(price.amount(), *range) {
+                add(card.product.id, PRICE, range_value(*range));
+            }
+        }
+    }
+    for (product_id, family) in families {
+        add(product_id, FAMILY, family);
+    }
+    let mut definitions = vec![
+        Definition {
+            key: String::from(SIZE),
+            label: String::from("Size"),
+            values: sizes.into_iter().map(|s| (s.clone(), s)).collect(),
+        },
+        Definition {
+            key: String::from(BURN_TIME),
+            label: String::from("Burn time"),
+            values: BURN_RANGES
+                .iter()
+                .map(|r| (range_value(*r), burn_label(*r)))
+                .collect(),
+        },
+        Definition {
+            key: String::from(PRICE),
+            label: String::from("Price"),
+            values: PRICE_RANGES
+                .iter()
+                .map(|r| (range_value(*r), price_label(*r, currency.currency)))
+                .collect(),
+        },
+        Definition {
+            key: String::from(FAMILY),
+            label: String::from("Scent family"),
+            values: FAMILIES
+                .iter()
+                .map(|f| (f.to_string(), scents::family_label(f)))
+                .collect(),
+        },
+    ];
+    for attribute in &attributes {
+        let mut values: Vec<(String, String)> = vec![];
+        let mut numbers: Vec<(i32, String, String)> = vec![];
+        for value in attribute_values
+            .iter()
+            .filter(|v| v.attribute_id == attribute.id)
+        {
+            let (label, key) = display_value(attribute, value);
+            add(value.product_id, &attribute.slug, key.clone());
+            if let Some(number) = value.number_value {
+                numbers.push((number, key, label));
+            } else {
+                values.push((key, label));
+            }
+        }
+        numbers.sort();
+        values.extend(numbers.into_iter().map(|(_, key, label)| (key, label)));
+        if attribute.kind != NUMBER {
+            values.sort();
+        }
+        values.dedup();
+        definitions.push(Definition {
+            key: attribute.slug.clone(),
+            label: attribute.name.clone(),
+            values,
+        });
+    }
+
+    let mut selected: HashMap<String, BTreeSet<String>> = HashMap::new();
+    for (key, value) in query {
+        let known = definitions
+            .iter()
+            .any(|d| d.key == *key && d.values.iter().any(|(v, _)| v == value));
+        if known {
+            selected
+                .entry(key.clone())
+                .or_default()
+                .insert(value.clone());
+        }
+    }
+    let matches = |product_id: i32, except: Option<&str>| {
+        selected.iter().all(|(key, values)| {
+            Some(key.as_str()) == except
+                || has
+                    .get(&(product_id, key.clone()))
+                    .is_some_and(|has| !has.is_disjoint(values))
+        })
+    };
+
+    let mut facets = vec![];
+    for definition in &definitions {
+        let mut options = vec![];
+        for (value, label) in &definition.values {
+            let count = ids
+                .iter()
+                .filter(|id| {
+                    matches(**id, Some(&definition.key))
+                        && has
+                            .get(&(**id, definition.key.clone()))
+                            .is_some_and(|has| has.contains(value))
+                })
+                .count();
+            let is_selected = selected
+                .get(&definition.key)
+                .is_some_and(|values| values.contains(value));
+            // choosing it would show nothing
+            if count == 0 && !is_selected {
+                continue;
+            }
+            let mut toggled = selected.clone();
+            let values = toggled.entry(definition.key.clone()).or_default();
+            if !values.remove(value) {
+                values.insert(value.clone());
+            }
+            options.push(FacetOption {
+                label: label.clone(),
+                count,
+                selected: is_selected,
+                href: browse_href(&definitions, &toggled),
+            });
+        }
+        if !options.is_empty() {
+            facets.push(Facet {
+                label: definition.label.clone(),
+                options,
+            });
+        }
+    }
+    let any_selected = !selected.is_empty();
+    let cards = cards
+        .into_iter()
+        .filter(|card| matches(card.product.id, None))
+        .collect();
+    Ok(Filtered {
+        facets,
+        cards,
+        any_selected,
+    })
+}
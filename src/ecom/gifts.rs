@@ -0,0 +1,459 @@
+use std::collections::{BTreeSet, HashMap};
+
+use askama::Template;
+use axum::{extract::State, http::StatusCode, response::Html};
+use axum_extra::extract::{CookieJar, Form};
+use diesel::{dsl::count_star, insert_into, ExpressionMethods, QueryDsl, QueryResult};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use serde::Deserialize;
+
+use crate::{
+    auth::session::optional_session,
+    db::{
+        models::Product,
+        schema::{giftquizanswers, products, productscentnotes, scentnotes},
+    },
+    images::filters,
+    internal_error,
+    money::Currency,
+    AppState,
+};
+
+use super::{
+    currency::{self, DisplayCurrency},
+    facets::{self, PRICE_RANGES},
+    product_cards,
+    scents::{self, FAMILIES, FLORAL, FRESH, FRUITY, GOURMAND},
+    schedule, ProductCard,
+};
+
+/// How many products the quiz suggests
+const SUGGESTIONS: usize = 6;
+
+/// Ticked instead of a family when any scent will do
+pub const ANY_SCENT: &str = "any";
+
+/// Each occasion with the families that tend to suit it
+pub const OCCASIONS: &[(&str, &str, &[&str])] = &[
+    ("birthday", "A birthday", &[FRUITY, GOURMAND]),
+    ("housewarming", "A housewarming", &[FRESH, FLORAL]),
+    ("thank-you", "Saying thank you", &[FLORAL]),
+    ("anniversary", "An anniversary", &[FLORAL, GOURMAND]),
+    ("holidays", "The holidays", &[GOURMAND]),
+    ("just-because", "Just because", &[]),
+];
+
+/// Each season with the families that tend to suit it
+pub const SEASONS: &[(&str, &str, &[&str])] = &[
+    ("spring", "Spring", &[FLORAL]),
+    ("summer", "Summer", &[FRESH, FRUITY]),
+    ("autumn", "Autumn", &[FRUITY, GOURMAND]),
+    ("winter", "Winter", &[GOURMAND]),
+];
+
+pub const OCCASION: &str = "occasion";
+pub const SEASON: &str = "season";
+pub const FAMILY: &str = "family";
+pub const BUDGET: &str = "budget";
+
+/// One step of the quiz, `options` are each answer's value and how it is shown
+pub struct Question {
+    pub key: &'static str,
+    pub title: &'static str,
+    pub options: Vec<(String, String)>,
+    /// Whether more than one answer can be ticked
+    pub multiple: bool,
+}
+
+/// The questions in the order they are asked, budgets are in `currency`
+pub fn questions(currency: Currency) -> Vec<Question> {
+    let mut families: Vec<(String, String)> = FAMILIES
+        .iter()
+        .map(|f| (f.to_string(), scents::family_label(f)))
+        .collect();
+    families.push((String::from(ANY_SCENT), String::from("Any scent")));
+    vec![
+        Question {
+            key: OCCASION,
+            title: "What's the occasion?",
+            options: OCCASIONS
+                .iter()
+                .map(|(value, label, _)| (value.to_string(), label.to_string()))
+                .collect(),
+            multiple: false,
+        },
+        Question {
+            key: SEASON,
+            title: "When will it be given?",
+            options: SEASONS
+                .iter()
+                .map(|(value, label, _)| (value.to_string(), label.to_string()))
+                .collect(),
+            multiple: false,
+        },
+        Question {
+            key: FAMILY,
+            title: "Which scents do they like?",
+            options: families,
+            multiple: true,
+        },
+        Question {
+            key: BUDGET,
+            title: "How much would you like to spend?",
+            options: PRICE_RANGES
+                .iter()
+                .map(|r| (facets::range_value(*r), facets::price_label(*r, currency)))
+                .collect(),
+            multiple: false,
+        },
+    ]
+}
+
+/// Everything answered so far. Each step sends the earlier answers along with its own, `step` is
+/// the question being answered
+#[derive(Deserialize)]
+pub struct Answers {
+    step: String,
+    occasion: Option<String>,
+    season: Option<String>,
+    #[serde(default)]
+    family: Vec<String>,
+    budget: Option<String>,
+}
+
+impl Answers {
+    fn given(&self, key: &str) -> bool {
+        match key {
+            OCCASION => self.occasion.is_some(),
+            SEASON => self.season.is_some(),
+            FAMILY => !self.family.is_empty(),
+            BUDGET => self.budget.is_some(),
+            _ => false,
+        }
+    }
+
+    /// The answers as form fields, to be sent again with the next step
+    fn fields(&self) -> Vec<(&'static str, String)> {
+        let mut fields = vec![];
+        if let Some(occasion) = &self.occasion {
+            fields.push((OCCASION, occasion.clone()));
+        }
+        if let Some(season) = &self.season {
+            fields.push((SEASON, season.clone()));
+        }
+        for family in &self.family {
+            fields.push((FAMILY, family.clone()));
+        }
+        if let Some(budget) = &self.budget {
+            fields.push((BUDGET, budget.clone()));
+        }
+        fields
+    }
+
+    /// The families that were picked, leaving out any scent
+    fn families(&self) -> Vec<&str> {
+        FAMILIES
+            .iter()
+            .copied()
+            .filter(|f| self.family.iter().any(|picked| picked == f))
+            .collect()
+    }
+
+    fn check(&self) -> Result<(), String> {
+        let known = |choices: &[(&str, &str, &[&str])], value: &Option<String>| {
+            value
+                .as_ref()
+                .is_none_or(|value| choices.iter().any(|(v, _, _)| v == value))
+        };
+        let families_known = self
+            .family
+            .iter()
+            .all(|f| f == ANY_SCENT || FAMILIES.contains(&f.as_str()));
+        let budget_known = self.budget.as_ref().is_none_or(|budget| {
+            PRICE_RANGES
+                .iter()
+                .any(|r| facets::range_value(*r) == *budget)
+        });
+        if known(OCCASIONS, &self.occasion)
+            && known(SEASONS, &self.season)
+            && families_known
+            && budget_known
+        {
+            Ok(())
+        } else {
+            Err(String::from("Unknown answer"))
+        }
+    }
+}
+
+/// How well a product with these scent families suits the answers. Families that were picked
+/// count the most, then those that suit the occasion and the season
+pub fn score(answers: &Answers, families: &BTreeSet<String>) -> i32 {
+    let suits = |choices: &[(&str, &str, &[&str])], value: &Option<String>| {
+        choices
+            .iter()
+            .find(|(v, _, _)| Some(*v) == value.as_deref())
+            .map_or(0, |(_, _, suited)| {
+                suited.iter().filter(|f| families.contains(**f)).count() as i32
+            })
+    };
+    let picked = answers
+        .families()
+        .iter()
+        .filter(|f| families.contains(**f))
+        .count() as i32;
+    3 * picked + suits(OCCASIONS, &answers.occasion) + suits(SEASONS, &answers.season)
+}
+
+/// Products in stock and within the budget, best suited first. Ties go to the better reviewed
+pub async fn suggest(
+    conn: &mut AsyncPgConnection,
+    answers: &Answers,
+    currency: &DisplayCurrency,
+    limit: usize,
+) -> Result<Vec<ProductCard>, (StatusCode, String)> {
+    let budget = PRICE_RANGES
+        .iter()
+        .find(|r| Some(facets::range_value(**r)) == answers.budget)
+        .copied()
+        .unwrap_or((0, None));
+    let products: Vec<Product> = products::table
+        .select(products::all_columns)
+        .filter(schedule::is_live())
+        .load(conn)
+        .await
+        .map_err(internal_error)?;
+    let cards: Vec<ProductCard> = product_cards(products, conn)
+        .await?
+        .into_iter()
+        .filter(|card| {
+            let Some(cost) = &card.from_cost else {
+                return false;
+            };
+            let price = match &card.sale {
+                Some(sale) => currency.convert(&sale.price(cost)),
+                None => currency.convert(cost),
+            };
+            card.in_stock && facets::in_range(price.amount(), budget)
+        })
+        .collect();
+    let ids: Vec<i32> = cards.iter().map(|c| c.product.id).collect();
+    let rows: Vec<(i32, String)> = productscentnotes::table
+        .inner_join(scentnotes::table)
+        .select((productscentnotes::product_id, scentnotes::family))
+        .filter(productscentnotes::product_id.eq_any(&ids))
+        .load(conn)
+        .await
+        .map_err(internal_error)?;
+    let mut families: HashMap<i32, BTreeSet<String>> = HashMap::new();
+    for (product_id, family) in rows {
+        families.entry(product_id).or_default().insert(family);
+    }
+    let mut scored: Vec<(i32, ProductCard)> = cards
+        .into_iter()
+        .map(|card| {
+            let families = families.remove(&card.product.id).unwrap_or_default();
+            (score(answers, &families), card)
+        })
+        .collect();
+    scored.sort_by(|(a_score, a), (b_score, b)| {
+        b_score
+            .cmp(a_score)
+            .then(
+                b.rating
+                    .as_ref()
+                    .map(|r| &r.0)
+                    .cmp(&a.rating.as_ref().map(|r| &r.0)),
+            )
+            .then(a.product.id.cmp(&b.product.id))
+    });
+    Ok(scored
+        .into_iter()
+        .take(limit)
+        .map(|(_, card)| card)
+        .collect())
+}
+
+/// How many times each answer to each question has been given
+pub struct AnswerCounts {
+    pub total: i64,
+    pub questions: Vec<(&'static str, Vec<(String, i64)>)>,
+}
+
+/// Tallies every stored answer for the dashboard
+pub async fn answer_counts(conn: &mut AsyncPgConnection) -> QueryResult<AnswerCounts> {
+    let occasions: Vec<(String, i64)> = giftquizanswers::table
+        .group_by(giftquizanswers::occasion)
+        .select((giftquizanswers::occasion, count_star()))
+        .load(conn)
+        .await?;
+    let seasons: Vec<(String, i64)> = giftquizanswers::table
+        .group_by(giftquizanswers::season)
+        .select((giftquizanswers::season, count_star()))
+        .load(conn)
+        .await?;
+    let families: Vec<(String, i64)> = giftquizanswers::table
+        .group_by(giftquizanswers::families)
+        .select((giftquizanswers::families, count_star()))
+        .load(conn)
+        .await?;
+    let budgets: Vec<(String, i64)> = giftquizanswers::table
+        .group_by(giftquizanswers::budget)
+        .select((giftquizanswers::budget, count_star()))
+        .load(conn)
+        .await?;
+    let total = occasions.iter().map(|(_, count)| count).sum();
+    let mut family_counts: HashMap<String, i64> = HashMap::new();
+    for (picked, count) in families {
+        if picked.is_empty() {
+            *family_counts.entry(String::from(ANY_SCENT)).or_default() += count;
+        }
+        for family in picked.split(',').filter(|f| !f.is_empty()) {
+            *family_counts.entry(family.to_owned()).or_default() += count;
+        }
+    }
+    // every option is listed in the order the quiz shows them, even those never picked
+    let tally = |question: Question, counts: &HashMap<String, i64>| {
+        let options = question
+            .options
+            .into_iter()
+            .map(|(value, label)| (label, counts.get(&value).copied().unwrap_or(0)))
+            .collect();
+        (question.title, options)
+    };
+    let counts = [
+        occasions.into_iter().collect(),
+        seasons.into_iter().collect(),
+        family_counts,
+        budgets.into_iter().collect(),
+    ];
+    Ok(AnswerCounts {
+        total,
+        // answers don't record the currency the budget was picked in
+        questions: questions(Currency::BASE)
+            .into_iter()
+            .zip(counts.iter())
+            .map(|(question, counts)| tally(question, counts))
+            .collect(),
+    })
+}
+
+/// The shop filtered to the budget and families that were picked
+fn browse_href(answers: &Answers) -> String {
+    let mut query = form_urlencoded::Serializer::new(String::new());
+    if let Some(budget) = &answers.budget {
+        query.append_pair(facets::PRICE, budget);
+    }
+    for family in answers.families() {
+        query.append_pair(facets::FAMILY, family);
+    }
+    format!("/browse?{}", query.finish())
+}
+
+#[derive(Template)]
+#[template(path = "gift_finder.html")]
+struct GiftFinderPage {
+    logged_in: bool,
+    quiz: GiftQuiz,
+}
+
+/// A question of the quiz, or the suggestions once every question is answered
+#[derive(Template)]
+#[template(path = "gift_quiz.html")]
+struct GiftQuiz {
+    question: Option<Question>,
+    /// Which question this is, counting from 1
+    number: usize,
+    total: usize,
+    answered: Vec<(&'static str, String)>,
+    suggestions: Vec<ProductCard>,
+    browse_href: String,
+    currency: DisplayCurrency,
+}
+
+pub async fn gift_finder(
+    jar: CookieJar,
+    State(state): State<AppState>,
+) -> Result<Html<String>, (StatusCode, String)> {
+    let mut conn = state.pool.get().await.map_err(internal_error)?;
+    let session = optional_session(&jar, &state.pool).await;
+    let currency = currency::display_currency(&mut conn, session.as_ref(), &jar)
+        .await
+        .map_err(internal_error)?;
+    let mut questions = questions(currency.currency);
+    let total = questions.len();
+    let template = GiftFinderPage {
+        logged_in: session.is_some(),
+        quiz: GiftQuiz {
+            question: Some(questions.remove(0)),
+            number: 1,
+            total,
+            answered: vec![],
+            suggestions: vec![],
+            browse_href: String::new(),
+            currency,
+        },
+    };
+    Ok(Html(template.render().unwrap()))
+}
+
+/// Answers with the next question, or with suggestions after the last one. Finished quizzes are
+/// stored without anything that says who took them
+pub async fn answer_question(
+    jar: CookieJar,
+    State(state): State<AppState>,
+    Form(answers): Form<Answers>,
+) -> Result<Html<String>, (StatusCode, String)> {
+    answers.check().map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    if !answers.given(&answers.step) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            String::from("Pick an answer to carry on"),
+        ));
+    }
+    let mut conn = state.pool.get().await.map_err(internal_error)?;
+    let session = optional_session(&jar, &state.pool).await;
+    let currency = currency::display_currency(&mut conn, session.as_ref(), &jar)
+        .await
+        .map_err(internal_error)?;
+    let questions = questions(currency.currency);
+    let total = questions.len();
+    let next = questions
+        .into_iter()
+        .enumerate()
+        .find(|(_, q)| !answers.given(q.key));
+    let quiz = match next {
+        Some((index, question)) => GiftQuiz {
+            question: Some(question),
+            number: index + 1,
+            total,
+            answered: answers.fields(),
+            suggestions: vec![],
+            browse_href: String::new(),
+            currency,
+        },
+        None => {
+            let families = answers.families();
+            insert_into(giftquizanswers::table)
+                .values((
+                    giftquizanswers::occasion.eq(answers.occasion.as_deref().unwrap_or_default()),
+                    giftquizanswers::season.eq(answers.season.as_deref().unwrap_or_default()),
+                    giftquizanswers::families.eq(families.join(",")),
+                    giftquizanswers::budget.eq(answers.budget.as_deref().unwrap_or_default()),
+                ))
+                .execute(&mut conn)
+                .await
+                .map_err(internal_error)?;
+            GiftQuiz {
+                question: None,
+                number: total,
+                total,
+                answered: vec![],
+                suggestions: suggest(&mut conn, &answers, &currency, SUGGESTIONS).await?,
+                browse_href: browse_href(&answers),
+                currency,
+            }
+        }
+    };
+    Ok(Html(quiz.render().unwrap()))
+}
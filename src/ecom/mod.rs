@@ -1,30 +1,71 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
 use askama::Template;
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
-    response::{AppendHeaders, Html, IntoResponse},
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
+    response::{AppendHeaders, Html, IntoResponse, Response},
 };
-use axum_extra::extract::{CookieJar, Form};
+use axum_extra::extract::{CookieJar, Form, SignedCookieJar};
 use bigdecimal::BigDecimal;
-use diesel::{delete, dsl::exists, insert_into, sql_query, ExpressionMethods, QueryDsl};
-use diesel_async::{pooled_connection::deadpool::Pool, AsyncPgConnection, RunQueryDsl};
+use diesel::{
+    delete,
+    dsl::{exists, not}, insert_into, update, BelongingToDsl, BoolExpressionMethods,
+    ExpressionMethods, OptionalExtension, QueryDsl, SelectableHelper,
+};
+use diesel_async::{
+    scoped_futures::ScopedFutureExt, AsyncConnection, AsyncPgConnection, RunQueryDsl,
+};
 use serde::Deserialize;
 
 use crate::{
     auth::session::validate_session,
     db::{
-        models::{Address, CartProduct, Order, OrderWithId, Product},
-        schema::{addresses, cartproducts, likedproducts, orders, productorders, products},
+        models::{
+            Address, CartProduct, NewStockAdjustment, Order, OrderWithId, PersonalisationField,
+            Product, ProductImage, ProductInOrder, ProductVariant, Review, Sale, Session, Wishlist,
+        },
+        schema::{
+            addresses, cartproducts, likedproducts, orders, productalerts, productimages,
+            productorders, productredirects, products, productvariants,
+            reviews as reviews_table, stockadjustments,
+        },
     },
-    internal_error, logged_in, AppState, SESSION_COOKIE_NAME,
+    images::filters,
+    internal_error,
+    money::{Currency, CurrencyMismatch, Money},
+    AppState, SESSION_COOKIE_NAME,
 };
+use currency::DisplayCurrency;
+use facets::Facet;
+use scents::ScentProfile;
 pub mod admin;
+pub mod alerts;
+pub mod collections;
+pub mod compare;
+pub mod currency;
+pub mod facets;
+pub mod gifts;
+pub mod markdown;
+pub mod personalisation;
+pub mod recent;
+pub mod recommendations;
+pub mod reviews;
+pub mod sales;
+pub mod scents;
+pub mod schedule;
+pub mod wishlists;
 
 #[derive(Template)]
 #[template(path = "browse.html")]
 struct BrowsePageTemplate {
     logged_in: bool,
-    products: Vec<Product>,
+    products: Vec<ProductCard>,
+    /// Filters narrowing down `products`, see `facets`
+    facets: Vec<Facet>,
+    any_selected: bool,
+    currency: DisplayCurrency,
+    /// Products the customer is comparing, see `compare`
+    compared: Vec<i32>,
 }
 
 #[derive(Template)]
@@ -32,24 +73,58 @@ struct BrowsePageTemplate {
 struct ProductPageTemplate {
     logged_in: bool,
     product: Product,
+    images: Vec<ProductImage>,
+    variants: Vec<ProductVariant>,
+    in_stock: bool,
     is_liked: bool,
+    /// Whether the customer is alerted when this product is relisted, restocked or cheaper
+    has_alert: bool,
+    /// The customer's named lists the product can be saved to
+    lists: Vec<Wishlist>,
+    rating: Option<(BigDecimal, i64)>,
+    reviews: Vec<Review>,
+    can_review: bool,
+    also_bought: Vec<ProductCard>,
+    /// Products with the most alike scent profiles, see `scents::similar`
+    similar: Vec<ProductCard>,
+    scent: ScentProfile,
+    recent: Vec<ProductCard>,
+    /// The sale taking the most off this product right now
+    sale: Option<Sale>,
+    /// The product's details rendered from Markdown and sanitised
+    details: String,
+    /// Each attribute the product has with its value, such as wax type
+    attributes: Vec<(String, String)>,
+    /// Boxes to fill in when adding the product to the cart
+    personalisation: Vec<PersonalisationField>,
+    currency: DisplayCurrency,
+    /// Whether the product can be bought right now, see `schedule::is_live`
+    live: bool,
+    /// Prefixes the canonical link, which must be absolute
+    site_url: String,
 }
 
 #[derive(Template)]
 #[template(path = "cart.html")]
 struct CartPageTemplate {
     logged_in: bool,
-    products: Option<Vec<(Product, i32)>>,
-    total_cost: Option<BigDecimal>,
+    products: Option<Vec<CartLine>>,
+    total_cost: Option<Money>,
+    /// `total_cost` as the customer sees it, see `DisplayCurrency::total`
+    shown_total: Option<Money>,
+    currency: DisplayCurrency,
 }
 
 #[derive(Template)]
 #[template(path = "checkout.html")]
 struct CheckoutPageTemplate {
-    cartproducts: Option<Vec<(Product, i32)>>,
+    cartproducts: Option<Vec<CartLine>>,
     logged_in: bool,
     saved_addresses: Option<Vec<Address>>,
-    total_cost: Option<BigDecimal>,
+    total_cost: Option<Money>,
+    /// `total_cost` as the customer sees it, see `DisplayCurrency::total`
+    shown_total: Option<Money>,
+    currency: DisplayCurrency,
 }
 
 #[derive(Template)]
@@ -67,6 +142,8 @@ struct CheckoutSuccess;
 #[template(path = "order_details.html")]
 struct OrderDetails {
     order_info: OrderInfo,
+    /// The currency the order was shown in when it was placed, at the rate used then
+    currency: DisplayCurrency,
 }
 
 #[derive(Deserialize)]
@@ -77,22 +154,111 @@ pub enum Action {
 
 #[derive(Deserialize)]
 pub struct CartAction {
-    product_id: i32,
+    variant_id: i32,
     action: Action,
     quantity: i32,
+    /// The product's personalisation fields, each with the value at the same place in
+    /// `field_value`
+    #[serde(default)]
+    field_id: Vec<i32>,
+    #[serde(default)]
+    field_value: Vec<String>,
+    /// The cart line to remove
+    line_id: Option<i32>,
 }
 
 #[derive(Deserialize)]
 pub struct LikeAction {
     product_id: i32,
     action: Action,
+    /// One of the customer's named lists, their favourites when missing
+    list_id: Option<i32>,
 }
 
 pub struct OrderInfo {
     info: OrderWithId,
     address: Address,
-    products: Vec<(Product, i32)>,
-    total: BigDecimal,
+    products: Vec<CartItem>,
+    /// The total the customer was shown, in the currency they were shown it in
+    total: Money,
+}
+
+/// A product as shown on the browse and home pages, `from_cost` is the cheapest listed variant
+/// and is `None` when no variant is currently for sale
+pub struct ProductCard {
+    pub product: Product,
+    pub image: String,
+    pub alt_text: String,
+    pub from_cost: Option<Money>,
+    pub in_stock: bool,
+    /// Average approved rating and the number of approved reviews
+    pub rating: Option<(BigDecimal, i64)>,
+    /// The sale taking the most off this product right now, `from_cost` is before the discount
+    pub sale: Option<Sale>,
+}
+
+/// A line in a cart or order with its personalisation lines, the variant's cost is the price
+/// charged including any surcharge
+pub type CartItem = (Product, ProductVariant, i32, String);
+
+/// An item in a customer's cart with the id of its cart line
+pub type CartLine = (i32, CartItem);
+
+/// A cart line being checked out: variant id, SKU, quantity, title, size, product id, full price,
+/// personalisation and surcharge
+type CheckoutLine = (i32, String, i32, String, String, i32, Money, String, Money);
+
+/// What a cart or order comes to, each line is the unit price times the quantity
+pub fn total<'a>(
+    items: impl IntoIterator<Item = &'a CartItem>,
+) -> Result<Money, CurrencyMismatch> {
+    items.into_iter().try_fold(Money::zero(Currency::BASE), |total, (_, variant, quantity, _)| {
+        total.checked_add(&variant.cost.times(*quantity))
+    })
+}
+
+/// Where the shop is served from for absolute links, set with `SITE_URL`
+pub fn site_url() -> String {
+    std::env::var("SITE_URL").unwrap_or_else(|_| String::from("http://localhost:1111"))
+}
+
+/// An unguessable token for links that work without signing in, such as unsubscribe links
+pub fn generate_token() -> String {
+    let mut bytes = [0; 20];
+    OsRng.fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+/// Turns a title into a url friendly slug, e.g. "Cherry Scented Candle" becomes "cherry-scented-candle"
+pub fn slugify(title: &str) -> String {
+    let mut slug = String::new();
+    for c in title.chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    // leave room for a numeric suffix if the slug is already taken
+    slug.truncate(200);
+    let slug = slug.trim_end_matches('-');
+    if slug.is_empty() {
+        String::from("product")
+    } else {
+        slug.to_owned()
+    }
+}
+
+/// Slugs are lowercase letters, numbers and single dashes between words
+pub fn is_valid_slug(slug: &str) -> bool {
+    !slug.is_empty()
+        && slug.len() <= 255
+        && !slug.starts_with('-')
+        && !slug.ends_with('-')
+        && !slug.contains("--")
+        && slug
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
 }
 
 #[derive(Debug, Deserialize)]
@@ -113,6 +279,48 @@ pub struct OrderDetailsForm {
     order_id: i32,
 }
 
+enum CheckoutError {
+    EmptyCart,
+    /// The product was unlisted or unpublished after it was added to the cart
+    NotForSale(String),
+    OutOfStock(String),
+    Database(diesel::result::Error),
+    Pricing(CurrencyMismatch),
+}
+
+impl From<diesel::result::Error> for CheckoutError {
+    fn from(err: diesel::result::Error) -> Self {
+        CheckoutError::Database(err)
+    }
+}
+
+impl From<CurrencyMismatch> for CheckoutError {
+    fn from(err: CurrencyMismatch) -> Self {
+        CheckoutError::Pricing(err)
+    }
+}
+
+impl From<CheckoutError> for (StatusCode, String) {
+    fn from(err: CheckoutError) -> Self {
+        match err {
+            CheckoutError::EmptyCart => (
+                StatusCode::BAD_REQUEST,
+                String::from("Please add items to your cart to buy them!"),
+            ),
+            CheckoutError::NotForSale(item) => (
+                StatusCode::CONFLICT,
+                format!("Sorry, {} is no longer for sale, please remove it from your cart", item),
+            ),
+            CheckoutError::OutOfStock(item) => (
+                StatusCode::CONFLICT,
+                format!("Sorry, there is not enough stock left of {} to complete your order", item),
+            ),
+            CheckoutError::Database(err) => internal_error(err),
+            CheckoutError::Pricing(err) => internal_error(err),
+        }
+    }
+}
+
 impl CheckoutForm {
     fn verify_data(&mut self) -> Result<(), (StatusCode, String)> {
         self.cardnum.retain(|c| !c.is_whitespace());
@@ -196,20 +404,104 @@ impl<'de> Deserialize<'de> for FormBool {
     }
 } */
 
+pub async fn product_cards(
+    products: Vec<Product>,
+    conn: &mut AsyncPgConnection,
+) -> Result<Vec<ProductCard>, (StatusCode, String)> {
+    let ids: Vec<i32> = products.iter().map(|p| p.id).collect();
+    let images: Vec<(i32, String, String)> = productimages::table
+        .select((
+            productimages::product_id,
+            productimages::imgname,
+            productimages::alt_text,
+        ))
+        .filter(productimages::product_id.eq_any(&ids))
+        .order(productimages::position.asc())
+        .load(conn)
+        .await
+        .map_err(internal_error)?;
+    let costs: Vec<(i32, Money, i32)> = productvariants::table
+        .select((
+            productvariants::product_id,
+            productvariants::cost,
+            productvariants::stock,
+        ))
+        .filter(productvariants::product_id.eq_any(&ids))
+        .filter(productvariants::listed.eq(true))
+        .order(productvariants::cost.asc())
+        .load(conn)
+        .await
+        .map_err(internal_error)?;
+    let ratings = reviews::ratings(conn, &ids).await.map_err(internal_error)?;
+    let mut sales = sales::current_sales(conn, &ids)
+        .await
+        .map_err(internal_error)?;
+    Ok(products
+        .into_iter()
+        .map(|product| {
+            let from_cost = costs
+                .iter()
+                .find(|c| c.0 == product.id)
+                .map(|c| c.1.clone());
+            let in_stock = costs.iter().any(|c| c.0 == product.id && c.2 > 0);
+            // the primary image is the first in the gallery
+            let (image, alt_text) = images
+                .iter()
+                .find(|i| i.0 == product.id)
+                .map(|i| (i.1.clone(), i.2.clone()))
+                .unwrap_or_else(|| (product.imgname.clone(), product.title.clone()));
+            ProductCard {
+                rating: ratings.get(&product.id).cloned(),
+                sale: sales.remove(&product.id),
+                product,
+                image,
+                alt_text,
+                from_cost,
+                in_stock,
+            }
+        })
+        .collect())
+}
+
+/// Every product for sale, narrowed down by any filters in the query such as `?size=Large`
 pub async fn browse(
     jar: CookieJar,
     State(state): State<AppState>,
+    Query(query): Query<Vec<(String, String)>>,
 ) -> Result<(StatusCode, Html<String>), (StatusCode, String)> {
     let mut conn = state.pool.get().await.map_err(internal_error)?;
     let products: Vec<Product> = products::table
         .select(products::all_columns)
-        .filter(products::listed.eq(true))
+        .filter(schedule::is_live())
         .load(&mut conn)
         .await
         .map_err(internal_error)?;
+    let session = match jar.get(SESSION_COOKIE_NAME) {
+        Some(cookie) => validate_session(cookie.value().to_owned(), &state.pool)
+            .await
+            .ok(),
+        None => None,
+    };
+    let compared = match &session {
+        Some(session) => compare::compared_ids(&mut conn, session)
+            .await
+            .map_err(internal_error)?,
+        None => vec![],
+    };
+    let cards = product_cards(products, &mut conn).await?;
+    let currency = currency::display_currency(&mut conn, session.as_ref(), &jar)
+        .await
+        .map_err(internal_error)?;
+    let filtered = facets::filter(&mut conn, cards, &query, &currency)
+        .await
+        .map_err(internal_error)?;
     let template = BrowsePageTemplate {
-        products,
-        logged_in: logged_in(&jar, &state.pool).await,
+        compared,
+        products: filtered.cards,
+        facets: filtered.facets,
+        any_selected: filtered.any_selected,
+        logged_in: session.is_some(),
+        currency,
     };
     let html = template.render().unwrap();
     Ok((StatusCode::OK, Html(html)))
@@ -218,18 +510,60 @@ pub async fn browse(
 pub async fn product(
     Path(path): Path<String>,
     jar: CookieJar,
+    recent_jar: SignedCookieJar,
     State(state): State<AppState>,
-) -> Result<(StatusCode, Html<String>), (StatusCode, String)> {
+) -> Result<Response, (StatusCode, String)> {
     let mut conn = state.pool.get().await.map_err(internal_error)?;
-    let product: Product = products::table
+    let product: Option<Product> = products::table
         .select(products::all_columns)
-        .filter(products::imgname.eq(path + ".jpg"))
+        .filter(products::slug.eq(&path))
+        .filter(products::archived_at.is_null())
         .first(&mut conn)
         .await
-        .map_err(|_| (StatusCode::NOT_FOUND, String::from("404 Not Found")))?;
+        .optional()
+        .map_err(internal_error)?;
+    // seasonal products are kept secret until they are published
+    let product = product.filter(|p| !p.is_upcoming());
+    let Some(product) = product else {
+        // the slug may have been changed since the link was made
+        let slug: String = productredirects::table
+            .inner_join(products::table)
+            .select(products::slug)
+            .filter(productredirects::slug.eq(&path))
+            .filter(products::archived_at.is_null())
+            .filter(schedule::is_live())
+            .first(&mut conn)
+            .await
+            .map_err(|_| (StatusCode::NOT_FOUND, String::from("404 Not Found")))?;
+        return Ok((
+            StatusCode::MOVED_PERMANENTLY,
+            [(header::LOCATION, format!("/browse/{}", slug))],
+        )
+            .into_response());
+    };
+    let session = match jar.get(SESSION_COOKIE_NAME) {
+        Some(session_cookie) => {
+            Some(validate_session(session_cookie.value().to_owned(), &state.pool).await?)
+        }
+        None => None,
+    };
     let is_liked;
-    if let Some(session_cookie) = jar.get(SESSION_COOKIE_NAME) {
-        let session = validate_session(session_cookie.value().to_owned(), &state.pool).await?;
+    let mut has_alert = false;
+    let mut lists = vec![];
+    let mut can_review = false;
+    if let Some(session) = &session {
+        let reviewed: bool = diesel::select(exists(
+            reviews_table::table
+                .filter(reviews_table::product_id.eq(product.id))
+                .filter(reviews_table::user_id.eq(session.user_id)),
+        ))
+        .get_result(&mut conn)
+        .await
+        .map_err(internal_error)?;
+        can_review = !reviewed
+            && reviews::has_purchased(&mut conn, session.user_id, product.id)
+                .await
+                .map_err(internal_error)?;
         is_liked = likedproducts::table
             .select(likedproducts::all_columns)
             .filter(likedproducts::product_id.eq(product.id))
@@ -239,16 +573,101 @@ pub async fn product(
             .map_err(internal_error)?
             .len()
             > 0;
+        has_alert = diesel::select(exists(
+            productalerts::table
+                .filter(productalerts::product_id.eq(product.id))
+                .filter(productalerts::user_id.eq(session.user_id)),
+        ))
+        .get_result(&mut conn)
+        .await
+        .map_err(internal_error)?;
+        lists = wishlists::user_lists(&mut conn, session.user_id)
+            .await
+            .map_err(internal_error)?;
     } else {
         is_liked = false
     }
+    let variants = ProductVariant::belonging_to(&product)
+        .select(ProductVariant::as_select())
+        .filter(productvariants::listed.eq(true))
+        .order(productvariants::cost.asc())
+        .load(&mut conn)
+        .await
+        .map_err(internal_error)?;
+    let images = ProductImage::belonging_to(&product)
+        .select(ProductImage::as_select())
+        .order(productimages::position.asc())
+        .load(&mut conn)
+        .await
+        .map_err(internal_error)?;
+    let reviews = Review::belonging_to(&product)
+        .select(Review::as_select())
+        .filter(reviews_table::status.eq(reviews::APPROVED))
+        .order(reviews_table::created_at.desc())
+        .load(&mut conn)
+        .await
+        .map_err(internal_error)?;
+    let rating = reviews::ratings(&mut conn, &[product.id])
+        .await
+        .map_err(internal_error)?
+        .remove(&product.id);
+    let also_bought = recommendations::also_bought(&mut conn, product.id, 4)
+        .await
+        .map_err(internal_error)?;
+    let also_bought = product_cards(also_bought, &mut conn).await?;
+    let similar = scents::similar(&mut conn, product.id, 4)
+        .await
+        .map_err(internal_error)?;
+    let similar = product_cards(similar, &mut conn).await?;
+    let scent = scents::profile(&mut conn, product.id)
+        .await
+        .map_err(internal_error)?;
+    let recent = recent::recently_viewed(&mut conn, session.as_ref(), &recent_jar, Some(product.id))
+        .await
+        .map_err(internal_error)?;
+    let recent = product_cards(recent, &mut conn).await?;
+    let recent_jar = recent::record_view(&mut conn, session.as_ref(), recent_jar, product.id)
+        .await
+        .map_err(internal_error)?;
+    let sale = sales::current_sales(&mut conn, &[product.id])
+        .await
+        .map_err(internal_error)?
+        .remove(&product.id);
+    let currency = currency::display_currency(&mut conn, session.as_ref(), &jar)
+        .await
+        .map_err(internal_error)?;
+    let attributes = facets::product_attributes(&mut conn, product.id)
+        .await
+        .map_err(internal_error)?;
+    let personalisation = personalisation::fields(&mut conn, product.id)
+        .await
+        .map_err(internal_error)?;
     let template = ProductPageTemplate {
+        attributes,
+        personalisation,
+        similar,
+        scent,
+        live: product.is_live(),
+        site_url: site_url(),
+        currency,
+        details: markdown::render(&product.details),
+        also_bought,
+        recent,
+        sale,
+        rating,
+        reviews,
+        can_review,
         product,
-        logged_in: logged_in(&jar, &state.pool).await,
+        images,
+        in_stock: variants.iter().any(|v| v.stock > 0),
+        variants,
+        logged_in: session.is_some(),
         is_liked,
+        has_alert,
+        lists,
     };
     let html = template.render().unwrap();
-    Ok((StatusCode::OK, Html(html)))
+    Ok((StatusCode::OK, recent_jar, Html(html)).into_response())
 }
 
 pub async fn cart(
@@ -258,33 +677,63 @@ pub async fn cart(
     let session_cookie = jar
         .get(SESSION_COOKIE_NAME)
         .ok_or((StatusCode::UNAUTHORIZED, String::from("401 unauthorized")))?;
-    let (products, total_cost) = get_cart_items(session_cookie.value().to_owned(), &state.pool).await?;
+    let session = validate_session(session_cookie.value().to_owned(), &state.pool).await?;
+    let mut conn = state.pool.get().await.map_err(internal_error)?;
+    let (products, total_cost) = get_cart_items(&session, &mut conn).await?;
+    let currency = currency::display_currency(&mut conn, Some(&session), &jar)
+        .await
+        .map_err(internal_error)?;
     let template = CartPageTemplate {
+        shown_total: products
+            .as_ref()
+            .map(|p| currency.total(p.iter().map(|(_, item)| item)))
+            .transpose()
+            .map_err(internal_error)?,
         products,
         total_cost,
         logged_in: true,
+        currency,
     };
     let html = template.render().unwrap();
     Ok((StatusCode::OK, Html(html)))
 }
 
 async fn get_cart_items(
-    session_token: String,
-    pool: &Pool<AsyncPgConnection>,
-) -> Result<(Option<Vec<(Product, i32)>>, Option<BigDecimal>), (StatusCode, String)> {
-    let mut conn = pool.get().await.map_err(internal_error)?;
-    let session = validate_session(session_token, pool).await?;
-    let cartitems = products::table
-        .inner_join(cartproducts::table)
-        .select((products::all_columns, cartproducts::quantity))
+    session: &Session,
+    conn: &mut AsyncPgConnection,
+) -> Result<(Option<Vec<CartLine>>, Option<Money>), (StatusCode, String)> {
+    let cartitems = cartproducts::table
+        .inner_join(productvariants::table.inner_join(products::table))
+        .select((
+            cartproducts::id,
+            products::all_columns,
+            productvariants::all_columns,
+            cartproducts::quantity,
+            cartproducts::personalisation,
+            cartproducts::surcharge,
+        ))
         .filter(cartproducts::user_id.eq(session.user_id))
-        .load::<(Product, i32)>(&mut conn)
+        .load::<(i32, Product, ProductVariant, i32, String, Money)>(conn)
         .await
         .map_err(internal_error)?;
+    let ids: Vec<i32> = cartitems.iter().map(|c| c.1.id).collect();
+    let sales = sales::current_sales(conn, &ids)
+        .await
+        .map_err(internal_error)?;
+    let cartitems: Vec<CartLine> = cartitems
+        .into_iter()
+        .map(|(line_id, product, variant, quantity, personalisation, surcharge)| {
+            let cost =
+                sales::current_price(&sales, product.id, &variant.cost).checked_add(&surcharge)?;
+            let item = (product, ProductVariant { cost, ..variant }, quantity, personalisation);
+            Ok((line_id, item))
+        })
+        .collect::<Result<_, CurrencyMismatch>>()
+        .map_err(internal_error)?;
     if cartitems.len() == 0 {
         return Ok((None, None));
     }
-    let total_cost: BigDecimal = cartitems.iter().map(|c| &c.0.cost * &c.1).sum();
+    let total_cost = total(cartitems.iter().map(|(_, item)| item)).map_err(internal_error)?;
     return Ok((Some(cartitems), Some(total_cost)));
 }
 
@@ -301,27 +750,54 @@ pub async fn cart_post_handler(
     match payload.action {
         Action::Add => {
             if (1..=32).contains(&payload.quantity) {
+                if payload.field_id.len() != payload.field_value.len() {
+                    return Err((StatusCode::BAD_REQUEST, String::from("Incorrect Fields")));
+                }
+                let product_id: i32 = productvariants::table
+                    .select(productvariants::product_id)
+                    .filter(productvariants::id.eq(payload.variant_id))
+                    .first(&mut conn).await
+                    .optional()
+                    .map_err(internal_error)?
+                    .ok_or((StatusCode::NOT_FOUND, String::from("404 Not Found")))?;
+                let fields = personalisation::fields(&mut conn, product_id)
+                    .await
+                    .map_err(internal_error)?;
+                let values: Vec<(i32, String)> = payload.field_id.into_iter().zip(payload.field_value).collect();
+                let (personalisation, surcharge) = personalisation::capture(&fields, &values)
+                    .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
                 let sub_query = cartproducts::table
-                    .select(cartproducts::product_id)
-                    .filter(cartproducts::product_id.eq(payload.product_id))
-                    .filter(cartproducts::user_id.eq(session.user_id));
-                let product: (bool, bool) = products::table
-                    .select((products::listed, exists(sub_query)))
-                    .filter(products::id.eq(payload.product_id))
+                    .select(cartproducts::variant_id)
+                    .filter(cartproducts::variant_id.eq(payload.variant_id))
+                    .filter(cartproducts::user_id.eq(session.user_id))
+                    .filter(cartproducts::personalisation.eq(&personalisation));
+                let product: (bool, bool, bool, i32) = productvariants::table
+                    .inner_join(products::table)
+                    .select((schedule::is_live(), productvariants::listed, exists(sub_query), productvariants::stock))
+                    .filter(productvariants::id.eq(payload.variant_id))
                     .first(&mut conn).await
                     .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, String::from("500 Internal Server Error")))?;
-                if product.1 { // is it in the users cart?
+                if product.2 { // is it in the users cart?
                     return Err((StatusCode::BAD_REQUEST,
                         String::from("This item is already in your cart"),
                     ));
                 }
-                if !product.0 { // is it listed?
+                if !(product.0 && product.1) { // are the product and variant listed?
                     return Err((StatusCode::BAD_REQUEST,
                         String::from("This item is no longer listed"),
                     ));
                 }
-                let entry = CartProduct {product_id: payload.product_id,user_id: session.user_id,
-                    quantity: payload.quantity,
+                if product.3 < payload.quantity { // is there enough stock?
+                    return Err((StatusCode::BAD_REQUEST,
+                        if product.3 == 0 {
+                            String::from("This item is out of stock")
+                        } else {
+                            format!("Sorry, only {} of this item are left in stock", product.3)
+                        },
+                    ));
+                }
+                let entry = CartProduct {variant_id: payload.variant_id,user_id: session.user_id,
+                    quantity: payload.quantity, personalisation, surcharge,
                 };
                 insert_into(cartproducts::table)
                     .values(entry)
@@ -337,9 +813,12 @@ pub async fn cart_post_handler(
             }
         }
         Action::Remove => {
+            let line_id = payload
+                .line_id
+                .ok_or((StatusCode::BAD_REQUEST, String::from("Incorrect Fields")))?;
             delete(cartproducts::table)
                 .filter(cartproducts::user_id.eq(session.user_id))
-                .filter(cartproducts::product_id.eq(payload.product_id))
+                .filter(cartproducts::id.eq(line_id))
                 .execute(&mut conn).await
                 .map_err(internal_error)?;
             return Ok(AppendHeaders([("HX-Location", "/cart")]).into_response());
@@ -360,12 +839,22 @@ pub async fn liked(
         .select(products::all_columns)
         .inner_join(likedproducts::table)
         .filter(likedproducts::user_id.eq(session.user_id))
+        .filter(products::archived_at.is_null())
         .load::<Product>(&mut conn)
         .await
         .map_err(internal_error)?;
     let template = BrowsePageTemplate {
-        products,
+        compared: compare::compared_ids(&mut conn, &session)
+            .await
+            .map_err(internal_error)?,
+        products: product_cards(products, &mut conn).await?,
+        // liked products aren't filtered, the filters lead back to browse
+        facets: vec![],
+        any_selected: false,
         logged_in: true,
+        currency: currency::display_currency(&mut conn, Some(&session), &jar)
+            .await
+            .map_err(internal_error)?,
     };
     let html = template.render().unwrap();
     Ok((StatusCode::OK, Html(html)))
@@ -381,6 +870,16 @@ pub async fn like_post_handler(
         .get(SESSION_COOKIE_NAME)
         .ok_or((StatusCode::UNAUTHORIZED, String::from("401 unauthorized")))?;
     let session = validate_session(session_cookie.value().to_owned(), &state.pool).await?;
+    if let Some(list_id) = payload.list_id {
+        return wishlists::update_list(
+            &mut conn,
+            &session,
+            list_id,
+            payload.product_id,
+            payload.action,
+        )
+        .await;
+    }
     match payload.action {
         Action::Add => {
             insert_into(likedproducts::table)
@@ -391,17 +890,23 @@ pub async fn like_post_handler(
                 .execute(&mut conn)
                 .await
                 .map_err(internal_error)?;
-            return Ok("Added ✔");
+            return Ok(String::from("Added ✔"));
         }
 
         Action::Remove => {
+            delete(productalerts::table)
+                .filter(productalerts::user_id.eq(session.user_id))
+                .filter(productalerts::product_id.eq(payload.product_id))
+                .execute(&mut conn)
+                .await
+                .map_err(internal_error)?;
             delete(likedproducts::table)
                 .filter(likedproducts::user_id.eq(session.user_id))
                 .filter(likedproducts::product_id.eq(payload.product_id))
                 .execute(&mut conn)
                 .await
                 .map_err(internal_error)?;
-            return Ok("Removed ✔");
+            return Ok(String::from("Removed ✔"));
         }
     }
 }
@@ -413,13 +918,23 @@ pub async fn checkout(
     let session_cookie = jar
         .get(SESSION_COOKIE_NAME)
         .ok_or((StatusCode::UNAUTHORIZED, String::from("401 unauthorized")))?;
-    let (cartproducts, total_cost) =
-        get_cart_items(session_cookie.value().to_owned(), &state.pool).await?;
+    let session = validate_session(session_cookie.value().to_owned(), &state.pool).await?;
+    let mut conn = state.pool.get().await.map_err(internal_error)?;
+    let (cartproducts, total_cost) = get_cart_items(&session, &mut conn).await?;
+    let currency = currency::display_currency(&mut conn, Some(&session), &jar)
+        .await
+        .map_err(internal_error)?;
     let template = CheckoutPageTemplate {
-        logged_in: logged_in(&jar, &state.pool).await,
+        logged_in: true,
         saved_addresses: None,
+        shown_total: cartproducts
+            .as_ref()
+            .map(|p| currency.total(p.iter().map(|(_, item)| item)))
+            .transpose()
+            .map_err(internal_error)?,
         cartproducts,
         total_cost,
+        currency,
     };
     let html = template.render().unwrap();
     Ok((StatusCode::OK, Html(html)))
@@ -436,40 +951,130 @@ pub async fn checkout_post_handler(
         .ok_or((StatusCode::UNAUTHORIZED, String::from("401 unauthorized")))?;
     let session = validate_session(session_cookie.value().to_owned(), &state.pool).await?;
     payload.verify_data()?;
-    if cartproducts::table
-        .filter(cartproducts::user_id.eq(session.user_id))
-        .count()
-        .get_result::<i64>(&mut conn).await
-        .map_err(internal_error)?
-        == 0
-    {
-        return Err((
-            StatusCode::BAD_REQUEST,
-            String::from("Please add items to your cart to buy them!"),
-        ));
-    }
-    let address_id = insert_into(addresses::table)
-        .values(payload.parse_address(session.user_id))
-        .returning(addresses::id)
-        .get_result::<i32>(&mut conn).await
-        .map_err(internal_error)?;
-    let order_id = insert_into(orders::table)
-        .values(Order {
-            user_id: session.user_id,
-            address_id,
-        })
-        .returning(orders::id)
-        .get_result::<i32>(&mut conn).await
-        .map_err(internal_error)?;
-    sql_query(format!("insert into productorders select product_id, {}, quantity from cartproducts where cartproducts.user_id = {};",
-        order_id as i32,
-        session.user_id))
-        .execute(&mut conn).await.map_err(internal_error)?;
-    diesel::delete(cartproducts::table)
-        .filter(cartproducts::user_id.eq(session.user_id))
-        .execute(&mut conn)
+    let address = payload.parse_address(session.user_id);
+    // the order records what the customer was looking at, but is always charged in pounds
+    let shown_in = currency::display_currency(&mut conn, Some(&session), &jar)
         .await
         .map_err(internal_error)?;
+    conn.transaction::<_, CheckoutError, _>(|conn| {
+        async move {
+            let items: Vec<CheckoutLine> = cartproducts::table
+                .inner_join(productvariants::table.inner_join(products::table))
+                .select((
+                    cartproducts::variant_id,
+                    productvariants::sku,
+                    cartproducts::quantity,
+                    products::title,
+                    productvariants::size,
+                    products::id,
+                    productvariants::cost,
+                    cartproducts::personalisation,
+                    cartproducts::surcharge,
+                ))
+                .filter(cartproducts::user_id.eq(session.user_id))
+                .load(conn)
+                .await?;
+            if items.is_empty() {
+                return Err(CheckoutError::EmptyCart);
+            }
+            let unavailable: Option<(String, String)> = cartproducts::table
+                .inner_join(productvariants::table.inner_join(products::table))
+                .select((products::title, productvariants::size))
+                .filter(cartproducts::user_id.eq(session.user_id))
+                .filter(not(schedule::is_live()).or(productvariants::listed.eq(false)))
+                .first(conn)
+                .await
+                .optional()?;
+            if let Some((title, size)) = unavailable {
+                return Err(CheckoutError::NotForSale(format!("{} ({})", title, size)));
+            }
+            // charge whatever the price is now, even if a sale started or ended since the cart was
+            // last looked at. Surcharges are the ones shown when the item was added
+            let ids: Vec<i32> = items.iter().map(|i| i.5).collect();
+            let sales = sales::current_sales(conn, &ids).await?;
+            let mut settlement_total = Money::zero(Currency::BASE);
+            let mut shown_total = Money::zero(shown_in.currency);
+            for (_, _, quantity, _, _, product_id, cost, _, surcharge) in &items {
+                let unit_cost =
+                    sales::current_price(&sales, *product_id, cost).checked_add(surcharge)?;
+                shown_total = shown_total.checked_add(&shown_in.line_total(&unit_cost, *quantity))?;
+                settlement_total = settlement_total.checked_add(&unit_cost.times(*quantity))?;
+            }
+            let address_id = insert_into(addresses::table)
+                .values(address)
+                .returning(addresses::id)
+                .get_result::<i32>(conn)
+                .await?;
+            let order_id = insert_into(orders::table)
+                .values(Order {
+                    user_id: session.user_id,
+                    address_id,
+                    currency: shown_in.currency.code().to_owned(),
+                    exchange_rate: shown_in.rate,
+                    settlement_total,
+                    shown_total: shown_total.amount().clone(),
+                })
+                .returning(orders::id)
+                .get_result::<i32>(conn)
+                .await?;
+            let mut lines = vec![];
+            // the stock check and decrement happen in one statement so a concurrent checkout for the
+            // same units blocks on the row lock and then fails the check instead of overselling
+            for (
+                variant_id,
+                sku,
+                quantity,
+                title,
+                size,
+                product_id,
+                cost,
+                personalisation,
+                surcharge,
+            ) in items
+            {
+                let n = update(productvariants::table)
+                    .filter(productvariants::id.eq(variant_id))
+                    .filter(productvariants::stock.ge(quantity))
+                    .set(productvariants::stock.eq(productvariants::stock - quantity))
+                    .execute(conn)
+                    .await?;
+                if n == 0 {
+                    return Err(CheckoutError::OutOfStock(format!("{} ({})", title, size)));
+                }
+                insert_into(stockadjustments::table)
+                    .values(NewStockAdjustment {
+                        variant_id,
+                        sku,
+                        delta: -quantity,
+                        reason: String::from("Customer order"),
+                        order_id: Some(order_id),
+                        user_id: session.user_id,
+                    })
+                    .execute(conn)
+                    .await?;
+                lines.push(ProductInOrder {
+                    variant_id,
+                    order_id,
+                    quantity,
+                    unit_cost: sales::current_price(&sales, product_id, &cost)
+                        .checked_add(&surcharge)?,
+                    personalisation,
+                    surcharge,
+                });
+            }
+            insert_into(productorders::table)
+                .values(lines)
+                .execute(conn)
+                .await?;
+            diesel::delete(cartproducts::table)
+                .filter(cartproducts::user_id.eq(session.user_id))
+                .execute(conn)
+                .await?;
+            Ok(())
+        }
+        .scope_boxed()
+    })
+    .await?;
     let html = CheckoutSuccess.render().unwrap();
     return Ok(html);
 }
@@ -506,7 +1111,7 @@ pub async fn orders(
             info: order,
             address: addr,
             products: vec![],
-            total: 0.into(),
+            total: Money::zero(Currency::BASE),
         })
     }
     let usr_orders = if usr_orders.is_empty() {
@@ -532,36 +1137,59 @@ pub async fn view_order_details(
         .get(SESSION_COOKIE_NAME)
         .ok_or((StatusCode::UNAUTHORIZED, String::from("401 unauthorized")))?;
     let session = validate_session(session_cookie.value().to_owned(), &state.pool).await?;
-    let address: Address = addresses::table
+    let (order, address): (OrderWithId, Address) = orders::table
+        .inner_join(addresses::table)
         .select((
-            addresses::user_id,
-            addresses::recipient_name,
-            addresses::line_1,
-            addresses::line_2,
-            addresses::postcode,
-            addresses::county,
+            orders::all_columns,
+            (
+                addresses::user_id,
+                addresses::recipient_name,
+                addresses::line_1,
+                addresses::line_2,
+                addresses::postcode,
+                addresses::county,
+            ),
         ))
-        .inner_join(orders::table)
         .filter(orders::id.eq(payload.order_id))
         .filter(orders::user_id.eq(session.user_id))
-        .first::<Address>(&mut conn)
+        .first(&mut conn)
         .await
         .map_err(internal_error)?;
     let products = productorders::table
-        .inner_join(products::table)
-        .select((products::all_columns, productorders::quantity))
+        .inner_join(productvariants::table.inner_join(products::table))
+        .select((
+            products::all_columns,
+            productvariants::all_columns,
+            productorders::quantity,
+            productorders::unit_cost,
+            productorders::personalisation,
+        ))
         .filter(productorders::order_id.eq(payload.order_id))
-        .load::<(Product, i32)>(&mut conn)
+        .load::<(Product, ProductVariant, i32, Money, String)>(&mut conn)
         .await
-        .map_err(internal_error)?;
-    let total: BigDecimal = products.iter().map(|c| &c.0.cost * &c.1).sum();
+        .map_err(internal_error)?
+        .into_iter()
+        // show what was paid rather than the current price
+        .map(|(product, variant, quantity, unit_cost, personalisation)| {
+            (product, ProductVariant { cost: unit_cost, ..variant }, quantity, personalisation)
+        })
+        .collect::<Vec<CartItem>>();
+    let currency = match Currency::from_code(&order.currency) {
+        Some(currency) => DisplayCurrency {
+            currency,
+            rate: order.exchange_rate.clone(),
+        },
+        None => DisplayCurrency::base(),
+    };
+    let total = Money::new(order.shown_total.clone(), currency.currency);
     let html = OrderDetails {
         order_info: OrderInfo {
             address,
-            info: OrderWithId::default(),
+            info: order,
             products,
             total,
         },
+        currency,
     }
     .render()
     .unwrap();
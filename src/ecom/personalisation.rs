@@ -0,0 +1,102 @@
+use diesel::{ExpressionMethods, QueryDsl, QueryResult, SelectableHelper};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+
+use crate::{
+    db::{models::PersonalisationField, schema::personalisationfields},
+    money::{Currency, Money},
+};
+
+/// Typed in by the customer, such as the text on a label
+pub const TEXT: &str = "text";
+/// Picked from the field's choices, such as a ribbon colour
+pub const CHOICE: &str = "choice";
+pub const KINDS: &[&str] = &[TEXT, CHOICE];
+
+/// The longest a text field can be made
+pub const MAX_LENGTH: i32 = 128;
+
+/// The most that can be stored for an item's personalisation, every line included
+const MAX_TOTAL_LENGTH: usize = 1024;
+
+impl PersonalisationField {
+    pub fn choice_list(&self) -> Vec<&str> {
+        self.choices
+            .split(',')
+            .map(str::trim)
+            .filter(|c| !c.is_empty())
+            .collect()
+    }
+
+    pub fn has_surcharge(&self) -> bool {
+        self.surcharge != Money::zero(Currency::BASE)
+    }
+}
+
+/// A product's fields in the order they are shown
+pub async fn fields(
+    conn: &mut AsyncPgConnection,
+    product_id: i32,
+) -> QueryResult<Vec<PersonalisationField>> {
+    personalisationfields::table
+        .select(PersonalisationField::as_select())
+        .filter(personalisationfields::product_id.eq(product_id))
+        .order((
+            personalisationfields::position.asc(),
+            personalisationfields::id.asc(),
+        ))
+        .load(conn)
+        .await
+}
+
+/// Checks what the customer filled in, given as field ids with their values, and gives it as one
+/// "name: value" line per filled in field along with what it adds to each unit's price. Values for
+/// fields the product doesn't have are refused
+pub fn capture(
+    fields: &[PersonalisationField],
+    values: &[(i32, String)],
+) -> Result<(String, Money), String> {
+    if let Some((id, _)) = values
+        .iter()
+        .find(|(id, _)| !fields.iter().any(|f| f.id == *id))
+    {
+        return Err(format!("Unknown personalisation field {}", id));
+    }
+    let mut lines = vec![];
+    let mut surcharge = Money::zero(Currency::BASE);
+    for field in fields {
+        let value = values
+            .iter()
+            .find(|(id, _)| *id == field.id)
+            .map_or("", |(_, value)| value.trim());
+        if value.is_empty() {
+            if field.required {
+                return Err(format!("Please fill in {}", field.name));
+            }
+            continue;
+        }
+        if field.kind == CHOICE {
+            if !field.choice_list().contains(&value) {
+                return Err(format!("Please pick one of the options for {}", field.name));
+            }
+        } else if value.chars().count() > field.max_length as usize {
+            return Err(format!(
+                "{} can be at most {} characters",
+                field.name, field.max_length
+            ));
+        } else if value.chars().any(char::is_control) {
+            return Err(format!("{} can only be one line", field.name));
+        }
+        lines.push(format!("{}: {}", field.name, value));
+        surcharge = surcharge
+            .checked_add(&field.surcharge)
+            .map_err(|e| e.to_string())?;
+    }
+    let lines = lines.join("\n");
+    if lines.chars().count() > MAX_TOTAL_LENGTH {
+        return Err(format!(
+            "Personalisation can be at most {} characters altogether",
+            MAX_TOTAL_LENGTH
+        ));
+    }
+    Ok((lines, surcharge))
+}
@@ -0,0 +1,139 @@
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{AppendHeaders, IntoResponse},
+};
+use axum_extra::extract::{
+    cookie::{Cookie, SameSite},
+    CookieJar, SignedCookieJar,
+};
+use diesel::{delete, insert_into, upsert::excluded, ExpressionMethods, QueryDsl, QueryResult};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use time::Duration;
+
+use crate::{
+    auth::session::validate_session,
+    db::{
+        models::{Product, Session},
+        schema::{products, recentlyviewed},
+    },
+    internal_error, AppState, SESSION_COOKIE_NAME,
+};
+
+use super::schedule;
+
+/// Guests' history is a comma separated list of product ids, most recent first
+pub const RECENT_COOKIE_NAME: &str = "sc-recent";
+
+/// How many products are remembered
+pub const MAX_RECENT: usize = 8;
+
+fn ids_from_cookie(jar: &SignedCookieJar) -> Vec<i32> {
+    let mut ids: Vec<i32> = vec![];
+    if let Some(cookie) = jar.get(RECENT_COOKIE_NAME) {
+        for id in cookie.value().split(',').filter_map(|id| id.parse().ok()) {
+            if !ids.contains(&id) && ids.len() < MAX_RECENT {
+                ids.push(id);
+            }
+        }
+    }
+    ids
+}
+
+/// Remembers that a product was viewed, the returned jar must be sent back for guests
+pub async fn record_view(
+    conn: &mut AsyncPgConnection,
+    session: Option<&Session>,
+    jar: SignedCookieJar,
+    product_id: i32,
+) -> QueryResult<SignedCookieJar> {
+    let Some(session) = session else {
+        let mut ids = ids_from_cookie(&jar);
+        ids.retain(|id| *id != product_id);
+        ids.insert(0, product_id);
+        ids.truncate(MAX_RECENT);
+        let value = ids
+            .iter()
+            .map(|id| id.to_string())
+            .collect::<Vec<_>>()
+            .join(",");
+        return Ok(jar.add(
+            Cookie::build((RECENT_COOKIE_NAME, value))
+                .http_only(true)
+                .same_site(SameSite::Lax)
+                .max_age(Duration::days(30))
+                .path("/"),
+        ));
+    };
+    insert_into(recentlyviewed::table)
+        .values((
+            recentlyviewed::user_id.eq(session.user_id),
+            recentlyviewed::product_id.eq(product_id),
+        ))
+        .on_conflict((recentlyviewed::user_id, recentlyviewed::product_id))
+        .do_update()
+        .set(recentlyviewed::viewed_at.eq(excluded(recentlyviewed::viewed_at)))
+        .execute(conn)
+        .await?;
+    let keep: Vec<i32> = recentlyviewed::table
+        .select(recentlyviewed::product_id)
+        .filter(recentlyviewed::user_id.eq(session.user_id))
+        .order(recentlyviewed::viewed_at.desc())
+        .limit(MAX_RECENT as i64)
+        .load(conn)
+        .await?;
+    delete(recentlyviewed::table)
+        .filter(recentlyviewed::user_id.eq(session.user_id))
+        .filter(recentlyviewed::product_id.ne_all(keep))
+        .execute(conn)
+        .await?;
+    Ok(jar)
+}
+
+/// Listed products the visitor viewed most recently, leaving out `current`
+pub async fn recently_viewed(
+    conn: &mut AsyncPgConnection,
+    session: Option<&Session>,
+    jar: &SignedCookieJar,
+    current: Option<i32>,
+) -> QueryResult<Vec<Product>> {
+    let ids: Vec<i32> = match session {
+        Some(session) => {
+            recentlyviewed::table
+                .select(recentlyviewed::product_id)
+                .filter(recentlyviewed::user_id.eq(session.user_id))
+                .order(recentlyviewed::viewed_at.desc())
+                .limit(MAX_RECENT as i64)
+                .load(conn)
+                .await?
+        }
+        None => ids_from_cookie(jar),
+    };
+    let ids: Vec<i32> = ids.into_iter().filter(|id| Some(*id) != current).collect();
+    let mut products: Vec<Product> = products::table
+        .select(products::all_columns)
+        .filter(products::id.eq_any(&ids))
+        .filter(schedule::is_live())
+        .load(conn)
+        .await?;
+    products.sort_by_key(|p| ids.iter().position(|id| *id == p.id));
+    Ok(products)
+}
+
+pub async fn clear_recent(
+    jar: CookieJar,
+    recent_jar: SignedCookieJar,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    if let Some(session_cookie) = jar.get(SESSION_COOKIE_NAME) {
+        let session = validate_session(session_cookie.value().to_owned(), &state.pool).await?;
+        let mut conn = state.pool.get().await.map_err(internal_error)?;
+        delete(recentlyviewed::table)
+            .filter(recentlyviewed::user_id.eq(session.user_id))
+            .execute(&mut conn)
+            .await
+            .map_err(internal_error)?;
+    }
+    let recent_jar = recent_jar.remove(Cookie::build(RECENT_COOKIE_NAME).path("/"));
+    Ok((AppendHeaders([("HX-Refresh", "true")]), recent_jar))
+}
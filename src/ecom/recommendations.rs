@@ -0,0 +1,128 @@
+use std::{collections::HashMap, time::Duration};
+
+use diesel::{
+    delete, dsl::not, sql_query, BoolExpressionMethods, ExpressionMethods, QueryDsl, QueryResult,
+};
+use diesel_async::{
+    pooled_connection::deadpool::Pool, scoped_futures::ScopedFutureExt, AsyncConnection,
+    AsyncPgConnection, RunQueryDsl,
+};
+
+use crate::db::{
+    models::Product,
+    schema::{
+        likedproducts, orders, productorders, productrecommendations, products, productvariants,
+    },
+};
+
+use super::schedule;
+
+/// How often the recommendations are rebuilt
+pub const REFRESH_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Recommendations kept for each product
+const PER_PRODUCT: i32 = 10;
+
+/// Rebuilds the co-occurrence table. Two products score 2 each time they appear in the same
+/// order and 1 each time the same customer likes both
+pub async fn refresh(conn: &mut AsyncPgConnection) -> QueryResult<()> {
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        async move {
+            delete(productrecommendations::table).execute(conn).await?;
+            sql_query(format!(
+                "with baskets as (
+                    select distinct 'order:' || productorders.order_id as basket, productvariants.product_id, 2 as weight
+                    from productorders inner join productvariants on productvariants.id = productorders.variant_id
+                    union all
+                    select 'like:' || likedproducts.user_id, likedproducts.product_id, 1 from likedproducts
+                ), pairs as (
+                    select a.product_id, b.product_id as recommended_id, sum(a.weight) as score
+                    from baskets a inner join baskets b on a.basket = b.basket and a.product_id <> b.product_id
+                    group by a.product_id, b.product_id
+                )
+                insert into productrecommendations (product_id, recommended_id, score)
+                select product_id, recommended_id, score from (
+                    select *, row_number() over (partition by product_id order by score desc, recommended_id) as rank from pairs
+                ) ranked where rank <= {};",
+                PER_PRODUCT
+            ))
+            .execute(conn)
+            .await?;
+            Ok(())
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+/// Keeps the recommendations up to date for as long as the server runs
+pub async fn refresh_periodically(pool: Pool<AsyncPgConnection>) {
+    let mut interval = tokio::time::interval(REFRESH_INTERVAL);
+    loop {
+        interval.tick().await;
+        let result = match pool.get().await {
+            Ok(mut conn) => refresh(&mut conn).await.map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+        if let Err(e) = result {
+            tracing::warn!("unable to refresh recommendations: {}", e);
+        }
+    }
+}
+
+/// Listed products most often bought or liked alongside `product_id`
+pub async fn also_bought(
+    conn: &mut AsyncPgConnection,
+    product_id: i32,
+    limit: i64,
+) -> QueryResult<Vec<Product>> {
+    productrecommendations::table
+        .inner_join(products::table)
+        .select(products::all_columns)
+        .filter(productrecommendations::product_id.eq(product_id))
+        .filter(schedule::is_live())
+        .order((
+            productrecommendations::score.desc(),
+            productrecommendations::recommended_id.asc(),
+        ))
+        .limit(limit)
+        .load(conn)
+        .await
+}
+
+/// Listed products recommended from everything a customer has bought or liked, leaving out
+/// anything they have already bought or liked
+pub async fn for_user(
+    conn: &mut AsyncPgConnection,
+    user_id: i32,
+    limit: usize,
+) -> QueryResult<Vec<Product>> {
+    let bought = productorders::table
+        .inner_join(orders::table)
+        .inner_join(productvariants::table)
+        .filter(orders::user_id.eq(user_id))
+        .select(productvariants::product_id);
+    let liked = likedproducts::table
+        .filter(likedproducts::user_id.eq(user_id))
+        .select(likedproducts::product_id);
+    let recommended: Vec<(Product, i32)> = productrecommendations::table
+        .inner_join(products::table)
+        .select((products::all_columns, productrecommendations::score))
+        .filter(
+            productrecommendations::product_id
+                .eq_any(bought)
+                .or(productrecommendations::product_id.eq_any(liked)),
+        )
+        .filter(not(products::id.eq_any(bought)))
+        .filter(not(products::id.eq_any(liked)))
+        .filter(schedule::is_live())
+        .load(conn)
+        .await?;
+    let mut scores: HashMap<i32, (Product, i32)> = HashMap::new();
+    for (product, score) in recommended {
+        scores.entry(product.id).or_insert((product, 0)).1 += score;
+    }
+    let mut scores: Vec<(Product, i32)> = scores.into_values().collect();
+    scores.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.id.cmp(&b.0.id)));
+    Ok(scores.into_iter().take(limit).map(|(p, _)| p).collect())
+}
@@ -0,0 +1,209 @@
+use std::{collections::HashMap, sync::LazyLock};
+
+use axum::{extract::State, http::StatusCode, response::IntoResponse};
+use axum_extra::extract::{CookieJar, Form};
+use bigdecimal::{BigDecimal, RoundingMode};
+use diesel::{dsl::exists, insert_into, select, ExpressionMethods, QueryDsl, QueryResult};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::{
+    auth::session::validate_session,
+    db::{
+        models::NewReview,
+        schema::{orders, productorders, productvariants, reviews},
+    },
+    internal_error, AppState, SESSION_COOKIE_NAME,
+};
+
+pub const PENDING: &str = "pending";
+pub const APPROVED: &str = "approved";
+pub const REJECTED: &str = "rejected";
+
+/// Reviews are matched word by word against this list, so words that merely contain one of these
+/// are not rejected
+const PROFANITY: &[&str] = &[
+    "arse",
+    "arsehole",
+    "asshole",
+    "bastard",
+    "bitch",
+    "bollocks",
+    "bullshit",
+    "cock",
+    "crap",
+    "cunt",
+    "dick",
+    "fuck",
+    "fucker",
+    "fucking",
+    "motherfucker",
+    "piss",
+    "prick",
+    "shit",
+    "shite",
+    "slut",
+    "twat",
+    "wanker",
+    "whore",
+];
+
+/// Web and email addresses, compiled once rather than for every review checked
+static LINKS: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)(https?://|www\.|\.(com|net|org|ru|xyz|io)\b|\S+@\S+\.\S+)").unwrap()
+});
+
+#[derive(Deserialize)]
+pub struct ReviewForm {
+    product_id: i32,
+    rating: i32,
+    body: String,
+}
+
+/// Lowercases a word, undoes common letter substitutions and collapses repeated letters, so
+/// "Sh1iiit" and "shit" compare equal
+fn normalise_word(word: &str) -> String {
+    let mut normalised = String::new();
+    for c in word.chars() {
+        let c = match c.to_ascii_lowercase() {
+            '0' => 'o',
+            '1' | '!' => 'i',
+            '3' => 'e',
+            '4' | '@' => 'a',
+            '5' | '$' => 's',
+            '7' => 't',
+            c if c.is_ascii_alphabetic() => c,
+            _ => continue,
+        };
+        if !normalised.ends_with(c) {
+            normalised.push(c);
+        }
+    }
+    normalised
+}
+
+/// Local spam and profanity checks, returning the reason a review was refused
+pub fn check_review(body: &str) -> Result<(), &'static str> {
+    let profanity: Vec<String> = PROFANITY.iter().map(|w| normalise_word(w)).collect();
+    if body
+        .split_whitespace()
+        .any(|word| profanity.contains(&normalise_word(word)))
+    {
+        return Err("Please keep reviews free of bad language");
+    }
+    if LINKS.is_match(body) {
+        return Err("Reviews cannot contain links or email addresses");
+    }
+    let mut run = 0;
+    let mut last = None;
+    for c in body.chars() {
+        run = if Some(c) == last { run + 1 } else { 1 };
+        last = Some(c);
+        if run >= 6 {
+            return Err("Reviews cannot contain long runs of the same character");
+        }
+    }
+    let letters: Vec<char> = body.chars().filter(|c| c.is_alphabetic()).collect();
+    let upper = letters.iter().filter(|c| c.is_uppercase()).count();
+    if letters.len() >= 20 && upper * 10 > letters.len() * 7 {
+        return Err("Please don't write reviews in capitals");
+    }
+    Ok(())
+}
+
+/// Only customers with the product in a past order may review it
+pub async fn has_purchased(
+    conn: &mut AsyncPgConnection,
+    user_id: i32,
+    product_id: i32,
+) -> QueryResult<bool> {
+    select(exists(
+        productorders::table
+            .inner_join(orders::table)
+            .inner_join(productvariants::table)
+            .filter(orders::user_id.eq(user_id))
+            .filter(productvariants::product_id.eq(product_id)),
+    ))
+    .get_result(conn)
+    .await
+}
+
+/// The average approved rating, to one decimal place, and number of approved reviews for each
+/// of `ids` that has any
+pub async fn ratings(
+    conn: &mut AsyncPgConnection,
+    ids: &[i32],
+) -> QueryResult<HashMap<i32, (BigDecimal, i64)>> {
+    // diesel's avg is ambiguous between its sql function and type alias, so average here instead
+    let approved: Vec<(i32, i32)> = reviews::table
+        .select((reviews::product_id, reviews::rating))
+        .filter(reviews::product_id.eq_any(ids))
+        .filter(reviews::status.eq(APPROVED))
+        .load(conn)
+        .await?;
+    let mut totals: HashMap<i32, (i64, i64)> = HashMap::new();
+    for (id, rating) in approved {
+        let total = totals.entry(id).or_default();
+        total.0 += rating as i64;
+        total.1 += 1;
+    }
+    Ok(totals
+        .into_iter()
+        .map(|(id, (sum, n))| {
+            let average = BigDecimal::from(sum) / BigDecimal::from(n);
+            (id, (average.with_scale_round(1, RoundingMode::HalfUp), n))
+        })
+        .collect())
+}
+
+pub async fn review_post_handler(
+    jar: CookieJar,
+    State(state): State<AppState>,
+    Form(payload): Form<ReviewForm>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let session_cookie = jar
+        .get(SESSION_COOKIE_NAME)
+        .ok_or((StatusCode::UNAUTHORIZED, String::from("401 unauthorized")))?;
+    let session = validate_session(session_cookie.value().to_owned(), &state.pool).await?;
+    if !(1..=5).contains(&payload.rating) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            String::from("Please give a rating of 1 to 5 stars"),
+        ));
+    }
+    let body = payload.body.trim().to_owned();
+    if body.chars().count() < 10 || body.chars().count() > 2000 {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            String::from("Reviews must be between 10 and 2000 characters"),
+        ));
+    }
+    check_review(&body).map_err(|e| (StatusCode::BAD_REQUEST, String::from(e)))?;
+    let mut conn = state.pool.get().await.map_err(internal_error)?;
+    if !has_purchased(&mut conn, session.user_id, payload.product_id)
+        .await
+        .map_err(internal_error)?
+    {
+        return Err((
+            StatusCode::FORBIDDEN,
+            String::from("Only customers who have bought this product can review it"),
+        ));
+    }
+    insert_into(reviews::table)
+        .values(NewReview {
+            product_id: payload.product_id,
+            user_id: session.user_id,
+            rating: payload.rating,
+            body,
+        })
+        .execute(&mut conn)
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::CONFLICT,
+                String::from("You have already reviewed this product"),
+            )
+        })?;
+    Ok("Thanks, your review will appear once it has been checked")
+}
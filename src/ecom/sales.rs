@@ -0,0 +1,96 @@
+use std::{cmp::Reverse, collections::HashMap};
+
+use diesel::{BoolExpressionMethods, ExpressionMethods, QueryDsl, QueryResult, SelectableHelper};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use time::OffsetDateTime;
+
+use crate::{
+    db::{
+        models::Sale,
+        schema::{collectionproducts, sales},
+    },
+    money::Money,
+};
+
+impl Sale {
+    /// `cost` with the discount taken off
+    pub fn price(&self, cost: &Money) -> Money {
+        cost.percent_off(self.percent_off)
+    }
+
+    /// How long is left, e.g. "2d 4h 13m 5s". `countdown.js` keeps this ticking in the browser
+    pub fn time_left(&self) -> String {
+        let left = (self.ends_at - OffsetDateTime::now_utc())
+            .whole_seconds()
+            .max(0);
+        let (days, hours, minutes, seconds) = (
+            left / 86400,
+            left % 86400 / 3600,
+            left % 3600 / 60,
+            left % 60,
+        );
+        if days > 0 {
+            format!("{}d {}h {}m {}s", days, hours, minutes, seconds)
+        } else {
+            format!("{}h {}m {}s", hours, minutes, seconds)
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        let now = OffsetDateTime::now_utc();
+        self.starts_at <= now && now < self.ends_at
+    }
+}
+
+/// The running sale taking the most off each of `ids`, sales for a single product, for a
+/// collection it is pinned to and sales on everything are treated alike
+pub async fn current_sales(
+    conn: &mut AsyncPgConnection,
+    ids: &[i32],
+) -> QueryResult<HashMap<i32, Sale>> {
+    let now = OffsetDateTime::now_utc();
+    let pinned: Vec<(i32, i32)> = collectionproducts::table
+        .select((collectionproducts::product_id, collectionproducts::collection_id))
+        .filter(collectionproducts::product_id.eq_any(ids))
+        .load(conn)
+        .await?;
+    let collection_ids: Vec<i32> = pinned.iter().map(|p| p.1).collect();
+    let running: Vec<Sale> = sales::table
+        .select(Sale::as_select())
+        .filter(sales::starts_at.le(now))
+        .filter(sales::ends_at.gt(now))
+        .filter(
+            sales::product_id
+                .is_null()
+                .and(sales::collection_id.is_null())
+                .or(sales::product_id.eq_any(ids))
+                .or(sales::collection_id.eq_any(collection_ids)),
+        )
+        .load(conn)
+        .await?;
+    let mut best = HashMap::new();
+    for id in ids {
+        let covers = |sale: &&Sale| match (sale.product_id, sale.collection_id) {
+            (Some(product_id), _) => product_id == *id,
+            (None, Some(collection_id)) => pinned.contains(&(*id, collection_id)),
+            (None, None) => true,
+        };
+        // the bigger discount wins, then the one ending soonest so the countdown is honest
+        let sale = running
+            .iter()
+            .filter(covers)
+            .max_by_key(|s| (s.percent_off, Reverse(s.ends_at)));
+        if let Some(sale) = sale {
+            best.insert(*id, sale.clone());
+        }
+    }
+    Ok(best)
+}
+
+/// What one of `product_id`'s variants costs right now
+pub fn current_price(sales: &HashMap<i32, Sale>, product_id: i32, cost: &Money) -> Money {
+    match sales.get(&product_id) {
+        Some(sale) => sale.price(cost),
+        None => cost.clone(),
+    }
+}
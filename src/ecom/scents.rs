@@ -0,0 +1,160 @@
+use std::collections::{BTreeSet, HashMap};
+
+use diesel::{ExpressionMethods, QueryDsl, QueryResult, SelectableHelper};
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+
+use crate::db::{
+    models::{Product, ScentNote},
+    schema::{products, productscentnotes, scentnotes},
+};
+
+use super::schedule;
+
+pub const FRUITY: &str = "fruity";
+pub const FLORAL: &str = "floral";
+pub const GOURMAND: &str = "gourmand";
+pub const FRESH: &str = "fresh";
+pub const FAMILIES: &[&str] = &[FRUITY, FLORAL, GOURMAND, FRESH];
+
+/// Smelt first, when the candle is lit
+pub const TOP: &str = "top";
+/// Smelt once the candle has warmed up
+pub const MIDDLE: &str = "middle";
+/// Lingers the longest
+pub const BASE: &str = "base";
+
+/// A product's notes by tier and the families they belong to
+#[derive(Default)]
+pub struct ScentProfile {
+    pub top: Vec<ScentNote>,
+    pub middle: Vec<ScentNote>,
+    pub base: Vec<ScentNote>,
+    pub families: Vec<String>,
+}
+
+impl ScentProfile {
+    pub fn is_empty(&self) -> bool {
+        self.top.is_empty() && self.middle.is_empty() && self.base.is_empty()
+    }
+
+    /// The notes of one tier as a comma separated list, as typed into the dashboard
+    pub fn names(&self, tier: &str) -> String {
+        let notes = match tier {
+            TOP => &self.top,
+            MIDDLE => &self.middle,
+            _ => &self.base,
+        };
+        notes
+            .iter()
+            .map(|n| n.name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// How a family is shown to customers
+pub fn family_label(family: &str) -> String {
+    let mut chars = family.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// Every note, grouped by family and then by name
+pub async fn all_notes(conn: &mut AsyncPgConnection) -> QueryResult<Vec<ScentNote>> {
+    scentnotes::table
+        .select(ScentNote::as_select())
+        .order((scentnotes::family.asc(), scentnotes::name.asc()))
+        .load(conn)
+        .await
+}
+
+pub async fn profile(conn: &mut AsyncPgConnection, product_id: i32) -> QueryResult<ScentProfile> {
+    let notes: Vec<(ScentNote, String)> = productscentnotes::table
+        .inner_join(scentnotes::table)
+        .select((ScentNote::as_select(), productscentnotes::tier))
+        .filter(productscentnotes::product_id.eq(product_id))
+        .order(scentnotes::name.asc())
+        .load(conn)
+        .await?;
+    let mut profile = ScentProfile::default();
+    let mut families = BTreeSet::new();
+    for (note, tier) in notes {
+        families.insert(note.family.clone());
+        match tier.as_str() {
+            TOP => profile.top.push(note),
+            MIDDLE => profile.middle.push(note),
+            _ => profile.base.push(note),
+        }
+    }
+    // in the order families are listed everywhere else
+    profile.families = FAMILIES
+        .iter()
+        .filter(|f| families.contains(**f))
+        .map(|f| f.to_string())
+        .collect();
+    Ok(profile)
+}
+
+/// How alike two scent profiles are from 0 to 1, the notes they share over all the notes either
+/// has. A shared note counts fully when it is in the same tier of both and half otherwise
+pub fn similarity(a: &[(i32, String)], b: &[(i32, String)]) -> f64 {
+    let mut shared = 0.0;
+    for (note, tier) in a {
+        if let Some((_, other)) = b.iter().find(|(n, _)| n == note) {
+            shared += if tier == other { 1.0 } else { 0.5 };
+        }
+    }
+    let all: BTreeSet<i32> = a.iter().chain(b).map(|(n, _)| *n).collect();
+    if all.is_empty() {
+        return 0.0;
+    }
+    shared / all.len() as f64
+}
+
+/// Products for sale that smell most like `product_id`, leaving out any with no notes in common
+pub async fn similar(
+    conn: &mut AsyncPgConnection,
+    product_id: i32,
+    limit: usize,
+) -> QueryResult<Vec<Product>> {
+    let own: Vec<(i32, String)> = productscentnotes::table
+        .select((productscentnotes::note_id, productscentnotes::tier))
+        .filter(productscentnotes::product_id.eq(product_id))
+        .load(conn)
+        .await?;
+    if own.is_empty() {
+        return Ok(vec![]);
+    }
+    let rows: Vec<(i32, i32, String)> = productscentnotes::table
+        .inner_join(products::table)
+        .select((
+            productscentnotes::product_id,
+            productscentnotes::note_id,
+            productscentnotes::tier,
+        ))
+        .filter(productscentnotes::product_id.ne(product_id))
+        .filter(schedule::is_live())
+        .load(conn)
+        .await?;
+    let mut profiles: HashMap<i32, Vec<(i32, String)>> = HashMap::new();
+    for (id, note, tier) in rows {
+        profiles.entry(id).or_default().push((note, tier));
+    }
+    let mut scored: Vec<(f64, i32)> = profiles
+        .iter()
+        .map(|(id, notes)| (similarity(&own, notes), *id))
+        .filter(|(score, _)| *score > 0.0)
+        .collect();
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(&b.1)));
+    scored.truncate(limit);
+    let ids: Vec<i32> = scored.iter().map(|(_, id)| *id).collect();
+    let mut products: Vec<Product> = products::table
+        .select(products::all_columns)
+        .filter(products::id.eq_any(&ids))
+        .load(conn)
+        .await?;
+    products.sort_by_key(|p| ids.iter().position(|id| *id == p.id));
+    Ok(products)
+}
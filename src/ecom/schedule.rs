@@ -0,0 +1,100 @@
+use std::time::Duration;
+
+use diesel::{
+    dsl, update, BoolExpressionMethods, ExpressionMethods, NullableExpressionMethods, QueryResult,
+};
+use diesel_async::{pooled_connection::deadpool::Pool, AsyncPgConnection, RunQueryDsl};
+use time::OffsetDateTime;
+
+use crate::db::{models::Product, schema::products};
+
+/// How often due publishing changes are made to `listed`, customers see them straight away
+/// either way because `is_live` checks the times itself
+const APPLY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Products customers can see right now, either listed or due to be published, and not due to be
+/// unpublished
+#[dsl::auto_type]
+pub fn is_live() -> _ {
+    products::listed
+        .eq(true)
+        .or(products::publish_at
+            .is_not_null()
+            .and(products::publish_at.assume_not_null().le(dsl::now)))
+        .and(
+            products::unpublish_at
+                .is_null()
+                .or(products::unpublish_at.assume_not_null().gt(dsl::now)),
+        )
+}
+
+impl Product {
+    /// The same check as `is_live` for a product that has already been loaded
+    pub fn is_live(&self) -> bool {
+        let now = OffsetDateTime::now_utc();
+        (self.listed || self.publish_at.is_some_and(|at| at <= now))
+            && self.unpublish_at.is_none_or(|at| at > now)
+    }
+
+    /// Whether the product is waiting to be published for the first time, it is kept secret
+    /// until then
+    pub fn is_upcoming(&self) -> bool {
+        !self.is_live()
+            && self
+                .publish_at
+                .is_some_and(|at| at > OffsetDateTime::now_utc())
+    }
+}
+
+/// Lists and unlists products whose times have passed and clears those times, so the dashboard
+/// and everything else that looks at `listed` agrees with what customers see
+pub async fn apply_due(conn: &mut AsyncPgConnection) -> QueryResult<usize> {
+    apply(conn, None).await
+}
+
+/// The same as `apply_due` for only the products in `ids`
+pub async fn apply_due_to(conn: &mut AsyncPgConnection, ids: &[i32]) -> QueryResult<usize> {
+    apply(conn, Some(ids)).await
+}
+
+async fn apply(conn: &mut AsyncPgConnection, ids: Option<&[i32]>) -> QueryResult<usize> {
+    // unpublishing goes first so a window that has already closed leaves the product unlisted
+    let mut unlist = update(products::table)
+        .filter(products::unpublish_at.le(dsl::now))
+        .set((
+            products::listed.eq(false),
+            products::publish_at.eq(None::<OffsetDateTime>),
+            products::unpublish_at.eq(None::<OffsetDateTime>),
+        ))
+        .into_boxed();
+    let mut list = update(products::table)
+        .filter(products::publish_at.le(dsl::now))
+        .filter(products::archived_at.is_null())
+        .set((
+            products::listed.eq(true),
+            products::publish_at.eq(None::<OffsetDateTime>),
+        ))
+        .into_boxed();
+    if let Some(ids) = ids {
+        unlist = unlist.filter(products::id.eq_any(ids));
+        list = list.filter(products::id.eq_any(ids));
+    }
+    let unlisted = unlist.execute(conn).await?;
+    let listed = list.execute(conn).await?;
+    Ok(unlisted + listed)
+}
+
+/// Keeps `listed` in step with the publishing times for as long as the server runs
+pub async fn apply_periodically(pool: Pool<AsyncPgConnection>) {
+    let mut interval = tokio::time::interval(APPLY_INTERVAL);
+    loop {
+        interval.tick().await;
+        let result = match pool.get().await {
+            Ok(mut conn) => apply_due(&mut conn).await.map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+        if let Err(e) = result {
+            tracing::warn!("unable to apply publishing times: {}", e);
+        }
+    }
+}
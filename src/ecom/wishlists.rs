@@ -0,0 +1,377 @@
+use askama::Template;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{AppendHeaders, Html, IntoResponse},
+};
+use axum_extra::extract::{CookieJar, Form};
+use diesel::{
+    delete, dsl::count_star, insert_into, update, ExpressionMethods, OptionalExtension, QueryDsl,
+    QueryResult, SelectableHelper,
+};
+use diesel_async::{
+    scoped_futures::ScopedFutureExt, AsyncConnection, AsyncPgConnection, RunQueryDsl,
+};
+use serde::Deserialize;
+
+use crate::{
+    auth::session::{optional_session, require_session},
+    db::{
+        models::{Product, ProductVariant, Session, Wishlist},
+        schema::{products, productvariants, wishlistproducts, wishlists},
+    },
+    images::filters,
+    internal_error, AppState,
+};
+
+use super::{
+    currency::{self, DisplayCurrency},
+    generate_token, product_cards, schedule, Action, ProductCard,
+};
+
+/// Named lists a customer can have, on top of their favourites
+pub const MAX_LISTS: i64 = 20;
+const MAX_NAME_LENGTH: usize = 64;
+
+#[derive(Deserialize)]
+pub struct NewListForm {
+    name: String,
+}
+
+#[derive(Deserialize)]
+pub struct ListForm {
+    id: i32,
+}
+
+#[derive(Deserialize)]
+pub struct ShareForm {
+    id: i32,
+    action: Action,
+}
+
+#[derive(Template)]
+#[template(path = "wishlists.html")]
+struct WishlistsTemplate {
+    logged_in: bool,
+    lists: Vec<(Wishlist, i64)>,
+}
+
+#[derive(Template)]
+#[template(path = "wishlist.html")]
+struct WishlistTemplate {
+    logged_in: bool,
+    list: Wishlist,
+    products: Vec<ProductCard>,
+    currency: DisplayCurrency,
+}
+
+#[derive(Template)]
+#[template(path = "shared_wishlist.html")]
+struct SharedWishlistTemplate {
+    logged_in: bool,
+    list: Wishlist,
+    /// Each product with its listed variants, so they can be added to the viewer's cart
+    items: Vec<(ProductCard, Vec<ProductVariant>)>,
+    currency: DisplayCurrency,
+}
+
+/// A list name as typed by the customer, e.g. "Christmas gifts"
+pub fn parse_name(name: &str) -> Result<String, String> {
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+        return Err(format!(
+            "List names must be between 1 and {} characters",
+            MAX_NAME_LENGTH
+        ));
+    }
+    Ok(name.to_owned())
+}
+
+/// One of the customer's own lists, `None` for anyone else's
+pub async fn owned_list(
+    conn: &mut AsyncPgConnection,
+    user_id: i32,
+    list_id: i32,
+) -> QueryResult<Option<Wishlist>> {
+    wishlists::table
+        .select(Wishlist::as_select())
+        .filter(wishlists::id.eq(list_id))
+        .filter(wishlists::user_id.eq(user_id))
+        .first(conn)
+        .await
+        .optional()
+}
+
+/// The customer's lists in the order they were made
+pub async fn user_lists(conn: &mut AsyncPgConnection, user_id: i32) -> QueryResult<Vec<Wishlist>> {
+    wishlists::table
+        .select(Wishlist::as_select())
+        .filter(wishlists::user_id.eq(user_id))
+        .order(wishlists::id.asc())
+        .load(conn)
+        .await
+}
+
+/// Products on a list that customers can see right now, most recently added first. Archived
+/// products and ones waiting to be published are left out
+async fn list_products(conn: &mut AsyncPgConnection, list_id: i32) -> QueryResult<Vec<Product>> {
+    wishlistproducts::table
+        .inner_join(products::table)
+        .select(products::all_columns)
+        .filter(wishlistproducts::wishlist_id.eq(list_id))
+        .filter(products::archived_at.is_null())
+        .filter(schedule::is_live())
+        .order(wishlistproducts::added_at.desc())
+        .load(conn)
+        .await
+}
+
+/// Adds a product to or removes it from one of the customer's lists, for `like_post_handler`
+pub async fn update_list(
+    conn: &mut AsyncPgConnection,
+    session: &Session,
+    list_id: i32,
+    product_id: i32,
+    action: Action,
+) -> Result<String, (StatusCode, String)> {
+    let list = owned_list(conn, session.user_id, list_id)
+        .await
+        .map_err(internal_error)?
+        .ok_or((StatusCode::NOT_FOUND, String::from("404 Not Found")))?;
+    match action {
+        Action::Add => {
+            let archived: Option<bool> = products::table
+                .select(products::archived_at.is_not_null())
+                .filter(products::id.eq(product_id))
+                .first(conn)
+                .await
+                .optional()
+                .map_err(internal_error)?;
+            if archived != Some(false) {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    String::from("This product is no longer for sale"),
+                ));
+            }
+            insert_into(wishlistproducts::table)
+                .values((
+                    wishlistproducts::wishlist_id.eq(list.id),
+                    wishlistproducts::product_id.eq(product_id),
+                ))
+                .on_conflict_do_nothing()
+                .execute(conn)
+                .await
+                .map_err(internal_error)?;
+            Ok(format!("Saved to {} ✔", list.name))
+        }
+        Action::Remove => {
+            delete(wishlistproducts::table)
+                .filter(wishlistproducts::wishlist_id.eq(list.id))
+                .filter(wishlistproducts::product_id.eq(product_id))
+                .execute(conn)
+                .await
+                .map_err(internal_error)?;
+            Ok(String::from("Removed ✔"))
+        }
+    }
+}
+
+pub async fn lists(
+    jar: CookieJar,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let session = require_session(&jar, &state.pool).await?;
+    let mut conn = state.pool.get().await.map_err(internal_error)?;
+    let lists = user_lists(&mut conn, session.user_id)
+        .await
+        .map_err(internal_error)?;
+    let ids: Vec<i32> = lists.iter().map(|l| l.id).collect();
+    let counts: Vec<(i32, i64)> = wishlistproducts::table
+        .inner_join(products::table)
+        .group_by(wishlistproducts::wishlist_id)
+        .select((wishlistproducts::wishlist_id, count_star()))
+        .filter(wishlistproducts::wishlist_id.eq_any(&ids))
+        .filter(products::archived_at.is_null())
+        .filter(schedule::is_live())
+        .load(&mut conn)
+        .await
+        .map_err(internal_error)?;
+    let template = WishlistsTemplate {
+        logged_in: true,
+        lists: lists
+            .into_iter()
+            .map(|list| {
+                let count = counts.iter().find(|c| c.0 == list.id).map_or(0, |c| c.1);
+                (list, count)
+            })
+            .collect(),
+    };
+    Ok(Html(template.render().unwrap()))
+}
+
+pub async fn create_list(
+    jar: CookieJar,
+    State(state): State<AppState>,
+    Form(form): Form<NewListForm>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let session = require_session(&jar, &state.pool).await?;
+    let name = parse_name(&form.name).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    let mut conn = state.pool.get().await.map_err(internal_error)?;
+    let existing = user_lists(&mut conn, session.user_id)
+        .await
+        .map_err(internal_error)?;
+    if existing.len() as i64 >= MAX_LISTS {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("You can have up to {} lists", MAX_LISTS),
+        ));
+    }
+    if existing.iter().any(|l| l.name == name) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            String::from("You already have a list with that name"),
+        ));
+    }
+    insert_into(wishlists::table)
+        .values((
+            wishlists::user_id.eq(session.user_id),
+            wishlists::name.eq(&name),
+        ))
+        .execute(&mut conn)
+        .await
+        .map_err(internal_error)?;
+    Ok(AppendHeaders([("HX-Refresh", "true")]))
+}
+
+pub async fn delete_list(
+    jar: CookieJar,
+    State(state): State<AppState>,
+    Form(form): Form<ListForm>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let session = require_session(&jar, &state.pool).await?;
+    let mut conn = state.pool.get().await.map_err(internal_error)?;
+    let list = owned_list(&mut conn, session.user_id, form.id)
+        .await
+        .map_err(internal_error)?
+        .ok_or((StatusCode::NOT_FOUND, String::from("404 Not Found")))?;
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        async move {
+            delete(wishlistproducts::table)
+                .filter(wishlistproducts::wishlist_id.eq(list.id))
+                .execute(conn)
+                .await?;
+            delete(wishlists::table)
+                .filter(wishlists::id.eq(list.id))
+                .execute(conn)
+                .await?;
+            Ok(())
+        }
+        .scope_boxed()
+    })
+    .await
+    .map_err(internal_error)?;
+    Ok(AppendHeaders([("HX-Location", "/lists")]))
+}
+
+/// Starts or stops sharing a list. Sharing again gives a new link, so one that has been passed
+/// around too far can be retired
+pub async fn share_list(
+    jar: CookieJar,
+    State(state): State<AppState>,
+    Form(form): Form<ShareForm>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let session = require_session(&jar, &state.pool).await?;
+    let mut conn = state.pool.get().await.map_err(internal_error)?;
+    let list = owned_list(&mut conn, session.user_id, form.id)
+        .await
+        .map_err(internal_error)?
+        .ok_or((StatusCode::NOT_FOUND, String::from("404 Not Found")))?;
+    let token = match form.action {
+        Action::Add => Some(generate_token()),
+        Action::Remove => None,
+    };
+    update(wishlists::table)
+        .filter(wishlists::id.eq(list.id))
+        .set(wishlists::share_token.eq(token))
+        .execute(&mut conn)
+        .await
+        .map_err(internal_error)?;
+    Ok(AppendHeaders([("HX-Refresh", "true")]))
+}
+
+/// One of the customer's own lists
+pub async fn list(
+    jar: CookieJar,
+    Path(id): Path<i32>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let session = require_session(&jar, &state.pool).await?;
+    let mut conn = state.pool.get().await.map_err(internal_error)?;
+    let list = owned_list(&mut conn, session.user_id, id)
+        .await
+        .map_err(internal_error)?
+        .ok_or((StatusCode::NOT_FOUND, String::from("404 Not Found")))?;
+    let products = list_products(&mut conn, list.id)
+        .await
+        .map_err(internal_error)?;
+    let template = WishlistTemplate {
+        logged_in: true,
+        list,
+        products: product_cards(products, &mut conn).await?,
+        currency: currency::display_currency(&mut conn, Some(&session), &jar)
+            .await
+            .map_err(internal_error)?,
+    };
+    Ok(Html(template.render().unwrap()))
+}
+
+/// A list someone has shared, anyone with the link can see it but only its owner can change it
+pub async fn shared_list(
+    jar: CookieJar,
+    Path(token): Path<String>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let mut conn = state.pool.get().await.map_err(internal_error)?;
+    let list: Wishlist = wishlists::table
+        .select(Wishlist::as_select())
+        .filter(wishlists::share_token.eq(&token))
+        .first(&mut conn)
+        .await
+        .optional()
+        .map_err(internal_error)?
+        .ok_or((StatusCode::NOT_FOUND, String::from("404 Not Found")))?;
+    let session = optional_session(&jar, &state.pool).await;
+    let products = list_products(&mut conn, list.id)
+        .await
+        .map_err(internal_error)?;
+    let ids: Vec<i32> = products.iter().map(|p| p.id).collect();
+    let variants: Vec<ProductVariant> = productvariants::table
+        .select(ProductVariant::as_select())
+        .filter(productvariants::product_id.eq_any(&ids))
+        .filter(productvariants::listed.eq(true))
+        .order(productvariants::cost.asc())
+        .load(&mut conn)
+        .await
+        .map_err(internal_error)?;
+    let items = product_cards(products, &mut conn)
+        .await?
+        .into_iter()
+        .map(|card| {
+            let variants = variants
+                .iter()
+                .filter(|v| v.product_id == card.product.id)
+                .cloned()
+                .collect();
+            (card, variants)
+        })
+        .collect();
+    let template = SharedWishlistTemplate {
+        logged_in: session.is_some(),
+        currency: currency::display_currency(&mut conn, session.as_ref(), &jar)
+            .await
+            .map_err(internal_error)?,
+        list,
+        items,
+    };
+    Ok(Html(template.render().unwrap()))
+}
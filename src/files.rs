@@ -0,0 +1,14 @@
+use std::{io, path::Path};
+
+use tokio::fs;
+
+/// Writes the file next to its final path then renames it into place, so a reader never sees
+/// a partially written file
+pub async fn write_atomic(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+    let tmp = path.with_file_name(format!(".{}.tmp", name.to_string_lossy()));
+    fs::write(&tmp, contents).await?;
+    fs::rename(&tmp, path).await
+}
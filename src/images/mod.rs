@@ -0,0 +1,440 @@
+use std::{env, io::Cursor, sync::Arc};
+
+use axum::{
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+};
+use diesel::{
+    delete, insert_into, update, upsert::excluded, ExpressionMethods, OptionalExtension, QueryDsl,
+    QueryResult,
+};
+use diesel_async::{
+    pooled_connection::deadpool::Pool, scoped_futures::ScopedFutureExt, AsyncConnection,
+    AsyncPgConnection, RunQueryDsl,
+};
+use image::{
+    codecs::{jpeg::JpegEncoder, png::PngEncoder, webp::WebPEncoder},
+    imageops::FilterType,
+    DynamicImage, ImageDecoder, ImageError, ImageFormat, ImageReader, Limits,
+};
+use sha2::Digest;
+use store::{FsImageStore, ImageStore, S3ImageStore};
+
+use crate::{
+    db::schema::{imageblobs, productimages},
+    internal_error, AppState,
+};
+
+pub mod store;
+
+/// Where the filesystem store keeps blobs, shared with the static files served under /files
+pub const IMAGE_DIR: &str = "server_files/images";
+/// Uploads above this are rejected before any decoding is attempted
+pub const MAX_UPLOAD_BYTES: usize = 4 * 1024 * 1024;
+const MAX_DIMENSION: u32 = 6000;
+const MIN_DIMENSION: u32 = 200;
+const JPEG_QUALITY: u8 = 85;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Rendition {
+    Thumb,
+    Medium,
+    Full,
+}
+
+impl Rendition {
+    pub const ALL: [Rendition; 3] = [Rendition::Thumb, Rendition::Medium, Rendition::Full];
+
+    /// The longest edge in pixels, images are never upscaled
+    pub fn max_edge(self) -> u32 {
+        match self {
+            Rendition::Thumb => 300,
+            Rendition::Medium => 800,
+            Rendition::Full => 1600,
+        }
+    }
+
+    fn suffix(self) -> &'static str {
+        match self {
+            Rendition::Thumb => "-thumb",
+            Rendition::Medium => "-medium",
+            Rendition::Full => "",
+        }
+    }
+}
+
+/// Re-encoded renditions of an upload, with all metadata dropped
+pub struct ProcessedImage {
+    pub extension: &'static str,
+    pub renditions: Vec<(Rendition, Vec<u8>)>,
+}
+
+/// The stored name of a rendition, the full rendition keeps the original name so `imgname`
+/// columns always point at something servable
+pub fn rendition_name(imgname: &str, rendition: Rendition) -> String {
+    match imgname.rsplit_once('.') {
+        Some((stem, ext)) => format!("{}{}.{}", stem, rendition.suffix(), ext),
+        None => format!("{}{}", imgname, rendition.suffix()),
+    }
+}
+
+/// Only these formats are accepted, identified by their magic bytes rather than the
+/// client supplied filename or content type
+pub fn detect_format(bytes: &[u8]) -> Option<ImageFormat> {
+    if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some(ImageFormat::Jpeg)
+    } else if bytes.starts_with(&[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A]) {
+        Some(ImageFormat::Png)
+    } else if bytes.len() >= 12 && &bytes[0..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+        Some(ImageFormat::WebP)
+    } else {
+        None
+    }
+}
+
+/// Keys are a lowercase name with a single extension, which rules out path traversal in any store
+pub fn is_valid_key(key: &str) -> bool {
+    match key.split_once('.') {
+        Some((stem, ext)) => {
+            !stem.is_empty()
+                && stem
+                    .chars()
+                    .all(|c| matches!(c, 'a'..='z' | '0'..='9' | '-' | '_'))
+                && matches!(ext, "jpg" | "png" | "webp")
+        }
+        None => false,
+    }
+}
+
+/// Picks the store from `IMAGE_STORE`, either `fs` (the default) or `s3`, which also needs the
+/// `S3_ENDPOINT`, `S3_BUCKET`, `S3_REGION`, `S3_ACCESS_KEY` and `S3_SECRET_KEY` variables
+pub fn store_from_env() -> Arc<dyn ImageStore> {
+    match env::var("IMAGE_STORE").as_deref() {
+        Ok("s3") => {
+            let var = |name: &str| {
+                env::var(name).unwrap_or_else(|_| panic!("Environment variable {} must be set", name))
+            };
+            Arc::new(S3ImageStore::new(
+                &var("S3_ENDPOINT"),
+                &var("S3_BUCKET"),
+                &var("S3_REGION"),
+                &var("S3_ACCESS_KEY"),
+                &var("S3_SECRET_KEY"),
+            ))
+        }
+        _ => Arc::new(FsImageStore::new(IMAGE_DIR)),
+    }
+}
+
+/// Validates an upload and produces every rendition, this is CPU bound so callers should run it
+/// on a blocking thread
+pub fn process_upload(bytes: &[u8], webp: bool) -> Result<ProcessedImage, (StatusCode, String)> {
+    if bytes.len() > MAX_UPLOAD_BYTES {
+        return Err((
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("Images must be smaller than {}MB", MAX_UPLOAD_BYTES / 1024 / 1024),
+        ));
+    }
+    let format = detect_format(bytes).ok_or((
+        StatusCode::UNSUPPORTED_MEDIA_TYPE,
+        String::from("Images must be JPEG, PNG or WebP"),
+    ))?;
+    let img = decode(bytes, format)?;
+    let extension = if webp { "webp" } else { "jpg" };
+    let renditions = Rendition::ALL
+        .iter()
+        .map(|r| Ok((*r, encode(&resize(&img, *r), extension)?)))
+        .collect::<Result<_, (StatusCode, String)>>()?;
+    Ok(ProcessedImage {
+        extension,
+        renditions,
+    })
+}
+
+fn decode(bytes: &[u8], format: ImageFormat) -> Result<DynamicImage, (StatusCode, String)> {
+    let invalid = || {
+        (
+            StatusCode::BAD_REQUEST,
+            String::from("The uploaded image could not be read"),
+        )
+    };
+    let mut reader = ImageReader::with_format(Cursor::new(bytes), format);
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_DIMENSION);
+    limits.max_image_height = Some(MAX_DIMENSION);
+    reader.limits(limits);
+    let mut decoder = reader.into_decoder().map_err(|e| match e {
+        ImageError::Limits(_) => (
+            StatusCode::BAD_REQUEST,
+            format!("Images must be at most {}px on each side", MAX_DIMENSION),
+        ),
+        _ => invalid(),
+    })?;
+    let (width, height) = decoder.dimensions();
+    if width < MIN_DIMENSION || height < MIN_DIMENSION {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("Images must be at least {}px on each side", MIN_DIMENSION),
+        ));
+    }
+    // the orientation tag is lost along with the rest of the metadata, so apply it to the pixels
+    let orientation = decoder.orientation().map_err(|_| invalid())?;
+    let mut img = DynamicImage::from_decoder(decoder).map_err(|_| invalid())?;
+    img.apply_orientation(orientation);
+    Ok(img)
+}
+
+fn resize(img: &DynamicImage, rendition: Rendition) -> DynamicImage {
+    let edge = rendition.max_edge();
+    if img.width() <= edge && img.height() <= edge {
+        img.clone()
+    } else {
+        img.resize(edge, edge, FilterType::Lanczos3)
+    }
+}
+
+fn encode(img: &DynamicImage, extension: &str) -> Result<Vec<u8>, (StatusCode, String)> {
+    let mut buf = Vec::new();
+    let result = match extension {
+        "webp" => img
+            .to_rgba8()
+            .write_with_encoder(WebPEncoder::new_lossless(&mut buf)),
+        "png" => img.to_rgba8().write_with_encoder(PngEncoder::new(&mut buf)),
+        _ => img
+            .to_rgb8()
+            .write_with_encoder(JpegEncoder::new_with_quality(&mut buf, JPEG_QUALITY)),
+    };
+    result.map_err(internal_error)?;
+    Ok(buf)
+}
+
+/// Writes every rendition of an image under a key derived from its content, so uploading the
+/// same image twice shares the stored blobs. Uploads should go through [`store_upload`] instead,
+/// which keeps the blobs from being removed before they are referenced
+pub async fn store_renditions(
+    store: &dyn ImageStore,
+    image: ProcessedImage,
+) -> Result<String, (StatusCode, String)> {
+    let key = content_key(&image)?;
+    for (rendition, bytes) in image.renditions {
+        store
+            .put(&rendition_name(&key, rendition), bytes)
+            .await
+            .map_err(internal_error)?;
+    }
+    Ok(key)
+}
+
+fn content_key(image: &ProcessedImage) -> Result<String, (StatusCode, String)> {
+    let full = image
+        .renditions
+        .iter()
+        .find(|(r, _)| *r == Rendition::Full)
+        .ok_or_else(|| internal_error(std::fmt::Error))?;
+    Ok(format!(
+        "{}.{}",
+        hex::encode(sha2::Sha256::digest(&full.1)),
+        image.extension
+    ))
+}
+
+/// Stores an upload holding a reference of its own while the renditions are written, so removing
+/// an earlier copy of the same image can't delete them from under it. The key must be passed to
+/// [`finish_upload`] once the transaction that references it has committed or failed
+pub async fn store_upload(
+    conn: &mut AsyncPgConnection,
+    store: &dyn ImageStore,
+    image: ProcessedImage,
+) -> Result<String, (StatusCode, String)> {
+    let key = content_key(&image)?;
+    acquire(conn, &key, 1).await.map_err(internal_error)?;
+    if let Err(e) = store_renditions(store, image).await {
+        finish_upload(conn, store, &key).await;
+        return Err(e);
+    }
+    Ok(key)
+}
+
+/// Drops the reference taken by [`store_upload`], removing the renditions if nothing else took
+/// one, e.g. because the transaction meant to reference the upload failed
+pub async fn finish_upload(conn: &mut AsyncPgConnection, store: &dyn ImageStore, key: &str) {
+    match release(conn, key).await {
+        Ok(true) => remove_unreferenced(conn, store, key).await,
+        Ok(false) => {}
+        Err(e) => tracing::warn!("unable to release {}: {}", key, e),
+    }
+}
+
+/// Removes an image and any renditions of it
+pub async fn remove_renditions(store: &dyn ImageStore, key: &str) {
+    for rendition in Rendition::ALL {
+        if let Err(e) = store.delete(&rendition_name(key, rendition)).await {
+            tracing::warn!("unable to remove {}: {}", key, e);
+        }
+    }
+}
+
+/// Removes the renditions of an image [`release`] found to be unreferenced, call it once the
+/// transaction that released the image commits. The row stays locked while the blobs are
+/// deleted, so an upload of the same image waits and then writes them again
+pub async fn remove_unreferenced(conn: &mut AsyncPgConnection, store: &dyn ImageStore, key: &str) {
+    let result = conn
+        .transaction::<_, diesel::result::Error, _>(|conn| {
+            async move {
+                // taken again since it was released
+                if lock(conn, key).await?.is_some_and(|refcount| refcount > 0) {
+                    return Ok(());
+                }
+                remove_renditions(store, key).await;
+                delete(imageblobs::table)
+                    .filter(imageblobs::key.eq(key))
+                    .execute(conn)
+                    .await?;
+                Ok(())
+            }
+            .scope_boxed()
+        })
+        .await;
+    if let Err(e) = result {
+        tracing::warn!("unable to remove {}: {}", key, e);
+    }
+}
+
+/// Locks an image's row until the end of the transaction, returning how many references it has
+async fn lock(conn: &mut AsyncPgConnection, key: &str) -> QueryResult<Option<i32>> {
+    imageblobs::table
+        .select(imageblobs::refcount)
+        .filter(imageblobs::key.eq(key))
+        .for_update()
+        .first(conn)
+        .await
+        .optional()
+}
+
+/// Records `count` new references to a stored image
+pub async fn acquire(conn: &mut AsyncPgConnection, key: &str, count: i32) -> QueryResult<()> {
+    lock(conn, key).await?;
+    insert_into(imageblobs::table)
+        .values((imageblobs::key.eq(key), imageblobs::refcount.eq(count)))
+        .on_conflict(imageblobs::key)
+        .do_update()
+        .set(imageblobs::refcount.eq(imageblobs::refcount + excluded(imageblobs::refcount)))
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// Drops a reference to a stored image, returning true once nothing refers to it. The row is kept
+/// with no references until [`remove_unreferenced`] deletes it along with the blobs
+pub async fn release(conn: &mut AsyncPgConnection, key: &str) -> QueryResult<bool> {
+    if lock(conn, key).await?.is_none() {
+        return Ok(false);
+    }
+    let remaining: i32 = update(imageblobs::table)
+        .filter(imageblobs::key.eq(key))
+        .set(imageblobs::refcount.eq(imageblobs::refcount - 1))
+        .returning(imageblobs::refcount)
+        .get_result(conn)
+        .await?;
+    Ok(remaining <= 0)
+}
+
+pub async fn serve_image(
+    Path(key): Path<String>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let not_found = || (StatusCode::NOT_FOUND, String::from("404 Not Found"));
+    // renditions add a suffix to the stem so they pass the same check
+    if !is_valid_key(&key) {
+        return Err(not_found());
+    }
+    let bytes = state
+        .images
+        .get(&key)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(not_found)?;
+    let content_type = match key.rsplit_once('.').map(|(_, ext)| ext) {
+        Some("png") => "image/png",
+        Some("webp") => "image/webp",
+        _ => "image/jpeg",
+    };
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type),
+            (header::CACHE_CONTROL, "public, max-age=86400"),
+        ],
+        bytes,
+    ))
+}
+
+/// Images uploaded before renditions existed only have the original file, generate the smaller
+/// sizes for them in the same format
+pub async fn backfill_renditions(pool: &Pool<AsyncPgConnection>, store: Arc<dyn ImageStore>) {
+    let Ok(mut conn) = pool.get().await else {
+        return;
+    };
+    let Ok(keys) = productimages::table
+        .select(productimages::imgname)
+        .distinct()
+        .load::<String>(&mut conn)
+        .await
+    else {
+        return;
+    };
+    for key in keys {
+        let thumb = rendition_name(&key, Rendition::Thumb);
+        if !matches!(store.get(&thumb).await, Ok(None)) {
+            continue;
+        }
+        let Ok(Some(bytes)) = store.get(&key).await else {
+            continue;
+        };
+        let extension = key.rsplit_once('.').map_or("jpg", |(_, ext)| ext).to_owned();
+        let renditions = tokio::task::spawn_blocking(move || {
+            let format = detect_format(&bytes)?;
+            let img = ImageReader::with_format(Cursor::new(&bytes), format)
+                .decode()
+                .ok()?;
+            [Rendition::Thumb, Rendition::Medium]
+                .into_iter()
+                .map(|r| Some((r, encode(&resize(&img, r), &extension).ok()?)))
+                .collect::<Option<Vec<_>>>()
+        })
+        .await;
+        let Ok(Some(renditions)) = renditions else {
+            tracing::warn!("unable to generate renditions for {}", key);
+            continue;
+        };
+        for (rendition, bytes) in renditions {
+            let name = rendition_name(&key, rendition);
+            if let Err(e) = store.put(&name, bytes).await {
+                tracing::warn!("unable to store {}: {}", name, e);
+            }
+        }
+    }
+}
+
+pub mod filters {
+    use std::fmt::Display;
+
+    use super::{rendition_name, Rendition};
+
+    /// `{{ product.imgname|rendition("thumb") }}` gives the url of a smaller copy of an image
+    pub fn rendition(
+        imgname: impl Display,
+        _: &dyn askama::Values,
+        size: &str,
+    ) -> askama::Result<String> {
+        let rendition = match size {
+            "thumb" => Rendition::Thumb,
+            "medium" => Rendition::Medium,
+            _ => Rendition::Full,
+        };
+        Ok(format!(
+            "/images/{}",
+            rendition_name(&imgname.to_string(), rendition)
+        ))
+    }
+}
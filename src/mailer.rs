@@ -0,0 +1,76 @@
+use std::{env, path::PathBuf, sync::Arc};
+
+use async_trait::async_trait;
+use time::OffsetDateTime;
+use tokio::fs;
+
+use crate::files::write_atomic;
+
+/// Where mailed files are written when `MAILER=dir` and `MAIL_DIR` isn't set
+const MAIL_DIR: &str = "server_files/mail";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Something that delivers plain text emails to customers
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, mail: &Mail) -> Result<(), String>;
+}
+
+/// Writes each email to the log, for development
+pub struct LogMailer;
+
+#[async_trait]
+impl Mailer for LogMailer {
+    async fn send(&self, mail: &Mail) -> Result<(), String> {
+        tracing::info!("mail to {}: {}\n{}", mail.to, mail.subject, mail.body);
+        Ok(())
+    }
+}
+
+/// Writes each email to its own `.eml` file, so a mail server or relay can pick them up
+pub struct DirMailer {
+    root: PathBuf,
+}
+
+impl DirMailer {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+}
+
+#[async_trait]
+impl Mailer for DirMailer {
+    async fn send(&self, mail: &Mail) -> Result<(), String> {
+        fs::create_dir_all(&self.root)
+            .await
+            .map_err(|e| e.to_string())?;
+        let name = format!(
+            "{}-{}.eml",
+            OffsetDateTime::now_utc().unix_timestamp_nanos(),
+            hex::encode(&mail.to)
+        );
+        let contents = format!(
+            "To: {}\r\nSubject: {}\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n{}\r\n",
+            mail.to, mail.subject, mail.body
+        );
+        write_atomic(&self.root.join(name), contents)
+            .await
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// Picks the mailer with `MAILER`, emails are only logged unless it is `dir`
+pub fn mailer_from_env() -> Arc<dyn Mailer> {
+    match env::var("MAILER").as_deref() {
+        Ok("dir") => Arc::new(DirMailer::new(
+            env::var("MAIL_DIR").unwrap_or_else(|_| String::from(MAIL_DIR)),
+        )),
+        _ => Arc::new(LogMailer),
+    }
+}
@@ -1,25 +1,29 @@
 #![allow(dead_code)]
 use askama::Template;
 use axum::{
-    extract::State,
+    extract::{FromRef, State},
     http::{HeaderName, HeaderValue, StatusCode},
     response::{Html, IntoResponse},
     routing::{get, post},
     Router,
 };
-use axum_extra::extract::CookieJar;
-use db::{models::Product, schema::products};
-use diesel::{define_sql_function, QueryDsl};
+use axum_extra::extract::{cookie::Key, CookieJar, SignedCookieJar};
 use diesel_async::{
     pooled_connection::{deadpool::Pool, AsyncDieselConnectionManager},
-    AsyncPgConnection, RunQueryDsl,
+    AsyncPgConnection,
 };
 use dotenvy::dotenv;
+use images::{filters, serve_image, store::ImageStore};
 use ecom::{
-    admin::admin_routes, browse, cart, cart_post_handler, checkout, checkout_post_handler,
-    like_post_handler, liked, orders, product, view_order_details,
+    admin::admin_routes, alerts, browse, cart, cart_post_handler, checkout, checkout_post_handler,
+    collections::{self, HomepageCache},
+    compare,
+    currency::{self, DisplayCurrency},
+    gifts,
+    like_post_handler, liked, orders, product, product_cards, recent, recommendations,
+    reviews::review_post_handler, schedule, view_order_details, wishlists, ProductCard,
 };
-use std::env;
+use std::{env, sync::Arc};
 use tower_http::{
     services::{ServeDir, ServeFile}, set_header::SetResponseHeaderLayer, trace::TraceLayer
 };
@@ -29,6 +33,10 @@ use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 mod auth;
 mod db;
 mod ecom;
+mod files;
+mod images;
+mod mailer;
+mod money;
 #[cfg(test)]
 mod tests;
 use auth::{
@@ -43,17 +51,33 @@ const SESSION_COOKIE_NAME: &str = "sc-auth-session";
 #[derive(Clone)]
 struct AppState {
     pool: Pool<AsyncPgConnection>,
+    images: Arc<dyn ImageStore>,
+    /// Store uploaded images as WebP instead of JPEG, set with `WEBP_IMAGES=true`
+    webp_images: bool,
+    /// Signs cookies that must not be tampered with, set with `COOKIE_KEY`
+    cookie_key: Key,
+    /// The homepage collections, cleared whenever an admin changes them
+    homepage: Arc<HomepageCache>,
+}
+
+impl FromRef<AppState> for Key {
+    fn from_ref(state: &AppState) -> Self {
+        state.cookie_key.clone()
+    }
 }
 
 #[derive(Template)]
 #[template(path = "homepage.html")]
 struct HomePageTemplate {
     logged_in: bool,
-    products: Vec<Product>,
+    /// Picks for the signed in customer, shown above the collections
+    picks: Vec<ProductCard>,
+    /// Each shown collection's name and products, in homepage order
+    sections: Vec<(String, Vec<ProductCard>)>,
+    recent: Vec<ProductCard>,
+    currency: DisplayCurrency,
 }
 
-define_sql_function!( fn random() -> Text);
-
 #[tokio::main]
 async fn main() {
     tracing_subscriber::registry()
@@ -70,6 +94,14 @@ async fn main() {
         .init();
 
     let root_app = create_srv().await;
+    tokio::spawn(async {
+        images::backfill_renditions(&create_pool().await, images::store_from_env()).await;
+    });
+    tokio::spawn(async { recommendations::refresh_periodically(create_pool().await).await });
+    tokio::spawn(async { schedule::apply_periodically(create_pool().await).await });
+    tokio::spawn(async {
+        alerts::check_periodically(create_pool().await, mailer::mailer_from_env()).await
+    });
 
     let listener = tokio::net::TcpListener::bind("0.0.0.0:1111")
         .await
@@ -80,19 +112,35 @@ async fn main() {
 
 async fn index(
     jar: CookieJar,
+    recent_jar: SignedCookieJar,
     State(state): State<AppState>,
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
     let mut conn = state.pool.get().await.map_err(internal_error)?;
-    let products: Vec<Product> = products::table
-        .select(products::all_columns)
-        .order(random())
-        .limit(7)
-        .load(&mut conn)
+    let session = match jar.get(SESSION_COOKIE_NAME) {
+        Some(cookie) => validate_session(cookie.value().to_owned(), &state.pool)
+            .await
+            .ok(),
+        None => None,
+    };
+    let picks = match &session {
+        Some(session) => recommendations::for_user(&mut conn, session.user_id, 7)
+            .await
+            .map_err(internal_error)?,
+        None => vec![],
+    };
+    let sections = collections::homepage(&state.homepage, &mut conn).await?;
+    let recent = recent::recently_viewed(&mut conn, session.as_ref(), &recent_jar, None)
+        .await
+        .map_err(internal_error)?;
+    let currency = currency::display_currency(&mut conn, session.as_ref(), &jar)
         .await
         .map_err(internal_error)?;
     let template = HomePageTemplate {
-        logged_in: logged_in(&jar, &state.pool).await,
-        products,
+        currency,
+        logged_in: session.is_some(),
+        picks: product_cards(picks, &mut conn).await?,
+        sections,
+        recent: product_cards(recent, &mut conn).await?,
     };
     let html = template.render().unwrap();
     Ok((StatusCode::OK, Html(html)))
@@ -101,6 +149,10 @@ async fn index(
 async fn create_srv() -> Router {
     let app_state = AppState {
         pool: create_pool().await,
+        images: images::store_from_env(),
+        webp_images: env::var("WEBP_IMAGES").is_ok_and(|v| v == "true"),
+        cookie_key: cookie_key_from_env(),
+        homepage: Arc::new(HomepageCache::default()),
     };
     Router::new()
         .nest("/adminpanel", admin_routes())
@@ -110,6 +162,7 @@ async fn create_srv() -> Router {
                 .not_found_service(ServeFile::new("server_files\\static\\404.txt")),
         )
         .route("/", get(index))
+        .route("/images/{key}", get(serve_image))
         .route("/sign-in", get(sign_in).post(process_sign_in))
         .route("/sign-up", get(sign_up).post(process_sign_up))
         .route("/sign-out", post(sign_out))
@@ -117,7 +170,32 @@ async fn create_srv() -> Router {
         .route("/cart", get(cart).post(cart_post_handler))
         .route("/cart/checkout", get(checkout).post(checkout_post_handler))
         .route("/liked", get(liked).post(like_post_handler))
+        .route("/liked/alerts", post(alerts::alert_post_handler))
+        .route(
+            "/alerts/unsubscribe/{token}",
+            get(alerts::unsubscribe_page).post(alerts::unsubscribe),
+        )
+        .route("/notifications", get(alerts::notifications_page))
+        .route("/lists", get(wishlists::lists).post(wishlists::create_list))
+        .route("/lists/{id}", get(wishlists::list))
+        .route("/lists/share", post(wishlists::share_list))
+        .route("/lists/delete", post(wishlists::delete_list))
+        .route("/shared/{token}", get(wishlists::shared_list))
+        .route(
+            "/compare",
+            get(compare::compare).post(compare::compare_post_handler),
+        )
+        .route(
+            "/gift-finder",
+            get(gifts::gift_finder).post(gifts::answer_question),
+        )
         .route("/browse/{product}", get(product))
+        .route("/reviews", post(review_post_handler))
+        .route("/recent/clear", post(recent::clear_recent))
+        .route(
+            "/currency",
+            get(currency::currency_select).post(currency::choose_currency),
+        )
         .route("/orders", get(orders).post(view_order_details))
         .fallback_service(ServeFile::new("server_files\\static\\404.txt"))
         .layer(SetResponseHeaderLayer::overriding(
@@ -142,6 +220,20 @@ async fn create_pool() -> Pool<AsyncPgConnection> {
         .unwrap_or_else(|_| panic!("Error creating pooled connection to db {}", url));
 }
 
+/// `COOKIE_KEY` must be at least 64 bytes. Without it a random key is used, so signed cookies
+/// stop working whenever the server restarts
+fn cookie_key_from_env() -> Key {
+    dotenv().ok();
+    match env::var("COOKIE_KEY") {
+        Ok(key) if key.len() >= 64 => Key::from(key.as_bytes()),
+        Ok(_) => panic!("COOKIE_KEY must be at least 64 bytes"),
+        Err(_) => {
+            tracing::warn!("COOKIE_KEY is not set, using a random key");
+            Key::generate()
+        }
+    }
+}
+
 fn internal_error<E>(_err: E) -> (StatusCode, String)
 where
     E: std::error::Error,
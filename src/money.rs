@@ -0,0 +1,221 @@
+use std::{fmt, str::FromStr};
+
+use bigdecimal::{BigDecimal, RoundingMode, Zero};
+use diesel::{
+    deserialize::{self, FromSql, FromSqlRow},
+    expression::AsExpression,
+    pg::{Pg, PgValue},
+    serialize::{self, Output, ToSql},
+    sql_types::Numeric,
+};
+
+/// Prices are stored as DECIMAL(10, 2), so every amount must be below this
+const MAX_STORED: i64 = 100_000_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Currency {
+    Gbp,
+    Eur,
+    Usd,
+}
+
+impl Currency {
+    /// Prices are stored and orders are charged in this currency
+    pub const BASE: Currency = Currency::Gbp;
+
+    pub const ALL: [Currency; 3] = [Currency::Gbp, Currency::Eur, Currency::Usd];
+
+    /// The ISO 4217 code
+    pub fn code(self) -> &'static str {
+        match self {
+            Currency::Gbp => "GBP",
+            Currency::Eur => "EUR",
+            Currency::Usd => "USD",
+        }
+    }
+
+    pub fn from_code(code: &str) -> Option<Currency> {
+        Currency::ALL
+            .into_iter()
+            .find(|c| c.code().eq_ignore_ascii_case(code.trim()))
+    }
+
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Currency::Gbp => "£",
+            Currency::Eur => "€",
+            Currency::Usd => "$",
+        }
+    }
+
+    /// Digits after the decimal point, e.g. 2 for pence
+    pub fn minor_units(self) -> i64 {
+        match self {
+            Currency::Gbp | Currency::Eur | Currency::Usd => 2,
+        }
+    }
+}
+
+/// An amount of money in a single currency. Amounts are always rounded to the currency's minor
+/// unit, with halves rounded away from zero, so £10.625 becomes £10.63
+#[derive(Clone, Debug, PartialEq, Eq, FromSqlRow, AsExpression)]
+#[diesel(sql_type = Numeric)]
+pub struct Money {
+    amount: BigDecimal,
+    currency: Currency,
+}
+
+impl Money {
+    pub fn new(amount: BigDecimal, currency: Currency) -> Self {
+        Money {
+            amount: amount.with_scale_round(currency.minor_units(), RoundingMode::HalfUp),
+            currency,
+        }
+    }
+
+    pub fn zero(currency: Currency) -> Self {
+        Money::new(BigDecimal::zero(), currency)
+    }
+
+    /// Reads an amount typed by a person, e.g. "12.5", "£1,250.00". More decimal places than the
+    /// currency has are refused rather than quietly rounded away
+    pub fn parse(input: &str, currency: Currency) -> Result<Self, String> {
+        let input = input.trim();
+        let digits = input.strip_prefix(currency.symbol()).unwrap_or(input);
+        if !is_plain_decimal(digits) {
+            return Err(String::from("Please enter a valid amount"));
+        }
+        let amount = BigDecimal::from_str(&digits.replace(',', ""))
+            .map_err(|_| String::from("Please enter a valid amount"))?;
+        if amount.normalized().fractional_digit_count() > currency.minor_units() {
+            return Err(format!(
+                "Amounts in {} can have at most {} decimal places",
+                currency.code(),
+                currency.minor_units()
+            ));
+        }
+        Ok(Money::new(amount, currency))
+    }
+
+    pub fn amount(&self) -> &BigDecimal {
+        &self.amount
+    }
+
+    pub fn currency(&self) -> Currency {
+        self.currency
+    }
+
+    /// Whether this can be a price in the catalogue, more than nothing and small enough to store
+    pub fn is_valid_price(&self) -> bool {
+        self.amount > BigDecimal::zero() && self.amount < BigDecimal::from(MAX_STORED)
+    }
+
+    /// The sum of two amounts, which have to be in the same currency
+    pub fn checked_add(&self, other: &Money) -> Result<Self, CurrencyMismatch> {
+        if self.currency != other.currency {
+            return Err(CurrencyMismatch(self.currency, other.currency));
+        }
+        Ok(Money::new(&self.amount + &other.amount, self.currency))
+    }
+
+    pub fn times(&self, quantity: i32) -> Self {
+        Money::new(&self.amount * BigDecimal::from(quantity), self.currency)
+    }
+
+    /// This amount in another currency, `rate` is how many units of `to` one unit of this
+    /// amount's currency buys
+    pub fn convert(&self, to: Currency, rate: &BigDecimal) -> Self {
+        Money::new(&self.amount * rate, to)
+    }
+
+    /// This amount with `percent` taken off
+    pub fn percent_off(&self, percent: i32) -> Self {
+        Money::new(
+            &self.amount * BigDecimal::from(100 - percent) / BigDecimal::from(100),
+            self.currency,
+        )
+    }
+}
+
+/// Whether an amount is written out in digits, with any thousands separators in the right places.
+/// "1,250.5" is, "1,2,50" and "1e3" are not
+fn is_plain_decimal(input: &str) -> bool {
+    let is_digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    let unsigned = input.strip_prefix('-').unwrap_or(input);
+    let (whole, fraction) = match unsigned.split_once('.') {
+        Some((whole, fraction)) => (whole, Some(fraction)),
+        None => (unsigned, None),
+    };
+    let mut groups = whole.split(',');
+    let first = groups.next().unwrap_or_default();
+    let grouped = groups.all(|g| g.len() == 3 && is_digits(g));
+    is_digits(first)
+        && grouped
+        && (first.len() <= 3 || !whole.contains(','))
+        && fraction.is_none_or(is_digits)
+}
+
+impl Default for Money {
+    fn default() -> Self {
+        Money::zero(Currency::BASE)
+    }
+}
+
+/// Adding amounts in different currencies is a bug, they must be converted first
+#[derive(Debug)]
+pub struct CurrencyMismatch(Currency, Currency);
+
+impl fmt::Display for CurrencyMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "cannot add {} to {}", self.1.code(), self.0.code())
+    }
+}
+
+impl std::error::Error for CurrencyMismatch {}
+
+/// Formats for display, e.g. "£1,250.00" or "-£5.00"
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let amount = self.amount.abs().to_string();
+        let (whole, fraction) = amount.split_once('.').unwrap_or((&amount, ""));
+        let mut grouped = String::new();
+        for (i, digit) in whole.chars().enumerate() {
+            if i > 0 && (whole.len() - i) % 3 == 0 {
+                grouped.push(',');
+            }
+            grouped.push(digit);
+        }
+        let sign = if self.amount < BigDecimal::zero() {
+            "-"
+        } else {
+            ""
+        };
+        write!(f, "{}{}{}", sign, self.currency.symbol(), grouped)?;
+        if !fraction.is_empty() {
+            write!(f, ".{}", fraction)?;
+        }
+        Ok(())
+    }
+}
+
+/// Stored amounts are always in the base currency
+impl FromSql<Numeric, Pg> for Money {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        let amount = <BigDecimal as FromSql<Numeric, Pg>>::from_sql(bytes)?;
+        Ok(Money::new(amount, Currency::BASE))
+    }
+}
+
+impl ToSql<Numeric, Pg> for Money {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        if self.currency != Currency::BASE {
+            return Err(format!(
+                "only {} amounts can be stored, not {}",
+                Currency::BASE.code(),
+                self.currency.code()
+            )
+            .into());
+        }
+        <BigDecimal as ToSql<Numeric, Pg>>::to_sql(&self.amount, &mut out.reborrow())
+    }
+}
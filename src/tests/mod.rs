@@ -1,11 +1,47 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
 use axum::{
-    body::Body,
-    http::{Request, StatusCode},
+    body::{Body, Bytes},
+    extract::{Path as AxumPath, State},
+    http::{HeaderMap, Method, Request, StatusCode},
+    routing::any,
+    Router,
 };
-use axum_test::TestServer;
+use axum_test::{
+    multipart::{MultipartForm, Part},
+    TestServer,
+};
+use axum_extra::extract::cookie::Cookie;
+use bigdecimal::BigDecimal;
+use diesel::{ExpressionMethods, QueryDsl};
+use diesel_async::RunQueryDsl;
 use tower::ServiceExt;
 
-use crate::create_srv;
+use crate::{
+    create_pool, create_srv,
+    db::{
+        models::{PersonalisationField, Product, Sale},
+        schema::{
+            attributes, cartproducts, collections, giftquizanswers, imageblobs, orders, personalisationfields, productorders,
+            productimages, products, productvariants, sales as sales_table, stockadjustments,
+        },
+    },
+    ecom::{
+        alerts::{self, Event, Snapshot},
+        currency::{self, DisplayCurrency},
+        is_valid_slug, markdown, personalisation, recent, recommendations, reviews,
+        sales, schedule, scents, slugify,
+    },
+    images::{
+        self,
+        store::{signing_key, FsImageStore, ImageStore, S3ImageStore},
+    },
+    mailer::{Mail, Mailer},
+    money::{Currency, Money},
+};
 /*
 Test User credentials exist in the database already, they should of been created on first start
 
@@ -100,4 +136,2227 @@ async fn logout() {
     assert_eq!(logout_resp.status_code(), StatusCode::OK);
 }
 
+#[tokio::test]
+async fn cart_add_variant() {
+    let creds = [
+        ("email", "testemail@securecart.com"),
+        ("password", "mysecurepassword"),
+    ];
+    let srv = TestServer::new(create_srv().await).unwrap();
+    srv.post("/sign-in").form(&creds).save_cookies().await;
+    let add = [("variant_id", "2"), ("action", "Add"), ("quantity", "1")];
+    let response = srv.post("/cart").form(&add).await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+    let cart = srv.get("/cart").await.text();
+    assert!(cart.contains("Cherry Scented Candle"));
+    // lines are removed by the id in their remove form
+    let form = &cart[cart.find(r#"<input value="2" name="variant_id""#).unwrap()..];
+    let line_id = form[..form.find(r#"" name="line_id""#).unwrap()]
+        .rsplit('"')
+        .next()
+        .unwrap();
+    let remove = [
+        ("variant_id", "2"),
+        ("action", "Remove"),
+        ("quantity", "1"),
+        ("line_id", line_id),
+    ];
+    let response = srv.post("/cart").form(&remove).await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+    assert!(!srv.get("/cart").await.text().contains("Cherry Scented Candle"));
+}
+
+#[tokio::test]
+async fn cart_add_missing_variant() {
+    let creds = [
+        ("email", "testemail@securecart.com"),
+        ("password", "mysecurepassword"),
+    ];
+    let srv = TestServer::new(create_srv().await).unwrap();
+    srv.post("/sign-in").form(&creds).save_cookies().await;
+    let add = [("variant_id", "99999"), ("action", "Add"), ("quantity", "1")];
+    let response = srv.post("/cart").form(&add).expect_failure().await;
+    assert_eq!(response.status_code(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn cart_add_more_than_stock() {
+    let creds = [
+        ("email", "testemail@securecart.com"),
+        ("password", "mysecurepassword"),
+    ];
+    let srv = TestServer::new(create_srv().await).unwrap();
+    srv.post("/sign-in").form(&creds).save_cookies().await;
+    // the large cinnamon candle is seeded with 5 in stock
+    let add = [("variant_id", "14"), ("action", "Add"), ("quantity", "6")];
+    let response = srv.post("/cart").form(&add).expect_failure().await;
+    assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn concurrent_checkout_for_last_unit() {
+    let admin = admin_server().await;
+    let (_, variant_id) = import_product(&admin, &unique("last-unit"), &unique("LAST-UNIT")).await;
+    let stock = [
+        ("id", variant_id.to_string()),
+        ("delta", String::from("1")),
+        ("reason", String::from("Delivery")),
+    ];
+    admin.post("/adminpanel/adjuststock").form(&stock).await;
+    let first = new_customer().await;
+    let second = new_customer().await;
+    let add = [
+        ("variant_id", variant_id.to_string()),
+        ("action", String::from("Add")),
+        ("quantity", String::from("1")),
+    ];
+    first.post("/cart").form(&add).await;
+    second.post("/cart").form(&add).await;
+    let checkout = [
+        ("cardnum", "4111111111111111"),
+        ("expiry", "12/99"),
+        ("cvv", "123"),
+        ("recipient_name", "Test Customer"),
+        ("line_1", "1 Test Street"),
+        ("postcode", "AB12CD"),
+        ("county", "Testshire"),
+    ];
+    // both wait on the same stock row, whichever gets it second finds nothing left
+    let (a, b) = tokio::join!(
+        first.post("/cart/checkout").form(&checkout),
+        second.post("/cart/checkout").form(&checkout),
+    );
+    let mut statuses = [a.status_code(), b.status_code()];
+    statuses.sort();
+    assert_eq!(statuses, [StatusCode::OK, StatusCode::CONFLICT]);
+    let mut conn = create_pool().await.get().await.unwrap();
+    let stock: i32 = productvariants::table
+        .select(productvariants::stock)
+        .filter(productvariants::id.eq(variant_id))
+        .first(&mut conn)
+        .await
+        .unwrap();
+    assert_eq!(stock, 0);
+    let sold: Vec<i32> = stockadjustments::table
+        .select(stockadjustments::delta)
+        .filter(stockadjustments::variant_id.eq(variant_id))
+        .filter(stockadjustments::order_id.is_not_null())
+        .load(&mut conn)
+        .await
+        .unwrap();
+    assert_eq!(sold, [-1]);
+}
+
+#[tokio::test]
+async fn product_page_gallery() {
+    let srv = TestServer::new(create_srv().await).unwrap();
+    let response = srv.get("/browse/cinnamon").await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+    assert!(response.text().contains(r#"alt="Cinnamon Scented Candle""#));
+}
+
+#[tokio::test]
+async fn admin_edit_rejects_invalid() {
+    let srv = admin_server().await;
+    let cost = [("id", "1"), ("cost", "12.345")];
+    let response = srv
+        .post("/adminpanel/editvariant")
+        .form(&cost)
+        .expect_failure()
+        .await;
+    assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+    let form = axum_test::multipart::MultipartForm::new()
+        .add_text("id", "1")
+        .add_text("title", "  ")
+        .add_text("description", "A candle");
+    let response = srv
+        .post("/adminpanel/editproduct")
+        .multipart(form)
+        .expect_failure()
+        .await;
+    assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+    // new products are held to the same limits
+    let form = MultipartForm::new()
+        .add_part(
+            "image",
+            Part::bytes(encoded_image(64, 64, image::ImageFormat::Png)).file_name("candle.png"),
+        )
+        .add_text("title", "x".repeat(256))
+        .add_text("description", "A candle")
+        .add_text("cost", "5.00");
+    let response = srv
+        .post("/adminpanel/addproduct")
+        .multipart(form)
+        .expect_failure()
+        .await;
+    assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+    assert!(response.text().contains("at most 255 characters"));
+    let form = MultipartForm::new()
+        .add_text("product_id", i32::MAX.to_string())
+        .add_text("alt_text", "A candle")
+        .add_part(
+            "image",
+            Part::bytes(encoded_image(64, 64, image::ImageFormat::Png)).file_name("candle.png"),
+        );
+    let response = srv
+        .post("/adminpanel/addimage")
+        .multipart(form)
+        .expect_failure()
+        .await;
+    assert_eq!(response.status_code(), StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn product_slugs() {
+    assert_eq!(slugify("Cherry Scented Candle"), "cherry-scented-candle");
+    assert_eq!(slugify("  Salt & Sea -- 3 Wick! "), "salt-sea-3-wick");
+    assert_eq!(slugify("!!!"), "product");
+    assert!(is_valid_slug("cherry-scented-candle"));
+    assert!(!is_valid_slug("Cherry"));
+    assert!(!is_valid_slug("../cherry"));
+    assert!(!is_valid_slug("cherry--candle"));
+}
+
+#[tokio::test]
+async fn product_slug_change_redirects() {
+    let srv = admin_server().await;
+    let (id, _) = import_product(&srv, "slug-change", "SLUG-CHANGE-1").await;
+    let rename = |slug: &'static str| {
+        MultipartForm::new()
+            .add_text("id", id.to_string())
+            .add_text("slug", slug)
+            .add_text("title", "Archive Test Candle")
+            .add_text("description", "A candle")
+    };
+    let response = srv.post("/adminpanel/editproduct").multipart(rename("slug-changed")).await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+    let response = srv.get("/browse/slug-change").await;
+    assert_eq!(response.status_code(), StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(response.header("location"), "/browse/slug-changed");
+    let response = srv.get("/browse/slug-changed").await;
+    assert!(response
+        .text()
+        .contains(r#"<link rel="canonical" href="http://localhost:1111/browse/slug-changed">"#));
+    // a slug already used by another product is refused
+    let response = srv
+        .post("/adminpanel/editproduct")
+        .multipart(rename("cherry"))
+        .expect_failure()
+        .await;
+    assert_eq!(response.status_code(), StatusCode::CONFLICT);
+    srv.post("/adminpanel/archiveproduct").form(&[("id", id)]).await;
+    srv.post("/adminpanel/purgeproduct").form(&[("id", id)]).await;
+}
+
+#[tokio::test]
+async fn upcoming_product_old_slug_stays_secret() {
+    let srv = admin_server().await;
+    let (old, new) = (unique("upcoming-slug"), unique("upcoming-slug-changed"));
+    let (id, _) = import_product(&srv, &old, &unique("UPCOMING-SLUG")).await;
+    let later = (time::OffsetDateTime::now_utc() + time::Duration::days(30))
+        .format(time::macros::format_description!("[year]-[month]-[day]T[hour]:[minute]"))
+        .unwrap();
+    let upcoming = [
+        ("id", id.to_string()),
+        ("publish_at", later),
+        ("unpublish_at", String::new()),
+    ];
+    srv.post("/adminpanel/scheduleproduct").form(&upcoming).await;
+    let rename = MultipartForm::new()
+        .add_text("id", id.to_string())
+        .add_text("slug", new)
+        .add_text("title", "Archive Test Candle")
+        .add_text("description", "A candle");
+    let response = srv.post("/adminpanel/editproduct").multipart(rename).await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+    // redirecting would give away the new slug before it is published
+    let guest = TestServer::new(create_srv().await).unwrap();
+    let response = guest.get(&format!("/browse/{}", old)).expect_failure().await;
+    assert_eq!(response.status_code(), StatusCode::NOT_FOUND);
+}
+
+async fn admin_server() -> TestServer {
+    let creds = [
+        ("email", "admin@securecart.com"),
+        ("password", "@8*aUxB2#fEnT]E"),
+    ];
+    let srv = TestServer::new(create_srv().await).unwrap();
+    srv.post("/sign-in").form(&creds).save_cookies().await;
+    srv
+}
+
+fn import_form(name: &'static str, catalog: Vec<u8>, apply: bool) -> MultipartForm {
+    MultipartForm::new()
+        .add_part("catalog", Part::bytes(catalog).file_name(name))
+        .add_text("apply", apply.to_string())
+}
+
+#[tokio::test]
+async fn catalog_export_round_trip() {
+    let srv = admin_server().await;
+    let csv = srv.get("/adminpanel/export?format=csv").await;
+    assert_eq!(csv.status_code(), StatusCode::OK);
+    assert!(csv
+        .text()
+        .starts_with("slug,title,description,image,sku,size,wicks,cost,details\n"));
+    // other tests edit products, so only round trip one that is left alone
+    let json: Vec<serde_json::Value> = srv.get("/adminpanel/export?format=json").await.json();
+    let cinnamon: Vec<_> = json.into_iter().filter(|r| r["slug"] == "cinnamon").collect();
+    assert_eq!(cinnamon.len(), 2);
+    let form = import_form("catalog.json", serde_json::to_vec(&cinnamon).unwrap(), false);
+    let report = srv.post("/adminpanel/import").multipart(form).await;
+    assert!(report.text().contains("there is nothing to change"));
+}
+
+#[tokio::test]
+async fn catalog_import_reports_lines() {
+    let srv = admin_server().await;
+    let csv = "slug,title,description,image,sku,size,wicks,cost
+Bad Slug,Candle,A candle,cinnamon.jpg,SC9001-STD,Standard,1,5.00
+cinnamon,Cinnamon Scented Candle,A candle,cinnamon.jpg,SC0001-STD,Standard,1,0
+cinnamon,Cinnamon Scented Candle,A candle,cinnamon.jpg,SC0002-STD,Standard,1,5.00
+";
+    let form = import_form("catalog.csv", csv.as_bytes().to_vec(), true);
+    let report = srv.post("/adminpanel/import").multipart(form).await.text();
+    assert!(report.contains("Nothing was imported"));
+    assert!(report.contains("line 2: slugs may only contain"));
+    assert!(report.contains("line 3: Cost must be between"));
+    assert!(report.contains("line 4: SKU SC0002-STD belongs to cherry"));
+}
+
+#[tokio::test]
+async fn catalog_import_with_archive() {
+    let srv = admin_server().await;
+    let mut archive = zip::ZipWriter::new(std::io::Cursor::new(vec![]));
+    archive
+        .start_file("images/taper.png", zip::write::SimpleFileOptions::default())
+        .unwrap();
+    std::io::Write::write_all(
+        &mut archive,
+        &encoded_image(400, 400, image::ImageFormat::Png),
+    )
+    .unwrap();
+    let archive = archive.finish().unwrap().into_inner();
+    let csv = "slug,title,description,image,sku,size,wicks,cost
+beeswax-taper,Beeswax Taper Candle,A tall unscented beeswax candle,images/taper.png,BW-TAPER,Standard,1,4.50
+";
+    let form = import_form("catalog.csv", csv.as_bytes().to_vec(), true)
+        .add_part("archive", Part::bytes(archive).file_name("images.zip"));
+    let report = srv.post("/adminpanel/import").multipart(form).await;
+    assert_eq!(report.status_code(), StatusCode::OK);
+    assert!(!report.text().contains("Nothing was imported"));
+    let page = srv.get("/browse/beeswax-taper").await;
+    assert_eq!(page.status_code(), StatusCode::OK);
+    assert!(page.text().contains("Beeswax Taper Candle"));
+    // leave nothing behind, so the import adds the product again next time
+    let id: i32 = products::table
+        .select(products::id)
+        .filter(products::slug.eq("beeswax-taper"))
+        .first(&mut create_pool().await.get().await.unwrap())
+        .await
+        .unwrap();
+    srv.post("/adminpanel/archiveproduct").form(&[("id", id)]).await;
+    let response = srv.post("/adminpanel/purgeproduct").form(&[("id", id)]).await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+}
+
+/// Signs up and signs in a new customer, so tests that place orders don't touch anyone else's cart
+async fn new_customer() -> TestServer {
+    let email = format!(
+        "customer{}@securecart.com",
+        time::OffsetDateTime::now_utc().unix_timestamp_nanos()
+    );
+    let srv = TestServer::new(create_srv().await).unwrap();
+    let sign_up = [
+        ("email", email.as_str()),
+        ("password", "mysecurepassword"),
+        ("password2", "mysecurepassword"),
+    ];
+    srv.post("/sign-up").form(&sign_up).await;
+    let creds = [("email", email.as_str()), ("password", "mysecurepassword")];
+    srv.post("/sign-in").form(&creds).save_cookies().await;
+    srv
+}
+
+async fn place_order(srv: &TestServer, variant_id: &str) {
+    let add = [("variant_id", variant_id), ("action", "Add"), ("quantity", "1")];
+    srv.post("/cart").form(&add).await;
+    let checkout = [
+        ("cardnum", "4111111111111111"),
+        ("expiry", "12/99"),
+        ("cvv", "123"),
+        ("recipient_name", "Test Customer"),
+        ("line_1", "1 Test Street"),
+        ("postcode", "AB12CD"),
+        ("county", "Testshire"),
+    ];
+    let response = srv.post("/cart/checkout").form(&checkout).await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+}
+
+#[test]
+fn review_spam_and_profanity() {
+    assert!(reviews::check_review("Lovely scent, it burns evenly for hours").is_ok());
+    assert!(reviews::check_review("A classic from Scunthorpe, smells like dahlias").is_ok());
+    assert!(reviews::check_review("This candle is sh1iiit").is_err());
+    assert!(reviews::check_review("Cheap candles at www.example.com").is_err());
+    assert!(reviews::check_review("Email me at someone@example.com").is_err());
+    assert!(reviews::check_review("Greaaaaaaat candle").is_err());
+    assert!(reviews::check_review("THIS IS THE BEST CANDLE EVER MADE").is_err());
+}
+
+#[tokio::test]
+async fn review_requires_purchase() {
+    let srv = new_customer().await;
+    let review = [
+        ("product_id", "1"),
+        ("rating", "5"),
+        ("body", "Never bought it but it looks nice"),
+    ];
+    let response = srv.post("/reviews").form(&review).expect_failure().await;
+    assert_eq!(response.status_code(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn review_moderation() {
+    let srv = new_customer().await;
+    // the standard coffee candle
+    place_order(&srv, "5").await;
+    let body = format!(
+        "Smells just like a fresh cup, review {}",
+        time::OffsetDateTime::now_utc().unix_timestamp_nanos()
+    );
+    let review = [("product_id", "5"), ("rating", "4"), ("body", body.as_str())];
+    let response = srv.post("/reviews").form(&review).await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+    let response = srv.post("/reviews").form(&review).expect_failure().await;
+    assert_eq!(response.status_code(), StatusCode::CONFLICT);
+    assert!(!srv.get("/browse/coffee").await.text().contains(&body));
+
+    let admin = admin_server().await;
+    let dashboard = admin.get("/adminpanel").await.text();
+    let queued = &dashboard[dashboard.find(&body).unwrap()..];
+    let id_start = queued.find("<input hidden value=\"").unwrap() + 21;
+    let id = &queued[id_start..id_start + queued[id_start..].find('"').unwrap()];
+    admin
+        .post("/adminpanel/approvereview")
+        .form(&[("id", id)])
+        .await;
+    let page = srv.get("/browse/coffee").await.text();
+    assert!(page.contains(&body));
+    assert!(page.contains("out of 5 from"));
+}
+
+#[tokio::test]
+async fn recommendations_from_orders() {
+    // ocean and peach candles bought together
+    let first = new_customer().await;
+    let add = [("variant_id", "9"), ("action", "Add"), ("quantity", "1")];
+    first.post("/cart").form(&add).await;
+    place_order(&first, "10").await;
+    let second = new_customer().await;
+    place_order(&second, "9").await;
+    recommendations::refresh(&mut create_pool().await.get().await.unwrap())
+        .await
+        .unwrap();
+
+    let page = second.get("/browse/ocean").await.text();
+    let strip = &page[page.find("Customers also bought").unwrap()..];
+    assert!(strip.contains("Peach Scented Candle"));
+    let home = second.get("/").await.text();
+    assert!(home.contains("Picked for you"));
+    assert!(home.contains("Peach Scented Candle"));
+}
+
+fn encoded_image(width: u32, height: u32, format: image::ImageFormat) -> Vec<u8> {
+    let img = image::RgbImage::from_pixel(width, height, image::Rgb([200, 120, 40]));
+    let mut buf = std::io::Cursor::new(Vec::new());
+    img.write_to(&mut buf, format).unwrap();
+    buf.into_inner()
+}
+
+#[test]
+fn image_upload_renditions() {
+    let png = encoded_image(1000, 500, image::ImageFormat::Png);
+    let processed = images::process_upload(&png, false).unwrap();
+    assert_eq!(processed.extension, "jpg");
+    for (rendition, bytes) in processed.renditions {
+        assert_eq!(images::detect_format(&bytes), Some(image::ImageFormat::Jpeg));
+        let img = image::load_from_memory(&bytes).unwrap();
+        assert!(img.width() <= rendition.max_edge() && img.height() <= rendition.max_edge());
+    }
+}
+
+#[test]
+fn image_upload_rejects_invalid() {
+    let not_image = b"<svg xmlns=\"http://www.w3.org/2000/svg\"></svg>".to_vec();
+    let err = images::process_upload(&not_image, false).err().unwrap();
+    assert_eq!(err.0, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    let tiny = encoded_image(50, 50, image::ImageFormat::Jpeg);
+    let err = images::process_upload(&tiny, false).err().unwrap();
+    assert_eq!(err.0, StatusCode::BAD_REQUEST);
+    let huge = encoded_image(6001, 200, image::ImageFormat::Png);
+    let err = images::process_upload(&huge, false).err().unwrap();
+    assert_eq!(err.1, "Images must be at most 6000px on each side");
+    // a cut off file is unreadable rather than too big
+    let truncated = encoded_image(300, 300, image::ImageFormat::Png)[..20].to_vec();
+    let err = images::process_upload(&truncated, false).err().unwrap();
+    assert_eq!(err.1, "The uploaded image could not be read");
+    assert!(images::is_valid_key("cinnamon-thumb.jpg"));
+    assert!(!images::is_valid_key("../../main.rs"));
+    assert!(!images::is_valid_key("evil.php.jpg"));
+}
+
+#[test]
+fn s3_signing_key() {
+    // example from the AWS signature version 4 documentation
+    let key = signing_key(
+        "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+        "20120215",
+        "us-east-1",
+        "iam",
+    );
+    assert_eq!(
+        hex::encode(key),
+        "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d"
+    );
+}
+
+/// Stands in for an S3 bucket, holding objects in memory and rejecting unsigned requests
+async fn s3_stand_in() -> String {
+    type Objects = Arc<Mutex<HashMap<String, Vec<u8>>>>;
+    async fn object(
+        method: Method,
+        headers: HeaderMap,
+        AxumPath((bucket, key)): AxumPath<(String, String)>,
+        State(objects): State<Objects>,
+        body: Bytes,
+    ) -> (StatusCode, Vec<u8>) {
+        let signed = headers
+            .get("authorization")
+            .and_then(|h| h.to_str().ok())
+            .is_some_and(|h| h.starts_with("AWS4-HMAC-SHA256 Credential=test/"));
+        if bucket != "images" || !signed {
+            return (StatusCode::FORBIDDEN, vec![]);
+        }
+        let mut objects = objects.lock().unwrap();
+        match method {
+            Method::PUT => {
+                objects.insert(key, body.to_vec());
+                (StatusCode::OK, vec![])
+            }
+            Method::GET => match objects.get(&key) {
+                Some(bytes) => (StatusCode::OK, bytes.clone()),
+                None => (StatusCode::NOT_FOUND, vec![]),
+            },
+            Method::DELETE => {
+                objects.remove(&key);
+                (StatusCode::NO_CONTENT, vec![])
+            }
+            _ => (StatusCode::METHOD_NOT_ALLOWED, vec![]),
+        }
+    }
+    let app = Router::new()
+        .route("/{bucket}/{key}", any(object))
+        .with_state(Objects::default());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    format!("http://{}", addr)
+}
+
+#[tokio::test]
+async fn s3_image_store() {
+    let endpoint = s3_stand_in().await;
+    let store = S3ImageStore::new(&endpoint, "images", "eu-west-2", "test", "secret");
+    assert_eq!(store.get("missing.jpg").await.unwrap(), None);
+    store.put("a.jpg", vec![1, 2, 3]).await.unwrap();
+    assert_eq!(store.get("a.jpg").await.unwrap(), Some(vec![1, 2, 3]));
+    store.delete("a.jpg").await.unwrap();
+    assert_eq!(store.get("a.jpg").await.unwrap(), None);
+    let unsigned = S3ImageStore::new(&endpoint, "other", "eu-west-2", "test", "secret");
+    assert!(unsigned.put("a.jpg", vec![]).await.is_err());
+}
+
+#[tokio::test]
+async fn fs_image_store_content_addressed() {
+    let dir = std::env::temp_dir().join(format!("securecart-store-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let store = FsImageStore::new(&dir);
+    let png = encoded_image(400, 400, image::ImageFormat::Png);
+    let first = images::store_renditions(&store, images::process_upload(&png, false).unwrap())
+        .await
+        .unwrap();
+    let second = images::store_renditions(&store, images::process_upload(&png, false).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(first, second);
+    assert!(images::is_valid_key(&first));
+    assert!(store.get(&first).await.unwrap().is_some());
+    images::remove_renditions(&store, &first).await;
+    assert_eq!(store.get(&first).await.unwrap(), None);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn image_references() {
+    let dir = std::env::temp_dir().join(format!("securecart-refs-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let store = FsImageStore::new(&dir);
+    let mut conn = create_pool().await.get().await.unwrap();
+    let upload = |width| {
+        let png = encoded_image(width, 300, image::ImageFormat::Png);
+        images::process_upload(&png, false).unwrap()
+    };
+    let key = images::store_upload(&mut conn, &store, upload(301)).await.unwrap();
+    images::acquire(&mut conn, &key, 1).await.unwrap();
+    images::finish_upload(&mut conn, &store, &key).await;
+    assert!(store.get(&key).await.unwrap().is_some());
+    // taken again between being released and the blobs being removed
+    assert!(images::release(&mut conn, &key).await.unwrap());
+    images::acquire(&mut conn, &key, 1).await.unwrap();
+    images::remove_unreferenced(&mut conn, &store, &key).await;
+    assert!(store.get(&key).await.unwrap().is_some());
+    assert!(images::release(&mut conn, &key).await.unwrap());
+    images::remove_unreferenced(&mut conn, &store, &key).await;
+    assert_eq!(store.get(&key).await.unwrap(), None);
+    // an upload nothing ended up referencing is removed along with its row
+    let key = images::store_upload(&mut conn, &store, upload(302)).await.unwrap();
+    images::finish_upload(&mut conn, &store, &key).await;
+    assert_eq!(store.get(&key).await.unwrap(), None);
+    let rows: i64 = imageblobs::table
+        .filter(imageblobs::key.eq(&key))
+        .count()
+        .get_result(&mut conn)
+        .await
+        .unwrap();
+    assert_eq!(rows, 0);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn remove_gallery_images() {
+    let admin = admin_server().await;
+    let (id, _) = import_product(&admin, &unique("gallery-test"), &unique("GALLERY-1")).await;
+    let add_image = |width| {
+        MultipartForm::new()
+            .add_text("product_id", id.to_string())
+            .add_text("alt_text", "A candle")
+            .add_part(
+                "image",
+                Part::bytes(encoded_image(width, 300, image::ImageFormat::Png))
+                    .file_name("candle.png"),
+            )
+    };
+    let gallery = || async {
+        productimages::table
+            .select((productimages::id, productimages::imgname))
+            .filter(productimages::product_id.eq(id))
+            .order(productimages::position.asc())
+            .load::<(i32, String)>(&mut create_pool().await.get().await.unwrap())
+            .await
+            .unwrap()
+    };
+    admin.post("/adminpanel/addimage").multipart(add_image(303)).await;
+    let images = gallery().await;
+    assert_eq!(images.len(), 2);
+    // the product's own image moves on to the next one
+    let response = admin.post("/adminpanel/removeimage").form(&[("id", images[0].0)]).await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+    let primary: String = products::table
+        .select(products::imgname)
+        .filter(products::id.eq(id))
+        .first(&mut create_pool().await.get().await.unwrap())
+        .await
+        .unwrap();
+    assert_eq!(primary, images[1].1);
+    // removing both at once still leaves one behind
+    admin.post("/adminpanel/addimage").multipart(add_image(304)).await;
+    let images = gallery().await;
+    let (a, b) = tokio::join!(
+        admin.post("/adminpanel/removeimage").form(&[("id", images[0].0)]),
+        admin.post("/adminpanel/removeimage").form(&[("id", images[1].0)]),
+    );
+    let mut statuses = [a.status_code(), b.status_code()];
+    statuses.sort();
+    assert_eq!(statuses, [StatusCode::OK, StatusCode::BAD_REQUEST]);
+    assert_eq!(gallery().await.len(), 1);
+    admin.post("/adminpanel/archiveproduct").form(&[("id", id)]).await;
+    admin.post("/adminpanel/purgeproduct").form(&[("id", id)]).await;
+}
+
+#[tokio::test]
+async fn recently_viewed_guest() {
+    let srv = TestServer::new(create_srv().await).unwrap();
+    let first = srv.get("/browse/coffee").save_cookies().await.text();
+    assert!(!first.contains("Recently viewed"));
+    srv.get("/browse/ocean").save_cookies().await;
+    let page = srv.get("/browse/peach").await.text();
+    let (_, recent) = page.split_once("Recently viewed").unwrap();
+    let ocean = recent.find("/browse/ocean").unwrap();
+    assert!(ocean < recent.find("/browse/coffee").unwrap());
+    assert!(!recent.contains("/browse/peach"));
+    // a tampered cookie is ignored rather than trusted
+    let srv = TestServer::new(create_srv().await).unwrap();
+    let page = srv
+        .get("/browse/peach")
+        .add_cookie(Cookie::new(recent::RECENT_COOKIE_NAME, "5,9"))
+        .await
+        .text();
+    assert!(!page.contains("Recently viewed"));
+}
+
+#[tokio::test]
+async fn recently_viewed_customer_clear() {
+    let srv = new_customer().await;
+    srv.get("/browse/coffee").await;
+    let home = srv.get("/").await.text();
+    let (_, recent) = home.split_once("Recently viewed").unwrap();
+    assert!(recent.contains("/browse/coffee"));
+    let response = srv.post("/recent/clear").await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+    assert!(!srv.get("/").await.text().contains("Recently viewed"));
+}
+
+#[test]
+fn sale_prices_round_to_the_penny() {
+    let now = time::OffsetDateTime::now_utc();
+    let sale = Sale {
+        id: 0,
+        name: String::from("Autumn sale"),
+        product_id: None,
+        collection_id: None,
+        percent_off: 15,
+        starts_at: now,
+        ends_at: now + time::Duration::days(1),
+    };
+    let cost = Money::parse("12.50", Currency::BASE).unwrap();
+    assert_eq!(sale.price(&cost).to_string(), "£10.63");
+    let sales = HashMap::from([(1, sale)]);
+    assert_eq!(sales::current_price(&sales, 2, &cost), cost);
+}
+
+#[test]
+fn money_rounds_parses_and_formats() {
+    let price = Money::parse("£1,250", Currency::BASE).unwrap();
+    assert_eq!(price.to_string(), "£1,250.00");
+    assert_eq!(price.amount().to_string(), "1250.00");
+    assert_eq!(price.times(3).to_string(), "£3,750.00");
+    assert_eq!(
+        Money::parse("0.125", Currency::BASE).unwrap_err(),
+        "Amounts in GBP can have at most 2 decimal places"
+    );
+    assert!(Money::parse("twelve", Currency::BASE).is_err());
+    for typo in ["1,2,3", "1e3", "12,50", ",250", "1,250,", "5.", "1.2.3"] {
+        assert!(Money::parse(typo, Currency::BASE).is_err(), "{}", typo);
+    }
+    assert_eq!(Money::parse("1,250,000.5", Currency::BASE).unwrap().to_string(), "£1,250,000.50");
+    let euro = Money::zero(Currency::Eur);
+    assert!(price.checked_add(&euro).is_err());
+    assert_eq!(price.checked_add(&price).unwrap().to_string(), "£2,500.00");
+    let pence = Money::new(BigDecimal::new(1005.into(), 3), Currency::BASE);
+    assert_eq!(pence.to_string(), "£1.01");
+    assert!(!Money::zero(Currency::BASE).is_valid_price());
+    assert!(!Money::parse("100000000", Currency::BASE).unwrap().is_valid_price());
+    assert!(Money::parse("99999999.99", Currency::BASE).unwrap().is_valid_price());
+}
+
+#[tokio::test]
+async fn variant_prices_over_a_hundred_pounds() {
+    let admin = admin_server().await;
+    let (_, variant_id) = import_product(&admin, "sandalwood", "SAND-STD").await;
+    let cost = [("id", variant_id.to_string()), ("cost", String::from("1250.00"))];
+    admin.post("/adminpanel/editvariant").form(&cost).await;
+    let mut conn = create_pool().await.get().await.unwrap();
+    let stored: Money = productvariants::table
+        .select(productvariants::cost)
+        .filter(productvariants::id.eq(variant_id))
+        .first(&mut conn)
+        .await
+        .unwrap();
+    assert_eq!(stored.to_string(), "£1,250.00");
+}
+
+#[tokio::test]
+async fn sale_price_charged_at_checkout() {
+    let admin = admin_server().await;
+    let format = time::macros::format_description!("[year]-[month]-[day]T[hour]:[minute]");
+    let now = time::OffsetDateTime::now_utc();
+    let starts = (now - time::Duration::hours(1)).format(&format).unwrap();
+    let ends = (now + time::Duration::days(1)).format(&format).unwrap();
+    let too_much = [
+        ("name", "Dahlia days"),
+        ("product_id", "6"),
+        ("percent_off", "95"),
+        ("starts_at", starts.as_str()),
+        ("ends_at", ends.as_str()),
+    ];
+    let response = admin
+        .post("/adminpanel/addsale")
+        .form(&too_much)
+        .expect_failure()
+        .await;
+    assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+    let backwards = [
+        ("name", "Dahlia days"),
+        ("product_id", "6"),
+        ("percent_off", "20"),
+        ("starts_at", ends.as_str()),
+        ("ends_at", starts.as_str()),
+    ];
+    let response = admin
+        .post("/adminpanel/addsale")
+        .form(&backwards)
+        .expect_failure()
+        .await;
+    assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+    // the seeded dahlia candle costs 14.50, so 20% off is 11.60
+    let sale = [
+        ("name", "Dahlia days"),
+        ("product_id", "6"),
+        ("percent_off", "20"),
+        ("starts_at", starts.as_str()),
+        ("ends_at", ends.as_str()),
+    ];
+    let response = admin.post("/adminpanel/addsale").form(&sale).await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+    let page = admin.get("/browse/dahlia").await.text();
+    assert!(page.contains("<s>£14.50</s> £11.60"));
+    assert!(page.contains("data-ends-at"));
+
+    let customer = new_customer().await;
+    place_order(&customer, "6").await;
+    let mut conn = create_pool().await.get().await.unwrap();
+    let sale_id: i32 = sales_table::table
+        .select(sales_table::id)
+        .filter(sales_table::product_id.eq(6))
+        .first(&mut conn)
+        .await
+        .unwrap();
+    admin
+        .post("/adminpanel/removesale")
+        .form(&[("id", sale_id)])
+        .await;
+    let page = admin.get("/browse/dahlia").await.text();
+    assert!(!page.contains("<s>£14.50</s>"));
+    // the order keeps the sale price after the sale has ended
+    let order_id: i32 = productorders::table
+        .select(productorders::order_id)
+        .filter(productorders::variant_id.eq(6))
+        .order(productorders::order_id.desc())
+        .first(&mut conn)
+        .await
+        .unwrap();
+    let details = customer
+        .post("/orders")
+        .form(&[("order_id", order_id)])
+        .await
+        .text();
+    assert!(details.contains("£11.60"));
+}
+
+#[tokio::test]
+async fn collection_sale() {
+    let admin = admin_server().await;
+    let (pinned, _) = import_product(&admin, "collection-sale-pinned", "COLLECTION-SALE-1").await;
+    import_product(&admin, "collection-sale-other", "COLLECTION-SALE-2").await;
+    admin
+        .post("/adminpanel/addcollection")
+        .form(&[
+            ("name", "Collection Sale Test"),
+            ("rule", "manual"),
+            ("max_products", "3"),
+        ])
+        .await;
+    let mut conn = create_pool().await.get().await.unwrap();
+    let collection_id: i32 = collections::table
+        .select(collections::id)
+        .filter(collections::name.eq("Collection Sale Test"))
+        .first(&mut conn)
+        .await
+        .unwrap();
+    let pin = [
+        ("collection_id", collection_id.to_string()),
+        ("slug", String::from("collection-sale-pinned")),
+    ];
+    admin.post("/adminpanel/pinproduct").form(&pin).await;
+    let format = time::macros::format_description!("[year]-[month]-[day]T[hour]:[minute]");
+    let now = time::OffsetDateTime::now_utc();
+    let starts = (now - time::Duration::hours(1)).format(&format).unwrap();
+    let ends = (now + time::Duration::days(1)).format(&format).unwrap();
+    let sale = |product_id: &str| {
+        [
+            ("name", String::from("Collection days")),
+            ("product_id", product_id.to_owned()),
+            ("collection_id", collection_id.to_string()),
+            ("percent_off", String::from("50")),
+            ("starts_at", starts.clone()),
+            ("ends_at", ends.clone()),
+        ]
+    };
+    let response = admin
+        .post("/adminpanel/addsale")
+        .form(&sale(&pinned.to_string()))
+        .expect_failure()
+        .await;
+    assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+    admin.post("/adminpanel/addsale").form(&sale("")).await;
+    let page = admin.get("/browse/collection-sale-pinned").await.text();
+    assert!(page.contains("Standard, 1 wick: <s>£5.00</s> £2.50"));
+    let page = admin.get("/browse/collection-sale-other").await.text();
+    assert!(!page.contains("Standard, 1 wick: <s>"));
+    // removing the collection ends its sales
+    admin
+        .post("/adminpanel/removecollection")
+        .form(&[("id", collection_id)])
+        .await;
+    let page = admin.get("/browse/collection-sale-pinned").await.text();
+    assert!(!page.contains("Standard, 1 wick: <s>"));
+}
+
+/// A name no earlier run has used, so tests can be rerun against the same database
+fn unique(prefix: &str) -> String {
+    format!("{}-{}", prefix, time::OffsetDateTime::now_utc().unix_timestamp_nanos())
+}
+
+/// Imports a single variant product using a seeded image, returning its product and variant ids
+async fn import_product(admin: &TestServer, slug: &str, sku: &str) -> (i32, i32) {
+    let csv = format!(
+        "slug,title,description,image,sku,size,wicks,cost\n{},Archive Test Candle,A candle,cherry.jpg,{},Standard,1,5.00\n",
+        slug, sku
+    );
+    let form = import_form("catalog.csv", csv.into_bytes(), true);
+    admin.post("/adminpanel/import").multipart(form).await;
+    productvariants::table
+        .select((productvariants::product_id, productvariants::id))
+        .filter(productvariants::sku.eq(sku))
+        .first(&mut create_pool().await.get().await.unwrap())
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn archive_and_purge_product() {
+    let admin = admin_server().await;
+    let (slug, sku) = (unique("archive-unsold"), unique("AR-UNSOLD"));
+    let (id, variant_id) = import_product(&admin, &slug, &sku).await;
+    let page = format!("/browse/{}", slug);
+    assert_eq!(admin.get(&page).await.status_code(), StatusCode::OK);
+    let stock = [
+        ("id", variant_id.to_string()),
+        ("delta", String::from("3")),
+        ("reason", String::from("Delivery")),
+    ];
+    admin.post("/adminpanel/adjuststock").form(&stock).await;
+    // products have to be archived before they are purged
+    let response = admin
+        .post("/adminpanel/purgeproduct")
+        .form(&[("id", id)])
+        .expect_failure()
+        .await;
+    assert_eq!(response.status_code(), StatusCode::CONFLICT);
+    admin.post("/adminpanel/archiveproduct").form(&[("id", id)]).await;
+    let response = admin.get(&page).expect_failure().await;
+    assert_eq!(response.status_code(), StatusCode::NOT_FOUND);
+    assert!(!admin.get("/browse").await.text().contains(&page));
+    let response = admin.post("/adminpanel/purgeproduct").form(&[("id", id)]).await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+    // the stock history outlives the purged variant
+    let kept: Vec<(Option<i32>, i32)> = stockadjustments::table
+        .select((stockadjustments::variant_id, stockadjustments::delta))
+        .filter(stockadjustments::sku.eq(&sku))
+        .load(&mut create_pool().await.get().await.unwrap())
+        .await
+        .unwrap();
+    assert_eq!(kept, vec![(None, 3)]);
+    let response = admin
+        .post("/adminpanel/restoreproduct")
+        .form(&[("id", id)])
+        .expect_failure()
+        .await;
+    assert_eq!(response.status_code(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn archived_product_keeps_order_history() {
+    let admin = admin_server().await;
+    let slug = unique("archive-sold");
+    let (id, variant_id) = import_product(&admin, &slug, &unique("AR-SOLD")).await;
+    let page = format!("/browse/{}", slug);
+    let stock = [
+        ("id", variant_id.to_string()),
+        ("delta", String::from("5")),
+        ("reason", String::from("Delivery")),
+    ];
+    admin.post("/adminpanel/adjuststock").form(&stock).await;
+    let customer = new_customer().await;
+    place_order(&customer, &variant_id.to_string()).await;
+    admin.post("/adminpanel/archiveproduct").form(&[("id", id)]).await;
+    let response = admin
+        .post("/adminpanel/purgeproduct")
+        .form(&[("id", id)])
+        .expect_failure()
+        .await;
+    assert_eq!(response.status_code(), StatusCode::CONFLICT);
+    let order_id: i32 = productorders::table
+        .select(productorders::order_id)
+        .filter(productorders::variant_id.eq(variant_id))
+        .first(&mut create_pool().await.get().await.unwrap())
+        .await
+        .unwrap();
+    let details = customer
+        .post("/orders")
+        .form(&[("order_id", order_id)])
+        .await
+        .text();
+    assert!(details.contains("Archive Test Candle"));
+    // restored products come back unlisted until they are relisted
+    admin.post("/adminpanel/restoreproduct").form(&[("id", id)]).await;
+    assert_eq!(admin.get(&page).await.status_code(), StatusCode::OK);
+    assert!(!admin.get("/browse").await.text().contains(&page));
+    admin.post("/adminpanel/relist").form(&[("id", id)]).await;
+    assert!(admin.get("/browse").await.text().contains(&page));
+}
+
+#[test]
+fn markdown_details_are_sanitised() {
+    let html = markdown::render(
+        "## Scent notes\n\n- **Top**: bergamot\n\n<script>alert(1)</script>\n\n\
+         <img src=x onerror=alert(1)> [safety](javascript:alert(1)) [guide](https://example.com)",
+    );
+    assert!(html.contains("<h2>Scent notes</h2>"));
+    assert!(html.contains("<li><strong>Top</strong>: bergamot</li>"));
+    assert!(html.contains(r#"<a href="https://example.com" rel="nofollow noopener noreferrer">"#));
+    assert!(!html.contains("<script"));
+    assert!(!html.contains("<img"));
+    assert!(!html.contains("onerror"));
+    assert!(!html.contains("javascript:"));
+}
+
+#[tokio::test]
+async fn product_details_from_import() {
+    let admin = admin_server().await;
+    let json = serde_json::json!([{
+        "slug": "details-candle",
+        "title": "Details Candle",
+        "description": "A short summary",
+        "image": "cherry.jpg",
+        "sku": "DT-STD",
+        "size": "Standard",
+        "wicks": 1,
+        "cost": "5.00",
+        "details": "Burns for **40 hours**\n\n<p onclick=\"alert(1)\">Keep away from drafts</p>"
+    }]);
+    let form = import_form("catalog.json", serde_json::to_vec(&json).unwrap(), true);
+    let report = admin.post("/adminpanel/import").multipart(form).await;
+    assert!(!report.text().contains("Nothing was imported"));
+    let page = admin.get("/browse/details-candle").await.text();
+    assert!(page.contains("Burns for <strong>40 hours</strong>"));
+    assert!(page.contains("<p>Keep away from drafts</p>"));
+    assert!(!page.contains("onclick"));
+    let preview = admin
+        .post("/adminpanel/previewdetails")
+        .form(&[("details", "*new*")])
+        .await
+        .text();
+    assert_eq!(preview.trim(), "<p><em>new</em></p>");
+}
+
+#[test]
+fn exchange_rate_files() {
+    let rates = currency::parse_rates(b"currency,rate\nEUR,1.17\nusd,1.25\n").unwrap();
+    assert_eq!(rates.len(), 2);
+    assert_eq!(rates[1].0, Currency::Usd);
+    let file = b"currency,rate\nGBP,1\nEUR,0\nXYZ,2\nUSD,1.2\nUSD,1.3\n";
+    let errors = currency::parse_rates(file).unwrap_err();
+    assert_eq!(errors.len(), 4);
+    assert!(errors[0].starts_with("line 2: GBP is the currency prices are set in"));
+    assert!(errors[1].starts_with("line 3: Rates must be between"));
+    assert!(errors[2].starts_with("line 4: XYZ is not a currency"));
+    assert_eq!(errors[3], "line 6: USD is listed twice");
+}
+
+#[test]
+fn converted_line_totals() {
+    let euros = DisplayCurrency {
+        currency: Currency::Eur,
+        rate: BigDecimal::new(117.into(), 2),
+    };
+    let unit_cost = Money::parse("3.33", Currency::BASE).unwrap();
+    // €3.90 each, converting the £9.99 line instead would show €11.69
+    assert_eq!(euros.convert(&unit_cost).to_string(), "€3.90");
+    assert_eq!(euros.line_total(&unit_cost, 3).to_string(), "€11.70");
+}
+
+#[tokio::test]
+async fn prices_shown_in_chosen_currency() {
+    let admin = admin_server().await;
+    let slug = unique("currency-test");
+    let (_, variant_id) = import_product(&admin, &slug, &unique("CUR-STD")).await;
+    let stock = [
+        ("id", variant_id.to_string()),
+        ("delta", String::from("5")),
+        ("reason", String::from("Delivery")),
+    ];
+    admin.post("/adminpanel/adjuststock").form(&stock).await;
+    let rates = MultipartForm::new().add_part(
+        "rates",
+        Part::bytes(b"currency,rate\nEUR,1.2\n".to_vec()).file_name("rates.csv"),
+    );
+    admin.post("/adminpanel/importrates").multipart(rates).await;
+
+    // guests keep their choice in a cookie
+    let guest = TestServer::new(create_srv().await).unwrap();
+    let response = guest
+        .post("/currency")
+        .form(&[("currency", "JPY")])
+        .expect_failure()
+        .await;
+    assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+    guest
+        .post("/currency")
+        .form(&[("currency", "EUR")])
+        .save_cookies()
+        .await;
+    let page = guest.get("/browse").await.text();
+    assert!(page.contains("From €6.00"));
+    assert!(!page.contains("From £"));
+    // price filters are in the chosen currency too
+    assert!(page.contains("Under €10"));
+    assert!(!page.contains("Under £10"));
+
+    // customers' choice is saved on their account, so no cookie is needed
+    let customer = new_customer().await;
+    customer.post("/currency").form(&[("currency", "EUR")]).await;
+    assert!(customer.get("/currency").await.text().contains(r#"value="EUR" selected"#));
+    let page = customer.get(&format!("/browse/{}", slug)).await.text();
+    assert!(page.contains("Standard, 1 wick - €6.00"));
+    place_order(&customer, &variant_id.to_string()).await;
+    let mut conn = create_pool().await.get().await.unwrap();
+    let (order_id, currency, rate, settlement): (i32, String, BigDecimal, Money) = orders::table
+        .inner_join(productorders::table)
+        .select((
+            orders::id,
+            orders::currency,
+            orders::exchange_rate,
+            orders::settlement_total,
+        ))
+        .filter(productorders::variant_id.eq(variant_id))
+        .first(&mut conn)
+        .await
+        .unwrap();
+    assert_eq!(currency, "EUR");
+    assert_eq!(rate, BigDecimal::new(12.into(), 1));
+    assert_eq!(settlement.to_string(), "£5.00");
+    let details = customer
+        .post("/orders")
+        .form(&[("order_id", order_id)])
+        .await
+        .text();
+    assert!(details.contains("Cost Per Item: €6.00"));
+    assert!(details.contains("Charged £5.00"));
+}
+
+#[tokio::test]
+async fn scheduled_publishing() {
+    let admin = admin_server().await;
+    let (id, variant_id) = import_product(&admin, "seasonal-test", "SEASON-STD").await;
+    let stock = [
+        ("id", variant_id.to_string()),
+        ("delta", String::from("1")),
+        ("reason", String::from("Delivery")),
+    ];
+    admin.post("/adminpanel/adjuststock").form(&stock).await;
+    let format = time::macros::format_description!("[year]-[month]-[day]T[hour]:[minute]");
+    let now = time::OffsetDateTime::now_utc();
+    let earlier = (now - time::Duration::hours(1)).format(&format).unwrap();
+    let later = (now + time::Duration::days(30)).format(&format).unwrap();
+    let guest = TestServer::new(create_srv().await).unwrap();
+    let listed = |page: String| page.contains("/browse/seasonal-test");
+
+    let backwards = [
+        ("id", id.to_string()),
+        ("publish_at", later.clone()),
+        ("unpublish_at", earlier.clone()),
+    ];
+    let response = admin
+        .post("/adminpanel/scheduleproduct")
+        .form(&backwards)
+        .expect_failure()
+        .await;
+    assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+
+    // not published yet, so kept secret
+    let upcoming = [
+        ("id", id.to_string()),
+        ("publish_at", later.clone()),
+        ("unpublish_at", String::new()),
+    ];
+    admin.post("/adminpanel/scheduleproduct").form(&upcoming).await;
+    assert!(!listed(guest.get("/browse").await.text()));
+    let page = guest.get("/browse/seasonal-test").expect_failure().await;
+    assert_eq!(page.status_code(), StatusCode::NOT_FOUND);
+    assert!(admin.get("/adminpanel").await.text().contains("Upcoming listing changes"));
+
+    // customers see it as soon as the time passes, before the scheduler has run
+    let window = [
+        ("id", id.to_string()),
+        ("publish_at", earlier.clone()),
+        ("unpublish_at", later.clone()),
+    ];
+    admin.post("/adminpanel/scheduleproduct").form(&window).await;
+    assert!(listed(guest.get("/browse").await.text()));
+    let mut conn = create_pool().await.get().await.unwrap();
+    schedule::apply_due_to(&mut conn, &[id]).await.unwrap();
+    let product: Product = products::table
+        .select(products::all_columns)
+        .filter(products::id.eq(id))
+        .first(&mut conn)
+        .await
+        .unwrap();
+    assert!(product.listed);
+    assert!(product.publish_at.is_none());
+    assert!(product.unpublish_at.is_some());
+    let customer = new_customer().await;
+    let add = [
+        ("variant_id", variant_id.to_string()),
+        ("action", String::from("Add")),
+        ("quantity", String::from("1")),
+    ];
+    customer.post("/cart").form(&add).await;
+
+    let ended = [
+        ("id", id.to_string()),
+        ("publish_at", String::new()),
+        ("unpublish_at", earlier),
+    ];
+    admin.post("/adminpanel/scheduleproduct").form(&ended).await;
+    assert!(!listed(guest.get("/browse").await.text()));
+    // it can't be bought from a cart it was added to while it was on sale
+    let checkout = [
+        ("cardnum", "4111111111111111"),
+        ("expiry", "12/99"),
+        ("cvv", "123"),
+        ("recipient_name", "Test Customer"),
+        ("line_1", "1 Test Street"),
+        ("postcode", "AB12CD"),
+        ("county", "Testshire"),
+    ];
+    let response = customer
+        .post("/cart/checkout")
+        .form(&checkout)
+        .expect_failure()
+        .await;
+    assert_eq!(response.status_code(), StatusCode::CONFLICT);
+    assert!(response.text().contains("no longer for sale"));
+    schedule::apply_due_to(&mut conn, &[id]).await.unwrap();
+    let listed: bool = products::table
+        .select(products::listed)
+        .filter(products::id.eq(id))
+        .first(&mut conn)
+        .await
+        .unwrap();
+    assert!(!listed);
+}
+
+#[test]
+fn alert_events() {
+    let price = |p: &str| Some(Money::parse(p, Currency::Gbp).unwrap());
+    let snapshot = |listed, in_stock, cost| Snapshot {
+        listed,
+        in_stock,
+        price: price(cost),
+    };
+    let on_sale = snapshot(true, true, "5.00");
+    let drop = |was: &str, now: &str| Event::PriceDrop {
+        was: price(was).unwrap(),
+        now: price(now).unwrap(),
+    };
+    assert_eq!(alerts::events(&on_sale, &on_sale), []);
+    // nothing is announced while it can't be bought, and coming back on sale covers the restock
+    assert_eq!(alerts::events(&on_sale, &snapshot(false, true, "4.00")), []);
+    assert_eq!(
+        alerts::events(&snapshot(false, false, "5.00"), &snapshot(true, true, "5.00")),
+        [Event::Relisted]
+    );
+    assert_eq!(
+        alerts::events(&snapshot(false, false, "5.00"), &snapshot(true, true, "4.00")),
+        [Event::Relisted, drop("5.00", "4.00")]
+    );
+    // every change between checks is announced
+    assert_eq!(
+        alerts::events(&snapshot(true, false, "5.00"), &snapshot(true, true, "4.00")),
+        [Event::BackInStock, drop("5.00", "4.00")]
+    );
+    assert_eq!(alerts::events(&on_sale, &snapshot(true, false, "5.00")), []);
+    assert_eq!(alerts::events(&on_sale, &snapshot(true, true, "6.00")), []);
+    assert_eq!(
+        alerts::events(&on_sale, &snapshot(true, true, "4.99")),
+        [drop("5.00", "4.99")]
+    );
+}
+
+#[derive(Default)]
+struct CapturingMailer(Mutex<Vec<Mail>>);
+
+#[async_trait::async_trait]
+impl Mailer for CapturingMailer {
+    async fn send(&self, mail: &Mail) -> Result<(), String> {
+        self.0.lock().unwrap().push(mail.clone());
+        Ok(())
+    }
+}
+
+/// Runs the alert check and returns the subjects of every alert emailed about the product at `page`
+async fn alert_subjects(
+    conn: &mut diesel_async::AsyncPgConnection,
+    mailer: &CapturingMailer,
+    page: &str,
+) -> Vec<String> {
+    alerts::check(conn, mailer).await.unwrap();
+    let mails = mailer.0.lock().unwrap();
+    mails
+        .iter()
+        .filter(|m| m.body.contains(page))
+        .map(|m| m.subject.clone())
+        .collect()
+}
+
+#[tokio::test]
+async fn product_alerts() {
+    let admin = admin_server().await;
+    let slug = unique("alert-test");
+    let (id, vid) = import_product(&admin, &slug, &unique("ALERT-STD")).await;
+    let page = format!("/browse/{}", slug);
+    let customer = new_customer().await;
+    let mailer = CapturingMailer::default();
+    let mut conn = create_pool().await.get().await.unwrap();
+    let product = [("product_id", id.to_string()), ("action", String::from("Add"))];
+    // only favourites can have alerts
+    let response = customer
+        .post("/liked/alerts")
+        .form(&product)
+        .expect_failure()
+        .await;
+    assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+    customer.post("/liked").form(&product).await;
+    assert_eq!(customer.post("/liked/alerts").form(&product).await.text(), "Alerts on ✔");
+    assert!(customer.get(&page).await.text().contains("Stop Alerts"));
+
+    assert!(alert_subjects(&mut conn, &mailer, &page).await.is_empty());
+
+    let listing = [("id", id)];
+    admin.post("/adminpanel/unlist").form(&listing).await;
+    assert!(alert_subjects(&mut conn, &mailer, &page).await.is_empty());
+    admin.post("/adminpanel/relist").form(&listing).await;
+    assert_eq!(
+        alert_subjects(&mut conn, &mailer, &page).await,
+        ["Archive Test Candle is back on sale"]
+    );
+    // each change is only announced once
+    assert_eq!(alert_subjects(&mut conn, &mailer, &page).await.len(), 1);
+
+    let stock = [
+        ("id", vid.to_string()),
+        ("delta", String::from("5")),
+        ("reason", String::from("Delivery")),
+    ];
+    admin.post("/adminpanel/adjuststock").form(&stock).await;
+    let cost = [("id", vid.to_string()), ("cost", String::from("4.00"))];
+    admin.post("/adminpanel/editvariant").form(&cost).await;
+    // both happened between checks, so both are announced
+    assert_eq!(
+        alert_subjects(&mut conn, &mailer, &page).await[1..],
+        [
+            "Archive Test Candle is back in stock",
+            "Archive Test Candle has dropped in price from £5.00 to £4.00"
+        ]
+    );
+    // a drop while it can't be bought is announced once it is back on sale
+    admin.post("/adminpanel/unlist").form(&listing).await;
+    let cost = [("id", vid.to_string()), ("cost", String::from("3.50"))];
+    admin.post("/adminpanel/editvariant").form(&cost).await;
+    assert_eq!(alert_subjects(&mut conn, &mailer, &page).await.len(), 3);
+    admin.post("/adminpanel/relist").form(&listing).await;
+    assert_eq!(
+        alert_subjects(&mut conn, &mailer, &page).await[3..],
+        [
+            "Archive Test Candle is back on sale",
+            "Archive Test Candle has dropped in price from £4.00 to £3.50"
+        ]
+    );
+
+    let notifications = customer.get("/notifications").await.text();
+    assert!(notifications.contains("Archive Test Candle is back on sale"));
+    assert!(notifications.contains("New</span>"));
+    // shown once as new
+    assert!(!customer.get("/notifications").await.text().contains("New</span>"));
+
+    let mail = mailer.0.lock().unwrap().last().unwrap().clone();
+    let link = mail.body.split("http://localhost:1111").last().unwrap().to_owned();
+    assert!(link.starts_with("/alerts/unsubscribe/"));
+    let guest = TestServer::new(create_srv().await).unwrap();
+    assert!(guest.get(&link).await.text().contains("Stop alerts for Archive Test Candle?"));
+    assert!(guest.post(&link).await.text().contains("You won't hear about"));
+    assert!(customer.get(&page).await.text().contains("Alert Me About Changes"));
+    let cost = [("id", vid.to_string()), ("cost", String::from("3.00"))];
+    admin.post("/adminpanel/editvariant").form(&cost).await;
+    assert_eq!(alert_subjects(&mut conn, &mailer, &page).await.len(), 5);
+}
+
+#[tokio::test]
+async fn shared_wishlists() {
+    let admin = admin_server().await;
+    let slug = unique("wishlist-test");
+    let (id, vid) = import_product(&admin, &slug, &unique("WISH-STD")).await;
+    let product_page = format!("/browse/{}", slug);
+    let stock = [
+        ("id", vid.to_string()),
+        ("delta", String::from("5")),
+        ("reason", String::from("Delivery")),
+    ];
+    admin.post("/adminpanel/adjuststock").form(&stock).await;
+    let owner = new_customer().await;
+    let friend = new_customer().await;
+
+    let response = owner
+        .post("/lists")
+        .form(&[("name", " ")])
+        .expect_failure()
+        .await;
+    assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+    owner.post("/lists").form(&[("name", "Christmas gifts")]).await;
+    let response = owner
+        .post("/lists")
+        .form(&[("name", "Christmas gifts")])
+        .expect_failure()
+        .await;
+    assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+    let page = owner.get("/lists").await.text();
+    let list_id: i32 = page.split("href=\"/lists/").nth(1).unwrap()
+        .split('"').next().unwrap()
+        .parse().unwrap();
+    let list = format!("/lists/{}", list_id);
+
+    assert!(owner.get(&product_page).await.text().contains("Save To List"));
+    let save = [
+        ("product_id", id.to_string()),
+        ("action", String::from("Add")),
+        ("list_id", list_id.to_string()),
+    ];
+    assert_eq!(owner.post("/liked").form(&save).await.text(), "Saved to Christmas gifts ✔");
+    // named lists are kept apart from favourites
+    assert!(!owner.get("/liked").await.text().contains(&product_page));
+    assert!(owner.get(&list).await.text().contains(&product_page));
+    // only the owner can see or change an unshared list
+    let response = friend.post("/liked").form(&save).expect_failure().await;
+    assert_eq!(response.status_code(), StatusCode::NOT_FOUND);
+    let response = friend.get(&list).expect_failure().await;
+    assert_eq!(response.status_code(), StatusCode::NOT_FOUND);
+
+    let share = [("id", list_id.to_string()), ("action", String::from("Add"))];
+    owner.post("/lists/share").form(&share).await;
+    let page = owner.get(&list).await.text();
+    let token = page.split("href=\"/shared/").nth(1).unwrap()
+        .split('"').next().unwrap()
+        .to_owned();
+    let shared = format!("/shared/{}", token);
+    let guest = TestServer::new(create_srv().await).unwrap();
+    let page = guest.get(&shared).await.text();
+    assert!(page.contains("Christmas gifts"));
+    assert!(page.contains(&product_page));
+    assert!(!page.contains("Add to Cart"));
+    assert!(friend.get(&shared).await.text().contains("Add to Cart"));
+    let add = [
+        ("variant_id", vid.to_string()),
+        ("action", String::from("Add")),
+        ("quantity", String::from("1")),
+    ];
+    friend.post("/cart").form(&add).await;
+    assert!(friend.get("/cart").await.text().contains("Archive Test Candle"));
+    // products waiting to be published stay secret on shared lists too
+    let later = (time::OffsetDateTime::now_utc() + time::Duration::days(30))
+        .format(time::macros::format_description!("[year]-[month]-[day]T[hour]:[minute]"))
+        .unwrap();
+    let upcoming = [
+        ("id", id.to_string()),
+        ("publish_at", later),
+        ("unpublish_at", String::new()),
+    ];
+    admin.post("/adminpanel/scheduleproduct").form(&upcoming).await;
+    assert!(!guest.get(&shared).await.text().contains(&product_page));
+    // and aren't counted on the owner's lists
+    assert!(owner.get("/lists").await.text().contains("Christmas gifts (0)"));
+
+    let unshare = [("id", list_id.to_string()), ("action", String::from("Remove"))];
+    owner.post("/lists/share").form(&unshare).await;
+    let response = guest.get(&shared).expect_failure().await;
+    assert_eq!(response.status_code(), StatusCode::NOT_FOUND);
+    owner.post("/lists/delete").form(&[("id", list_id)]).await;
+    let response = owner.get(&list).expect_failure().await;
+    assert_eq!(response.status_code(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn product_comparison() {
+    let admin = admin_server().await;
+    let mut products = vec![];
+    for i in 1..=5 {
+        let slug = format!("compare-test-{}", i);
+        products.push(import_product(&admin, &slug, &format!("COMPARE-{}", i)).await);
+    }
+    let (first, first_variant) = products[0];
+    let burn = |hours: &str| {
+        [
+            ("id", first_variant.to_string()),
+            ("cost", String::from("5.00")),
+            ("burn_hours", hours.to_owned()),
+        ]
+    };
+    let response = admin
+        .post("/adminpanel/editvariant")
+        .form(&burn("0"))
+        .expect_failure()
+        .await;
+    assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+    admin.post("/adminpanel/editvariant").form(&burn("40")).await;
+    let notes = [
+        ("product_id", first.to_string()),
+        ("top", String::from("Cherry")),
+        ("middle", String::new()),
+        ("base", String::from("Cinnamon")),
+    ];
+    admin.post("/adminpanel/setnotes").form(&notes).await;
+
+    let guest = TestServer::new(create_srv().await).unwrap();
+    assert!(!guest.get("/browse").await.text().contains("hx-post=\"/compare\""));
+    let response = guest.get("/compare").expect_failure().await;
+    assert_eq!(response.status_code(), StatusCode::UNAUTHORIZED);
+
+    let customer = new_customer().await;
+    assert!(customer.get("/browse").await.text().contains("hx-post=\"/compare\""));
+    let compare = |id: i32, action: &str| {
+        [("product_id", id.to_string()), ("action", action.to_owned())]
+    };
+    for (id, _) in &products[..4] {
+        let response = customer.post("/compare").form(&compare(*id, "Add")).await;
+        assert!(response.text().contains("Remove from comparison"));
+        assert_eq!(response.header("HX-Trigger"), "compare-changed");
+    }
+    // four at most, adding one already being compared is fine
+    let response = customer
+        .post("/compare")
+        .form(&compare(products[4].0, "Add"))
+        .expect_failure()
+        .await;
+    assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+    customer.post("/compare").form(&compare(first, "Add")).await;
+    customer.post("/compare").form(&compare(products[3].0, "Remove")).await;
+    // only one of two adds made at the same time gets the last place
+    let (a, b) = tokio::join!(
+        customer.post("/compare").form(&compare(products[3].0, "Add")),
+        customer.post("/compare").form(&compare(products[4].0, "Add")),
+    );
+    let mut statuses = [a.status_code(), b.status_code()];
+    statuses.sort();
+    assert_eq!(statuses, [StatusCode::OK, StatusCode::BAD_REQUEST]);
+    customer.post("/compare").form(&compare(products[3].0, "Remove")).await;
+    customer.post("/compare").form(&compare(products[4].0, "Add")).await;
+
+    let page = customer.get("/compare").await.text();
+    assert!(page.contains("/browse/compare-test-1"));
+    assert!(page.contains("/browse/compare-test-5"));
+    assert!(!page.contains("/browse/compare-test-4"));
+    assert!(page.contains("Standard: about 40 hours"));
+    assert!(page.contains("Top: Cherry"));
+    assert!(page.contains("Base: Cinnamon"));
+    assert!(page.contains("Not listed"));
+    assert!(page.contains("No reviews yet"));
+    // signing out throws the comparison away with the session
+    assert_eq!(customer.post("/sign-out").await.status_code(), StatusCode::OK);
+}
+
+/// The products a homepage section shows, in order
+fn section_slugs(page: &str, name: &str) -> Vec<String> {
+    let Some(section) = page.split(&format!(">{}</p>", name)).nth(1) else {
+        return vec![];
+    };
+    // stop at the next section's heading
+    let section = section.split("<p class=\"text-2xl\">").next().unwrap();
+    let section = section.split("Recently viewed").next().unwrap();
+    section
+        .split("href='/browse/")
+        .skip(1)
+        .map(|s| s.split('\'').next().unwrap().to_owned())
+        .collect()
+}
+
+#[tokio::test]
+async fn homepage_collections() {
+    let admin = admin_server().await;
+    let slugs = [
+        unique("collection-test-1"),
+        unique("collection-test-2"),
+        unique("collection-test-3"),
+    ];
+    let (first, _) = import_product(&admin, &slugs[0], &unique("COLLECTION-1")).await;
+    let (second, _) = import_product(&admin, &slugs[1], &unique("COLLECTION-2")).await;
+    let (third, _) = import_product(&admin, &slugs[2], &unique("COLLECTION-3")).await;
+    let name = unique("Collection Test");
+    let response = admin
+        .post("/adminpanel/addcollection")
+        .form(&[
+            ("name", name.as_str()),
+            ("rule", "sometimes"),
+            ("max_products", "3"),
+        ])
+        .expect_failure()
+        .await;
+    assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+    admin
+        .post("/adminpanel/addcollection")
+        .form(&[
+            ("name", name.as_str()),
+            ("rule", "manual"),
+            ("max_products", "3"),
+        ])
+        .await;
+    let id: i32 = collections::table
+        .select(collections::id)
+        .filter(collections::name.eq(&name))
+        .first(&mut create_pool().await.get().await.unwrap())
+        .await
+        .unwrap();
+    let pin = |slug: &str| [("collection_id", id.to_string()), ("slug", slug.to_owned())];
+    for slug in &slugs {
+        admin.post("/adminpanel/pinproduct").form(&pin(slug)).await;
+    }
+    let response = admin
+        .post("/adminpanel/pinproduct")
+        .form(&pin("no-such-candle"))
+        .expect_failure()
+        .await;
+    assert_eq!(response.status_code(), StatusCode::NOT_FOUND);
+    let pinned = |product_id: i32| {
+        [
+            ("collection_id", id.to_string()),
+            ("product_id", product_id.to_string()),
+        ]
+    };
+    admin
+        .post("/adminpanel/movepinned")
+        .form(&[
+            ("collection_id", id.to_string()),
+            ("product_id", third.to_string()),
+            ("direction", String::from("Up")),
+        ])
+        .await;
+
+    let guest = TestServer::new(create_srv().await).unwrap();
+    assert_eq!(
+        section_slugs(&guest.get("/").await.text(), &name),
+        [slugs[0].as_str(), &slugs[2], &slugs[1]]
+    );
+    assert!(admin
+        .get("/adminpanel")
+        .await
+        .text()
+        .contains("Homepage collections"));
+
+    // admin changes show straight away on the server that made them
+    admin.get("/").await;
+    admin
+        .post("/adminpanel/unpinproduct")
+        .form(&pinned(second))
+        .await;
+    assert_eq!(
+        section_slugs(&admin.get("/").await.text(), &name),
+        [slugs[0].as_str(), &slugs[2]]
+    );
+    // products that stop being for sale drop out without waiting for the cache
+    admin
+        .post("/adminpanel/unlist")
+        .form(&[("id", first)])
+        .await;
+    assert_eq!(
+        section_slugs(&admin.get("/").await.text(), &name),
+        [slugs[2].as_str()]
+    );
+    let edit = |rule: &str, shown: bool| {
+        let mut form = vec![
+            ("id", id.to_string()),
+            ("name", name.clone()),
+            ("rule", rule.to_owned()),
+            ("max_products", String::from("3")),
+            ("position", String::from("100")),
+        ];
+        if shown {
+            form.push(("shown", String::from("true")));
+        }
+        form
+    };
+    // the newest products fill the places left over
+    admin
+        .post("/adminpanel/editcollection")
+        .form(&edit("newest", true))
+        .await;
+    let shown = section_slugs(&admin.get("/").await.text(), &name);
+    assert_eq!(shown.len(), 3);
+    assert_eq!(shown[0], slugs[2]);
+    assert!(!shown.contains(&slugs[0]));
+    admin
+        .post("/adminpanel/editcollection")
+        .form(&edit("newest", false))
+        .await;
+    let heading = format!(">{}</p>", name);
+    assert!(!admin.get("/").await.text().contains(&heading));
+    // other servers keep their cached sections until they expire
+    assert!(guest.get("/").await.text().contains(&heading));
+
+    admin
+        .post("/adminpanel/removecollection")
+        .form(&[("id", id)])
+        .await;
+    let found: Vec<i32> = collections::table
+        .select(collections::id)
+        .filter(collections::id.eq(id))
+        .load(&mut create_pool().await.get().await.unwrap())
+        .await
+        .unwrap();
+    assert!(found.is_empty());
+}
+
+#[tokio::test]
+async fn faceted_browse() {
+    let admin = admin_server().await;
+    let attribute = |name: &str, kind: &str, unit: &str| {
+        [
+            ("name", name.to_owned()),
+            ("kind", kind.to_owned()),
+            ("unit", unit.to_owned()),
+        ]
+    };
+    for bad in [
+        attribute("Price", "text", ""),
+        attribute("Colour", "colour", ""),
+    ] {
+        let response = admin
+            .post("/adminpanel/addattribute")
+            .form(&bad)
+            .expect_failure()
+            .await;
+        assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+    }
+    admin
+        .post("/adminpanel/addattribute")
+        .form(&attribute("Refillable", "flag", ""))
+        .await;
+    admin
+        .post("/adminpanel/addattribute")
+        .form(&attribute("Height", "number", "cm"))
+        .await;
+    let mut conn = create_pool().await.get().await.unwrap();
+    let ids: HashMap<String, i32> = attributes::table
+        .select((attributes::slug, attributes::id))
+        .load::<(String, i32)>(&mut conn)
+        .await
+        .unwrap()
+        .into_iter()
+        .collect();
+    let (first, first_variant) = import_product(&admin, "facet-test-1", "FACET-1").await;
+    let (second, _) = import_product(&admin, "facet-test-2", "FACET-2").await;
+    let values = |product_id: i32, refillable: &str, height: &str| {
+        [
+            ("product_id", product_id.to_string()),
+            (
+                &format!("attribute-{}", ids["refillable"]),
+                refillable.to_owned(),
+            ),
+            (&format!("attribute-{}", ids["height"]), height.to_owned()),
+        ]
+        .map(|(name, value)| (name.to_owned(), value))
+    };
+    let response = admin
+        .post("/adminpanel/setattributes")
+        .form(&values(first, "yes", "tall"))
+        .expect_failure()
+        .await;
+    assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+    admin
+        .post("/adminpanel/setattributes")
+        .form(&values(first, "yes", "9"))
+        .await;
+    admin
+        .post("/adminpanel/setattributes")
+        .form(&values(second, "no", "12"))
+        .await;
+    admin
+        .post("/adminpanel/editvariant")
+        .form(&[
+            ("id", first_variant.to_string()),
+            ("cost", String::from("5.00")),
+            ("burn_hours", String::from("45")),
+        ])
+        .await;
+
+    let guest = TestServer::new(create_srv().await).unwrap();
+    let page = guest.get("/browse?refillable=yes").await.text();
+    assert!(page.contains("/browse/facet-test-1"));
+    assert!(!page.contains("/browse/facet-test-2"));
+    assert!(page.contains("Clear filters"));
+    // values of the same filter widen the results, different filters narrow them
+    let page = guest.get("/browse?height=9&height=12").await.text();
+    assert!(page.contains("/browse/facet-test-1"));
+    assert!(page.contains("/browse/facet-test-2"));
+    assert!(!page.contains("/browse/cherry"));
+    assert!(page.contains("40 to 60 hours (1)"));
+    assert!(page.contains("Yes (1)"));
+    let page = guest.get("/browse?height=12&refillable=yes").await.text();
+    assert!(page.contains("No candles match"));
+    // links always list filters in the same order
+    let page = guest.get("/browse?height=9&refillable=yes").await.text();
+    assert!(page.contains("href=\"/browse?height=9\""));
+    assert!(page.contains("href=\"/browse?refillable=yes\""));
+    assert!(guest
+        .get("/browse?colour=red")
+        .await
+        .text()
+        .contains("/browse/cherry"));
+
+    let page = guest.get("/browse/facet-test-1").await.text();
+    assert!(page.contains("Refillable"));
+    assert!(page.contains("9 cm"));
+
+    admin
+        .post("/adminpanel/removeattribute")
+        .form(&[("id", ids["height"])])
+        .await;
+    let page = guest.get("/browse?height=9").await.text();
+    assert!(page.contains("/browse/facet-test-2"));
+}
+
+#[test]
+fn scent_similarity() {
+    let notes = |notes: &[(i32, &str)]| -> Vec<(i32, String)> {
+        notes.iter().map(|(n, t)| (*n, t.to_string())).collect()
+    };
+    let cherry = notes(&[(1, "top"), (2, "middle"), (3, "base")]);
+    assert_eq!(scents::similarity(&cherry, &cherry), 1.0);
+    assert_eq!(scents::similarity(&cherry, &notes(&[(4, "top")])), 0.0);
+    assert_eq!(scents::similarity(&cherry, &[]), 0.0);
+    // the same note in another tier counts half
+    let same_tier = notes(&[(3, "base"), (4, "top")]);
+    let other_tier = notes(&[(3, "top"), (4, "top")]);
+    assert_eq!(scents::similarity(&cherry, &same_tier), 0.25);
+    assert_eq!(scents::similarity(&cherry, &other_tier), 0.125);
+}
+
+#[tokio::test]
+async fn scent_profiles() {
+    let admin = admin_server().await;
+    let note =
+        |name: &str, family: &str| [("name", name.to_owned()), ("family", family.to_owned())];
+    let response = admin
+        .post("/adminpanel/addnote")
+        .form(&note("Smoke", "woody"))
+        .expect_failure()
+        .await;
+    assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+    admin
+        .post("/adminpanel/addnote")
+        .form(&note("Fig leaf", "fresh"))
+        .await;
+    admin
+        .post("/adminpanel/addnote")
+        .form(&note("Tonka", "gourmand"))
+        .await;
+    let response = admin
+        .post("/adminpanel/addnote")
+        .form(&note("tonka", "gourmand"))
+        .expect_failure()
+        .await;
+    assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+    let mut products = vec![];
+    for i in 1..=3 {
+        let slug = format!("scent-test-{}", i);
+        products.push(
+            import_product(&admin, &slug, &format!("SCENT-{}", i))
+                .await
+                .0,
+        );
+    }
+    let profile = |product_id: i32, top: &str, middle: &str, base: &str| {
+        [
+            ("product_id", product_id.to_string()),
+            ("top", top.to_owned()),
+            ("middle", middle.to_owned()),
+            ("base", base.to_owned()),
+        ]
+    };
+    for bad in [
+        profile(products[0], "Fig leaf, Smoke", "", ""),
+        profile(products[0], "Fig leaf", "fig leaf", ""),
+    ] {
+        let response = admin
+            .post("/adminpanel/setnotes")
+            .form(&bad)
+            .expect_failure()
+            .await;
+        assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+    }
+    admin
+        .post("/adminpanel/setnotes")
+        .form(&profile(products[0], "fig leaf", "", "Tonka"))
+        .await;
+    admin
+        .post("/adminpanel/setnotes")
+        .form(&profile(products[1], "Fig leaf", "", "Tonka"))
+        .await;
+    admin
+        .post("/adminpanel/setnotes")
+        .form(&profile(products[2], "", "Fig leaf", ""))
+        .await;
+
+    let guest = TestServer::new(create_srv().await).unwrap();
+    let page = guest.get("/browse/scent-test-1").await.text();
+    assert!(page.contains("Fig leaf"));
+    assert!(page.contains("/browse?family=fresh"));
+    let similar = page.split("You may also like").nth(1).unwrap();
+    let closest = similar.find("/browse/scent-test-2").unwrap();
+    let further = similar.find("/browse/scent-test-3").unwrap();
+    assert!(closest < further);
+    assert!(!similar.contains("/browse/cherry"));
+
+    let page = guest.get("/browse?family=fresh").await.text();
+    assert!(page.contains("/browse/scent-test-3"));
+    assert!(page.contains("/browse/citrus"));
+    assert!(!page.contains("/browse/cherry"));
+    assert!(page.contains("Scent family"));
+
+    // clearing every tier leaves the product without a profile
+    admin
+        .post("/adminpanel/setnotes")
+        .form(&profile(products[2], "", "", ""))
+        .await;
+    let page = guest.get("/browse/scent-test-1").await.text();
+    assert!(!page.contains("/browse/scent-test-3"));
+}
+
+#[tokio::test]
+async fn gift_finder_quiz() {
+    let admin = admin_server().await;
+    // priced above every seeded candle so the quiz only has these to choose from
+    let csv = "slug,title,description,image,sku,size,wicks,cost\n\
+        gift-test-1,Gift Test Candle,A candle,cherry.jpg,GIFT-1,Standard,1,95.00\n\
+        gift-test-2,Gift Test Candle,A candle,cherry.jpg,GIFT-2,Standard,1,95.00\n\
+        gift-test-3,Gift Test Candle,A candle,cherry.jpg,GIFT-3,Standard,1,95.00\n\
+        gift-test-4,Gift Test Candle,A candle,cherry.jpg,GIFT-4,Standard,1,95.00\n";
+    let form = import_form("catalog.csv", csv.as_bytes().to_vec(), true);
+    admin.post("/adminpanel/import").multipart(form).await;
+    let mut conn = create_pool().await.get().await.unwrap();
+    let mut ids = vec![];
+    for i in 1..=4 {
+        let ids_for_sku: (i32, i32) = productvariants::table
+            .select((productvariants::product_id, productvariants::id))
+            .filter(productvariants::sku.eq(format!("GIFT-{}", i)))
+            .first(&mut conn)
+            .await
+            .unwrap();
+        ids.push(ids_for_sku);
+    }
+    // the fourth is out of stock
+    for (_, variant_id) in &ids[..3] {
+        let stock = [
+            ("id", variant_id.to_string()),
+            ("delta", String::from("5")),
+            ("reason", String::from("Delivery")),
+        ];
+        admin.post("/adminpanel/adjuststock").form(&stock).await;
+    }
+    let notes = |product_id: i32, top: &str| {
+        [
+            ("product_id", product_id.to_string()),
+            ("top", top.to_owned()),
+            ("middle", String::new()),
+            ("base", String::new()),
+        ]
+    };
+    for (product_id, top) in [
+        (ids[0].0, "Lemon, Rose"),
+        (ids[1].0, "Lemon"),
+        (ids[3].0, "Rose"),
+    ] {
+        admin
+            .post("/adminpanel/setnotes")
+            .form(&notes(product_id, top))
+            .await;
+    }
+
+    let guest = TestServer::new(create_srv().await).unwrap();
+    assert!(guest
+        .get("/gift-finder")
+        .await
+        .text()
+        .contains("What&#39;s the occasion?"));
+    for bad in [
+        vec![("step", "occasion")],
+        vec![("step", "occasion"), ("occasion", "wedding")],
+    ] {
+        let response = guest.post("/gift-finder").form(&bad).expect_failure().await;
+        assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+    }
+    let step = guest
+        .post("/gift-finder")
+        .form(&[("step", "occasion"), ("occasion", "housewarming")])
+        .await
+        .text();
+    assert!(step.contains("Question 2 of 4"));
+    assert!(step.contains(r#"name="occasion" value="housewarming""#));
+    // nothing ticked for scents
+    let response = guest
+        .post("/gift-finder")
+        .form(&[
+            ("step", "family"),
+            ("occasion", "housewarming"),
+            ("season", "spring"),
+        ])
+        .expect_failure()
+        .await;
+    assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+
+    let answered = || {
+        giftquizanswers::table
+            .filter(giftquizanswers::occasion.eq("housewarming"))
+            .filter(giftquizanswers::season.eq("spring"))
+            .filter(giftquizanswers::families.eq("floral,fresh"))
+            .filter(giftquizanswers::budget.eq("30-"))
+            .count()
+    };
+    let before: i64 = answered().get_result(&mut conn).await.unwrap();
+    let results = guest
+        .post("/gift-finder")
+        .form(&[
+            ("step", "budget"),
+            ("occasion", "housewarming"),
+            ("season", "spring"),
+            ("family", "fresh"),
+            ("family", "floral"),
+            ("budget", "30-"),
+        ])
+        .await
+        .text();
+    let position = |slug: &str| results.find(&format!("/browse/{}'", slug));
+    assert!(position("gift-test-1").unwrap() < position("gift-test-2").unwrap());
+    assert!(position("gift-test-2").unwrap() < position("gift-test-3").unwrap());
+    assert!(position("gift-test-4").is_none());
+    assert!(position("cherry").is_none());
+    assert!(results.contains("/browse?price=30-&#38;family=floral&#38;family=fresh"));
+    let after: i64 = answered().get_result(&mut conn).await.unwrap();
+    assert_eq!(after, before + 1);
+    assert!(admin
+        .get("/adminpanel")
+        .await
+        .text()
+        .contains("Gift finder answers"));
+}
+
+#[test]
+fn personalisation_fits_in_a_cart_line() {
+    let field = |id: i32| PersonalisationField {
+        id,
+        product_id: 1,
+        name: format!("Line {}", id),
+        kind: String::from(personalisation::TEXT),
+        max_length: personalisation::MAX_LENGTH,
+        choices: String::new(),
+        surcharge: Money::zero(Currency::BASE),
+        required: false,
+        position: id,
+    };
+    let fields: Vec<PersonalisationField> = (1..=8).map(field).collect();
+    let long = "x".repeat(personalisation::MAX_LENGTH as usize);
+    let values: Vec<(i32, String)> = (1..=7).map(|id| (id, long.clone())).collect();
+    assert!(personalisation::capture(&fields, &values).is_ok());
+    let values: Vec<(i32, String)> = (1..=8).map(|id| (id, long.clone())).collect();
+    assert_eq!(
+        personalisation::capture(&fields, &values),
+        Err(String::from("Personalisation can be at most 1024 characters altogether"))
+    );
+}
+
+#[tokio::test]
+async fn personalised_order() {
+    let admin = admin_server().await;
+    let (slug, sku) = (unique("personal-test"), unique("PERSONAL-1"));
+    let (product_id, variant_id) = import_product(&admin, &slug, &sku).await;
+    let stock = [
+        ("id", variant_id.to_string()),
+        ("delta", String::from("10")),
+        ("reason", String::from("Delivery")),
+    ];
+    admin.post("/adminpanel/adjuststock").form(&stock).await;
+    let field = |name: &str, kind: &str, max_length: &str, choices: &str, surcharge: &str| {
+        vec![
+            ("product_id", product_id.to_string()),
+            ("name", name.to_owned()),
+            ("kind", kind.to_owned()),
+            ("max_length", max_length.to_owned()),
+            ("choices", choices.to_owned()),
+            ("surcharge", surcharge.to_owned()),
+        ]
+    };
+    for bad in [
+        field("Engraving", "engraving", "", "", ""),
+        field("Ribbon colour", "choice", "", " , ", ""),
+        field("Label text", "text", "500", "", ""),
+        field("Label text", "text", "12", "", "-1"),
+    ] {
+        let response = admin
+            .post("/adminpanel/addfield")
+            .form(&bad)
+            .expect_failure()
+            .await;
+        assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+    }
+    let mut label = field("Label text", "text", "12", "", "3.00");
+    label.push(("required", String::from("true")));
+    admin.post("/adminpanel/addfield").form(&label).await;
+    admin
+        .post("/adminpanel/addfield")
+        .form(&field("Ribbon colour", "choice", "", "Red,Gold", "1.50"))
+        .await;
+    let response = admin
+        .post("/adminpanel/addfield")
+        .form(&label)
+        .expect_failure()
+        .await;
+    assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+    let page = admin.get(&format!("/browse/{}", slug)).await.text();
+    assert!(page.contains("Label text (+£3.00)"));
+    assert!(page.contains(r#"<option value="Gold">"#));
+
+    let mut conn = create_pool().await.get().await.unwrap();
+    let fields: Vec<i32> = personalisationfields::table
+        .select(personalisationfields::id)
+        .filter(personalisationfields::product_id.eq(product_id))
+        .order(personalisationfields::position.asc())
+        .load(&mut conn)
+        .await
+        .unwrap();
+    let add = |label: &str, ribbon: &str, quantity: &str| {
+        vec![
+            ("variant_id", variant_id.to_string()),
+            ("action", String::from("Add")),
+            ("quantity", quantity.to_owned()),
+            ("field_id", fields[0].to_string()),
+            ("field_value", label.to_owned()),
+            ("field_id", fields[1].to_string()),
+            ("field_value", ribbon.to_owned()),
+        ]
+    };
+    let customer = new_customer().await;
+    let mut unknown_field = add("Happy 30th", "Gold", "2");
+    unknown_field.push(("field_id", String::from("0")));
+    unknown_field.push(("field_value", String::from("Surprise")));
+    for bad in [
+        add("", "Gold", "1"),
+        add("Happy 30th birthday", "Gold", "1"),
+        add("Happy 30th", "Blue", "1"),
+        unknown_field,
+    ] {
+        let response = customer.post("/cart").form(&bad).expect_failure().await;
+        assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+    }
+    customer
+        .post("/cart")
+        .form(&add(" Happy 30th ", "Gold", "2"))
+        .await;
+    let response = customer
+        .post("/cart")
+        .form(&add("Happy 30th", "Gold", "1"))
+        .expect_failure()
+        .await;
+    assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+    // the same candle personalised differently is another line
+    customer.post("/cart").form(&add("Get well", "", "1")).await;
+    let cart = customer.get("/cart").await.text();
+    assert!(cart.contains("Label text: Happy 30th"));
+    assert!(cart.contains("Ribbon colour: Gold"));
+    assert!(cart.contains("£9.50"));
+    assert!(cart.contains("£8.00"));
+    let line_id: i32 = cartproducts::table
+        .select(cartproducts::id)
+        .filter(cartproducts::variant_id.eq(variant_id))
+        .filter(cartproducts::personalisation.eq("Label text: Get well"))
+        .first(&mut conn)
+        .await
+        .unwrap();
+    assert!(cart.contains(&format!(r#"value="{}" name="line_id""#, line_id)));
+    let remove = [
+        ("variant_id", variant_id.to_string()),
+        ("action", String::from("Remove")),
+        ("quantity", String::from("1")),
+        ("line_id", line_id.to_string()),
+    ];
+    customer.post("/cart").form(&remove).await;
+    assert!(!customer.get("/cart").await.text().contains("Get well"));
+
+    let checkout = [
+        ("cardnum", "4111111111111111"),
+        ("expiry", "12/99"),
+        ("cvv", "123"),
+        ("recipient_name", "Test Customer"),
+        ("line_1", "1 Test Street"),
+        ("postcode", "AB12CD"),
+        ("county", "Testshire"),
+    ];
+    customer.post("/cart/checkout").form(&checkout).await;
+    let (order_id, quantity, unit_cost, personalisation, surcharge): (
+        i32,
+        i32,
+        Money,
+        String,
+        Money,
+    ) = productorders::table
+        .select((
+            productorders::order_id,
+            productorders::quantity,
+            productorders::unit_cost,
+            productorders::personalisation,
+            productorders::surcharge,
+        ))
+        .filter(productorders::variant_id.eq(variant_id))
+        .first(&mut conn)
+        .await
+        .unwrap();
+    assert_eq!(quantity, 2);
+    assert_eq!(unit_cost, Money::parse("9.50", Currency::BASE).unwrap());
+    assert_eq!(surcharge, Money::parse("4.50", Currency::BASE).unwrap());
+    assert_eq!(
+        personalisation,
+        "Label text: Happy 30th\nRibbon colour: Gold"
+    );
+    let settlement_total: Money = orders::table
+        .select(orders::settlement_total)
+        .filter(orders::id.eq(order_id))
+        .first(&mut conn)
+        .await
+        .unwrap();
+    assert_eq!(
+        settlement_total,
+        Money::parse("19.00", Currency::BASE).unwrap()
+    );
+    let details = customer
+        .post("/orders")
+        .form(&[("order_id", order_id)])
+        .await
+        .text();
+    assert!(details.contains("Label text: Happy 30th"));
+    assert!(details.contains("£19.00"));
+    let dashboard = admin.get("/adminpanel").await.text();
+    let packing = &dashboard[dashboard.find(&format!("Order {} for", order_id)).unwrap()..];
+    assert!(packing.contains(&format!("2 x Archive Test Candle (Standard, {})", sku)));
+    assert!(packing.contains("Ribbon colour: Gold"));
+}
+
 async fn login_success() {}